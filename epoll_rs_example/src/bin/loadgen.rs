@@ -0,0 +1,234 @@
+/// HTTP/1.1 load generator.
+///
+/// Opens `--connections` connections, each on its own thread, and sends GET
+/// requests back to back for `--duration` seconds, then prints throughput and
+/// latency percentiles.  Connections are kept alive unless the server closes
+/// them, so the same run works against this crate's server (port 8080),
+/// `reactor_rs_example` (8000, closes after every response) and
+/// `webserver_sample` (8811):
+///
+///   cargo run --release -- --threads 4 &
+///   cargo run --release --bin loadgen -- --addr 127.0.0.1:8080 --connections 64 --duration 10
+///
+/// With `--close` every request uses a fresh connection, which is what the
+/// servers that don't support keep-alive get anyway.
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Barrier},
+    thread,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str =
+    "usage: loadgen [--addr ADDR] [--path PATH] [--connections N] [--duration SECS] [--close]";
+
+struct Args {
+    addr: String,
+    path: String,
+    connections: usize,
+    duration: Duration,
+    close: bool,
+}
+
+impl Args {
+    fn parse() -> Args {
+        let mut parsed = Args {
+            addr: "127.0.0.1:8080".to_string(),
+            path: "/".to_string(),
+            connections: 32,
+            duration: Duration::from_secs(10),
+            close: false,
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().expect(USAGE);
+            match arg.as_str() {
+                "--addr" => parsed.addr = value(),
+                "--path" => parsed.path = value(),
+                "--connections" => parsed.connections = value().parse().expect(USAGE),
+                "--duration" => parsed.duration = Duration::from_secs_f64(value().parse().expect(USAGE)),
+                "--close" => parsed.close = true,
+                _ => panic!("{}", USAGE),
+            }
+        }
+        parsed
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    /// Microseconds per successful request.
+    latencies: Vec<u64>,
+    non_2xx: u64,
+    errors: u64,
+    reconnects: u64,
+    bytes: u64,
+}
+
+impl Stats {
+    fn merge(&mut self, other: Stats) {
+        self.latencies.extend(other.latencies);
+        self.non_2xx += other.non_2xx;
+        self.errors += other.errors;
+        self.reconnects += other.reconnects;
+        self.bytes += other.bytes;
+    }
+}
+
+struct Response {
+    status: u16,
+    bytes: u64,
+    keep_alive: bool,
+}
+
+/// Read one response, or `Ok(None)` if the server closed the connection
+/// before sending anything.
+fn read_response(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Response>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut bytes = line.len() as u64;
+    let status = line
+        .split(' ')
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad status line {:?}", line)))?;
+
+    let mut content_length = None;
+    let mut keep_alive = true;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        bytes += line.len() as u64;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<u64>().ok();
+            } else if name.eq_ignore_ascii_case("connection") && value.trim().eq_ignore_ascii_case("close") {
+                keep_alive = false;
+            }
+        }
+    }
+
+    bytes += match content_length {
+        Some(length) => io::copy(&mut reader.by_ref().take(length), &mut io::sink())?,
+        // Without a length the body runs to the end of the connection.
+        None => {
+            keep_alive = false;
+            io::copy(reader, &mut io::sink())?
+        }
+    };
+    Ok(Some(Response { status, bytes, keep_alive }))
+}
+
+fn run_connection(args: &Args, deadline: Instant) -> Stats {
+    let mut stats = Stats::default();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\n{}\r\n",
+        args.path,
+        args.addr,
+        if args.close { "Connection: close\r\n" } else { "" }
+    );
+    let connect = || -> io::Result<BufReader<TcpStream>> {
+        let stream = TcpStream::connect(&args.addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        Ok(BufReader::new(stream))
+    };
+
+    let mut conn: Option<BufReader<TcpStream>> = None;
+    while Instant::now() < deadline {
+        let start = Instant::now();
+        let mut attempt = || -> io::Result<Option<Response>> {
+            let reused = conn.is_some();
+            let reader = match &mut conn {
+                Some(reader) => reader,
+                None => conn.insert(connect()?),
+            };
+            match reader.get_mut().write_all(request.as_bytes()).and_then(|_| read_response(reader)) {
+                // The server closed an idle keep-alive connection; try a new one.
+                Ok(None) | Err(_) if reused => {
+                    stats.reconnects += 1;
+                    let reader = conn.insert(connect()?);
+                    reader.get_mut().write_all(request.as_bytes())?;
+                    read_response(reader)
+                }
+                result => result,
+            }
+        };
+        match attempt() {
+            Ok(Some(response)) => {
+                stats.latencies.push(start.elapsed().as_micros() as u64);
+                stats.bytes += response.bytes;
+                if !(200..300).contains(&response.status) {
+                    stats.non_2xx += 1;
+                }
+                if !response.keep_alive || args.close {
+                    conn = None;
+                }
+            }
+            Ok(None) | Err(_) => {
+                stats.errors += 1;
+                conn = None;
+            }
+        }
+    }
+    stats
+}
+
+fn percentile(sorted: &[u64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1] as f64 / 1000.0
+}
+
+fn main() {
+    let args = Arc::new(Args::parse());
+    println!(
+        "{} connections to http://{}{} for {:?}{}",
+        args.connections,
+        args.addr,
+        args.path,
+        args.duration,
+        if args.close { ", one request per connection" } else { "" }
+    );
+
+    let barrier = Arc::new(Barrier::new(args.connections + 1));
+    let workers: Vec<_> = (0..args.connections)
+        .map(|_| {
+            let args = args.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                run_connection(&args, Instant::now() + args.duration)
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    for worker in workers {
+        stats.merge(worker.join().expect("load thread panicked"));
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    stats.latencies.sort_unstable();
+    let requests = stats.latencies.len();
+    let mean = stats.latencies.iter().sum::<u64>() as f64 / requests.max(1) as f64 / 1000.0;
+    println!("requests:    {} in {:.2}s", requests, elapsed);
+    println!("throughput:  {:.0} req/s, {:.2} MiB/s", requests as f64 / elapsed, stats.bytes as f64 / elapsed / 1048576.0);
+    println!("errors:      {} ({} non-2xx, {} reconnects)", stats.errors, stats.non_2xx, stats.reconnects);
+    println!("latency ms:  mean {:.3}", mean);
+    for p in [50.0, 90.0, 99.0, 99.9, 100.0] {
+        println!("  p{:<6} {:.3}", p, percentile(&stats.latencies, p));
+    }
+}
@@ -56,8 +56,7 @@ pub fn epoll_wait(fd_context: &EpollContext) {
     let nfds = syscall!(epoll_wait(fd_context.epfd, events.as_mut_ptr(), events.len() as i32, 5000)).expect("epoll_wait failed");
     println!("epoll_wait reported {} events", nfds);
 
-    for i in 0..nfds as usize {
-        let ev = events[i];
+    for ev in &events[..nfds as usize] {
         if ev.u64 == fd_context.read_fd as u64 {
             let mut buf = [0u8; 64];
             let n = syscall!(read(fd_context.read_fd, buf.as_mut_ptr() as *mut _, buf.len())).unwrap();
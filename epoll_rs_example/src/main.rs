@@ -1,7 +1,16 @@
-use epoll_rs_example::{example_epoll::*, tcp_epoll::listening_tcp};
-use std::{io::{self}, net::TcpListener};
-use std::os::unix::io::{AsRawFd, RawFd};
-use std::io::prelude::*;
+use epoll_rs_example::tcp_epoll::{Balance, Config, Response, Router, Server};
+use std::{io, thread};
+
+const USAGE: &str = "usage: epoll_rs_example [--addr ADDR] [--threads N] [--exclusive]";
+
+fn router() -> Router {
+    Router::new()
+        .get("/", |_| Response::html(200, "Hello World!"))
+        .post("/echo", |req| {
+            let content_type = req.header("content-type").unwrap_or("application/octet-stream");
+            Response::new(200).header("content-type", content_type).body(req.body.clone())
+        })
+}
 
 fn main() -> io::Result<()> {
 
@@ -13,6 +22,34 @@ fn main() -> io::Result<()> {
     // epoll_wait(&fd_context);
     // close_fd(&fd_context);
 
-    listening_tcp("127.0.0.1:8080")?;
+    let mut addr = "127.0.0.1:8080".to_string();
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut balance = Balance::ReusePort;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => addr = args.next().expect(USAGE),
+            "--threads" => threads = args.next().and_then(|n| n.parse().ok()).expect(USAGE),
+            "--exclusive" => balance = Balance::Exclusive,
+            _ => panic!("{}", USAGE),
+        }
+    }
+
+    let addr = addr.parse().map_err(io::Error::other)?;
+    let server = Server::spawn(addr, threads, balance, Config::default(), || {
+        let router = router();
+        move |req| router.handle(req)
+    })?;
+    println!("listening on http://{} with {} threads ({:?})", server.local_addr(), threads, balance);
+
+    let shutdown = server.shutdown_handle();
+    ctrlc::set_handler(move || {
+        println!("Got it! Draining connections...");
+        shutdown.shutdown();
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    server.join()?;
+    println!("Exiting.");
     Ok(())
 }
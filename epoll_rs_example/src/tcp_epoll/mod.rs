@@ -1,145 +1,296 @@
-use std::{collections::HashMap, io::{self, Read, Write}, net::{TcpListener, TcpStream}, os::fd::{AsRawFd, IntoRawFd, RawFd}};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    time::{Duration, Instant},
+};
 use crate::syscall;
 
+pub mod parser;
+pub mod response;
+pub mod router;
+pub mod server;
+
+pub use parser::{Limits, ParseError, Request, Version};
+pub use response::Response;
+pub use router::Router;
+pub use server::{Balance, Server, ShutdownHandle};
+
+const SHUTDOWN_KEY: u64 = 99;
+const LISTENER_KEY: u64 = 100;
 const READ_FLAGS: i32 = libc::EPOLLONESHOT | libc::EPOLLIN;
 const WRITE_FLAGS: i32 = libc::EPOLLONESHOT | libc::EPOLLOUT;
-const HTTP_RESP: &[u8] = b"HTTP/1.1 200 OK
-content-type: text/html
-content-length: 12
+/// Stop answering pipelined requests while this much output is unsent.
+const MAX_PENDING_OUTPUT: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    pub limits: Limits,
+    /// How long a connection may go without reading or writing anything.
+    pub idle_timeout: Duration,
+}
 
-Hello World!";
+impl Default for Config {
+    fn default() -> Self {
+        Config { limits: Limits::default(), idle_timeout: Duration::from_secs(5) }
+    }
+}
+
+/// What a connection waits for next.
+enum Status {
+    Read,
+    Write,
+    Closed,
+}
 
-#[derive(Debug)]
-pub struct RequestContext {
+/// One client connection, answering any number of requests in order.
+pub struct Connection {
     pub stream: TcpStream,
-    pub content_length: usize,
-    pub buf: Vec<u8>,
+    parser: parser::Parser,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    written: usize,
+    /// Close once `output` is sent: the client asked for it or sent garbage.
+    closing: bool,
+    /// The server is shutting down: answer what has arrived, then close.
+    draining: bool,
+    deadline: Instant,
 }
 
-impl RequestContext {
-    fn new(stream: TcpStream) -> Self {
+impl Connection {
+    fn new(stream: TcpStream, config: &Config) -> Self {
         Self {
             stream,
-            buf: Vec::new(),
-            content_length: 0,
+            parser: parser::Parser::new(config.limits),
+            input: Vec::new(),
+            output: Vec::new(),
+            written: 0,
+            closing: false,
+            draining: false,
+            deadline: Instant::now() + config.idle_timeout,
         }
     }
 
-    fn read_cb(&mut self, key: u64, epoll_fd: RawFd) -> io::Result<()> {
+    /// Make as much progress as the socket allows.
+    fn ready(&mut self, handler: &mut impl FnMut(&Request) -> Response, config: &Config) -> io::Result<Status> {
         let mut buf = [0u8; 4096];
-        match self.stream.read(&mut buf) {
-            Ok(_) => {
-                if let Ok(data) = std::str::from_utf8(&buf) {
-                    self.parse_and_set_content_length(data);
+        loop {
+            self.respond(handler);
+            if !self.flush(config)? {
+                return Ok(Status::Write);
+            }
+            if self.closing || (self.draining && self.is_idle()) {
+                if let Err(e) = self.stream.shutdown(Shutdown::Write)
+                    && e.kind() != io::ErrorKind::NotConnected
+                {
+                    return Err(e);
                 }
+                return Ok(Status::Closed);
             }
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
-            Err(e) => {
-                return Err(e);
+
+            // Only read once everything so far is answered, so a client that
+            // doesn't read its responses can't make us buffer without bound.
+            match self.stream.read(&mut buf) {
+                // A request cut off by the client is dropped with the connection.
+                Ok(0) => return Ok(Status::Closed),
+                Ok(n) => {
+                    self.input.extend_from_slice(&buf[..n]);
+                    self.deadline = Instant::now() + config.idle_timeout;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Status::Read),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
             }
-        };
-        self.buf.extend_from_slice(&buf);
-        if self.buf.len() >= self.content_length {
-            println!("got all data: {} bytes", self.buf.len());
-            modify_interest(epoll_fd, self.stream.as_raw_fd(), listener_write_event(key))?;
-        } else {
-            modify_interest(epoll_fd, self.stream.as_raw_fd(), listener_read_event(key))?;
         }
-        Ok(())
     }
 
-    fn write_cb(&mut self, key: u64, epoll_fd: RawFd) -> io::Result<()> {
-        match self.stream.write(HTTP_RESP) {
-            Ok(_) => println!("answered from request {}", key),
-            Err(e) => eprintln!("could not answer to request {}, {}", key, e),
-        };
-        self.stream.shutdown(std::net::Shutdown::Both)?;
-        let fd = self.stream.as_raw_fd();
-        remove_interest(epoll_fd, fd)?;
-        Ok(())
-    }
-
-    fn parse_and_set_content_length(&mut self, data: &str) {
-        if data.contains("HTTP") {
-            if let Some(content_length) = data
-                .lines()
-                .find(|l| l.to_lowercase().starts_with("content-length: "))
-            {
-                if let Some(len) = content_length
-                    .to_lowercase()
-                    .strip_prefix("content-length: ")
-                {
-                    self.content_length = len.parse::<usize>().expect("content-length is valid");
-                    println!("set content length: {} bytes", self.content_length);
+    /// Nothing received is waiting for an answer.
+    fn is_idle(&self) -> bool {
+        self.input.is_empty() && !self.parser.in_progress() && self.output.is_empty()
+    }
+
+    /// Answer the complete requests in `input`.
+    fn respond(&mut self, handler: &mut impl FnMut(&Request) -> Response) {
+        while !self.closing && self.output.len() < MAX_PENDING_OUTPUT {
+            match self.parser.parse(&mut self.input) {
+                Ok(Some(request)) => {
+                    let keep_alive = request.keep_alive() && !self.draining;
+                    handler(&request).write_to(&mut self.output, keep_alive, request.method == "HEAD");
+                    self.closing = !keep_alive;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    Response::text(e.status(), format!("{}\n", e)).write_to(&mut self.output, false, false);
+                    self.closing = true;
+                }
+            }
+        }
+    }
+
+    /// Write pending output.  Returns whether all of it went out.
+    fn flush(&mut self, config: &Config) -> io::Result<bool> {
+        while self.written < self.output.len() {
+            match self.stream.write(&self.output[self.written..]) {
+                Ok(n) => {
+                    self.written += n;
+                    self.deadline = Instant::now() + config.idle_timeout;
                 }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
             }
         }
+        self.output.clear();
+        self.written = 0;
+        Ok(true)
     }
 }
 
-fn close(fd: RawFd) {
-    let _ = syscall!(close(fd));
+pub fn listening_tcp(addr: &str, handler: impl FnMut(&Request) -> Response) -> io::Result<()> {
+    serve(TcpListener::bind(addr)?, Config::default(), handler)
 }
 
-pub fn listening_tcp(addr:&str) -> io::Result<(i32,i32)> {
-    let listener = TcpListener::bind(addr)?; 
-    let mut events : Vec<libc::epoll_event> = Vec::with_capacity(1024);
-    let mut request_contexts: HashMap<u64, RequestContext> = HashMap::new();
-    let mut key = 100;
+/// Serve HTTP/1.1 on `listener` forever, answering each request with `handler`.
+pub fn serve(listener: TcpListener, config: Config, handler: impl FnMut(&Request) -> Response) -> io::Result<()> {
+    event_loop(&listener, false, config, handler, None)
+}
+
+/// Run one epoll loop accepting from `listener` until `shutdown` fires and
+/// the connections still answering a request are done.  With `exclusive`
+/// the listener is shared with other loops and registered `EPOLLEXCLUSIVE`,
+/// so a new connection wakes only one of them.
+pub(crate) fn event_loop(
+    listener: &TcpListener,
+    exclusive: bool,
+    config: Config,
+    mut handler: impl FnMut(&Request) -> Response,
+    shutdown: Option<&ShutdownHandle>,
+) -> io::Result<()> {
+    let mut events: Vec<libc::epoll_event> = Vec::with_capacity(1024);
+    let mut connections: HashMap<u64, Connection> = HashMap::new();
+    // One entry per connection; an entry whose connection has been active
+    // since is pushed back with the new deadline when it comes up.
+    let mut deadlines: BinaryHeap<Reverse<(Instant, u64)>> = BinaryHeap::new();
+    let mut key = LISTENER_KEY;
+    let mut draining = false;
     listener.set_nonblocking(true)?;
     let listener_fd = listener.as_raw_fd();
-    let epoll_fd = epoll_create()?;
-    add_interest(epoll_fd, listener_fd, listener_read_event(key))?;
+    // SAFETY: the fd was just created and nothing else owns it.
+    let epoll = unsafe { OwnedFd::from_raw_fd(epoll_create()?) };
+    let epoll_fd = epoll.as_raw_fd();
+    if exclusive {
+        let events = (libc::EPOLLIN | libc::EPOLLEXCLUSIVE) as u32;
+        add_interest(epoll_fd, listener_fd, libc::epoll_event { events, u64: LISTENER_KEY })?;
+    } else {
+        add_interest(epoll_fd, listener_fd, listener_read_event(LISTENER_KEY))?;
+    }
+    if let Some(shutdown) = shutdown {
+        // Level-triggered and never reset, so every loop sees it.
+        let event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: SHUTDOWN_KEY };
+        add_interest(epoll_fd, shutdown.as_raw_fd(), event)?;
+    }
 
     loop {
-        println!("requests in flight: {}", request_contexts.len());
+        let timeout = deadlines.peek().map_or(-1, |Reverse((deadline, _))| {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            wait.as_millis().saturating_add(1).min(libc::c_int::MAX as u128) as libc::c_int
+        });
         events.clear();
-        let res = syscall!(epoll_wait(epoll_fd,events.as_mut_ptr() as *mut libc::epoll_event,1024,-1 as libc::c_int))?;
+        let res = match syscall!(epoll_wait(epoll_fd, events.as_mut_ptr(), 1024, timeout)) {
+            Ok(res) => res,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => 0,
+            Err(e) => return Err(e),
+        };
         unsafe { events.set_len(res as usize) };
+
         for e in &events {
             match e.u64 {
-                100 => {
-                    match listener.accept() {
-                        Ok((stream, addr)) => {
-                            stream.set_nonblocking(true)?;
-                            println!("new client: {}", addr);
-                            key += 1;
-                            add_interest(epoll_fd, stream.as_raw_fd(), listener_read_event(key))?;
-                            request_contexts.insert(key, RequestContext::new(stream));
-                        }
-                        Err(e) => eprintln!("couldn't accept: {}", e),
-                    };
-                    modify_interest(epoll_fd, listener_fd, listener_read_event(100))?;
+                SHUTDOWN_KEY => {
+                    draining = true;
+                    remove_interest(epoll_fd, listener_fd)?;
+                    if let Some(shutdown) = shutdown {
+                        remove_interest(epoll_fd, shutdown.as_raw_fd())?;
+                    }
+                    let idle: Vec<u64> = connections.iter().filter(|(_, c)| c.is_idle()).map(|(key, _)| *key).collect();
+                    for key in idle {
+                        close_connection(epoll_fd, &mut connections, key)?;
+                    }
+                    for connection in connections.values_mut() {
+                        connection.draining = true;
+                    }
                 }
-                key =>{
-                    let mut to_delete: Option<u64> = None;
-                    if let Some(context) = request_contexts.get_mut(&key) {
-                        let events: u32 = e.events;
-                        match events {
-                            v  if v as i32 & libc::EPOLLIN == libc::EPOLLIN => {
-                                context.read_cb(key, epoll_fd)?;
+                LISTENER_KEY if draining => {}
+                LISTENER_KEY => {
+                    loop {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                stream.set_nonblocking(true)?;
+                                key += 1;
+                                add_interest(epoll_fd, stream.as_raw_fd(), listener_read_event(key))?;
+                                let connection = Connection::new(stream, &config);
+                                deadlines.push(Reverse((connection.deadline, key)));
+                                connections.insert(key, connection);
                             }
-                            v if v as i32 & libc::EPOLLOUT == libc::EPOLLOUT => {
-                                context.write_cb(key, epoll_fd)?;
-                                to_delete = Some(key);
+                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(e) => {
+                                eprintln!("couldn't accept: {}", e);
+                                break;
                             }
-                            v => println!("unexpected events: {}", v),
-                        };
+                        }
                     }
-                    if let Some(key) = to_delete {
-                        request_contexts.remove(&key);
+                    if !exclusive {
+                        modify_interest(epoll_fd, listener_fd, listener_read_event(LISTENER_KEY))?;
+                    }
+                }
+                key => {
+                    let Some(connection) = connections.get_mut(&key) else {
+                        continue;
+                    };
+                    let fd = connection.stream.as_raw_fd();
+                    match connection.ready(&mut handler, &config) {
+                        Ok(Status::Read) => modify_interest(epoll_fd, fd, listener_read_event(key))?,
+                        Ok(Status::Write) => modify_interest(epoll_fd, fd, listener_write_event(key))?,
+                        Ok(Status::Closed) => close_connection(epoll_fd, &mut connections, key)?,
+                        Err(e) => {
+                            eprintln!("connection {} failed: {}", key, e);
+                            close_connection(epoll_fd, &mut connections, key)?;
+                        }
                     }
                 }
             }
         }
 
+        let now = Instant::now();
+        while let Some(&Reverse((deadline, key))) = deadlines.peek()
+            && deadline <= now
+        {
+            deadlines.pop();
+            match connections.get(&key) {
+                Some(connection) if connection.deadline <= now => close_connection(epoll_fd, &mut connections, key)?,
+                Some(connection) => deadlines.push(Reverse((connection.deadline, key))),
+                None => {}
+            }
+        }
+
+        if draining && connections.is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+fn close_connection(epoll_fd: RawFd, connections: &mut HashMap<u64, Connection>, key: u64) -> io::Result<()> {
+    if let Some(connection) = connections.remove(&key) {
+        remove_interest(epoll_fd, connection.stream.as_raw_fd())?;
     }
+    Ok(())
 }
 
 fn epoll_create() -> io::Result<RawFd>{
     let fd = syscall!(epoll_create1(0))?;
     if let Ok(flag) = syscall!(fcntl(fd, libc::F_GETFD)) {
-        syscall!(fcntl(fd, libc::F_SETFD, flag | libc::FD_CLOEXEC));
+        syscall!(fcntl(fd, libc::F_SETFD, flag | libc::FD_CLOEXEC))?;
     }
     Ok(fd)
 }
@@ -179,4 +330,66 @@ fn remove_interest(epoll_fd : RawFd,fd :RawFd) -> io::Result<()> {
         std::ptr::null_mut()
     ))?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::SocketAddr, thread};
+
+    fn spawn_server(config: Config) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let router = Router::new()
+                .get("/", |_| Response::text(200, "hello"))
+                .post("/echo", |req| Response::new(200).body(req.body.clone()));
+            serve(listener, config, |req| router.handle(req))
+        });
+        addr
+    }
+
+    fn read_to_end(stream: &mut TcpStream) -> String {
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn answers_pipelined_requests_on_one_connection() {
+        let addr = spawn_server(Config::default());
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\nPOST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        stream.write_all(b"HEAD / HTTP/1.1\r\n\r\nGET /missing HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+
+        let out = read_to_end(&mut stream);
+        let responses: Vec<_> = out.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(responses.len(), 4, "{out}");
+        assert!(responses[0].starts_with("200 OK") && responses[0].ends_with("\r\n\r\nhello"));
+        assert!(responses[1].contains("content-length: 2\r\n") && responses[1].ends_with("\r\n\r\nhi"));
+        assert!(responses[2].contains("content-length: 5\r\n") && responses[2].ends_with("\r\n\r\n"));
+        assert!(responses[3].starts_with("404") && responses[3].contains("connection: close"));
+    }
+
+    #[test]
+    fn closes_after_a_malformed_request() {
+        let addr = spawn_server(Config::default());
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nBad Header: x\r\n\r\nGET / HTTP/1.1\r\n\r\n").unwrap();
+        let out = read_to_end(&mut stream);
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{out}");
+        assert_eq!(out.matches("HTTP/1.1").count(), 1);
+    }
+
+    #[test]
+    fn closes_idle_connections() {
+        let addr = spawn_server(Config { idle_timeout: Duration::from_millis(50), ..Config::default() });
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\nGET / HTT").unwrap();
+        let start = Instant::now();
+        let out = read_to_end(&mut stream);
+        assert_eq!(out.matches("200 OK").count(), 1);
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+}
@@ -0,0 +1,426 @@
+use std::{error::Error, fmt};
+
+/// Bounds on what a client may send in one request.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Request line plus headers, and separately the trailers of a chunked body.
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { max_header_bytes: 8 * 1024, max_headers: 100, max_body: 1024 * 1024 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub version: Version,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// First header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    /// Comma-separated elements of every header called `name`, untrimmed.
+    pub fn header_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers.iter().filter(move |(n, _)| n.eq_ignore_ascii_case(name)).flat_map(|(_, v)| v.split(','))
+    }
+
+    /// Path part of the target, without the query.
+    pub fn path(&self) -> &str {
+        self.target.split_once('?').map_or(&self.target, |(path, _)| path)
+    }
+
+    /// Whether the client wants the connection kept open after this request.
+    pub fn keep_alive(&self) -> bool {
+        let has = |option: &str| self.header_values("connection").any(|v| v.trim().eq_ignore_ascii_case(option));
+        match self.version {
+            Version::Http11 => !has("close"),
+            Version::Http10 => has("keep-alive"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    BadRequestLine,
+    BadHeader,
+    BadContentLength,
+    BadChunk,
+    HeadersTooLarge,
+    TooManyHeaders,
+    BodyTooLarge,
+    UnsupportedVersion,
+    UnsupportedTransferEncoding,
+}
+
+impl ParseError {
+    /// Status code to answer the request with.
+    pub fn status(self) -> u16 {
+        match self {
+            ParseError::HeadersTooLarge | ParseError::TooManyHeaders => 431,
+            ParseError::BodyTooLarge => 413,
+            ParseError::UnsupportedVersion => 505,
+            ParseError::UnsupportedTransferEncoding => 501,
+            _ => 400,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ParseError::BadRequestLine => "malformed request line",
+            ParseError::BadHeader => "malformed header",
+            ParseError::BadContentLength => "invalid content-length",
+            ParseError::BadChunk => "malformed chunked body",
+            ParseError::HeadersTooLarge => "request headers too large",
+            ParseError::TooManyHeaders => "too many request headers",
+            ParseError::BodyTooLarge => "request body too large",
+            ParseError::UnsupportedVersion => "unsupported HTTP version",
+            ParseError::UnsupportedTransferEncoding => "unsupported transfer-encoding",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Error for ParseError {}
+
+enum State {
+    Head,
+    Body { remaining: usize },
+    ChunkSize,
+    ChunkData { remaining: usize },
+    ChunkEnd,
+    Trailers,
+}
+
+/// Streaming HTTP/1.x request parser.  Feed it whatever has arrived; it takes
+/// complete pieces off the front of the buffer and keeps its place between
+/// calls, so a request may arrive in any number of reads and several requests
+/// may share one.
+pub struct Parser {
+    limits: Limits,
+    state: State,
+    request: Option<Request>,
+}
+
+impl Parser {
+    pub fn new(limits: Limits) -> Self {
+        Parser { limits, state: State::Head, request: None }
+    }
+
+    /// Whether part of a request has been consumed.
+    pub fn in_progress(&self) -> bool {
+        !matches!(self.state, State::Head)
+    }
+
+    /// Parse the next request out of `buf`, removing the bytes it used.
+    /// Returns `Ok(None)` if more input is needed.  After an error the
+    /// connection can't be resynchronised and should be closed.
+    pub fn parse(&mut self, buf: &mut Vec<u8>) -> Result<Option<Request>, ParseError> {
+        let mut pos = 0;
+        let result = self.advance(buf, &mut pos);
+        buf.drain(..pos);
+        result
+    }
+
+    fn advance(&mut self, buf: &[u8], pos: &mut usize) -> Result<Option<Request>, ParseError> {
+        loop {
+            match self.state {
+                State::Head => {
+                    // Empty lines before a request line are allowed and ignored.
+                    while let Some(n) = line_break(&buf[*pos..]) {
+                        *pos += n;
+                    }
+                    let Some(end) = find_empty_line(&buf[*pos..]) else {
+                        if buf.len() - *pos > self.limits.max_header_bytes {
+                            return Err(ParseError::HeadersTooLarge);
+                        }
+                        return Ok(None);
+                    };
+                    if end > self.limits.max_header_bytes {
+                        return Err(ParseError::HeadersTooLarge);
+                    }
+                    let head = std::str::from_utf8(&buf[*pos..*pos + end]).map_err(|_| ParseError::BadHeader)?;
+                    let (request, framing) = self.parse_head(head)?;
+                    *pos += end;
+                    self.request = Some(request);
+                    self.state = match framing {
+                        Framing::Chunked => State::ChunkSize,
+                        Framing::Length(0) => return Ok(self.finish()),
+                        Framing::Length(remaining) => State::Body { remaining },
+                    };
+                }
+                State::Body { remaining } | State::ChunkData { remaining } => {
+                    let n = remaining.min(buf.len() - *pos);
+                    self.body().extend_from_slice(&buf[*pos..*pos + n]);
+                    *pos += n;
+                    if n < remaining {
+                        self.state = match self.state {
+                            State::Body { .. } => State::Body { remaining: remaining - n },
+                            _ => State::ChunkData { remaining: remaining - n },
+                        };
+                        return Ok(None);
+                    }
+                    if let State::Body { .. } = self.state {
+                        return Ok(self.finish());
+                    }
+                    self.state = State::ChunkEnd;
+                }
+                State::ChunkSize => {
+                    let Some(end) = buf[*pos..].iter().position(|&b| b == b'\n') else {
+                        // A size line is a few hex digits plus extensions.
+                        if buf.len() - *pos > 1024 {
+                            return Err(ParseError::BadChunk);
+                        }
+                        return Ok(None);
+                    };
+                    let line = std::str::from_utf8(&buf[*pos..*pos + end]).map_err(|_| ParseError::BadChunk)?;
+                    let size = line.trim_end_matches('\r').split(';').next().unwrap_or_default().trim();
+                    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return Err(ParseError::BadChunk);
+                    }
+                    let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::BadChunk)?;
+                    *pos += end + 1;
+                    if size > self.limits.max_body - self.body().len() {
+                        return Err(ParseError::BodyTooLarge);
+                    }
+                    self.state = if size == 0 { State::Trailers } else { State::ChunkData { remaining: size } };
+                }
+                State::ChunkEnd => match line_break(&buf[*pos..]) {
+                    Some(n) => {
+                        *pos += n;
+                        self.state = State::ChunkSize;
+                    }
+                    None if buf.len() - *pos < 2 && b"\r\n".starts_with(&buf[*pos..]) => return Ok(None),
+                    None => return Err(ParseError::BadChunk),
+                },
+                State::Trailers => {
+                    // Trailers are read to find the end of the message, then dropped.
+                    if let Some(n) = line_break(&buf[*pos..]) {
+                        *pos += n;
+                        return Ok(self.finish());
+                    }
+                    let Some(end) = find_empty_line(&buf[*pos..]) else {
+                        if buf.len() - *pos > self.limits.max_header_bytes {
+                            return Err(ParseError::HeadersTooLarge);
+                        }
+                        return Ok(None);
+                    };
+                    if end > self.limits.max_header_bytes {
+                        return Err(ParseError::HeadersTooLarge);
+                    }
+                    *pos += end;
+                    return Ok(self.finish());
+                }
+            }
+        }
+    }
+
+    fn body(&mut self) -> &mut Vec<u8> {
+        &mut self.request.as_mut().expect("request head was parsed").body
+    }
+
+    fn finish(&mut self) -> Option<Request> {
+        self.state = State::Head;
+        self.request.take()
+    }
+
+    fn parse_head(&self, head: &str) -> Result<(Request, Framing), ParseError> {
+        let mut lines = head.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line));
+        let mut parts = lines.next().unwrap_or_default().split(' ');
+        let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::BadRequestLine);
+        };
+        if !is_token(method) || target.is_empty() || target.bytes().any(|b| b.is_ascii_control()) {
+            return Err(ParseError::BadRequestLine);
+        }
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            v if v.starts_with("HTTP/") => return Err(ParseError::UnsupportedVersion),
+            _ => return Err(ParseError::BadRequestLine),
+        };
+
+        let mut headers = Vec::new();
+        for line in lines.take_while(|line| !line.is_empty()) {
+            if headers.len() == self.limits.max_headers {
+                return Err(ParseError::TooManyHeaders);
+            }
+            // Whitespace before the colon or a folded line is rejected.
+            let (name, value) = line.split_once(':').ok_or(ParseError::BadHeader)?;
+            if !is_token(name) {
+                return Err(ParseError::BadHeader);
+            }
+            headers.push((name.to_string(), value.trim_matches([' ', '\t']).to_string()));
+        }
+
+        let request = Request { method: method.to_string(), target: target.to_string(), version, headers, body: Vec::new() };
+        let framing = self.framing(&request)?;
+        Ok((request, framing))
+    }
+
+    /// How the body is delimited, per RFC 9112 section 6.3.
+    fn framing(&self, request: &Request) -> Result<Framing, ParseError> {
+        if request.header("transfer-encoding").is_some() {
+            // Both headers at once is how requests get smuggled past proxies.
+            if request.header("content-length").is_some() {
+                return Err(ParseError::BadContentLength);
+            }
+            let codings: Vec<_> = request.header_values("transfer-encoding").map(str::trim).collect();
+            return match codings[..] {
+                [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(Framing::Chunked),
+                _ => Err(ParseError::UnsupportedTransferEncoding),
+            };
+        }
+
+        let mut length = None;
+        for value in request.header_values("content-length") {
+            let value = value.trim();
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::BadContentLength);
+            }
+            let value: usize = value.parse().map_err(|_| ParseError::BodyTooLarge)?;
+            if length.is_some_and(|length| length != value) {
+                return Err(ParseError::BadContentLength);
+            }
+            length = Some(value);
+        }
+        let length = length.unwrap_or(0);
+        if length > self.limits.max_body {
+            return Err(ParseError::BodyTooLarge);
+        }
+        Ok(Framing::Length(length))
+    }
+}
+
+enum Framing {
+    Length(usize),
+    Chunked,
+}
+
+/// Length of the line break at the start of `buf`, if there is one.
+fn line_break(buf: &[u8]) -> Option<usize> {
+    match buf {
+        [b'\r', b'\n', ..] => Some(2),
+        [b'\n', ..] => Some(1),
+        _ => None,
+    }
+}
+
+/// Length of `buf` up to and including the first empty line.
+fn find_empty_line(buf: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    while let Some(n) = buf[start..].iter().position(|&b| b == b'\n') {
+        let line = &buf[start..start + n];
+        if line.is_empty() || line == b"\r" {
+            return Some(start + n + 1);
+        }
+        start += n + 1;
+    }
+    None
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(input: &[u8], step: usize) -> Result<Vec<Request>, ParseError> {
+        let mut parser = Parser::new(Limits::default());
+        let mut buf = Vec::new();
+        let mut requests = Vec::new();
+        for chunk in input.chunks(step) {
+            buf.extend_from_slice(chunk);
+            while let Some(request) = parser.parse(&mut buf)? {
+                requests.push(request);
+            }
+        }
+        assert!(buf.is_empty() && !parser.in_progress(), "leftover input {buf:?}");
+        Ok(requests)
+    }
+
+    #[test]
+    fn parses_pipelined_requests_split_at_every_byte() {
+        let input = b"GET /a?x=1 HTTP/1.1\r\nHost: h\r\n\r\n\
+            POST /b HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+            PUT /c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3;ext=1\r\nabc\r\n2\r\nde\r\n0\r\nX-Trailer: t\r\n\r\n";
+        for step in [1, 2, 7, input.len()] {
+            let requests = parse_all(input, step).unwrap();
+            assert_eq!(requests.len(), 3);
+            assert_eq!((requests[0].method.as_str(), requests[0].path()), ("GET", "/a"));
+            assert_eq!(requests[0].header("HOST"), Some("h"));
+            assert_eq!(requests[1].body, b"hello");
+            assert_eq!(requests[2].body, b"abcde");
+            assert_eq!(requests[2].header("x-trailer"), None);
+        }
+    }
+
+    #[test]
+    fn keep_alive_depends_on_version_and_connection() {
+        let requests = parse_all(
+            b"GET / HTTP/1.1\r\n\r\n\
+              GET / HTTP/1.1\r\nConnection: foo, Close\r\n\r\n\
+              GET / HTTP/1.0\r\n\r\n\
+              GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+            usize::MAX,
+        )
+        .unwrap();
+        let keep_alive: Vec<_> = requests.iter().map(Request::keep_alive).collect();
+        assert_eq!(keep_alive, [true, false, false, true]);
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let cases: &[(&[u8], ParseError)] = &[
+            (b"GET /\r\n\r\n", ParseError::BadRequestLine),
+            (b"GET / HTTP/2.0\r\n\r\n", ParseError::UnsupportedVersion),
+            (b"GET / HTTP/1.1\r\nHost : h\r\n\r\n", ParseError::BadHeader),
+            (b"GET / HTTP/1.1\r\nX: a\r\n folded\r\n\r\n", ParseError::BadHeader),
+            (b"GET / HTTP/1.1\r\nContent-Length: -1\r\n\r\n", ParseError::BadContentLength),
+            (b"GET / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n", ParseError::BadContentLength),
+            (b"GET / HTTP/1.1\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n", ParseError::BadContentLength),
+            (b"GET / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n", ParseError::UnsupportedTransferEncoding),
+            (b"GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n", ParseError::BadChunk),
+            (b"GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\naXY", ParseError::BadChunk),
+        ];
+        for (input, error) in cases {
+            assert_eq!(parse_all(input, usize::MAX), Err(*error), "{}", String::from_utf8_lossy(input));
+        }
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = Limits { max_header_bytes: 64, max_headers: 2, max_body: 8 };
+        let parse = |input: &[u8]| Parser::new(limits).parse(&mut input.to_vec());
+
+        assert_eq!(parse(&[b'a'; 65]), Err(ParseError::HeadersTooLarge));
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"), Err(ParseError::TooManyHeaders));
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nContent-Length: 9\r\n\r\n"), Err(ParseError::BodyTooLarge));
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nabcde\r\n4\r\n"),
+            Err(ParseError::BodyTooLarge)
+        );
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nContent-Length: 8\r\n\r\n"), Ok(None));
+    }
+}
@@ -0,0 +1,73 @@
+use std::io::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Response { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Response::new(status).header("content-type", "text/plain; charset=utf-8").body(body.into())
+    }
+
+    pub fn html(status: u16, body: impl Into<String>) -> Self {
+        Response::new(status).header("content-type", "text/html; charset=utf-8").body(body.into())
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Serialise into `out`.  `content-length` and `connection` are set here,
+    /// replacing any the handler added, and `head` leaves out the body.
+    pub fn write_to(&self, out: &mut Vec<u8>, keep_alive: bool, head: bool) {
+        let _ = write!(out, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("content-length") && !name.eq_ignore_ascii_case("connection") {
+                let _ = write!(out, "{}: {}\r\n", name, value);
+            }
+        }
+        // 1xx, 204 and 304 responses never carry a body.
+        let bodiless = self.status < 200 || self.status == 204 || self.status == 304;
+        if !bodiless {
+            let _ = write!(out, "content-length: {}\r\n", self.body.len());
+        }
+        let _ = write!(out, "connection: {}\r\n\r\n", if keep_alive { "keep-alive" } else { "close" });
+        if !head && !bodiless {
+            out.extend_from_slice(&self.body);
+        }
+    }
+}
+
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
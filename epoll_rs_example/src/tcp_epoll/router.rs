@@ -0,0 +1,45 @@
+use super::{parser::Request, response::Response};
+
+type Route = Box<dyn Fn(&Request) -> Response>;
+
+/// Dispatches on method and exact path.  `HEAD` falls back to the `GET`
+/// route; the server leaves out the body.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<(String, String, Route)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, method: &str, path: &str, handler: impl Fn(&Request) -> Response + 'static) -> Self {
+        self.routes.push((method.to_string(), path.to_string(), Box::new(handler)));
+        self
+    }
+
+    pub fn get(self, path: &str, handler: impl Fn(&Request) -> Response + 'static) -> Self {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post(self, path: &str, handler: impl Fn(&Request) -> Response + 'static) -> Self {
+        self.route("POST", path, handler)
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        let path = request.path();
+        let find = |method: &str| self.routes.iter().find(|(m, p, _)| m == method && p == path);
+        let route = find(&request.method).or_else(|| (request.method == "HEAD").then(|| find("GET")).flatten());
+        if let Some((_, _, handler)) = route {
+            return handler(request);
+        }
+
+        let allowed: Vec<&str> = self.routes.iter().filter(|(_, p, _)| p == path).map(|(m, _, _)| m.as_str()).collect();
+        if allowed.is_empty() {
+            Response::text(404, "not found\n")
+        } else {
+            Response::text(405, "method not allowed\n").header("allow", allowed.join(", "))
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    io, mem,
+    net::{SocketAddr, TcpListener},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+use crate::syscall;
+use super::{event_loop, Config, Request, Response};
+
+/// How connections are spread over the event-loop threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Balance {
+    /// Each thread binds its own `SO_REUSEPORT` listener and the kernel
+    /// hashes new connections across them.
+    ReusePort,
+    /// The threads share one listener registered with `EPOLLEXCLUSIVE`, so a
+    /// new connection wakes one idle thread instead of all of them.
+    Exclusive,
+}
+
+/// Asks every event loop of a `Server` to stop accepting, finish the
+/// requests in flight and exit.  Backed by an eventfd that is written once
+/// and never reset.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    fd: Arc<OwnedFd>,
+}
+
+impl ShutdownHandle {
+    fn new() -> io::Result<Self> {
+        let fd = syscall!(eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK))?;
+        // SAFETY: the fd was just created and nothing else owns it.
+        Ok(ShutdownHandle { fd: Arc::new(unsafe { OwnedFd::from_raw_fd(fd) }) })
+    }
+
+    pub fn shutdown(&self) {
+        let one = 1u64;
+        // Only fails if the counter would overflow, in which case it is already set.
+        let _ = syscall!(write(self.fd.as_raw_fd(), &one as *const u64 as *const libc::c_void, 8));
+    }
+}
+
+impl AsRawFd for ShutdownHandle {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// A server running one epoll loop per thread.
+pub struct Server {
+    addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    threads: Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl Server {
+    /// Bind `addr` and start `threads` event loops.  Each thread builds its
+    /// own handler with `make_handler`, so handlers need not be `Send`.
+    pub fn spawn<F, H>(addr: SocketAddr, threads: usize, balance: Balance, config: Config, make_handler: F) -> io::Result<Server>
+    where
+        F: Fn() -> H + Send + Sync + 'static,
+        H: FnMut(&Request) -> Response,
+    {
+        let threads = threads.max(1);
+        let mut listeners = Vec::with_capacity(threads);
+        match balance {
+            Balance::ReusePort => {
+                // Bind the first one before the rest so port 0 picks one port for all.
+                let first = reuseport_listener(addr)?;
+                let addr = first.local_addr()?;
+                listeners.push(first);
+                for _ in 1..threads {
+                    listeners.push(reuseport_listener(addr)?);
+                }
+            }
+            Balance::Exclusive => {
+                let listener = TcpListener::bind(addr)?;
+                for _ in 1..threads {
+                    listeners.push(listener.try_clone()?);
+                }
+                listeners.push(listener);
+            }
+        }
+        let addr = listeners[0].local_addr()?;
+
+        let shutdown = ShutdownHandle::new()?;
+        let make_handler = Arc::new(make_handler);
+        let threads = listeners
+            .into_iter()
+            .enumerate()
+            .map(|(i, listener)| {
+                let shutdown = shutdown.clone();
+                let make_handler = make_handler.clone();
+                thread::Builder::new().name(format!("event-loop-{}", i)).spawn(move || {
+                    let exclusive = balance == Balance::Exclusive;
+                    event_loop(&listener, exclusive, config, make_handler(), Some(&shutdown))
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Server { addr, shutdown, threads })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Wait for every event loop to exit, returning the first error.
+    pub fn join(self) -> io::Result<()> {
+        let mut result = Ok(());
+        for thread in self.threads {
+            let exited = thread.join().unwrap_or_else(|_| Err(io::Error::other("event loop panicked")));
+            if result.is_ok() {
+                result = exited;
+            }
+        }
+        result
+    }
+}
+
+/// A listening socket with `SO_REUSEPORT` set before `bind`.
+fn reuseport_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+    let domain = if addr.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+    let fd = syscall!(socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0))?;
+    // SAFETY: the fd was just created and nothing else owns it.
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let one: libc::c_int = 1;
+    for option in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        syscall!(setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            &one as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        ))?;
+    }
+
+    // SAFETY: both sockaddr types are plain data, and an all-zero one is valid.
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            let sin = &mut storage as *mut _ as *mut libc::sockaddr_in;
+            // SAFETY: sockaddr_storage is large and aligned enough for any sockaddr.
+            unsafe {
+                (*sin).sin_family = libc::AF_INET as libc::sa_family_t;
+                (*sin).sin_port = v4.port().to_be();
+                (*sin).sin_addr.s_addr = u32::from(*v4.ip()).to_be();
+            }
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let sin6 = &mut storage as *mut _ as *mut libc::sockaddr_in6;
+            // SAFETY: as above.
+            unsafe {
+                (*sin6).sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                (*sin6).sin6_port = v6.port().to_be();
+                (*sin6).sin6_addr.s6_addr = v6.ip().octets();
+                (*sin6).sin6_flowinfo = v6.flowinfo();
+                (*sin6).sin6_scope_id = v6.scope_id();
+            }
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    syscall!(bind(fd, &storage as *const _ as *const libc::sockaddr, len as libc::socklen_t))?;
+    syscall!(listen(fd, 1024))?;
+    Ok(TcpListener::from(socket))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        time::Duration,
+    };
+
+    fn get(stream: &mut TcpStream) -> String {
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    }
+
+    fn serves_and_drains_on_shutdown(balance: Balance) {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let server = Server::spawn(addr, 4, balance, Config::default(), || {
+            |_: &Request| Response::text(200, thread::current().name().unwrap_or_default())
+        })
+        .unwrap();
+        let mut streams: Vec<_> = (0..32).map(|_| TcpStream::connect(server.local_addr()).unwrap()).collect();
+        for stream in &mut streams {
+            assert!(get(stream).contains("event-loop-"));
+        }
+
+        // A request in flight when the shutdown starts still gets its answer.
+        let mut in_flight = streams.pop().unwrap();
+        in_flight.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        server.shutdown_handle().shutdown();
+        thread::sleep(Duration::from_millis(20));
+        in_flight.write_all(b"\r\n").unwrap();
+        let mut out = String::new();
+        in_flight.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK") && out.contains("connection: close"), "{out}");
+
+        // Idle keep-alive connections are closed.
+        for stream in &mut streams {
+            assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reuseport() {
+        serves_and_drains_on_shutdown(Balance::ReusePort);
+    }
+
+    #[test]
+    fn exclusive() {
+        serves_and_drains_on_shutdown(Balance::Exclusive);
+    }
+}
@@ -1,6 +1,7 @@
 #[macro_export]
 macro_rules! syscall {
     ($fn: ident ( $($arg: expr),* $(,)* ) ) => {{
+        #[allow(clippy::macro_metavars_in_unsafe)]
         let res = unsafe { libc::$fn($($arg, )*) };
         if res == -1 {
             Err(std::io::Error::last_os_error())
@@ -9,6 +10,7 @@ macro_rules! syscall {
         }
     }};
     ( $($fn: ident ( $($arg: expr),* $(,)* )),* $(,)* ) => {{
+        #[allow(clippy::macro_metavars_in_unsafe)]
         unsafe{
             $(
                 libc::$fn($($arg, )*);
@@ -0,0 +1,250 @@
+/// Operator controls: runtime config changes and manual failover.
+///
+/// Endpoints (mounted on the main HTTP server):
+/// - GET  /admin/config   -> current startup config and live tuning values
+/// - PUT  /admin/config   -> partial `TuningUpdate` JSON; validated, applied atomically
+/// - POST /admin/promote  -> promote as soon as we hold the election slot
+/// - POST /admin/demote   -> step down and release the election slot (`?hold_secs=N`)
+///
+/// Every request must carry `Authorization: Bearer <admin_token>`.  Without an
+/// `admin_token` only the read-only `GET /admin/config` is mounted, unauthenticated.
+///
+/// Tuning can also be reloaded from the config layers (file + env + CLI) by
+/// sending SIGHUP to the process.
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::config::{Cli, ExporterConfig, TuningConfig, TuningUpdate};
+use crate::state::{AdminCommand, SharedState};
+
+#[derive(Serialize)]
+struct ConfigResponse<'a> {
+    config: &'a ExporterConfig,
+    tuning: TuningConfig,
+}
+
+#[derive(Deserialize)]
+struct DemoteParams {
+    hold_secs: Option<u64>,
+}
+
+pub fn router(state: Arc<SharedState>) -> Router<Arc<SharedState>> {
+    if state.config.admin_token.is_none() {
+        warn!("ADMIN_TOKEN is not set: admin API auth is off, config changes and failover are disabled");
+        return Router::new().route("/admin/config", get(get_config));
+    }
+    Router::new()
+        .route("/admin/config", get(get_config).put(put_config))
+        .route("/admin/promote", post(promote))
+        .route("/admin/demote", post(demote))
+        .route_layer(middleware::from_fn_with_state(state, require_token))
+}
+
+async fn require_token(
+    State(state): State<Arc<SharedState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let presented = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let expected = state.config.admin_token.as_deref().unwrap_or_default();
+    if !presented.is_some_and(|token| tokens_match(token.as_bytes(), expected.as_bytes())) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "missing or invalid admin token" })),
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+/// Constant-time comparison: the loop always runs over the whole expected
+/// token, so the response time does not reveal how much of a guess matched.
+fn tokens_match(presented: &[u8], expected: &[u8]) -> bool {
+    let mut diff = presented.len() ^ expected.len();
+    for (i, &byte) in expected.iter().enumerate() {
+        diff |= usize::from(byte ^ presented.get(i).copied().unwrap_or(0));
+    }
+    diff == 0 && !expected.is_empty()
+}
+
+async fn get_config(State(state): State<Arc<SharedState>>) -> Response {
+    Json(ConfigResponse {
+        config: &state.config,
+        tuning: state.tuning(),
+    })
+    .into_response()
+}
+
+async fn put_config(
+    State(state): State<Arc<SharedState>>,
+    Json(update): Json<TuningUpdate>,
+) -> Response {
+    match state.update_tuning(&update) {
+        Ok(tuning) => {
+            info!(?tuning, "Tuning updated via admin API");
+            Json(tuning).into_response()
+        }
+        Err(e) => {
+            warn!(errors = ?e.errors, "Rejected admin config update");
+            (StatusCode::BAD_REQUEST, Json(e)).into_response()
+        }
+    }
+}
+
+async fn promote(State(state): State<Arc<SharedState>>) -> Response {
+    info!("Manual promotion requested via admin API");
+    state.set_admin_command(AdminCommand::Promote).await;
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "command": "promote",
+            "role": state.get_role().await.to_string(),
+        })),
+    )
+        .into_response()
+}
+
+async fn demote(
+    State(state): State<Arc<SharedState>>,
+    Query(params): Query<DemoteParams>,
+) -> Response {
+    // Default hold: long enough for our last claim to expire so the peer can
+    // take over, plus the peer's claim interval to establish its own claim.
+    let hold_secs = params.hold_secs.unwrap_or(
+        state.config.failover_timeout_secs + state.config.leader_claim_interval_secs * 2,
+    );
+    info!(hold_secs, "Manual demotion requested via admin API");
+    state
+        .set_admin_command(AdminCommand::Demote {
+            hold: Duration::from_secs(hold_secs),
+        })
+        .await;
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "command": "demote",
+            "hold_secs": hold_secs,
+            "role": state.get_role().await.to_string(),
+        })),
+    )
+        .into_response()
+}
+
+/// Re-read all config layers on SIGHUP and apply the reloadable settings.
+/// Changes to restart-only settings are logged and ignored.
+#[cfg(unix)]
+pub async fn reload_on_sighup(state: Arc<SharedState>, cli: Cli) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!(error = %e, "Failed to install SIGHUP handler, config reload disabled");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading configuration");
+        match ExporterConfig::load(&cli) {
+            Ok(new_config) => {
+                let ignored = state.config.restart_required_changes(&new_config);
+                if !ignored.is_empty() {
+                    warn!(fields = ?ignored, "Changed settings require a restart and were not applied");
+                }
+                let tuning = new_config.tuning();
+                state.tuning_tx.send_replace(tuning);
+                info!(?tuning, "Tuning reloaded");
+            }
+            Err(e) => {
+                error!("Config reload failed, keeping current settings: {:#}", e);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(_state: Arc<SharedState>, _cli: Cli) {
+    std::future::pending::<()>().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PartialConfig;
+
+    /// Serves the admin router on a loopback port and returns its base URL.
+    async fn serve(admin_token: Option<&str>) -> String {
+        let mut config = PartialConfig::default().resolve().unwrap();
+        config.admin_token = admin_token.map(str::to_string);
+        let state = SharedState::new(config);
+        let app = router(Arc::clone(&state)).with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        base
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match(b"s3cret", b"s3cret"));
+        assert!(!tokens_match(b"s3creT", b"s3cret"));
+        assert!(!tokens_match(b"s3cre", b"s3cret"));
+        assert!(!tokens_match(b"s3cret!", b"s3cret"));
+        assert!(!tokens_match(b"", b""));
+    }
+
+    #[tokio::test]
+    async fn test_without_token_only_config_is_readable() {
+        let base = serve(None).await;
+        let client = reqwest::Client::new();
+
+        let resp = client
+            .get(format!("{base}/admin/config"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let update = json!({ "slow_batch_size": 2000 });
+        let resp = client
+            .put(format!("{base}/admin/config"))
+            .json(&update)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        for path in ["/admin/promote", "/admin/demote"] {
+            let resp = client.post(format!("{base}{path}")).send().await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_is_required_when_configured() {
+        let base = serve(Some("s3cret")).await;
+        let client = reqwest::Client::new();
+        let promote = || client.post(format!("{base}/admin/promote"));
+
+        let resp = promote().send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = promote().bearer_auth("wrong").send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = promote().bearer_auth("s3cret").send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    }
+}
@@ -0,0 +1,384 @@
+/// Edge-side aggregation: downsample high-frequency series before upload.
+///
+/// Records from the configured `aggregate_topics` are folded into tumbling
+/// windows (aligned to the epoch, `aggregate_window_secs` long) keyed by
+/// `equipment_id` / `metric_id` / `unit`; each closed window is uploaded as a
+/// single `MetricAggregate` (min / max / avg / count / last) to
+/// `/ingest/aggregates`.  Records from `metrics.alarm` are never aggregated.
+///
+/// With `backfill_aggregate_only` set, every non-alarm topic is aggregated
+/// while the connection is Backfilling, which cuts the recovery upload
+/// volume to one record per series per window.
+///
+/// Offsets: records still sitting in an open window have not been uploaded,
+/// so `pending_offsets` reports the oldest such offset per partition and
+/// neither consumer commits further than that.
+///
+/// Realtime closes windows by wall clock.  Backfill reads old data, so it
+/// closes a window only once every partition it aggregates from has read
+/// past it (`watermark`); a window spanning several pulls is still uploaded
+/// once.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tracing::{error, info, info_span, Instrument};
+
+use crate::models::{AggregatesBatch, MetricAggregate, MetricRecord};
+use crate::state::SharedState;
+
+/// High-priority topic that always passes through raw.
+pub const ALARM_TOPIC: &str = "metrics.alarm";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct SeriesWindow {
+    window_start: i64,
+    equipment_id: String,
+    metric_id: String,
+    unit: String,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    line_id: String,
+    count: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    last: f64,
+    last_timestamp: DateTime<Utc>,
+    /// Oldest source offset per (topic, partition) folded into this bucket.
+    first_offsets: HashMap<(String, i32), i64>,
+}
+
+pub struct Aggregator {
+    window_secs: i64,
+    topics: HashSet<String>,
+    buckets: BTreeMap<SeriesWindow, Bucket>,
+    /// Newest record timestamp folded in per (topic, partition).
+    latest: HashMap<(String, i32), DateTime<Utc>>,
+}
+
+impl Aggregator {
+    pub fn new(topics: &[String], window: Duration) -> Self {
+        Aggregator {
+            window_secs: window.as_secs().max(1) as i64,
+            topics: topics.iter().cloned().collect(),
+            buckets: BTreeMap::new(),
+            latest: HashMap::new(),
+        }
+    }
+
+    /// Whether records from `topic` should be aggregated.  `aggregate_all`
+    /// extends aggregation to every non-alarm topic (backfill-only mode).
+    pub fn applies_to(&self, topic: &str, aggregate_all: bool) -> bool {
+        topic != ALARM_TOPIC && (aggregate_all || self.topics.contains(topic))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    fn window_start(&self, ts: DateTime<Utc>) -> i64 {
+        let secs = ts.timestamp();
+        secs - secs.rem_euclid(self.window_secs)
+    }
+
+    /// Fold a record (read from `topic` / `partition` / `offset`) into its window.
+    pub fn add(&mut self, record: &MetricRecord, topic: &str, partition: i32, offset: i64) {
+        let key = SeriesWindow {
+            window_start: self.window_start(record.timestamp),
+            equipment_id: record.equipment_id.clone(),
+            metric_id: record.metric_id.clone(),
+            unit: record.unit.clone(),
+        };
+        let bucket = self.buckets.entry(key).or_insert_with(|| Bucket {
+            line_id: record.line_id.clone(),
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            last: record.value,
+            last_timestamp: record.timestamp,
+            first_offsets: HashMap::new(),
+        });
+        bucket.count += 1;
+        bucket.min = bucket.min.min(record.value);
+        bucket.max = bucket.max.max(record.value);
+        bucket.sum += record.value;
+        if record.timestamp >= bucket.last_timestamp {
+            bucket.last = record.value;
+            bucket.last_timestamp = record.timestamp;
+        }
+        bucket
+            .first_offsets
+            .entry((topic.to_string(), partition))
+            .and_modify(|o| *o = (*o).min(offset))
+            .or_insert(offset);
+        self.latest
+            .entry((topic.to_string(), partition))
+            .and_modify(|t| *t = (*t).max(record.timestamp))
+            .or_insert(record.timestamp);
+    }
+
+    /// Event time every partition seen so far has read up to: the oldest of
+    /// the per-partition newest timestamps.  `None` until a record is added.
+    pub fn watermark(&self) -> Option<DateTime<Utc>> {
+        self.latest.values().min().copied()
+    }
+
+    /// Remove and return every window that ended at or before `now`.
+    pub fn drain_closed(&mut self, now: DateTime<Utc>) -> Vec<MetricAggregate> {
+        let open_from = SeriesWindow {
+            window_start: self.window_start(now),
+            equipment_id: String::new(),
+            metric_id: String::new(),
+            unit: String::new(),
+        };
+        let open = self.buckets.split_off(&open_from);
+        let closed = std::mem::replace(&mut self.buckets, open);
+        self.finish(closed)
+    }
+
+    /// Remove and return every window, open or not.
+    pub fn drain_all(&mut self) -> Vec<MetricAggregate> {
+        self.latest.clear();
+        let all = std::mem::take(&mut self.buckets);
+        self.finish(all)
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+        self.latest.clear();
+    }
+
+    /// Oldest not-yet-uploaded offset per (topic, partition).
+    pub fn pending_offsets(&self) -> HashMap<(String, i32), i64> {
+        let mut pending: HashMap<(String, i32), i64> = HashMap::new();
+        for bucket in self.buckets.values() {
+            for (tp, &offset) in &bucket.first_offsets {
+                pending
+                    .entry(tp.clone())
+                    .and_modify(|o| *o = (*o).min(offset))
+                    .or_insert(offset);
+            }
+        }
+        pending
+    }
+
+    fn finish(&self, buckets: BTreeMap<SeriesWindow, Bucket>) -> Vec<MetricAggregate> {
+        buckets
+            .into_iter()
+            .map(|(key, b)| MetricAggregate {
+                window_start: DateTime::from_timestamp(key.window_start, 0).unwrap_or_default(),
+                window_end: DateTime::from_timestamp(key.window_start + self.window_secs, 0)
+                    .unwrap_or_default(),
+                equipment_id: key.equipment_id,
+                metric_id: key.metric_id,
+                unit: key.unit,
+                line_id: b.line_id,
+                count: b.count,
+                min: b.min,
+                max: b.max,
+                avg: b.sum / b.count as f64,
+                last: b.last,
+                last_timestamp: b.last_timestamp,
+            })
+            .collect()
+    }
+}
+
+/// POST aggregates to `/ingest/aggregates` and return the payload size.  On
+/// failure the aggregates are handed back so the caller can retry them.
+pub async fn upload_aggregates(
+    http_client: &reqwest::Client,
+    state: &SharedState,
+    path: &'static str,
+    aggregates: Vec<MetricAggregate>,
+) -> Result<u64, Vec<MetricAggregate>> {
+    let payload = AggregatesBatch {
+        fab_id: state.config.fab_id.clone(),
+        batch_id: uuid::Uuid::new_v4().to_string(),
+        aggregates,
+    };
+    let json = serde_json::to_vec(&payload).unwrap();
+    let json_len = json.len() as u64;
+    let url = format!("{}/ingest/aggregates", state.config.aws_endpoint);
+
+    let span = info_span!(
+        "upload_aggregates",
+        path,
+        batch_id = %payload.batch_id,
+        aggregates = payload.aggregates.len(),
+        bytes = json_len,
+    );
+    let result = http_client
+        .post(&url)
+        .body(json)
+        .send()
+        .instrument(span.clone())
+        .await;
+
+    match result {
+        Ok(resp) if resp.status().is_success() => {
+            state
+                .metrics
+                .aggregates_sent
+                .with_label_values(&[path])
+                .inc_by(payload.aggregates.len() as u64);
+            state
+                .metrics
+                .bytes_uploaded
+                .with_label_values(&[path])
+                .inc_by(json_len);
+            info!(parent: &span, aggregates = payload.aggregates.len(), "Aggregates sent");
+            Ok(json_len)
+        }
+        Ok(resp) => {
+            state
+                .metrics
+                .batches_failed
+                .with_label_values(&[path])
+                .inc();
+            error!(parent: &span, status = %resp.status(), "Cloud rejected aggregates");
+            Err(payload.aggregates)
+        }
+        Err(e) => {
+            state
+                .metrics
+                .batches_failed
+                .with_label_values(&[path])
+                .inc();
+            error!(parent: &span, error = %e, "Failed to send aggregates");
+            Err(payload.aggregates)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 - 1_700_000_000 % 60 + secs, 0).unwrap()
+    }
+
+    fn record(equipment: &str, ts: DateTime<Utc>, value: f64) -> MetricRecord {
+        MetricRecord {
+            timestamp: ts,
+            equipment_id: equipment.to_string(),
+            metric_id: "vibration".to_string(),
+            value,
+            unit: "mm/s".to_string(),
+            line_id: "LINE-C".to_string(),
+        }
+    }
+
+    fn aggregator() -> Aggregator {
+        Aggregator::new(&["metrics.raw".to_string()], WINDOW)
+    }
+
+    #[test]
+    fn test_alarm_topic_is_never_aggregated() {
+        let agg = aggregator();
+        assert!(agg.applies_to("metrics.raw", false));
+        assert!(!agg.applies_to("metrics.key", false));
+        assert!(agg.applies_to("metrics.key", true));
+        assert!(!agg.applies_to(ALARM_TOPIC, true));
+    }
+
+    #[test]
+    fn test_window_statistics() {
+        let mut agg = aggregator();
+        agg.add(&record("CMP-A-001", at(1), 4.0), "metrics.raw", 0, 10);
+        agg.add(&record("CMP-A-001", at(30), 1.0), "metrics.raw", 0, 11);
+        agg.add(&record("CMP-A-001", at(20), 7.0), "metrics.raw", 0, 12);
+
+        let out = agg.drain_all();
+        assert_eq!(out.len(), 1);
+        let a = &out[0];
+        assert_eq!((a.window_start, a.window_end), (at(0), at(60)));
+        assert_eq!(a.count, 3);
+        assert_eq!((a.min, a.max, a.avg), (1.0, 7.0, 4.0));
+        assert_eq!(a.last, 1.0, "last is by timestamp, not arrival order");
+        assert_eq!(a.last_timestamp, at(30));
+    }
+
+    #[test]
+    fn test_series_and_windows_are_separate() {
+        let mut agg = aggregator();
+        agg.add(&record("CMP-A-001", at(5), 1.0), "metrics.raw", 0, 1);
+        agg.add(&record("CMP-A-002", at(5), 2.0), "metrics.raw", 1, 1);
+        agg.add(&record("CMP-A-001", at(65), 3.0), "metrics.raw", 0, 2);
+        assert_eq!(agg.drain_all().len(), 3);
+        assert!(agg.is_empty());
+    }
+
+    #[test]
+    fn test_drain_closed_keeps_open_window() {
+        let mut agg = aggregator();
+        agg.add(&record("CMP-A-001", at(5), 1.0), "metrics.raw", 0, 100);
+        agg.add(&record("CMP-A-001", at(65), 2.0), "metrics.raw", 0, 101);
+
+        let closed = agg.drain_closed(at(70));
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].window_start, at(0));
+
+        // Only the open window's offset is still pending.
+        let pending = agg.pending_offsets();
+        assert_eq!(pending.get(&("metrics.raw".to_string(), 0)), Some(&101));
+
+        assert!(agg.drain_closed(at(70)).is_empty());
+        assert_eq!(agg.drain_closed(at(120)).len(), 1);
+        assert!(agg.pending_offsets().is_empty());
+    }
+
+    #[test]
+    fn test_window_spanning_pulls_is_drained_once() {
+        let mut agg = aggregator();
+        let mut uploaded = Vec::new();
+
+        // First pull: both partitions are inside window 0.
+        agg.add(&record("CMP-A-001", at(5), 1.0), "metrics.raw", 0, 1);
+        agg.add(&record("CMP-A-001", at(10), 2.0), "metrics.raw", 1, 1);
+        let watermark = agg.watermark().unwrap();
+        uploaded.extend(agg.drain_closed(watermark));
+        assert!(uploaded.is_empty());
+
+        // Second pull: window 0 continues and partition 0 moves on, but
+        // partition 1 has not, so the window stays open.
+        agg.add(&record("CMP-A-001", at(30), 3.0), "metrics.raw", 1, 2);
+        agg.add(&record("CMP-A-001", at(65), 4.0), "metrics.raw", 0, 2);
+        let watermark = agg.watermark().unwrap();
+        uploaded.extend(agg.drain_closed(watermark));
+        assert!(uploaded.is_empty());
+
+        // Third pull: partition 1 passes window 0 as well.
+        agg.add(&record("CMP-A-002", at(70), 5.0), "metrics.raw", 1, 3);
+        let watermark = agg.watermark().unwrap();
+        uploaded.extend(agg.drain_closed(watermark));
+        assert_eq!(uploaded.len(), 1);
+        assert_eq!((uploaded[0].window_start, uploaded[0].count), (at(0), 3));
+        assert_eq!(
+            agg.pending_offsets().get(&("metrics.raw".to_string(), 1)),
+            Some(&3)
+        );
+
+        // Backfill finished: the open windows go out as well.
+        assert_eq!(agg.drain_all().len(), 2);
+        assert!(agg.watermark().is_none());
+    }
+
+    #[test]
+    fn test_pending_offsets_take_minimum_per_partition() {
+        let mut agg = aggregator();
+        agg.add(&record("CMP-A-001", at(5), 1.0), "metrics.raw", 0, 50);
+        agg.add(&record("CMP-A-002", at(6), 1.0), "metrics.raw", 0, 42);
+        agg.add(&record("CMP-A-002", at(7), 1.0), "metrics.raw", 3, 7);
+        let pending = agg.pending_offsets();
+        assert_eq!(pending.get(&("metrics.raw".to_string(), 0)), Some(&42));
+        assert_eq!(pending.get(&("metrics.raw".to_string(), 3)), Some(&7));
+    }
+}
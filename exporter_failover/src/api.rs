@@ -3,13 +3,15 @@
 /// Endpoints:
 /// - GET /health       -> 200 OK (for peer health checks and load balancer)
 /// - GET /status       -> JSON with current role, connection state, instance info
-/// - GET /metrics      -> Prometheus-style metrics (simplified)
+/// - GET /metrics      -> Prometheus metrics (see `metrics.rs`)
+/// - /admin/*          -> operator controls (see `admin.rs`)
 use std::sync::Arc;
 
 use axum::extract::State;
 use axum::response::Json;
 use axum::routing::get;
 use axum::Router;
+use chrono::Utc;
 use serde::Serialize;
 use tracing::info;
 
@@ -47,32 +49,20 @@ async fn status_handler(State(state): State<Arc<SharedState>>) -> Json<StatusRes
 }
 
 async fn metrics_handler(State(state): State<Arc<SharedState>>) -> String {
-    let role = state.get_role().await;
-    let conn = state.get_connection_state();
-
-    let role_val = match role {
-        crate::state::HaRole::Active => 1,
-        crate::state::HaRole::Standby => 0,
-    };
+    let metrics = &state.metrics;
+    metrics.set_role(state.get_role().await);
+    metrics.set_connection_state(state.get_connection_state());
 
-    let conn_val = match conn {
-        crate::state::ConnectionState::Connected => 0,
-        crate::state::ConnectionState::Disconnected => 1,
-        crate::state::ConnectionState::Backfilling => 2,
+    let claim_age = match state.get_leader_claim().await {
+        Some(claim) => Utc::now()
+            .signed_duration_since(claim.timestamp)
+            .num_milliseconds() as f64
+            / 1000.0,
+        None => -1.0,
     };
+    metrics.leader_claim_age_seconds.set(claim_age);
 
-    format!(
-        "# HELP exporter_ha_role Current HA role (1=active, 0=standby)\n\
-         # TYPE exporter_ha_role gauge\n\
-         exporter_ha_role{{instance=\"{}\"}} {}\n\
-         # HELP exporter_connection_state Connection state (0=connected, 1=disconnected, 2=backfill)\n\
-         # TYPE exporter_connection_state gauge\n\
-         exporter_connection_state{{instance=\"{}\"}} {}\n",
-        state.config.instance_id,
-        role_val,
-        state.config.instance_id,
-        conn_val,
-    )
+    metrics.encode()
 }
 
 pub async fn run_http_server(state: Arc<SharedState>) {
@@ -82,6 +72,7 @@ pub async fn run_http_server(state: Arc<SharedState>) {
         .route("/health", get(health_handler))
         .route("/status", get(status_handler))
         .route("/metrics", get(metrics_handler))
+        .merge(crate::admin::router(Arc::clone(&state)))
         .with_state(state);
 
     let addr = format!("0.0.0.0:{}", port);
@@ -5,17 +5,32 @@
 /// - Token bucket rate limiter: only uses configured % of bandwidth
 /// - Priority sort: alarm > key > raw
 /// - Automatically stops when caught up, transitions back to CONNECTED
+/// - Progress is exported as `exporter_backfill_*` gauges and per-partition lag
+/// - Records failing validation are routed to the dead-letter topic; offsets
+///   are never committed past one whose dead letter failed
+/// - `aggregate_topics` (or, with `backfill_aggregate_only`, every non-alarm
+///   topic) are uploaded as window aggregates instead of raw records; a window
+///   goes out once every partition has read past it, or when backfill is done
+/// - A failed upload is retried before anything new is pulled, and offsets are
+///   never committed past a record or open window that was not uploaded
 use std::sync::Arc;
 use std::time::Duration;
 
 use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::consumer::{Consumer, StreamConsumer};
 use rdkafka::message::Message;
-use tracing::{error, info, warn};
+use tracing::{error, info, info_span, warn, Instrument};
 
-use crate::models::MetricRecord;
+use crate::aggregation::{upload_aggregates, Aggregator};
+use crate::dlq::{accept_record, rewind, DeadLetterSink};
+use crate::metrics::record_consumer_lag;
+use crate::models::{MetricAggregate, MetricRecord};
+use crate::realtime::commit_offsets;
 use crate::state::{ConnectionState, SharedState};
 
+/// How often to refresh the backfill lag / remaining-records gauges.
+const LAG_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
 pub async fn backfill_engine(state: Arc<SharedState>) {
     let brokers = state.config.kafka_brokers.join(",");
 
@@ -34,28 +49,43 @@ pub async fn backfill_engine(state: Arc<SharedState>) {
         .subscribe(&["metrics.alarm", "metrics.key", "metrics.raw"])
         .expect("Failed to subscribe for backfill");
 
+    let rules = state.config.validation_rules();
+    let dlq = DeadLetterSink::new(&state.config.kafka_brokers, &state.config.dlq_topic)
+        .expect("Failed to create dead-letter producer");
+
+    let mut aggregator = Aggregator::new(
+        &state.config.aggregate_topics,
+        Duration::from_secs(state.config.aggregate_window_secs),
+    );
+
     let http_client = reqwest::Client::builder()
         .timeout(Duration::from_secs(30))
         .danger_accept_invalid_certs(true)
         .build()
         .unwrap();
 
+    // Pulled but not yet uploaded.  Kept across backfill runs: the consumer
+    // position is already past them and nothing was committed beyond them.
+    let mut records: Vec<MetricRecord> = Vec::new();
+    let mut ready_aggregates: Vec<MetricAggregate> = Vec::new();
+
     loop {
         // Sleep until Health Monitor wakes us up
         info!("Backfill engine dormant, waiting for signal...");
         state.backfill_notify.notified().await;
         info!("Backfill engine woken up! Starting recovery...");
+        state.metrics.backfill_active.set(1);
 
         // Token bucket rate limiter
-        // Calculate bytes per second based on bandwidth cap
-        let bytes_per_sec: u64 =
-            (1_000_000_000u64 * state.config.backfill_bandwidth_cap_pct as u64) / (100 * 8);
-        let mut tokens: u64 = bytes_per_sec; // start with 1 second of tokens
+        // Bytes per second come from the live tuning (bandwidth cap % of the WAN
+        // link) and are re-read every batch, so a reload takes effect mid-backfill.
+        let mut tokens: u64 = state.tuning().backfill_bytes_per_sec(); // start with 1 second of tokens
         let mut last_refill = tokio::time::Instant::now();
         let refill_interval = Duration::from_millis(100);
 
         let mut total_sent: u64 = 0;
         let mut batch_count: u64 = 0;
+        let mut last_lag_refresh: Option<tokio::time::Instant> = None;
 
         loop {
             // Check if still in backfill state
@@ -65,6 +95,9 @@ pub async fn backfill_engine(state: Arc<SharedState>) {
                 break;
             }
 
+            let tuning = state.tuning();
+            let bytes_per_sec = tuning.backfill_bytes_per_sec();
+
             // Refill tokens
             let now = tokio::time::Instant::now();
             let elapsed = now.duration_since(last_refill);
@@ -74,31 +107,94 @@ pub async fn backfill_engine(state: Arc<SharedState>) {
                 last_refill = now;
             }
 
-            // Pull batch from Kafka
-            let mut records: Vec<MetricRecord> = Vec::with_capacity(1000);
+            if last_lag_refresh.is_none_or(|t| t.elapsed() >= LAG_REFRESH_INTERVAL) {
+                let remaining = record_consumer_lag(&state.metrics, "backfill", &consumer);
+                state.metrics.backfill_remaining_records.set(remaining);
+                last_lag_refresh = Some(tokio::time::Instant::now());
+            }
+
+            // Pull the next batch, unless the last one is still waiting to be
+            // uploaded.
+            if records.is_empty() && ready_aggregates.is_empty() {
+                let mut pulled = 0;
 
-            for _ in 0..1000 {
-                match tokio::time::timeout(Duration::from_millis(10), consumer.recv()).await {
-                    Ok(Ok(msg)) => {
-                        if let Some(payload) = msg.payload() {
-                            if let Ok(record) = serde_json::from_slice::<MetricRecord>(payload) {
+                for _ in 0..tuning.backfill_batch_size {
+                    match tokio::time::timeout(Duration::from_millis(10), consumer.recv()).await {
+                        Ok(Ok(msg)) => {
+                            pulled += 1;
+                            let record =
+                                match accept_record(&msg, "backfill", &rules, &dlq, &state).await {
+                                    Ok(Some(record)) => record,
+                                    Ok(None) => continue,
+                                    Err(_) => {
+                                        // The dead letter was not written: stop this pull at
+                                        // the record so the commit below cannot pass it.
+                                        if let Err(e) = rewind(&consumer, &msg) {
+                                            error!("Backfill: rewind to dead letter failed: {}", e);
+                                            state.metrics.backfill_active.set(0);
+                                            return;
+                                        }
+                                        break;
+                                    }
+                                };
+                            if aggregator.applies_to(msg.topic(), tuning.backfill_aggregate_only) {
+                                aggregator.add(&record, msg.topic(), msg.partition(), msg.offset());
+                                state
+                                    .metrics
+                                    .records_aggregated
+                                    .with_label_values(&["backfill"])
+                                    .inc();
+                            } else {
                                 records.push(record);
                             }
                         }
+                        _ => break,
+                    }
+                }
+
+                if pulled > 0 {
+                    if let Some(watermark) = aggregator.watermark() {
+                        ready_aggregates = aggregator.drain_closed(watermark);
+                    }
+                } else {
+                    // No more data to backfill: flush the windows still open
+                    ready_aggregates = aggregator.drain_all();
+                    if ready_aggregates.is_empty() {
+                        info!(
+                            batches = batch_count,
+                            bytes = total_sent,
+                            "Backfill complete!"
+                        );
+                        state.metrics.backfill_remaining_records.set(0);
+                        let _ = state.connection_tx.send(ConnectionState::Connected);
+                        break;
+                    }
+                }
+            }
+
+            // Aggregates go first; they are small and charged against the
+            // token bucket after the fact.
+            if !ready_aggregates.is_empty() {
+                let aggregates = std::mem::take(&mut ready_aggregates);
+                match upload_aggregates(&http_client, &state, "backfill", aggregates).await {
+                    Ok(bytes) => {
+                        tokens = tokens.saturating_sub(bytes);
+                        total_sent += bytes;
+                    }
+                    Err(aggregates) => {
+                        ready_aggregates = aggregates;
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        continue;
                     }
-                    _ => break,
                 }
             }
 
             if records.is_empty() {
-                // No more data to backfill -> done
-                info!(
-                    batches = batch_count,
-                    bytes = total_sent,
-                    "Backfill complete!"
-                );
-                let _ = state.connection_tx.send(ConnectionState::Connected);
-                break;
+                // Everything in this pull was aggregated or dead-lettered.
+                if let Err(e) = commit_offsets(&consumer, &aggregator.pending_offsets()) {
+                    warn!("Backfill: failed to commit: {}", e);
+                }
+                continue;
             }
 
             // Sort by priority: alarm > key > raw
@@ -111,26 +207,53 @@ pub async fn backfill_engine(state: Arc<SharedState>) {
             let payload = serde_json::to_vec(&records).unwrap();
             let payload_len = payload.len() as u64;
 
-            // Rate limit: wait for sufficient tokens
+            // Rate limit: wait for sufficient tokens. The bucket may hold more
+            // than 2s worth when a single batch is larger than that (small cap),
+            // otherwise the batch could never be sent.
+            let bucket_cap = (bytes_per_sec * 2).max(payload_len);
             while tokens < payload_len {
                 tokio::time::sleep(refill_interval).await;
                 let now = tokio::time::Instant::now();
                 let elapsed = now.duration_since(last_refill);
                 tokens += bytes_per_sec * elapsed.as_millis() as u64 / 1000;
-                tokens = tokens.min(bytes_per_sec * 2);
+                tokens = tokens.min(bucket_cap);
                 last_refill = now;
             }
             tokens -= payload_len;
 
             // Send to cloud
             let url = format!("{}/ingest/backfill", state.config.aws_endpoint);
-            match http_client.post(&url).body(payload).send().await {
+            let span = info_span!(
+                "upload_batch",
+                path = "backfill",
+                batch = batch_count + 1,
+                records = records.len(),
+                bytes = payload_len,
+            );
+            let timer = state
+                .metrics
+                .batch_latency_seconds
+                .with_label_values(&["backfill"])
+                .start_timer();
+            let result = http_client.post(&url).body(payload).send().instrument(span.clone()).await;
+            timer.observe_duration();
+
+            // On failure `records` are kept and resent before anything new is pulled.
+            match result {
                 Ok(resp) if resp.status().is_success() => {
-                    if let Err(e) = consumer.commit_consumer_state(CommitMode::Async) {
-                        warn!("Backfill: failed to commit: {}", e);
+                    // Hold back partitions whose records are still in an open window.
+                    if let Err(e) = commit_offsets(&consumer, &aggregator.pending_offsets()) {
+                        warn!(parent: &span, "Backfill: failed to commit: {}", e);
                     }
+                    records.clear();
                     total_sent += payload_len;
                     batch_count += 1;
+                    state.metrics.batches_sent.with_label_values(&["backfill"]).inc();
+                    state
+                        .metrics
+                        .bytes_uploaded
+                        .with_label_values(&["backfill"])
+                        .inc_by(payload_len);
 
                     if batch_count % 10 == 0 {
                         info!(
@@ -141,14 +264,18 @@ pub async fn backfill_engine(state: Arc<SharedState>) {
                     }
                 }
                 Ok(resp) => {
-                    error!(status = %resp.status(), "Backfill: cloud returned error");
+                    state.metrics.batches_failed.with_label_values(&["backfill"]).inc();
+                    error!(parent: &span, status = %resp.status(), "Backfill: cloud returned error");
                     tokio::time::sleep(Duration::from_secs(5)).await;
                 }
                 Err(e) => {
-                    error!(error = %e, "Backfill send failed");
+                    state.metrics.batches_failed.with_label_values(&["backfill"]).inc();
+                    error!(parent: &span, error = %e, "Backfill send failed");
                     tokio::time::sleep(Duration::from_secs(5)).await;
                 }
             }
         }
+
+        state.metrics.backfill_active.set(0);
     }
 }
@@ -0,0 +1,316 @@
+/// Dead-letter replay tool.
+///
+/// Reads the dead-letter topic (or a JSONL export of it), re-validates each
+/// payload with the exporter's current rules and produces the ones that now
+/// pass back to their original topic.  Letters written by both the realtime
+/// and the backfill path for the same record are deduped by source
+/// coordinates.
+///
+/// Typical workflows:
+///   # Unit whitelist was too strict: widen it and replay straight from the DLQ
+///   ALLOWED_UNITS=C,PSI,mm/s,L/min,bar dlq_replay replay --reason unknown_unit
+///
+///   # Rejected as too old: replay regardless of age (the exporter's
+///   # MAX_TIMESTAMP_AGE_SECS must cover them as well, or they bounce again)
+///   dlq_replay replay --reason timestamp_too_old --ignore-age
+///
+///   # Records need hand fixes: export, edit the `payload` fields, replay the file
+///   dlq_replay export --out dlq.jsonl
+///   dlq_replay replay --file dlq.jsonl
+///
+/// Brokers, DLQ topic and validation rules come from the same config layers
+/// as the exporter (`--config`, env vars).
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::message::Message;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::{Offset, TopicPartitionList};
+
+use exporter_failover::config::{Cli as ConfigCli, ExporterConfig};
+use exporter_failover::dlq::DeadLetter;
+use exporter_failover::models::MetricRecord;
+use exporter_failover::validation::ValidationRules;
+
+#[derive(Parser)]
+#[command(
+    name = "dlq_replay",
+    about = "Inspect and re-ingest dead-lettered metric records"
+)]
+struct Args {
+    /// Exporter YAML config (brokers, DLQ topic, validation rules)
+    #[arg(short, long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Dump the dead-letter topic as JSONL (one `DeadLetter` per line)
+    Export {
+        /// Output file (default: stdout)
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Only letters with this reason code (e.g. `unknown_unit`)
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Re-validate letters and produce the passing payloads to their source topic
+    Replay {
+        /// JSONL file from `export` (default: read the dead-letter topic)
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Only letters with this reason code
+        #[arg(long)]
+        reason: Option<String>,
+        /// Validate and report, but don't produce anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Skip the timestamp age / clock skew check
+        #[arg(long)]
+        ignore_age: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = Args::parse();
+    let config = ExporterConfig::load(&ConfigCli {
+        config: args.config.clone(),
+        ..Default::default()
+    })?;
+
+    match args.command {
+        Command::Export { out, reason } => {
+            let letters = filter(read_topic(&config)?, reason.as_deref());
+            let writer: Box<dyn Write> = match &out {
+                Some(path) => Box::new(
+                    std::fs::File::create(path)
+                        .with_context(|| format!("failed to create {}", path.display()))?,
+                ),
+                None => Box::new(std::io::stdout()),
+            };
+            let mut writer = BufWriter::new(writer);
+            for letter in &letters {
+                serde_json::to_writer(&mut writer, letter)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+            tracing::info!(letters = letters.len(), "Export complete");
+        }
+        Command::Replay {
+            file,
+            reason,
+            dry_run,
+            ignore_age,
+        } => {
+            let letters = match &file {
+                Some(path) => read_file(path)?,
+                None => read_topic(&config)?,
+            };
+            let mut rules = config.validation_rules();
+            if ignore_age {
+                rules = rules.ignoring_age();
+            }
+            replay(&config, &rules, filter(letters, reason.as_deref()), dry_run).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Dedupe by source coordinates (first letter wins) and apply the reason filter.
+fn filter(letters: Vec<DeadLetter>, reason: Option<&str>) -> Vec<DeadLetter> {
+    let mut seen = HashSet::new();
+    letters
+        .into_iter()
+        .filter(|l| seen.insert(l.key()))
+        .filter(|l| reason.is_none_or(|r| l.reason == r))
+        .collect()
+}
+
+fn read_file(path: &PathBuf) -> anyhow::Result<Vec<DeadLetter>> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut letters = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        letters.push(
+            serde_json::from_str(&line)
+                .with_context(|| format!("{}:{}: not a dead letter", path.display(), i + 1))?,
+        );
+    }
+    Ok(letters)
+}
+
+/// Read every partition of the dead-letter topic from the beginning up to
+/// its current high watermark.  Does not commit offsets.
+fn read_topic(config: &ExporterConfig) -> anyhow::Result<Vec<DeadLetter>> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("group.id", "dlq-replay")
+        .set("bootstrap.servers", config.kafka_brokers.join(","))
+        .set("enable.auto.commit", "false")
+        .create()?;
+
+    let topic = config.dlq_topic.as_str();
+    let metadata = consumer.fetch_metadata(Some(topic), Duration::from_secs(10))?;
+    let partitions: Vec<i32> = metadata
+        .topics()
+        .iter()
+        .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+        .collect();
+
+    let mut assignment = TopicPartitionList::new();
+    let mut remaining: i64 = 0;
+    for &partition in &partitions {
+        let (low, high) = consumer.fetch_watermarks(topic, partition, Duration::from_secs(10))?;
+        remaining += high - low;
+        assignment.add_partition_offset(topic, partition, Offset::Beginning)?;
+    }
+    consumer.assign(&assignment)?;
+
+    let mut letters = Vec::new();
+    let mut skipped = 0;
+    while remaining > 0 {
+        let Some(msg) = consumer.poll(Duration::from_secs(5)) else {
+            tracing::warn!(remaining, "Timed out before reaching the end of the topic");
+            break;
+        };
+        let msg = msg?;
+        remaining -= 1;
+        match msg.payload().map(serde_json::from_slice::<DeadLetter>) {
+            Some(Ok(letter)) => letters.push(letter),
+            _ => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        tracing::warn!(skipped, "Ignored messages that are not dead letters");
+    }
+    Ok(letters)
+}
+
+/// Re-validate `letters` as of `now`.  Returns the ones that pass, with
+/// their parsed record, and how many are still invalid.
+fn revalidate<'a>(
+    rules: &ValidationRules,
+    letters: &'a [DeadLetter],
+    now: DateTime<Utc>,
+) -> (Vec<(&'a DeadLetter, MetricRecord)>, usize) {
+    let mut passed = Vec::new();
+    let mut still_invalid = 0;
+    for letter in letters {
+        match rules.validate(letter.payload.as_bytes(), now) {
+            Ok(record) => passed.push((letter, record)),
+            Err(e) => {
+                still_invalid += 1;
+                tracing::warn!(key = %letter.key(), reason = e.reason(), "Still invalid: {}", e);
+            }
+        }
+    }
+    (passed, still_invalid)
+}
+
+async fn replay(
+    config: &ExporterConfig,
+    rules: &ValidationRules,
+    letters: Vec<DeadLetter>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let producer: FutureProducer = ClientConfig::new()
+        .set("bootstrap.servers", config.kafka_brokers.join(","))
+        .set("message.timeout.ms", "5000")
+        .create()?;
+
+    let (passed, still_invalid) = revalidate(rules, &letters, Utc::now());
+    let mut replayed = 0;
+    for (letter, record) in passed {
+        if !dry_run {
+            // Same key as the producer uses, so partitioning is unchanged.
+            producer
+                .send(
+                    FutureRecord::to(&letter.source_topic)
+                        .key(&record.equipment_id)
+                        .payload(&letter.payload),
+                    Duration::from_secs(5),
+                )
+                .await
+                .map_err(|(e, _)| e)
+                .with_context(|| format!("failed to replay {}", letter.key()))?;
+        }
+        replayed += 1;
+    }
+
+    tracing::info!(
+        total = letters.len(),
+        replayed,
+        still_invalid,
+        dry_run,
+        "Replay complete"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exporter_failover::config::PartialConfig;
+    use serde_json::json;
+
+    fn too_old_letter(now: DateTime<Utc>) -> DeadLetter {
+        let taken = now - chrono::Duration::days(30);
+        DeadLetter {
+            reason: "timestamp_too_old".to_string(),
+            error: "timestamp is 2592000s old".to_string(),
+            source_topic: "metrics.raw".to_string(),
+            partition: 0,
+            offset: 42,
+            path: "backfill".to_string(),
+            instance_id: "exporter-1".to_string(),
+            failed_at: now,
+            payload: json!({
+                "timestamp": taken,
+                "equipment_id": "CMP-A-001",
+                "metric_id": "temperature",
+                "value": 21.5,
+                "unit": "C",
+                "line_id": "LINE-C",
+            })
+            .to_string(),
+        }
+    }
+
+    #[test]
+    fn test_too_old_letter_replays_only_when_ignoring_age() {
+        let now = Utc::now();
+        let letters = [too_old_letter(now)];
+        let rules = PartialConfig::default()
+            .resolve()
+            .unwrap()
+            .validation_rules();
+
+        let (passed, still_invalid) = revalidate(&rules, &letters, now);
+        assert!(passed.is_empty());
+        assert_eq!(still_invalid, 1);
+
+        let (passed, still_invalid) = revalidate(&rules.ignoring_age(), &letters, now);
+        assert_eq!(still_invalid, 0);
+        assert_eq!(passed.len(), 1);
+        assert_eq!(passed[0].0.offset, 42);
+        assert_eq!(passed[0].1.equipment_id, "CMP-A-001");
+    }
+}
@@ -15,6 +15,7 @@ struct MockState {
     connected: AtomicBool,
     metrics_received: AtomicU64,
     backfill_received: AtomicU64,
+    aggregates_received: AtomicU64,
 }
 
 #[derive(Serialize)]
@@ -22,6 +23,7 @@ struct StatsResponse {
     connected: bool,
     metrics_batches: u64,
     backfill_batches: u64,
+    aggregate_batches: u64,
 }
 
 #[tokio::main]
@@ -32,6 +34,7 @@ async fn main() {
         connected: AtomicBool::new(true),
         metrics_received: AtomicU64::new(0),
         backfill_received: AtomicU64::new(0),
+        aggregates_received: AtomicU64::new(0),
     });
 
     let app = Router::new()
@@ -41,6 +44,7 @@ async fn main() {
         // Ingest endpoints
         .route("/ingest/metrics", post(metrics_handler))
         .route("/ingest/backfill", post(backfill_handler))
+        .route("/ingest/aggregates", post(aggregates_handler))
         // Admin endpoints (to simulate WAN outage)
         .route("/admin/disconnect", post(disconnect_handler))
         .route("/admin/connect", post(connect_handler))
@@ -98,6 +102,18 @@ async fn backfill_handler(
     StatusCode::OK
 }
 
+async fn aggregates_handler(
+    AxumState(state): AxumState<Arc<MockState>>,
+    body: axum::body::Bytes,
+) -> StatusCode {
+    if !state.connected.load(Ordering::Relaxed) {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    state.aggregates_received.fetch_add(1, Ordering::Relaxed);
+    tracing::info!(bytes = body.len(), "Received aggregates batch");
+    StatusCode::OK
+}
+
 async fn disconnect_handler(
     AxumState(state): AxumState<Arc<MockState>>,
 ) -> &'static str {
@@ -121,5 +137,6 @@ async fn stats_handler(
         connected: state.connected.load(Ordering::Relaxed),
         metrics_batches: state.metrics_received.load(Ordering::Relaxed),
         backfill_batches: state.backfill_received.load(Ordering::Relaxed),
+        aggregate_batches: state.aggregates_received.load(Ordering::Relaxed),
     })
 }
@@ -0,0 +1,23 @@
+/// Mock coordination service for the `lease` election backend.
+/// Serves `exporter_failover::lease_service::router` — a single TTL lease plus
+/// the leader claim key — as a local stand-in for etcd / Consul.
+/// Listens on LEASE_PORT (default 2379).
+use exporter_failover::lease_service::{router, LeaseStore};
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let port: u16 = std::env::var("LEASE_PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(2379);
+
+    let app = router(LeaseStore::new());
+
+    let addr = format!("0.0.0.0:{}", port);
+    tracing::info!("Mock lease service listening on {}", addr);
+
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
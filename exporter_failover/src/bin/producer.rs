@@ -1,6 +1,8 @@
 /// Kafka metric producer for testing.
 /// Produces random metric records to Kafka topics.
 /// Also produces a periodic heartbeat to the __exporter_leader topic.
+/// With INVALID_EVERY=N (N > 0), every Nth record carries an unknown unit so
+/// the exporter's dead-letter path can be exercised.
 use chrono::Utc;
 use rdkafka::config::ClientConfig;
 use rdkafka::producer::{FutureProducer, FutureRecord};
@@ -26,8 +28,12 @@ async fn main() {
         .unwrap_or_else(|_| "1000".into())
         .parse()
         .unwrap_or(1000);
+    let invalid_every: u64 = std::env::var("INVALID_EVERY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
 
-    tracing::info!(brokers = %brokers, interval_ms = interval_ms, "Starting metric producer");
+    tracing::info!(brokers = %brokers, interval_ms = interval_ms, invalid_every = invalid_every, "Starting metric producer");
 
     // Wait for Kafka to be ready
     tokio::time::sleep(Duration::from_secs(10)).await;
@@ -71,6 +77,11 @@ async fn main() {
         let equip = equipment[counter as usize % equipment.len()];
         let topic = topics[counter as usize % topics.len()];
         let (metric_id, unit) = &metrics[counter as usize % metrics.len()];
+        let unit = if invalid_every > 0 && counter % invalid_every == invalid_every - 1 {
+            "furlong/fortnight"
+        } else {
+            unit
+        };
 
         let record = MetricRecord {
             timestamp: Utc::now(),
@@ -1,7 +1,30 @@
-use serde::Deserialize;
+/// Exporter configuration.
+///
+/// Settings are layered, later layers overriding earlier ones:
+///
+///   defaults  <  YAML file (`--config` / first positional arg)  <  env vars  <  CLI flags
+///
+/// Every layer is parsed strictly: a malformed value or an unknown YAML key
+/// is an error naming the offending source, never a silent fallback.  The
+/// merged result is then validated as a whole (`ExporterConfig::validate`).
+///
+/// The batch / backfill / bandwidth settings are grouped into `TuningConfig`
+/// and can be changed at runtime (SIGHUP or `PUT /admin/config`); everything
+/// else requires a restart.
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-/// Exporter configuration, loaded from config.yaml or environment variables.
-#[derive(Debug, Clone, Deserialize)]
+use anyhow::Context;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::aggregation::ALARM_TOPIC;
+use crate::validation::ValidationRules;
+
+/// Exporter configuration after all layers are merged and validated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ExporterConfig {
     pub kafka_brokers: Vec<String>,
     pub aws_endpoint: String,
@@ -16,6 +39,23 @@ pub struct ExporterConfig {
 
     // Backfill settings
     pub backfill_bandwidth_cap_pct: u8,
+    /// Max records pulled from Kafka per backfill upload.
+    #[serde(default = "default_backfill_batch_size")]
+    pub backfill_batch_size: usize,
+    /// WAN link capacity the bandwidth cap is a percentage of.
+    #[serde(default = "default_wan_bandwidth_mbps")]
+    pub wan_bandwidth_mbps: u64,
+    /// While Backfilling, upload only aggregates for every non-alarm topic.
+    #[serde(default)]
+    pub backfill_aggregate_only: bool,
+
+    // Edge aggregation
+    /// Topics whose records are aggregated instead of uploaded raw.
+    #[serde(default)]
+    pub aggregate_topics: Vec<String>,
+    /// Tumbling window length for aggregation.
+    #[serde(default = "default_aggregate_window_secs")]
+    pub aggregate_window_secs: u64,
 
     // HA settings
     pub heartbeat_interval_secs: u64,
@@ -33,64 +73,987 @@ pub struct ExporterConfig {
     // Default: leader_claim_interval_secs * 2 + 2
     #[serde(default)]
     pub startup_grace_secs: u64,
+
+    // Bearer token required by the /admin endpoints (None = no auth)
+    #[serde(default, skip_serializing)]
+    pub admin_token: Option<String>,
+
+    // Leader election backend and its settings
+    #[serde(default)]
+    pub election_backend: ElectionBackend,
+    /// Shared directory for the `file` backend.
+    #[serde(default)]
+    pub election_dir: Option<String>,
+    /// Coordination service URL for the `lease` backend.
+    #[serde(default)]
+    pub election_lease_endpoint: Option<String>,
+    /// Lease TTL for the `lease` backend.
+    #[serde(default = "default_election_lease_ttl_secs")]
+    pub election_lease_ttl_secs: u64,
+
+    // Record validation and dead-letter routing
+    #[serde(default = "default_dlq_topic")]
+    pub dlq_topic: String,
+    #[serde(default = "default_allowed_units")]
+    pub allowed_units: Vec<String>,
+    #[serde(default = "default_value_min")]
+    pub value_min: f64,
+    #[serde(default = "default_value_max")]
+    pub value_max: f64,
+    /// Oldest accepted record timestamp, relative to now.  Generous by
+    /// default so backfill after a long WAN outage still passes.
+    #[serde(default = "default_max_timestamp_age_secs")]
+    pub max_timestamp_age_secs: u64,
+    /// How far ahead of our clock a record timestamp may be.
+    #[serde(default = "default_max_timestamp_ahead_secs")]
+    pub max_timestamp_ahead_secs: u64,
 }
 
-impl ExporterConfig {
-    /// Load config from a YAML file path.
-    pub fn from_file(path: &str) -> anyhow::Result<Self> {
-        let contents = std::fs::read_to_string(path)?;
-        let config: ExporterConfig = serde_yaml::from_str(&contents)?;
-        Ok(config)
+/// Which `LeaderElector` implementation to run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ElectionBackend {
+    /// Consumer group on the single-partition `__exporter_leader` topic.
+    #[default]
+    Kafka,
+    /// Exclusive lock file on shared storage (`election_dir`).
+    File,
+    /// TTL lease on an etcd-style coordination service (`election_lease_endpoint`).
+    Lease,
+}
+
+impl FromStr for ElectionBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "kafka" => Ok(ElectionBackend::Kafka),
+            "file" => Ok(ElectionBackend::File),
+            "lease" => Ok(ElectionBackend::Lease),
+            other => Err(format!(
+                "unknown election backend '{}' (expected kafka, file or lease)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ElectionBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElectionBackend::Kafka => write!(f, "kafka"),
+            ElectionBackend::File => write!(f, "file"),
+            ElectionBackend::Lease => write!(f, "lease"),
+        }
     }
+}
+
+fn default_backfill_batch_size() -> usize {
+    1000
+}
+
+fn default_wan_bandwidth_mbps() -> u64 {
+    1000
+}
+
+fn default_aggregate_window_secs() -> u64 {
+    60
+}
+
+fn default_election_lease_ttl_secs() -> u64 {
+    10
+}
+
+fn default_dlq_topic() -> String {
+    "metrics.dlq".to_string()
+}
+
+fn default_allowed_units() -> Vec<String> {
+    ["C", "F", "K", "PSI", "Pa", "kPa", "mm/s", "L/min", "rpm", "V", "A", "W", "%"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+fn default_value_min() -> f64 {
+    -1.0e9
+}
+
+fn default_value_max() -> f64 {
+    1.0e9
+}
+
+fn default_max_timestamp_age_secs() -> u64 {
+    7 * 24 * 3600
+}
+
+fn default_max_timestamp_ahead_secs() -> u64 {
+    300
+}
+
+/// Settings that may be changed without a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TuningConfig {
+    pub normal_batch_size: usize,
+    pub normal_flush_secs: u64,
+    pub slow_batch_size: usize,
+    pub slow_flush_secs: u64,
+    pub backfill_batch_size: usize,
+    pub backfill_bandwidth_cap_pct: u8,
+    pub wan_bandwidth_mbps: u64,
+    pub backfill_aggregate_only: bool,
+}
+
+impl TuningConfig {
+    /// Backfill token-bucket refill rate: `cap_pct` of the WAN link, in bytes/s.
+    pub fn backfill_bytes_per_sec(&self) -> u64 {
+        self.wan_bandwidth_mbps * 1_000_000 * self.backfill_bandwidth_cap_pct as u64 / (100 * 8)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = ConfigError::default();
+        self.check(&mut errors);
+        errors.into_result()
+    }
+
+    fn check(&self, errors: &mut ConfigError) {
+        if self.normal_batch_size == 0 {
+            errors.push("normal_batch_size", "must be greater than 0");
+        }
+        if self.slow_batch_size == 0 {
+            errors.push("slow_batch_size", "must be greater than 0");
+        }
+        if self.backfill_batch_size == 0 {
+            errors.push("backfill_batch_size", "must be greater than 0");
+        }
+        if self.normal_flush_secs == 0 {
+            errors.push("normal_flush_secs", "must be greater than 0");
+        }
+        if self.slow_flush_secs == 0 {
+            errors.push("slow_flush_secs", "must be greater than 0");
+        }
+        if self.slow_batch_size < self.normal_batch_size {
+            errors.push(
+                "slow_batch_size",
+                format!(
+                    "({}) must be >= normal_batch_size ({}); slow mode is meant to batch more",
+                    self.slow_batch_size, self.normal_batch_size
+                ),
+            );
+        }
+        if !(1..=100).contains(&self.backfill_bandwidth_cap_pct) {
+            errors.push(
+                "backfill_bandwidth_cap_pct",
+                format!("({}) must be between 1 and 100", self.backfill_bandwidth_cap_pct),
+            );
+        }
+        if self.wan_bandwidth_mbps == 0 {
+            errors.push("wan_bandwidth_mbps", "must be greater than 0");
+        }
+    }
+}
+
+/// A partial tuning change, as accepted by `PUT /admin/config`.
+/// Omitted fields keep their current value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TuningUpdate {
+    pub normal_batch_size: Option<usize>,
+    pub normal_flush_secs: Option<u64>,
+    pub slow_batch_size: Option<usize>,
+    pub slow_flush_secs: Option<u64>,
+    pub backfill_batch_size: Option<usize>,
+    pub backfill_bandwidth_cap_pct: Option<u8>,
+    pub wan_bandwidth_mbps: Option<u64>,
+    pub backfill_aggregate_only: Option<bool>,
+}
+
+impl TuningUpdate {
+    /// Apply the update on top of `base` and validate the result.
+    pub fn apply(&self, base: TuningConfig) -> Result<TuningConfig, ConfigError> {
+        let tuning = TuningConfig {
+            normal_batch_size: self.normal_batch_size.unwrap_or(base.normal_batch_size),
+            normal_flush_secs: self.normal_flush_secs.unwrap_or(base.normal_flush_secs),
+            slow_batch_size: self.slow_batch_size.unwrap_or(base.slow_batch_size),
+            slow_flush_secs: self.slow_flush_secs.unwrap_or(base.slow_flush_secs),
+            backfill_batch_size: self.backfill_batch_size.unwrap_or(base.backfill_batch_size),
+            backfill_bandwidth_cap_pct: self
+                .backfill_bandwidth_cap_pct
+                .unwrap_or(base.backfill_bandwidth_cap_pct),
+            wan_bandwidth_mbps: self.wan_bandwidth_mbps.unwrap_or(base.wan_bandwidth_mbps),
+            backfill_aggregate_only: self
+                .backfill_aggregate_only
+                .unwrap_or(base.backfill_aggregate_only),
+        };
+        tuning.validate()?;
+        Ok(tuning)
+    }
+}
+
+/// One or more configuration problems, each tagged with the setting (or
+/// source, e.g. an env var name) it came from.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl ConfigError {
+    fn push(&mut self, field: &str, msg: impl fmt::Display) {
+        self.errors.push(format!("{}: {}", field, msg));
+    }
+
+    fn into_result(self) -> Result<(), ConfigError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration")?;
+        for e in &self.errors {
+            write!(f, "\n  - {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Command-line flags.  Each flag overrides the env var / YAML key of the
+/// same name.
+#[derive(Debug, Clone, Default, Parser)]
+#[command(name = "exporter", about = "Metrics exporter with Active/Standby failover")]
+pub struct Cli {
+    /// YAML config file
+    #[arg(short, long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// YAML config file (positional form, kept for `exporter config.yaml`)
+    #[arg(value_name = "CONFIG", conflicts_with = "config")]
+    pub config_positional: Option<PathBuf>,
+
+    #[arg(long, value_delimiter = ',')]
+    pub kafka_brokers: Option<Vec<String>>,
+    #[arg(long)]
+    pub aws_endpoint: Option<String>,
+    #[arg(long)]
+    pub fab_id: Option<String>,
+    #[arg(long)]
+    pub instance_id: Option<String>,
+    #[arg(long)]
+    pub normal_batch_size: Option<usize>,
+    #[arg(long)]
+    pub normal_flush_secs: Option<u64>,
+    #[arg(long)]
+    pub slow_batch_size: Option<usize>,
+    #[arg(long)]
+    pub slow_flush_secs: Option<u64>,
+    #[arg(long)]
+    pub backfill_bandwidth_cap_pct: Option<u8>,
+    #[arg(long)]
+    pub backfill_batch_size: Option<usize>,
+    #[arg(long)]
+    pub wan_bandwidth_mbps: Option<u64>,
+    #[arg(long)]
+    pub backfill_aggregate_only: Option<bool>,
+    #[arg(long, value_delimiter = ',')]
+    pub aggregate_topics: Option<Vec<String>>,
+    #[arg(long)]
+    pub aggregate_window_secs: Option<u64>,
+    #[arg(long)]
+    pub heartbeat_interval_secs: Option<u64>,
+    #[arg(long)]
+    pub failover_timeout_secs: Option<u64>,
+    #[arg(long)]
+    pub leader_claim_interval_secs: Option<u64>,
+    #[arg(long)]
+    pub http_port: Option<u16>,
+    #[arg(long)]
+    pub peer_endpoint: Option<String>,
+    #[arg(long)]
+    pub startup_grace_secs: Option<u64>,
+    #[arg(long)]
+    pub admin_token: Option<String>,
+    #[arg(long)]
+    pub election_backend: Option<ElectionBackend>,
+    #[arg(long)]
+    pub election_dir: Option<String>,
+    #[arg(long)]
+    pub election_lease_endpoint: Option<String>,
+    #[arg(long)]
+    pub election_lease_ttl_secs: Option<u64>,
+    #[arg(long)]
+    pub dlq_topic: Option<String>,
+    #[arg(long, value_delimiter = ',')]
+    pub allowed_units: Option<Vec<String>>,
+    #[arg(long, allow_negative_numbers = true)]
+    pub value_min: Option<f64>,
+    #[arg(long, allow_negative_numbers = true)]
+    pub value_max: Option<f64>,
+    #[arg(long)]
+    pub max_timestamp_age_secs: Option<u64>,
+    #[arg(long)]
+    pub max_timestamp_ahead_secs: Option<u64>,
+}
 
-    /// Load config from environment variables (for Docker).
-    pub fn from_env() -> anyhow::Result<Self> {
-        let brokers = std::env::var("KAFKA_BROKERS").unwrap_or_else(|_| "kafka:9092".into());
-        let aws_endpoint =
-            std::env::var("AWS_ENDPOINT").unwrap_or_else(|_| "http://mock-aws:8080".into());
-        let fab_id = std::env::var("FAB_ID").unwrap_or_else(|_| "TW-1".into());
-        let instance_id =
-            std::env::var("INSTANCE_ID").unwrap_or_else(|_| "exporter-1".into());
-        let http_port: u16 = std::env::var("HTTP_PORT")
-            .unwrap_or_else(|_| "9090".into())
-            .parse()
-            .unwrap_or(9090);
-        let failover_timeout_secs: u64 = std::env::var("FAILOVER_TIMEOUT_SECS")
-            .unwrap_or_else(|_| "15".into())
-            .parse()
-            .unwrap_or(15);
-        let leader_claim_interval_secs: u64 = std::env::var("LEADER_CLAIM_INTERVAL_SECS")
-            .unwrap_or_else(|_| "3".into())
-            .parse()
-            .unwrap_or(3);
-
-        let peer_endpoint = std::env::var("PEER_ENDPOINT").ok().and_then(|v| {
-            let trimmed = v.trim().to_string();
-            if trimmed.is_empty() { None } else { Some(trimmed) }
-        });
-
-        let default_grace = leader_claim_interval_secs * 2 + 2;
-        let startup_grace_secs: u64 = std::env::var("STARTUP_GRACE_SECS")
-            .unwrap_or_else(|_| default_grace.to_string())
-            .parse()
-            .unwrap_or(default_grace);
-
-        Ok(ExporterConfig {
-            kafka_brokers: brokers.split(',').map(|s| s.trim().to_string()).collect(),
-            aws_endpoint,
-            fab_id,
-            instance_id,
-            normal_batch_size: 100,
-            normal_flush_secs: 5,
-            slow_batch_size: 500,
-            slow_flush_secs: 15,
-            backfill_bandwidth_cap_pct: 30,
-            heartbeat_interval_secs: 5,
-            failover_timeout_secs,
+impl Cli {
+    pub fn config_path(&self) -> Option<&Path> {
+        self.config
+            .as_deref()
+            .or(self.config_positional.as_deref())
+    }
+}
+
+/// One configuration layer.  `None` means "not set in this layer".
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartialConfig {
+    pub kafka_brokers: Option<Vec<String>>,
+    pub aws_endpoint: Option<String>,
+    pub fab_id: Option<String>,
+    pub instance_id: Option<String>,
+    pub normal_batch_size: Option<usize>,
+    pub normal_flush_secs: Option<u64>,
+    pub slow_batch_size: Option<usize>,
+    pub slow_flush_secs: Option<u64>,
+    pub backfill_bandwidth_cap_pct: Option<u8>,
+    pub backfill_batch_size: Option<usize>,
+    pub wan_bandwidth_mbps: Option<u64>,
+    pub backfill_aggregate_only: Option<bool>,
+    pub aggregate_topics: Option<Vec<String>>,
+    pub aggregate_window_secs: Option<u64>,
+    pub heartbeat_interval_secs: Option<u64>,
+    pub failover_timeout_secs: Option<u64>,
+    pub leader_claim_interval_secs: Option<u64>,
+    pub http_port: Option<u16>,
+    pub peer_endpoint: Option<String>,
+    pub startup_grace_secs: Option<u64>,
+    pub admin_token: Option<String>,
+    pub election_backend: Option<ElectionBackend>,
+    pub election_dir: Option<String>,
+    pub election_lease_endpoint: Option<String>,
+    pub election_lease_ttl_secs: Option<u64>,
+    pub dlq_topic: Option<String>,
+    pub allowed_units: Option<Vec<String>>,
+    pub value_min: Option<f64>,
+    pub value_max: Option<f64>,
+    pub max_timestamp_age_secs: Option<u64>,
+    pub max_timestamp_ahead_secs: Option<u64>,
+}
+
+impl PartialConfig {
+    pub fn from_yaml(contents: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(contents)
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        Self::from_yaml(&contents)
+            .with_context(|| format!("failed to parse config file {}", path.display()))
+    }
+
+    /// Read every supported environment variable.  Unset or blank variables
+    /// are skipped; unparsable ones are reported together.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut errors = ConfigError::default();
+        let layer = PartialConfig {
+            kafka_brokers: env_var::<String>("KAFKA_BROKERS", &mut errors).map(|v| split_list(&v)),
+            aws_endpoint: env_var("AWS_ENDPOINT", &mut errors),
+            fab_id: env_var("FAB_ID", &mut errors),
+            instance_id: env_var("INSTANCE_ID", &mut errors),
+            normal_batch_size: env_var("NORMAL_BATCH_SIZE", &mut errors),
+            normal_flush_secs: env_var("NORMAL_FLUSH_SECS", &mut errors),
+            slow_batch_size: env_var("SLOW_BATCH_SIZE", &mut errors),
+            slow_flush_secs: env_var("SLOW_FLUSH_SECS", &mut errors),
+            backfill_bandwidth_cap_pct: env_var("BACKFILL_BANDWIDTH_CAP_PCT", &mut errors),
+            backfill_batch_size: env_var("BACKFILL_BATCH_SIZE", &mut errors),
+            wan_bandwidth_mbps: env_var("WAN_BANDWIDTH_MBPS", &mut errors),
+            backfill_aggregate_only: env_var("BACKFILL_AGGREGATE_ONLY", &mut errors),
+            aggregate_topics: env_var::<String>("AGGREGATE_TOPICS", &mut errors)
+                .map(|v| split_list(&v)),
+            aggregate_window_secs: env_var("AGGREGATE_WINDOW_SECS", &mut errors),
+            heartbeat_interval_secs: env_var("HEARTBEAT_INTERVAL_SECS", &mut errors),
+            failover_timeout_secs: env_var("FAILOVER_TIMEOUT_SECS", &mut errors),
+            leader_claim_interval_secs: env_var("LEADER_CLAIM_INTERVAL_SECS", &mut errors),
+            http_port: env_var("HTTP_PORT", &mut errors),
+            peer_endpoint: env_var("PEER_ENDPOINT", &mut errors),
+            startup_grace_secs: env_var("STARTUP_GRACE_SECS", &mut errors),
+            admin_token: env_var("ADMIN_TOKEN", &mut errors),
+            election_backend: env_var("ELECTION_BACKEND", &mut errors),
+            election_dir: env_var("ELECTION_DIR", &mut errors),
+            election_lease_endpoint: env_var("ELECTION_LEASE_ENDPOINT", &mut errors),
+            election_lease_ttl_secs: env_var("ELECTION_LEASE_TTL_SECS", &mut errors),
+            dlq_topic: env_var("DLQ_TOPIC", &mut errors),
+            allowed_units: env_var::<String>("ALLOWED_UNITS", &mut errors).map(|v| split_list(&v)),
+            value_min: env_var("VALUE_MIN", &mut errors),
+            value_max: env_var("VALUE_MAX", &mut errors),
+            max_timestamp_age_secs: env_var("MAX_TIMESTAMP_AGE_SECS", &mut errors),
+            max_timestamp_ahead_secs: env_var("MAX_TIMESTAMP_AHEAD_SECS", &mut errors),
+        };
+        errors.into_result()?;
+        Ok(layer)
+    }
+
+    pub fn from_cli(cli: &Cli) -> Self {
+        PartialConfig {
+            kafka_brokers: cli.kafka_brokers.clone(),
+            aws_endpoint: cli.aws_endpoint.clone(),
+            fab_id: cli.fab_id.clone(),
+            instance_id: cli.instance_id.clone(),
+            normal_batch_size: cli.normal_batch_size,
+            normal_flush_secs: cli.normal_flush_secs,
+            slow_batch_size: cli.slow_batch_size,
+            slow_flush_secs: cli.slow_flush_secs,
+            backfill_bandwidth_cap_pct: cli.backfill_bandwidth_cap_pct,
+            backfill_batch_size: cli.backfill_batch_size,
+            wan_bandwidth_mbps: cli.wan_bandwidth_mbps,
+            backfill_aggregate_only: cli.backfill_aggregate_only,
+            aggregate_topics: cli.aggregate_topics.clone(),
+            aggregate_window_secs: cli.aggregate_window_secs,
+            heartbeat_interval_secs: cli.heartbeat_interval_secs,
+            failover_timeout_secs: cli.failover_timeout_secs,
+            leader_claim_interval_secs: cli.leader_claim_interval_secs,
+            http_port: cli.http_port,
+            peer_endpoint: cli.peer_endpoint.clone(),
+            startup_grace_secs: cli.startup_grace_secs,
+            admin_token: cli.admin_token.clone(),
+            election_backend: cli.election_backend,
+            election_dir: cli.election_dir.clone(),
+            election_lease_endpoint: cli.election_lease_endpoint.clone(),
+            election_lease_ttl_secs: cli.election_lease_ttl_secs,
+            dlq_topic: cli.dlq_topic.clone(),
+            allowed_units: cli.allowed_units.clone(),
+            value_min: cli.value_min,
+            value_max: cli.value_max,
+            max_timestamp_age_secs: cli.max_timestamp_age_secs,
+            max_timestamp_ahead_secs: cli.max_timestamp_ahead_secs,
+        }
+    }
+
+    /// Overlay `over` on top of `self`; fields set in `over` win.
+    pub fn merge(self, over: PartialConfig) -> PartialConfig {
+        PartialConfig {
+            kafka_brokers: over.kafka_brokers.or(self.kafka_brokers),
+            aws_endpoint: over.aws_endpoint.or(self.aws_endpoint),
+            fab_id: over.fab_id.or(self.fab_id),
+            instance_id: over.instance_id.or(self.instance_id),
+            normal_batch_size: over.normal_batch_size.or(self.normal_batch_size),
+            normal_flush_secs: over.normal_flush_secs.or(self.normal_flush_secs),
+            slow_batch_size: over.slow_batch_size.or(self.slow_batch_size),
+            slow_flush_secs: over.slow_flush_secs.or(self.slow_flush_secs),
+            backfill_bandwidth_cap_pct: over
+                .backfill_bandwidth_cap_pct
+                .or(self.backfill_bandwidth_cap_pct),
+            backfill_batch_size: over.backfill_batch_size.or(self.backfill_batch_size),
+            wan_bandwidth_mbps: over.wan_bandwidth_mbps.or(self.wan_bandwidth_mbps),
+            backfill_aggregate_only: over
+                .backfill_aggregate_only
+                .or(self.backfill_aggregate_only),
+            aggregate_topics: over.aggregate_topics.or(self.aggregate_topics),
+            aggregate_window_secs: over.aggregate_window_secs.or(self.aggregate_window_secs),
+            heartbeat_interval_secs: over
+                .heartbeat_interval_secs
+                .or(self.heartbeat_interval_secs),
+            failover_timeout_secs: over.failover_timeout_secs.or(self.failover_timeout_secs),
+            leader_claim_interval_secs: over
+                .leader_claim_interval_secs
+                .or(self.leader_claim_interval_secs),
+            http_port: over.http_port.or(self.http_port),
+            peer_endpoint: over.peer_endpoint.or(self.peer_endpoint),
+            startup_grace_secs: over.startup_grace_secs.or(self.startup_grace_secs),
+            admin_token: over.admin_token.or(self.admin_token),
+            election_backend: over.election_backend.or(self.election_backend),
+            election_dir: over.election_dir.or(self.election_dir),
+            election_lease_endpoint: over
+                .election_lease_endpoint
+                .or(self.election_lease_endpoint),
+            election_lease_ttl_secs: over
+                .election_lease_ttl_secs
+                .or(self.election_lease_ttl_secs),
+            dlq_topic: over.dlq_topic.or(self.dlq_topic),
+            allowed_units: over.allowed_units.or(self.allowed_units),
+            value_min: over.value_min.or(self.value_min),
+            value_max: over.value_max.or(self.value_max),
+            max_timestamp_age_secs: over.max_timestamp_age_secs.or(self.max_timestamp_age_secs),
+            max_timestamp_ahead_secs: over
+                .max_timestamp_ahead_secs
+                .or(self.max_timestamp_ahead_secs),
+        }
+    }
+
+    /// Fill unset fields with defaults and validate the result.
+    pub fn resolve(self) -> Result<ExporterConfig, ConfigError> {
+        let leader_claim_interval_secs = self.leader_claim_interval_secs.unwrap_or(3);
+        let config = ExporterConfig {
+            kafka_brokers: self
+                .kafka_brokers
+                .unwrap_or_else(|| vec!["kafka:9092".to_string()]),
+            aws_endpoint: self
+                .aws_endpoint
+                .unwrap_or_else(|| "http://mock-aws:8080".to_string()),
+            fab_id: self.fab_id.unwrap_or_else(|| "TW-1".to_string()),
+            instance_id: self.instance_id.unwrap_or_else(|| "exporter-1".to_string()),
+            normal_batch_size: self.normal_batch_size.unwrap_or(100),
+            normal_flush_secs: self.normal_flush_secs.unwrap_or(5),
+            slow_batch_size: self.slow_batch_size.unwrap_or(500),
+            slow_flush_secs: self.slow_flush_secs.unwrap_or(15),
+            backfill_bandwidth_cap_pct: self.backfill_bandwidth_cap_pct.unwrap_or(30),
+            backfill_batch_size: self
+                .backfill_batch_size
+                .unwrap_or_else(default_backfill_batch_size),
+            wan_bandwidth_mbps: self
+                .wan_bandwidth_mbps
+                .unwrap_or_else(default_wan_bandwidth_mbps),
+            backfill_aggregate_only: self.backfill_aggregate_only.unwrap_or(false),
+            aggregate_topics: self.aggregate_topics.unwrap_or_default(),
+            aggregate_window_secs: self
+                .aggregate_window_secs
+                .unwrap_or_else(default_aggregate_window_secs),
+            heartbeat_interval_secs: self.heartbeat_interval_secs.unwrap_or(5),
+            failover_timeout_secs: self.failover_timeout_secs.unwrap_or(15),
             leader_claim_interval_secs,
-            http_port,
-            peer_endpoint,
-            startup_grace_secs,
-        })
+            http_port: self.http_port.unwrap_or(9090),
+            peer_endpoint: self.peer_endpoint.filter(|p| !p.trim().is_empty()),
+            startup_grace_secs: self
+                .startup_grace_secs
+                .unwrap_or(leader_claim_interval_secs * 2 + 2),
+            admin_token: self.admin_token.filter(|t| !t.is_empty()),
+            election_backend: self.election_backend.unwrap_or_default(),
+            election_dir: self.election_dir,
+            election_lease_endpoint: self.election_lease_endpoint,
+            election_lease_ttl_secs: self
+                .election_lease_ttl_secs
+                .unwrap_or_else(default_election_lease_ttl_secs),
+            dlq_topic: self.dlq_topic.unwrap_or_else(default_dlq_topic),
+            allowed_units: self.allowed_units.unwrap_or_else(default_allowed_units),
+            value_min: self.value_min.unwrap_or_else(default_value_min),
+            value_max: self.value_max.unwrap_or_else(default_value_max),
+            max_timestamp_age_secs: self
+                .max_timestamp_age_secs
+                .unwrap_or_else(default_max_timestamp_age_secs),
+            max_timestamp_ahead_secs: self
+                .max_timestamp_ahead_secs
+                .unwrap_or_else(default_max_timestamp_ahead_secs),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl ExporterConfig {
+    /// Load the full layered config: defaults < file < env < CLI.
+    pub fn load(cli: &Cli) -> anyhow::Result<Self> {
+        let mut layers = PartialConfig::default();
+        if let Some(path) = cli.config_path() {
+            layers = layers.merge(PartialConfig::from_file(path)?);
+        }
+        layers = layers.merge(PartialConfig::from_env()?);
+        layers = layers.merge(PartialConfig::from_cli(cli));
+        Ok(layers.resolve()?)
+    }
+
+    pub fn tuning(&self) -> TuningConfig {
+        TuningConfig {
+            normal_batch_size: self.normal_batch_size,
+            normal_flush_secs: self.normal_flush_secs,
+            slow_batch_size: self.slow_batch_size,
+            slow_flush_secs: self.slow_flush_secs,
+            backfill_batch_size: self.backfill_batch_size,
+            backfill_bandwidth_cap_pct: self.backfill_bandwidth_cap_pct,
+            wan_bandwidth_mbps: self.wan_bandwidth_mbps,
+            backfill_aggregate_only: self.backfill_aggregate_only,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = ConfigError::default();
+
+        if self.kafka_brokers.is_empty() {
+            errors.push("kafka_brokers", "at least one broker is required");
+        }
+        for broker in &self.kafka_brokers {
+            if !is_host_port(broker) {
+                errors.push(
+                    "kafka_brokers",
+                    format!("'{}' is not a host:port address", broker),
+                );
+            }
+        }
+        if !(self.aws_endpoint.starts_with("http://") || self.aws_endpoint.starts_with("https://"))
+        {
+            errors.push(
+                "aws_endpoint",
+                format!("'{}' must start with http:// or https://", self.aws_endpoint),
+            );
+        }
+        if self.fab_id.trim().is_empty() {
+            errors.push("fab_id", "must not be empty");
+        }
+        if self.instance_id.trim().is_empty() {
+            errors.push("instance_id", "must not be empty");
+        }
+        if self.heartbeat_interval_secs == 0 {
+            errors.push("heartbeat_interval_secs", "must be greater than 0");
+        }
+        if self.leader_claim_interval_secs == 0 {
+            errors.push("leader_claim_interval_secs", "must be greater than 0");
+        }
+        // A healthy leader must be able to refresh its claim at least twice
+        // before it is considered dead, or a single delayed claim flips roles.
+        if self.failover_timeout_secs <= self.leader_claim_interval_secs * 2 {
+            errors.push(
+                "failover_timeout_secs",
+                format!(
+                    "({}) must be greater than 2 x leader_claim_interval_secs ({})",
+                    self.failover_timeout_secs, self.leader_claim_interval_secs
+                ),
+            );
+        }
+        if self.http_port == 0 {
+            errors.push("http_port", "must not be 0");
+        }
+        if let Some(peer) = &self.peer_endpoint {
+            if !is_host_port(peer) {
+                errors.push(
+                    "peer_endpoint",
+                    format!("'{}' is not a host:port address", peer),
+                );
+            }
+        }
+
+        match self.election_backend {
+            ElectionBackend::Kafka => {}
+            ElectionBackend::File => {
+                if self.election_dir.as_deref().is_none_or(|d| d.trim().is_empty()) {
+                    errors.push("election_dir", "is required when election_backend = file");
+                }
+            }
+            ElectionBackend::Lease => match &self.election_lease_endpoint {
+                Some(url) if url.starts_with("http://") || url.starts_with("https://") => {}
+                Some(url) => errors.push(
+                    "election_lease_endpoint",
+                    format!("'{}' must start with http:// or https://", url),
+                ),
+                None => errors.push(
+                    "election_lease_endpoint",
+                    "is required when election_backend = lease",
+                ),
+            },
+        }
+        // The lease must expire before the leader claim does, or failover
+        // waits on the lease rather than on the claim.
+        if self.election_backend == ElectionBackend::Lease
+            && (self.election_lease_ttl_secs == 0
+                || self.election_lease_ttl_secs >= self.failover_timeout_secs)
+        {
+            errors.push(
+                "election_lease_ttl_secs",
+                format!(
+                    "({}) must be between 1 and failover_timeout_secs ({})",
+                    self.election_lease_ttl_secs, self.failover_timeout_secs
+                ),
+            );
+        }
+
+        if self.aggregate_window_secs == 0 {
+            errors.push("aggregate_window_secs", "must be greater than 0");
+        }
+        if self.aggregate_topics.iter().any(|t| t == ALARM_TOPIC) {
+            errors.push(
+                "aggregate_topics",
+                format!("'{}' is never aggregated; alarms always pass through", ALARM_TOPIC),
+            );
+        }
+        if self.dlq_topic.trim().is_empty() {
+            errors.push("dlq_topic", "must not be empty");
+        }
+        if self.allowed_units.is_empty() {
+            errors.push("allowed_units", "at least one unit is required");
+        }
+        if !(self.value_min.is_finite() && self.value_max.is_finite())
+            || self.value_min >= self.value_max
+        {
+            errors.push(
+                "value_min",
+                format!(
+                    "({}) must be finite and less than value_max ({})",
+                    self.value_min, self.value_max
+                ),
+            );
+        }
+        if self.max_timestamp_age_secs == 0 {
+            errors.push("max_timestamp_age_secs", "must be greater than 0");
+        }
+
+        self.tuning().check(&mut errors);
+        errors.into_result()
+    }
+
+    /// Record validation rules derived from this config.
+    pub fn validation_rules(&self) -> ValidationRules {
+        ValidationRules::new(
+            &self.allowed_units,
+            self.value_min..=self.value_max,
+            Duration::from_secs(self.max_timestamp_age_secs),
+            Duration::from_secs(self.max_timestamp_ahead_secs),
+        )
+    }
+
+    /// Names of settings that differ from `other` and only take effect after
+    /// a restart (i.e. everything outside `TuningConfig`).
+    pub fn restart_required_changes(&self, other: &ExporterConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.kafka_brokers != other.kafka_brokers {
+            changed.push("kafka_brokers");
+        }
+        if self.aws_endpoint != other.aws_endpoint {
+            changed.push("aws_endpoint");
+        }
+        if self.fab_id != other.fab_id {
+            changed.push("fab_id");
+        }
+        if self.instance_id != other.instance_id {
+            changed.push("instance_id");
+        }
+        if self.heartbeat_interval_secs != other.heartbeat_interval_secs {
+            changed.push("heartbeat_interval_secs");
+        }
+        if self.failover_timeout_secs != other.failover_timeout_secs {
+            changed.push("failover_timeout_secs");
+        }
+        if self.leader_claim_interval_secs != other.leader_claim_interval_secs {
+            changed.push("leader_claim_interval_secs");
+        }
+        if self.http_port != other.http_port {
+            changed.push("http_port");
+        }
+        if self.peer_endpoint != other.peer_endpoint {
+            changed.push("peer_endpoint");
+        }
+        if self.startup_grace_secs != other.startup_grace_secs {
+            changed.push("startup_grace_secs");
+        }
+        if self.admin_token != other.admin_token {
+            changed.push("admin_token");
+        }
+        if self.election_backend != other.election_backend
+            || self.election_dir != other.election_dir
+            || self.election_lease_endpoint != other.election_lease_endpoint
+            || self.election_lease_ttl_secs != other.election_lease_ttl_secs
+        {
+            changed.push("election_*");
+        }
+        if self.aggregate_topics != other.aggregate_topics
+            || self.aggregate_window_secs != other.aggregate_window_secs
+        {
+            changed.push("aggregation");
+        }
+        if self.dlq_topic != other.dlq_topic {
+            changed.push("dlq_topic");
+        }
+        if self.allowed_units != other.allowed_units
+            || self.value_min != other.value_min
+            || self.value_max != other.value_max
+            || self.max_timestamp_age_secs != other.max_timestamp_age_secs
+            || self.max_timestamp_ahead_secs != other.max_timestamp_ahead_secs
+        {
+            changed.push("validation");
+        }
+        changed
+    }
+}
+
+fn env_var<T>(name: &str, errors: &mut ConfigError) -> Option<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let raw = std::env::var(name).ok()?;
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    match trimmed.parse() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            errors.push(name, format!("'{}' is invalid: {}", trimmed, e));
+            None
+        }
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn is_host_port(addr: &str) -> bool {
+    match addr.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p > 0),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_are_valid() {
+        let config = PartialConfig::default().resolve().unwrap();
+        assert_eq!(config.kafka_brokers, vec!["kafka:9092"]);
+        assert_eq!(config.normal_batch_size, 100);
+        assert_eq!(config.startup_grace_secs, 8);
+        assert_eq!(config.backfill_batch_size, 1000);
+    }
+
+    #[test]
+    fn test_later_layer_wins() {
+        let file = PartialConfig::from_yaml("instance_id: from-file\nhttp_port: 9100\n").unwrap();
+        let env = PartialConfig {
+            instance_id: Some("from-env".to_string()),
+            ..Default::default()
+        };
+        let config = file.merge(env).resolve().unwrap();
+        assert_eq!(config.instance_id, "from-env");
+        assert_eq!(config.http_port, 9100);
+    }
+
+    #[test]
+    fn test_unknown_yaml_key_is_rejected() {
+        let err = PartialConfig::from_yaml("normal_batch_sise: 10\n").unwrap_err();
+        assert!(err.to_string().contains("normal_batch_sise"));
+    }
+
+    #[test]
+    fn test_wrong_yaml_type_is_rejected() {
+        assert!(PartialConfig::from_yaml("http_port: not-a-port\n").is_err());
+    }
+
+    #[test]
+    fn test_validation_reports_every_problem() {
+        let layer = PartialConfig {
+            kafka_brokers: Some(vec!["kafka".to_string()]),
+            aws_endpoint: Some("mock-aws:8080".to_string()),
+            normal_batch_size: Some(0),
+            backfill_bandwidth_cap_pct: Some(150),
+            ..Default::default()
+        };
+        let err = layer.resolve().unwrap_err();
+        let joined = err.to_string();
+        assert!(joined.contains("kafka_brokers"), "{}", joined);
+        assert!(joined.contains("aws_endpoint"), "{}", joined);
+        assert!(joined.contains("normal_batch_size"), "{}", joined);
+        assert!(joined.contains("backfill_bandwidth_cap_pct"), "{}", joined);
+    }
+
+    #[test]
+    fn test_failover_timeout_must_exceed_two_claim_intervals() {
+        let layer = PartialConfig {
+            failover_timeout_secs: Some(6),
+            leader_claim_interval_secs: Some(3),
+            ..Default::default()
+        };
+        let err = layer.resolve().unwrap_err();
+        assert!(err.errors[0].starts_with("failover_timeout_secs"));
+    }
+
+    #[test]
+    fn test_tuning_update_keeps_omitted_fields() {
+        let base = PartialConfig::default().resolve().unwrap().tuning();
+        let update = TuningUpdate {
+            slow_batch_size: Some(2000),
+            ..Default::default()
+        };
+        let tuning = update.apply(base).unwrap();
+        assert_eq!(tuning.slow_batch_size, 2000);
+        assert_eq!(tuning.normal_batch_size, base.normal_batch_size);
+    }
+
+    #[test]
+    fn test_tuning_update_is_validated() {
+        let base = PartialConfig::default().resolve().unwrap().tuning();
+        let update = TuningUpdate {
+            backfill_bandwidth_cap_pct: Some(0),
+            ..Default::default()
+        };
+        assert!(update.apply(base).is_err());
+    }
+
+    #[test]
+    fn test_backfill_bytes_per_sec() {
+        let tuning = PartialConfig::default().resolve().unwrap().tuning();
+        // 30% of 1 Gbps = 37.5 MB/s
+        assert_eq!(tuning.backfill_bytes_per_sec(), 37_500_000);
+    }
+
+    #[test]
+    fn test_restart_required_changes() {
+        let a = PartialConfig::default().resolve().unwrap();
+        let mut b = a.clone();
+        b.normal_batch_size = 200;
+        assert!(a.restart_required_changes(&b).is_empty());
+        b.http_port = 9191;
+        assert_eq!(a.restart_required_changes(&b), vec!["http_port"]);
+    }
+
+    #[test]
+    fn test_file_backend_requires_dir() {
+        let layer = PartialConfig {
+            election_backend: Some(ElectionBackend::File),
+            ..Default::default()
+        };
+        let err = layer.resolve().unwrap_err();
+        assert!(err.errors[0].starts_with("election_dir"));
+    }
+
+    #[test]
+    fn test_election_backend_from_yaml_and_str() {
+        let layer = PartialConfig::from_yaml(
+            "election_backend: lease\nelection_lease_endpoint: http://lease:2379\n",
+        )
+        .unwrap();
+        assert_eq!(layer.resolve().unwrap().election_backend, ElectionBackend::Lease);
+        assert_eq!("FILE".parse::<ElectionBackend>(), Ok(ElectionBackend::File));
+        assert!("zookeeper".parse::<ElectionBackend>().is_err());
+    }
+
+    #[test]
+    fn test_validation_settings_from_env_style_list() {
+        let layer = PartialConfig {
+            allowed_units: Some(split_list("C, PSI")),
+            value_min: Some(10.0),
+            value_max: Some(-10.0),
+            ..Default::default()
+        };
+        let err = layer.resolve().unwrap_err();
+        assert_eq!(err.errors.len(), 1);
+        assert!(err.errors[0].starts_with("value_min"));
+    }
+
+    #[test]
+    fn test_alarm_topic_cannot_be_aggregated() {
+        let layer = PartialConfig {
+            aggregate_topics: Some(vec!["metrics.raw".to_string(), "metrics.alarm".to_string()]),
+            ..Default::default()
+        };
+        let err = layer.resolve().unwrap_err();
+        assert_eq!(err.errors.len(), 1);
+        assert!(err.errors[0].starts_with("aggregate_topics"));
+    }
+
+    #[test]
+    fn test_cli_positional_config_path() {
+        let cli = Cli::parse_from(["exporter", "config.yaml", "--http-port", "9100"]);
+        assert_eq!(cli.config_path(), Some(Path::new("config.yaml")));
+        assert_eq!(PartialConfig::from_cli(&cli).http_port, Some(9100));
     }
 }
@@ -0,0 +1,264 @@
+/// Dead-letter routing for records that fail validation.
+///
+/// Rejected records are written to `dlq_topic` wrapped in a `DeadLetter`
+/// envelope: the original payload plus the rejection reason and the source
+/// coordinates (topic / partition / offset).  The envelope is keyed by those
+/// coordinates, so the `dlq_replay` tool can dedupe letters written by both
+/// the realtime and the backfill path for the same record.
+///
+/// The DLQ lives on the local Kafka cluster, so it keeps working during a
+/// WAN outage.  The dead letter is produced (and awaited) before the batch
+/// containing its neighbours commits offsets, preserving at-least-once.  If
+/// it still cannot be written after a few attempts, the caller rewinds the
+/// partition to the rejected record, so the next commit stops short of it
+/// and the record is validated (and dead-lettered) again.
+use std::future::Future;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::Consumer;
+use rdkafka::error::KafkaResult;
+use rdkafka::message::Message;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::Offset;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::models::MetricRecord;
+use crate::state::SharedState;
+use crate::validation::{ValidationError, ValidationRules};
+
+/// Attempts at producing one dead letter before giving up on the record.
+const DEAD_LETTER_ATTEMPTS: u32 = 3;
+/// Delay before the second attempt; doubles after each failure.
+const DEAD_LETTER_BACKOFF: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// `ValidationError::reason` code.
+    pub reason: String,
+    /// Human-readable error.
+    pub error: String,
+    pub source_topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    /// `"realtime"` or `"backfill"`.
+    pub path: String,
+    pub instance_id: String,
+    pub failed_at: DateTime<Utc>,
+    /// Original payload (lossy UTF-8; non-UTF-8 bytes become U+FFFD).
+    pub payload: String,
+}
+
+impl DeadLetter {
+    pub fn new(
+        msg: &impl Message,
+        err: &ValidationError,
+        path: &str,
+        instance_id: &str,
+        failed_at: DateTime<Utc>,
+    ) -> Self {
+        DeadLetter {
+            reason: err.reason().to_string(),
+            error: err.to_string(),
+            source_topic: msg.topic().to_string(),
+            partition: msg.partition(),
+            offset: msg.offset(),
+            path: path.to_string(),
+            instance_id: instance_id.to_string(),
+            failed_at,
+            payload: String::from_utf8_lossy(msg.payload().unwrap_or_default()).into_owned(),
+        }
+    }
+
+    /// Message key: the source coordinates.
+    pub fn key(&self) -> String {
+        format!("{}/{}/{}", self.source_topic, self.partition, self.offset)
+    }
+}
+
+/// Destination for dead letters.  Implemented by `DeadLetterSink`; tests
+/// substitute sinks that fail.
+pub trait DeadLetterWriter {
+    fn send(&self, letter: &DeadLetter) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+pub struct DeadLetterSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl DeadLetterSink {
+    pub fn new(brokers: &[String], topic: &str) -> anyhow::Result<Self> {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", brokers.join(","))
+            .set("message.timeout.ms", "5000")
+            .create()?;
+        Ok(DeadLetterSink {
+            producer,
+            topic: topic.to_string(),
+        })
+    }
+}
+
+impl DeadLetterWriter for DeadLetterSink {
+    async fn send(&self, letter: &DeadLetter) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(letter)?;
+        let key = letter.key();
+        self.producer
+            .send(
+                FutureRecord::to(&self.topic).key(&key).payload(&payload),
+                Duration::from_secs(5),
+            )
+            .await
+            .map_err(|(e, _)| e)?;
+        Ok(())
+    }
+}
+
+/// Validate a consumed message.  Valid records are counted as consumed and
+/// returned; invalid ones are counted as rejected and dead-lettered.
+///
+/// Returns `Err` if the dead letter could not be written.  The caller must
+/// then `rewind` to the message instead of committing past it.
+pub async fn accept_record(
+    msg: &impl Message,
+    path: &str,
+    rules: &ValidationRules,
+    sink: &impl DeadLetterWriter,
+    state: &SharedState,
+) -> anyhow::Result<Option<MetricRecord>> {
+    let now = Utc::now();
+    let err = match rules.validate(msg.payload().unwrap_or_default(), now) {
+        Ok(record) => {
+            state
+                .metrics
+                .records_consumed
+                .with_label_values(&[path, msg.topic()])
+                .inc();
+            return Ok(Some(record));
+        }
+        Err(e) => e,
+    };
+
+    state
+        .metrics
+        .records_rejected
+        .with_label_values(&[path, err.reason()])
+        .inc();
+    warn!(
+        path,
+        topic = msg.topic(),
+        partition = msg.partition(),
+        offset = msg.offset(),
+        reason = err.reason(),
+        "Rejected record: {}",
+        err
+    );
+
+    let letter = DeadLetter::new(msg, &err, path, &state.config.instance_id, now);
+    let mut attempt = 1;
+    loop {
+        let e = match sink.send(&letter).await {
+            Ok(()) => return Ok(None),
+            Err(e) => e,
+        };
+        state
+            .metrics
+            .dead_letter_failures
+            .with_label_values(&[path])
+            .inc();
+        if attempt == DEAD_LETTER_ATTEMPTS {
+            error!(
+                path,
+                key = %letter.key(),
+                "Failed to write dead letter, record will be re-consumed: {:#}",
+                e
+            );
+            return Err(e.context(format!("dead letter {} not written", letter.key())));
+        }
+        warn!(path, key = %letter.key(), attempt, "Failed to write dead letter, retrying: {:#}", e);
+        tokio::time::sleep(DEAD_LETTER_BACKOFF * 2u32.pow(attempt - 1)).await;
+        attempt += 1;
+    }
+}
+
+/// Move `msg`'s partition back so `msg` is the next record consumed.  Commits
+/// are taken from the consumer position, so this also keeps them from
+/// passing a record whose dead letter was not written.
+pub fn rewind(consumer: &impl Consumer, msg: &impl Message) -> KafkaResult<()> {
+    consumer.seek(
+        msg.topic(),
+        msg.partition(),
+        Offset::Offset(msg.offset()),
+        Duration::from_secs(5),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PartialConfig;
+    use rdkafka::message::{OwnedMessage, Timestamp};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn message(offset: i64, payload: &[u8]) -> OwnedMessage {
+        OwnedMessage::new(
+            Some(payload.to_vec()),
+            None,
+            "metrics.raw".to_string(),
+            Timestamp::NotAvailable,
+            2,
+            offset,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_dead_letter_carries_source_and_reason() {
+        let err = ValidationError::UnknownUnit("furlong".to_string());
+        let letter = DeadLetter::new(
+            &message(42, b"{}"),
+            &err,
+            "realtime",
+            "server-a",
+            Utc::now(),
+        );
+        assert_eq!(letter.reason, "unknown_unit");
+        assert_eq!(letter.key(), "metrics.raw/2/42");
+        assert_eq!(letter.payload, "{}");
+
+        let json = serde_json::to_string(&letter).unwrap();
+        assert_eq!(serde_json::from_str::<DeadLetter>(&json).unwrap(), letter);
+    }
+
+    /// Counts attempts; fails all of them.
+    struct BrokenSink(AtomicU32);
+
+    impl DeadLetterWriter for BrokenSink {
+        async fn send(&self, _letter: &DeadLetter) -> anyhow::Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            anyhow::bail!("broker unreachable")
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_dead_letter_is_an_error_not_a_drop() {
+        let state = SharedState::new(PartialConfig::default().resolve().unwrap());
+        let rules = state.config.validation_rules();
+        let sink = BrokenSink(AtomicU32::new(0));
+
+        let result =
+            accept_record(&message(7, b"not json"), "realtime", &rules, &sink, &state).await;
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("metrics.raw/2/7"), "{err:#}");
+        assert_eq!(sink.0.load(Ordering::SeqCst), DEAD_LETTER_ATTEMPTS);
+        let failures = state
+            .metrics
+            .dead_letter_failures
+            .with_label_values(&["realtime"])
+            .get();
+        assert_eq!(failures, u64::from(DEAD_LETTER_ATTEMPTS));
+    }
+}
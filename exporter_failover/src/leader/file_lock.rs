@@ -0,0 +1,99 @@
+/// Shared-storage election backend (no Kafka required).
+///
+/// The slot is an exclusive lock on `<dir>/leader.lock`, held for as long as
+/// the open file handle lives — the OS releases it when the holder exits or
+/// crashes.  Claims are written to `<dir>/leader_claim.json` via write +
+/// rename, so readers never see a half-written claim.
+///
+/// `dir` must be on storage visible to every instance (NFS v4, SMB, a shared
+/// volume) whose locks are honoured across hosts.
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{ElectionPoll, LeaderElector};
+use crate::state::LeaderClaim;
+
+pub struct FileLockElector {
+    dir: PathBuf,
+    instance_id: String,
+    /// Open handle holding the exclusive lock, if we own the slot.
+    lock: Option<File>,
+    released: bool,
+}
+
+impl FileLockElector {
+    pub fn new(dir: impl Into<PathBuf>, instance_id: &str) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(FileLockElector {
+            dir,
+            instance_id: instance_id.to_string(),
+            lock: None,
+            released: false,
+        })
+    }
+
+    fn try_acquire(&mut self) -> anyhow::Result<()> {
+        if self.lock.is_some() || self.released {
+            return Ok(());
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join("leader.lock"))?;
+        match file.try_lock() {
+            Ok(()) => {
+                self.lock = Some(file);
+                Ok(())
+            }
+            Err(TryLockError::WouldBlock) => Ok(()),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    fn read_claim(&self) -> anyhow::Result<Option<LeaderClaim>> {
+        match std::fs::read(self.dir.join("leader_claim.json")) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl LeaderElector for FileLockElector {
+    fn name(&self) -> &'static str {
+        "file-lock"
+    }
+
+    async fn poll(&mut self, timeout: Duration) -> anyhow::Result<ElectionPoll> {
+        tokio::time::sleep(timeout).await;
+        self.try_acquire()?;
+        Ok(ElectionPoll {
+            claims: self.read_claim()?.into_iter().collect(),
+            holds_slot: self.lock.is_some(),
+        })
+    }
+
+    async fn publish_claim(&mut self, claim: &LeaderClaim) -> anyhow::Result<()> {
+        let tmp = self
+            .dir
+            .join(format!(".leader_claim.{}.tmp", self.instance_id));
+        std::fs::write(&tmp, serde_json::to_vec(claim)?)?;
+        std::fs::rename(&tmp, self.dir.join("leader_claim.json"))?;
+        Ok(())
+    }
+
+    async fn release(&mut self) -> anyhow::Result<()> {
+        // Dropping the handle releases the lock.
+        self.lock = None;
+        self.released = true;
+        Ok(())
+    }
+
+    async fn rejoin(&mut self) -> anyhow::Result<()> {
+        self.released = false;
+        Ok(())
+    }
+}
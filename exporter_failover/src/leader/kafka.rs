@@ -0,0 +1,142 @@
+/// Kafka election backend.
+///
+/// Both instances join consumer group `exporter-leader` on the single-partition
+/// topic `__exporter_leader`; whoever is assigned the partition holds the slot.
+/// Leader claims are written to the same topic, keyed by instance_id.
+///
+/// Two kinds of messages live on this topic:
+///   * **Producer heartbeats** — key = `"leader"`, no structured payload.
+///     These are sent by the `producer` binary to keep the topic alive
+///     (triggering partition assignment).  We ignore the payload.
+///   * **Leader claims** — key = instance_id, JSON payload with
+///     `{ "type": "leader_claim", "instance_id": "...", "ts": "..." }`.
+use std::time::Duration;
+
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+
+use super::{ElectionPoll, LeaderElector};
+use crate::state::LeaderClaim;
+
+const LEADER_TOPIC: &str = "__exporter_leader";
+
+pub struct KafkaElector {
+    consumer: StreamConsumer,
+    producer: FutureProducer,
+}
+
+impl KafkaElector {
+    pub fn new(brokers: &[String]) -> anyhow::Result<Self> {
+        let brokers = brokers.join(",");
+
+        // ── Consumer: cooperative-sticky assignment ──
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("group.id", "exporter-leader")
+            .set("bootstrap.servers", &brokers)
+            .set("session.timeout.ms", "10000")
+            .set("heartbeat.interval.ms", "3000")
+            .set("max.poll.interval.ms", "30000")
+            .set("partition.assignment.strategy", "cooperative-sticky")
+            .set("enable.auto.commit", "true")
+            .set("auto.offset.reset", "earliest")
+            .create()?;
+        consumer.subscribe(&[LEADER_TOPIC])?;
+
+        // ── Producer: for writing leader claims ──
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("message.timeout.ms", "5000")
+            .create()?;
+
+        Ok(KafkaElector { consumer, producer })
+    }
+}
+
+/// Parse a message from the `__exporter_leader` topic into a leader claim.
+/// Returns `None` for producer heartbeats and anything that isn't a claim.
+pub fn parse_leader_message(key: Option<&[u8]>, payload: Option<&[u8]>) -> Option<LeaderClaim> {
+    let key = std::str::from_utf8(key?).ok()?;
+
+    // Skip producer heartbeats (key = "leader")
+    if key == "leader" {
+        return None;
+    }
+
+    serde_json::from_slice::<LeaderClaim>(payload?)
+        .ok()
+        .filter(|claim| claim.claim_type == "leader_claim")
+}
+
+impl LeaderElector for KafkaElector {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn poll(&mut self, timeout: Duration) -> anyhow::Result<ElectionPoll> {
+        match tokio::time::timeout(timeout, self.consumer.recv()).await {
+            // Receiving a message means we hold the partition.
+            Ok(Ok(msg)) => Ok(ElectionPoll {
+                claims: parse_leader_message(msg.key(), msg.payload())
+                    .into_iter()
+                    .collect(),
+                holds_slot: true,
+            }),
+            Ok(Err(e)) => Err(e.into()),
+            // Timeout: no message. Check partition assignment.
+            Err(_) => Ok(ElectionPoll {
+                claims: Vec::new(),
+                holds_slot: self.consumer.assignment()?.count() > 0,
+            }),
+        }
+    }
+
+    async fn publish_claim(&mut self, claim: &LeaderClaim) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(claim)?;
+        let record = FutureRecord::to(LEADER_TOPIC)
+            .key(&claim.instance_id)
+            .payload(&payload);
+        self.producer
+            .send(record, Duration::from_secs(5))
+            .await
+            .map_err(|(e, _)| e)?;
+        Ok(())
+    }
+
+    async fn release(&mut self) -> anyhow::Result<()> {
+        // Leave the group so the peer is assigned the partition.
+        self.consumer.unsubscribe();
+        Ok(())
+    }
+
+    async fn rejoin(&mut self) -> anyhow::Result<()> {
+        self.consumer.subscribe(&[LEADER_TOPIC])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_heartbeat_is_not_a_claim() {
+        assert!(parse_leader_message(Some(b"leader"), Some(b"ping")).is_none());
+    }
+
+    #[test]
+    fn test_claim_is_parsed() {
+        let claim = LeaderClaim::new("server-a", Utc::now());
+        let payload = serde_json::to_vec(&claim).unwrap();
+        let parsed = parse_leader_message(Some(b"server-a"), Some(&payload)).unwrap();
+        assert_eq!(parsed.instance_id, "server-a");
+    }
+
+    #[test]
+    fn test_missing_key_is_ignored() {
+        let payload = serde_json::to_vec(&LeaderClaim::new("server-a", Utc::now())).unwrap();
+        assert!(parse_leader_message(None, Some(&payload)).is_none());
+    }
+}
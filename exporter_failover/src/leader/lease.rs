@@ -0,0 +1,122 @@
+/// Lease election backend, modelled on etcd / Raft-style leader leases.
+///
+/// A coordination service grants a single named lease with a TTL.  The holder
+/// renews it on every poll; if the holder dies the lease expires after the
+/// TTL and the next instance to ask is granted it.  Leader claims are stored
+/// as a key on the same service.
+///
+/// The service's HTTP API and an in-process implementation live in
+/// `crate::lease_service`.
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use super::{ElectionPoll, LeaderElector};
+use crate::state::LeaderClaim;
+
+// ── Wire types ──────────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaseRequest {
+    pub holder: String,
+    pub ttl_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaseGrant {
+    pub granted: bool,
+    /// Current holder after the request (us if granted).
+    pub holder: String,
+}
+
+// ── Client ──────────────────────────────────────────────────────────────────
+
+pub struct LeaseElector {
+    client: reqwest::Client,
+    endpoint: String,
+    instance_id: String,
+    ttl: Duration,
+    released: bool,
+}
+
+impl LeaseElector {
+    pub fn new(endpoint: &str, instance_id: &str, ttl: Duration) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(3))
+            .build()?;
+        Ok(LeaseElector {
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            instance_id: instance_id.to_string(),
+            ttl,
+            released: false,
+        })
+    }
+}
+
+impl LeaderElector for LeaseElector {
+    fn name(&self) -> &'static str {
+        "lease"
+    }
+
+    async fn poll(&mut self, timeout: Duration) -> anyhow::Result<ElectionPoll> {
+        tokio::time::sleep(timeout).await;
+
+        let holds_slot = if self.released {
+            false
+        } else {
+            let grant: LeaseGrant = self
+                .client
+                .post(format!("{}/v1/lease", self.endpoint))
+                .json(&LeaseRequest {
+                    holder: self.instance_id.clone(),
+                    ttl_ms: self.ttl.as_millis() as u64,
+                })
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            grant.granted
+        };
+
+        let resp = self
+            .client
+            .get(format!("{}/v1/claim", self.endpoint))
+            .send()
+            .await?;
+        let claims = if resp.status() == StatusCode::NOT_FOUND {
+            Vec::new()
+        } else {
+            vec![resp.error_for_status()?.json().await?]
+        };
+
+        Ok(ElectionPoll { claims, holds_slot })
+    }
+
+    async fn publish_claim(&mut self, claim: &LeaderClaim) -> anyhow::Result<()> {
+        self.client
+            .put(format!("{}/v1/claim", self.endpoint))
+            .json(claim)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn release(&mut self) -> anyhow::Result<()> {
+        self.released = true;
+        self.client
+            .delete(format!("{}/v1/lease/{}", self.endpoint, self.instance_id))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn rejoin(&mut self) -> anyhow::Result<()> {
+        self.released = false;
+        Ok(())
+    }
+}
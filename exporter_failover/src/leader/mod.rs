@@ -0,0 +1,520 @@
+/// Leader Election with Sticky Primary Failover
+///
+/// The election is split into two parts:
+///
+///   * A `LeaderElector` backend answers "do we own the election slot?" and
+///     carries leader claims between instances.  Backends:
+///       - `kafka::KafkaElector`     — single-partition `__exporter_leader` topic
+///       - `file_lock::FileLockElector` — exclusive lock file on shared storage
+///       - `lease::LeaseElector`     — etcd-style TTL lease (stand-in: `mock_lease`)
+///   * `ElectionDriver` turns those observations into promote / demote
+///     decisions.  It is backend-agnostic, so the sticky-failover rules below
+///     behave the same everywhere.
+///
+/// Defense against leadership flip-flop:
+///
+/// Layer 1 — Startup Grace Period (Application-level):
+///   On startup, the election loop waits `startup_grace_secs` before allowing
+///   promotion.  During this period it still polls the backend to populate
+///   `last_known_leader`, giving the current active leader time to publish at
+///   least 2 claims.
+///
+/// Layer 2 — Peer Health Check (Application-level):
+///   Before promoting, query the peer's `/status` HTTP endpoint.  If the peer
+///   responds with `role: "ACTIVE"`, defer promotion (peer is alive and leading).
+///   Uses a 3-second HTTP timeout — won't delay failover when peer is truly dead.
+///
+/// Layer 3 — Sticky Slot Ownership (Backend-level):
+///   Whoever holds the slot keeps it until it dies or lets go: Kafka uses
+///   `cooperative-sticky` assignment, the file lock is held by an open
+///   handle, and the lease is renewed by its holder.  A recovering old primary
+///   therefore cannot take the slot back.
+///
+/// Layer 4 — Leader Claim Fencing (Application-level):
+///   The Active instance periodically publishes "leader claims" with its
+///   `instance_id`.  Before promoting, any instance checks for recent claims.
+///   If another instance has a fresh claim (within `failover_timeout_secs`),
+///   promotion is deferred.
+///
+/// Layer 5 — Demotion Grace (Application-level):
+///   Don't demote immediately when the slot is not held (a Kafka partition can
+///   vanish briefly during cooperative rebalance).  Require 3 consecutive
+///   polls without the slot before demoting.
+///
+/// Operator overrides (`POST /admin/promote` / `/admin/demote`) are consumed
+/// at the top of each step: a forced promotion skips Layers 2 and 4 the next
+/// time we hold the slot; a demotion steps down and releases the slot for a
+/// hold period so the peer can take it.
+///
+/// Together these ensure that after failover, the new primary keeps its role
+/// even when the old primary recovers.
+pub mod file_lock;
+pub mod kafka;
+pub mod lease;
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tracing::{debug, info, warn};
+
+use crate::config::ExporterConfig;
+use crate::state::{AdminCommand, HaRole, LeaderClaim, LeaderClaimState, SharedState};
+
+// ── Pure logic: should we promote to Active? ────────────────────────────────
+
+/// Decide whether this instance should promote to Active.
+///
+/// Rules (evaluated in order):
+///   1. No known leader            → true  (first startup / clean slate)
+///   2. Known leader is us         → true  (we are already the leader)
+///   3. Known leader is someone else AND claim is fresh (within timeout)
+///      → false (defer — they are still alive)
+///   4. Known leader is someone else AND claim is expired
+///      → true  (they are dead — take over)
+pub fn should_promote(
+    our_id: &str,
+    known_leader: Option<&LeaderClaimState>,
+    now: DateTime<Utc>,
+    failover_timeout: Duration,
+) -> bool {
+    match known_leader {
+        None => true,
+        Some(claim) => {
+            if claim.instance_id == our_id {
+                return true;
+            }
+            let claim_age = now
+                .signed_duration_since(claim.timestamp)
+                .to_std()
+                .unwrap_or(Duration::ZERO);
+            claim_age > failover_timeout
+        }
+    }
+}
+
+// ── Backend abstraction ─────────────────────────────────────────────────────
+
+/// What one `LeaderElector::poll` observed.
+#[derive(Debug, Default)]
+pub struct ElectionPoll {
+    /// Leader claims read from the backend during this poll (may repeat
+    /// claims already seen; `SharedState` keeps only the newest).
+    pub claims: Vec<LeaderClaim>,
+    /// Whether this instance currently owns the election slot.
+    pub holds_slot: bool,
+}
+
+/// An election backend: a mutually exclusive slot plus a channel for leader
+/// claims.
+///
+/// Implementations must guarantee that at most one instance holds the slot
+/// at a time and that the slot is freed (eventually) when its holder dies.
+pub trait LeaderElector: Send {
+    /// Short backend name for logs.
+    fn name(&self) -> &'static str;
+
+    /// Wait up to `timeout` for election activity, try to (re)acquire the
+    /// slot, and report claims seen plus current slot ownership.
+    fn poll(
+        &mut self,
+        timeout: Duration,
+    ) -> impl Future<Output = anyhow::Result<ElectionPoll>> + Send;
+
+    /// Publish a leader claim so other instances can see it.
+    fn publish_claim(
+        &mut self,
+        claim: &LeaderClaim,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Give up the slot (manual demotion).  `poll` must not re-acquire it
+    /// until `rejoin` is called.
+    fn release(&mut self) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Compete for the slot again after `release`.
+    fn rejoin(&mut self) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+// ── Peer health check ──────────────────────────────────────────────────────
+
+/// Check whether the peer is currently Active by querying its /status endpoint.
+/// Returns `true` if the peer responds with `role: "ACTIVE"`, `false` otherwise
+/// (unreachable, timeout, non-ACTIVE role, parse error).
+async fn peer_is_active(peer_endpoint: &str) -> bool {
+    let url = format!("http://{}/status", peer_endpoint);
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(3))
+        .build();
+
+    let client = match client {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+
+    match client.get(&url).send().await {
+        Ok(resp) if resp.status().is_success() => {
+            if let Ok(body) = resp.text().await {
+                // Parse the JSON and check the role field
+                if let Ok(json) = serde_json::from_str::<serde_json::Value>(&body) {
+                    if let Some(role) = json.get("role").and_then(|v| v.as_str()) {
+                        return role == "ACTIVE";
+                    }
+                }
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+// ── Election driver ─────────────────────────────────────────────────────────
+
+/// Consecutive polls without the slot before an Active instance demotes.
+const DEMOTION_THRESHOLD: u32 = 3;
+
+/// How long each backend poll waits in the election loop.
+const POLL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Backend-independent promotion / demotion state machine.
+///
+/// All time-based decisions use the `now` passed to `step`, so scenarios can
+/// be replayed deterministically in tests.
+pub struct ElectionDriver {
+    our_id: String,
+    failover_timeout: Duration,
+    claim_interval: chrono::Duration,
+    startup_grace: chrono::Duration,
+    peer_endpoint: Option<String>,
+    poll_timeout: Duration,
+
+    started_at: Option<DateTime<Utc>>,
+    startup_complete: bool,
+    zero_slot_count: u32,
+    last_claim_at: Option<DateTime<Utc>>,
+    force_promote: bool,
+    demote_hold_until: Option<DateTime<Utc>>,
+}
+
+impl ElectionDriver {
+    /// `poll_timeout` bounds how long each backend poll may wait.
+    pub fn new(config: &ExporterConfig, poll_timeout: Duration) -> Self {
+        ElectionDriver {
+            our_id: config.instance_id.clone(),
+            failover_timeout: Duration::from_secs(config.failover_timeout_secs),
+            claim_interval: chrono::Duration::seconds(config.leader_claim_interval_secs as i64),
+            startup_grace: chrono::Duration::seconds(config.startup_grace_secs as i64),
+            peer_endpoint: config.peer_endpoint.clone(),
+            poll_timeout,
+            started_at: None,
+            startup_complete: false,
+            zero_slot_count: 0,
+            last_claim_at: None,
+            force_promote: false,
+            demote_hold_until: None,
+        }
+    }
+
+    /// Run one election round: handle operator commands, poll the backend,
+    /// promote/demote, and publish a claim if Active and one is due.
+    pub async fn step<E: LeaderElector>(
+        &mut self,
+        elector: &mut E,
+        state: &SharedState,
+        now: DateTime<Utc>,
+    ) {
+        let our_id = self.our_id.clone();
+
+        // ── Layer 1: Startup grace period ──
+        let started_at = *self.started_at.get_or_insert(now);
+        if !self.startup_complete && now >= started_at + self.startup_grace {
+            self.startup_complete = true;
+            info!(
+                instance = %our_id,
+                "Startup grace period complete, promotion decisions now active"
+            );
+        }
+
+        // ── Operator overrides ──
+        match state.take_admin_command().await {
+            Some(AdminCommand::Demote { hold }) => {
+                let mut role = state.ha_role.lock().await;
+                if *role == HaRole::Active {
+                    warn!(instance = %our_id, "Manual demotion -- stepping down to STANDBY");
+                    *role = HaRole::Standby;
+                }
+                drop(role);
+                // Release the slot so the peer can take it.
+                if let Err(e) = elector.release().await {
+                    warn!(instance = %our_id, error = %e, "Failed to release election slot");
+                }
+                self.demote_hold_until = Some(
+                    now + chrono::Duration::from_std(hold).unwrap_or(chrono::Duration::zero()),
+                );
+                self.force_promote = false;
+                self.zero_slot_count = 0;
+                info!(
+                    instance = %our_id,
+                    hold_secs = hold.as_secs(),
+                    "Released election slot, holding off re-election"
+                );
+            }
+            Some(AdminCommand::Promote) => {
+                if self.demote_hold_until.take().is_some() {
+                    self.rejoin(elector).await;
+                }
+                info!(instance = %our_id, "Manual promotion pending until slot is held");
+                self.force_promote = true;
+            }
+            None => {}
+        }
+
+        if let Some(until) = self.demote_hold_until {
+            if now < until {
+                tokio::time::sleep(self.poll_timeout).await;
+                return;
+            }
+            self.demote_hold_until = None;
+            self.rejoin(elector).await;
+        }
+
+        // ── Poll the backend ──
+        let poll = match elector.poll(self.poll_timeout).await {
+            Ok(poll) => poll,
+            Err(e) => {
+                warn!(backend = elector.name(), "Leader election poll error: {:#}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                return;
+            }
+        };
+
+        for claim in &poll.claims {
+            if claim.claim_type == "leader_claim" {
+                debug!(from = %claim.instance_id, ts = %claim.ts, "Received leader claim");
+                state.update_leader_claim(&claim.instance_id, claim.ts).await;
+            }
+        }
+
+        // During startup grace, consume claims but skip promotion/demotion
+        if !self.startup_complete {
+            debug!(
+                instance = %our_id,
+                "Startup grace period: consuming claims but deferring promotion"
+            );
+            return;
+        }
+
+        let mut role = state.ha_role.lock().await;
+        if poll.holds_slot {
+            // Reset demotion grace counter — we hold the slot
+            self.zero_slot_count = 0;
+
+            if *role == HaRole::Standby {
+                let claim = state.get_leader_claim().await;
+                if self.force_promote
+                    || should_promote(&our_id, claim.as_ref(), now, self.failover_timeout)
+                {
+                    // Layer 2: Peer health check before promoting (skipped when forced)
+                    if let Some(endpoint) = self.peer_endpoint.as_ref().filter(|_| !self.force_promote) {
+                        if peer_is_active(endpoint).await {
+                            info!(
+                                instance = %our_id,
+                                peer = %endpoint,
+                                "Peer is ACTIVE, deferring promotion"
+                            );
+                            return;
+                        }
+                    }
+                    info!(
+                        instance = %our_id,
+                        backend = elector.name(),
+                        forced = self.force_promote,
+                        "Election slot held + promotion approved -- promoting to ACTIVE"
+                    );
+                    *role = HaRole::Active;
+                    self.force_promote = false;
+                } else {
+                    debug!(
+                        instance = %our_id,
+                        "Election slot held but another leader has fresh claim -- deferring"
+                    );
+                }
+            }
+        } else if *role == HaRole::Active {
+            // Layer 5: Demotion grace — require consecutive polls without the slot
+            self.zero_slot_count += 1;
+            if self.zero_slot_count >= DEMOTION_THRESHOLD {
+                warn!(
+                    instance = %our_id,
+                    backend = elector.name(),
+                    consecutive_zero_checks = self.zero_slot_count,
+                    "Lost election slot for {} consecutive checks -- demoting to STANDBY",
+                    DEMOTION_THRESHOLD
+                );
+                *role = HaRole::Standby;
+                self.zero_slot_count = 0;
+            } else {
+                debug!(
+                    instance = %our_id,
+                    consecutive_zero_checks = self.zero_slot_count,
+                    threshold = DEMOTION_THRESHOLD,
+                    "Election slot not held, demotion grace {}/{}",
+                    self.zero_slot_count, DEMOTION_THRESHOLD
+                );
+            }
+        }
+        let is_active = *role == HaRole::Active;
+        drop(role);
+
+        // ── While Active: periodically publish leader claims ──
+        let claim_due = self
+            .last_claim_at
+            .is_none_or(|t| now - t >= self.claim_interval);
+        if is_active && claim_due {
+            let claim = LeaderClaim::new(&our_id, now);
+            match elector.publish_claim(&claim).await {
+                Ok(()) => debug!(instance = %our_id, "Published leader claim"),
+                Err(e) => {
+                    warn!(instance = %our_id, error = %e, "Failed to publish leader claim")
+                }
+            }
+            // Also update our own shared state so we see our own claim
+            state.update_leader_claim(&our_id, now).await;
+            self.last_claim_at = Some(now);
+        }
+    }
+
+    async fn rejoin<E: LeaderElector>(&mut self, elector: &mut E) {
+        match elector.rejoin().await {
+            Ok(()) => info!(instance = %self.our_id, "Rejoined leader election"),
+            Err(e) => {
+                warn!(instance = %self.our_id, error = %e, "Failed to rejoin leader election")
+            }
+        }
+    }
+}
+
+// ── Main election loop ──────────────────────────────────────────────────────
+
+/// Run the leader election loop on the given backend.
+/// This function never returns under normal operation.
+pub async fn leader_election_loop<E: LeaderElector>(state: Arc<SharedState>, mut elector: E) {
+    let mut driver = ElectionDriver::new(&state.config, POLL_TIMEOUT);
+
+    info!(
+        instance = %state.config.instance_id,
+        backend = elector.name(),
+        startup_grace_secs = state.config.startup_grace_secs,
+        "Joined leader election, waiting for the election slot..."
+    );
+
+    loop {
+        driver.step(&mut elector, &state, Utc::now()).await;
+    }
+}
+
+/// Wait until this instance becomes Active.
+pub async fn wait_until_active(state: Arc<SharedState>) {
+    loop {
+        {
+            let role = state.ha_role.lock().await;
+            if *role == HaRole::Active {
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Continuously check if we are still Active. If demoted, log and return false.
+/// This is used by worker tasks to gracefully stop when losing leadership.
+pub async fn check_still_active(state: &SharedState) -> bool {
+    let role = state.ha_role.lock().await;
+    *role == HaRole::Active
+}
+
+// ── Unit tests ──────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration as ChronoDuration;
+
+    const TIMEOUT: Duration = Duration::from_secs(15);
+
+    fn claim(id: &str, ts: DateTime<Utc>) -> LeaderClaimState {
+        LeaderClaimState {
+            instance_id: id.to_string(),
+            timestamp: ts,
+        }
+    }
+
+    #[test]
+    fn test_no_known_leader_promotes() {
+        let now = Utc::now();
+        assert!(should_promote("server-a", None, now, TIMEOUT));
+    }
+
+    #[test]
+    fn test_self_claim_promotes() {
+        let now = Utc::now();
+        let c = claim("server-a", now - ChronoDuration::seconds(2));
+        assert!(should_promote("server-a", Some(&c), now, TIMEOUT));
+    }
+
+    #[test]
+    fn test_fresh_other_claim_defers() {
+        let now = Utc::now();
+        // Claim is 5s old, timeout is 15s → fresh → defer
+        let c = claim("server-b", now - ChronoDuration::seconds(5));
+        assert!(!should_promote("server-a", Some(&c), now, TIMEOUT));
+    }
+
+    #[test]
+    fn test_expired_other_claim_promotes() {
+        let now = Utc::now();
+        // Claim is 20s old, timeout is 15s → expired → promote
+        let c = claim("server-b", now - ChronoDuration::seconds(20));
+        assert!(should_promote("server-a", Some(&c), now, TIMEOUT));
+    }
+
+    #[test]
+    fn test_boundary_exact_timeout_does_not_promote() {
+        let now = Utc::now();
+        // Claim is exactly 15s old = timeout → NOT expired (need strictly >)
+        let c = claim("server-b", now - ChronoDuration::seconds(15));
+        assert!(!should_promote("server-a", Some(&c), now, TIMEOUT));
+    }
+
+    #[test]
+    fn test_boundary_just_past_timeout_promotes() {
+        let now = Utc::now();
+        // Claim is 16s old, timeout is 15s → expired → promote
+        let c = claim("server-b", now - ChronoDuration::seconds(16));
+        assert!(should_promote("server-a", Some(&c), now, TIMEOUT));
+    }
+
+    #[test]
+    fn test_very_old_claim_promotes() {
+        let now = Utc::now();
+        // Claim is 10 minutes old → definitely expired
+        let c = claim("server-b", now - ChronoDuration::seconds(600));
+        assert!(should_promote("server-a", Some(&c), now, TIMEOUT));
+    }
+
+    #[test]
+    fn test_future_claim_defers() {
+        // Edge case: claim timestamp is in the future (clock skew)
+        let now = Utc::now();
+        let c = claim("server-b", now + ChronoDuration::seconds(5));
+        assert!(!should_promote("server-a", Some(&c), now, TIMEOUT));
+    }
+
+    #[test]
+    fn test_self_claim_even_if_old_promotes() {
+        let now = Utc::now();
+        // Even a very old self-claim should promote (we are the leader)
+        let c = claim("server-a", now - ChronoDuration::seconds(600));
+        assert!(should_promote("server-a", Some(&c), now, TIMEOUT));
+    }
+}
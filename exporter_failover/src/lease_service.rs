@@ -0,0 +1,155 @@
+/// In-process coordination service for the `lease` election backend.
+///
+/// `LeaseStore` + `router` implement the lease API that
+/// `leader::lease::LeaseElector` talks to.  The `mock_lease` binary serves it
+/// as a local stand-in for a real etcd / Consul cluster; integration tests
+/// mount it on an ephemeral port.
+///
+/// HTTP API (JSON):
+/// - POST   /v1/lease           {holder, ttl_ms} -> {granted, holder}
+/// - DELETE /v1/lease/{holder}  -> 204 (no-op if `holder` doesn't own it)
+/// - GET    /v1/claim           -> LeaderClaim | 404
+/// - PUT    /v1/claim           LeaderClaim -> 204
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{get, post};
+use axum::Router;
+
+use crate::leader::lease::{LeaseGrant, LeaseRequest};
+use crate::state::LeaderClaim;
+
+#[derive(Default)]
+struct LeaseInner {
+    holder: Option<(String, Instant)>,
+    claim: Option<LeaderClaim>,
+}
+
+/// Single-lease store with TTL expiry.
+#[derive(Default)]
+pub struct LeaseStore {
+    inner: Mutex<LeaseInner>,
+}
+
+impl LeaseStore {
+    pub fn new() -> Arc<Self> {
+        Arc::new(LeaseStore::default())
+    }
+
+    /// Grant or renew the lease if it is free, expired, or already ours.
+    pub fn acquire(&self, holder: &str, ttl: Duration) -> LeaseGrant {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let available = match &inner.holder {
+            None => true,
+            Some((current, expires)) => current == holder || *expires <= now,
+        };
+        if available {
+            inner.holder = Some((holder.to_string(), now + ttl));
+        }
+        LeaseGrant {
+            granted: available,
+            holder: inner
+                .holder
+                .as_ref()
+                .map(|(h, _)| h.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn release(&self, holder: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.holder.as_ref().is_some_and(|(h, _)| h == holder) {
+            inner.holder = None;
+        }
+    }
+
+    pub fn put_claim(&self, claim: LeaderClaim) {
+        self.inner.lock().unwrap().claim = Some(claim);
+    }
+
+    pub fn get_claim(&self) -> Option<LeaderClaim> {
+        self.inner.lock().unwrap().claim.clone()
+    }
+}
+
+pub fn router(store: Arc<LeaseStore>) -> Router {
+    Router::new()
+        .route("/v1/lease", post(acquire_handler))
+        .route("/v1/lease/{holder}", axum::routing::delete(release_handler))
+        .route("/v1/claim", get(get_claim_handler).put(put_claim_handler))
+        .with_state(store)
+}
+
+async fn acquire_handler(
+    State(store): State<Arc<LeaseStore>>,
+    Json(req): Json<LeaseRequest>,
+) -> Json<LeaseGrant> {
+    Json(store.acquire(&req.holder, Duration::from_millis(req.ttl_ms)))
+}
+
+async fn release_handler(
+    State(store): State<Arc<LeaseStore>>,
+    Path(holder): Path<String>,
+) -> StatusCode {
+    store.release(&holder);
+    StatusCode::NO_CONTENT
+}
+
+async fn get_claim_handler(State(store): State<Arc<LeaseStore>>) -> Response {
+    match store.get_claim() {
+        Some(claim) => Json(claim).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn put_claim_handler(
+    State(store): State<Arc<LeaseStore>>,
+    Json(claim): Json<LeaderClaim>,
+) -> StatusCode {
+    store.put_claim(claim);
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn test_first_holder_is_granted() {
+        let store = LeaseStore::new();
+        assert!(store.acquire("server-a", TTL).granted);
+        let grant = store.acquire("server-b", TTL);
+        assert!(!grant.granted);
+        assert_eq!(grant.holder, "server-a");
+    }
+
+    #[test]
+    fn test_holder_can_renew() {
+        let store = LeaseStore::new();
+        assert!(store.acquire("server-a", TTL).granted);
+        assert!(store.acquire("server-a", TTL).granted);
+    }
+
+    #[test]
+    fn test_expired_lease_is_taken_over() {
+        let store = LeaseStore::new();
+        assert!(store.acquire("server-a", Duration::ZERO).granted);
+        assert!(store.acquire("server-b", TTL).granted);
+    }
+
+    #[test]
+    fn test_release_only_by_holder() {
+        let store = LeaseStore::new();
+        store.acquire("server-a", TTL);
+        store.release("server-b");
+        assert!(!store.acquire("server-b", TTL).granted);
+        store.release("server-a");
+        assert!(store.acquire("server-b", TTL).granted);
+    }
+}
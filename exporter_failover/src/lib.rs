@@ -1,5 +1,10 @@
 // Re-export modules for integration tests and external use.
+pub mod aggregation;
 pub mod config;
+pub mod dlq;
 pub mod leader;
+pub mod lease_service;
+pub mod metrics;
 pub mod models;
 pub mod state;
+pub mod validation;
@@ -2,53 +2,57 @@
 // Exporter Service with Active/Standby Failover
 //
 // Architecture:
-//   - Leader Election via Kafka Consumer Group (single-partition topic)
+//   - Leader Election: pluggable backend (Kafka consumer group, file lock, lease)
 //   - Realtime Consumer: Kafka -> Cloud streaming with adaptive micro-batch
 //   - Backfill Engine: dormant until WAN recovery, rate-limited replay
+//   - Validation: malformed records go to a dead-letter topic (see dlq_replay)
+//   - Aggregation: optional edge downsampling into tumbling-window aggregates
 //   - Health Monitor: connection state machine (Connected/Disconnected/Backfilling)
-//   - HTTP API: health, status, and metrics endpoints
+//   - HTTP API: health, status, metrics, and /admin operator endpoints
+//   - Config: layered (file + env + CLI), tuning hot-reloadable via SIGHUP
+//   - Telemetry: Prometheus registry + optional OTLP trace export
 // ============================================================================
 
+mod admin;
+mod aggregation;
 mod api;
 mod backfill;
 mod config;
+mod dlq;
 mod health;
 mod leader;
+mod metrics;
 mod models;
 mod realtime;
 mod state;
+mod telemetry;
+mod validation;
 
 use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
 use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("exporter_failover=info".parse().unwrap())
-                .add_directive("rdkafka=warn".parse().unwrap()),
-        )
-        .with_target(true)
-        .init();
-
-    // Load config: try file first, fallback to env
-    let config = match std::env::args().nth(1) {
-        Some(path) => {
-            info!(path = %path, "Loading config from file");
-            config::ExporterConfig::from_file(&path)?
-        }
-        None => {
-            info!("Loading config from environment variables");
-            config::ExporterConfig::from_env()?
-        }
-    };
+    // Load config: defaults < YAML file < env vars < CLI flags
+    let cli = config::Cli::parse();
+    let config = config::ExporterConfig::load(&cli)?;
+
+    // Initialize tracing (and OTLP span export if configured)
+    let tracer_provider = telemetry::init_tracing(&config.instance_id);
+
+    match cli.config_path() {
+        Some(path) => info!(path = %path.display(), "Loaded config from file + environment"),
+        None => info!("Loaded config from environment variables"),
+    }
 
     info!(
         fab = %config.fab_id,
         instance = %config.instance_id,
         brokers = ?config.kafka_brokers,
+        election = %config.election_backend,
         "Exporter service starting..."
     );
 
@@ -61,11 +65,31 @@ async fn main() -> anyhow::Result<()> {
         api::run_http_server(state_http).await;
     });
 
+    // ── 1b. Reload tuning on SIGHUP ──
+    tokio::spawn(admin::reload_on_sighup(Arc::clone(&state), cli));
+
     // ── 2. Start Leader Election ──
     let state_leader = Arc::clone(&state);
-    let leader_handle = tokio::spawn(async move {
-        leader::leader_election_loop(state_leader).await;
-    });
+    let leader_handle = match state.config.election_backend {
+        config::ElectionBackend::Kafka => {
+            let elector = leader::kafka::KafkaElector::new(&state.config.kafka_brokers)?;
+            tokio::spawn(leader::leader_election_loop(state_leader, elector))
+        }
+        config::ElectionBackend::File => {
+            let dir = state.config.election_dir.as_deref().unwrap_or_default();
+            let elector = leader::file_lock::FileLockElector::new(dir, &state.config.instance_id)?;
+            tokio::spawn(leader::leader_election_loop(state_leader, elector))
+        }
+        config::ElectionBackend::Lease => {
+            let endpoint = state.config.election_lease_endpoint.as_deref().unwrap_or_default();
+            let elector = leader::lease::LeaseElector::new(
+                endpoint,
+                &state.config.instance_id,
+                Duration::from_secs(state.config.election_lease_ttl_secs),
+            )?;
+            tokio::spawn(leader::leader_election_loop(state_leader, elector))
+        }
+    };
 
     // ── 3. Lifecycle loop: wait for promotion, run workers, handle demotion ──
     //
@@ -128,5 +152,11 @@ async fn main() -> anyhow::Result<()> {
         _ = lifecycle_handle => error!("Lifecycle loop exited unexpectedly"),
     }
 
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            warn!(error = %e, "Failed to flush trace spans");
+        }
+    }
+
     Ok(())
 }
@@ -0,0 +1,302 @@
+/// Prometheus metrics registry for the exporter data path.
+///
+/// Every metric carries a constant `instance` label so that Active and
+/// Standby can be scraped into the same Prometheus without relabeling.
+/// The `path` label distinguishes the realtime consumer from the backfill
+/// engine (`"realtime"` / `"backfill"`).
+use std::time::Duration;
+
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::Offset;
+
+use crate::state::{ConnectionState, HaRole};
+
+/// Upload latency buckets (seconds): 10ms .. 30s, covering both the 10s
+/// realtime and the 30s backfill HTTP timeouts.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+
+    pub ha_role: IntGauge,
+    pub connection_state: IntGauge,
+    pub leader_claim_age_seconds: Gauge,
+
+    pub records_consumed: IntCounterVec,
+    pub records_rejected: IntCounterVec,
+    pub records_aggregated: IntCounterVec,
+    pub aggregates_sent: IntCounterVec,
+    pub dead_letter_failures: IntCounterVec,
+    pub batches_sent: IntCounterVec,
+    pub batches_failed: IntCounterVec,
+    pub batch_latency_seconds: HistogramVec,
+    pub bytes_uploaded: IntCounterVec,
+    pub consumer_lag: IntGaugeVec,
+
+    pub backfill_active: IntGauge,
+    pub backfill_remaining_records: IntGauge,
+}
+
+impl Metrics {
+    pub fn new(instance_id: &str) -> Self {
+        let labels = [("instance".to_string(), instance_id.to_string())]
+            .into_iter()
+            .collect();
+        let registry = Registry::new_custom(None, Some(labels))
+            .expect("Registry with constant labels cannot fail");
+
+        let ha_role = IntGauge::new(
+            "exporter_ha_role",
+            "Current HA role (1=active, 0=standby)",
+        )
+        .unwrap();
+        let connection_state = IntGauge::new(
+            "exporter_connection_state",
+            "Connection state (0=connected, 1=disconnected, 2=backfill)",
+        )
+        .unwrap();
+        let leader_claim_age_seconds = Gauge::new(
+            "exporter_leader_claim_age_seconds",
+            "Age of the most recent leader claim seen on __exporter_leader (-1 if none)",
+        )
+        .unwrap();
+
+        let records_consumed = IntCounterVec::new(
+            Opts::new(
+                "exporter_records_consumed_total",
+                "Metric records consumed from Kafka",
+            ),
+            &["path", "topic"],
+        )
+        .unwrap();
+        let records_rejected = IntCounterVec::new(
+            Opts::new(
+                "exporter_records_rejected_total",
+                "Records that failed validation and were routed to the dead-letter topic",
+            ),
+            &["path", "reason"],
+        )
+        .unwrap();
+        let records_aggregated = IntCounterVec::new(
+            Opts::new(
+                "exporter_records_aggregated_total",
+                "Records folded into edge aggregates instead of being uploaded raw",
+            ),
+            &["path"],
+        )
+        .unwrap();
+        let aggregates_sent = IntCounterVec::new(
+            Opts::new(
+                "exporter_aggregates_sent_total",
+                "Window aggregates acknowledged by the cloud endpoint",
+            ),
+            &["path"],
+        )
+        .unwrap();
+        let dead_letter_failures = IntCounterVec::new(
+            Opts::new(
+                "exporter_dead_letter_failures_total",
+                "Failed attempts to write a rejected record to the dead-letter topic",
+            ),
+            &["path"],
+        )
+        .unwrap();
+        let batches_sent = IntCounterVec::new(
+            Opts::new(
+                "exporter_batches_sent_total",
+                "Batches acknowledged by the cloud endpoint",
+            ),
+            &["path"],
+        )
+        .unwrap();
+        let batches_failed = IntCounterVec::new(
+            Opts::new(
+                "exporter_batches_failed_total",
+                "Batches rejected by the cloud endpoint or failed to send",
+            ),
+            &["path"],
+        )
+        .unwrap();
+        let batch_latency_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "exporter_batch_latency_seconds",
+                "Time from sending a batch to receiving the cloud response",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["path"],
+        )
+        .unwrap();
+        let bytes_uploaded = IntCounterVec::new(
+            Opts::new(
+                "exporter_bytes_uploaded_total",
+                "Payload bytes acknowledged by the cloud endpoint",
+            ),
+            &["path"],
+        )
+        .unwrap();
+        let consumer_lag = IntGaugeVec::new(
+            Opts::new(
+                "exporter_consumer_lag",
+                "High watermark minus consumer position, per partition",
+            ),
+            &["group", "topic", "partition"],
+        )
+        .unwrap();
+
+        let backfill_active = IntGauge::new(
+            "exporter_backfill_active",
+            "1 while the backfill engine is replaying, 0 otherwise",
+        )
+        .unwrap();
+        let backfill_remaining_records = IntGauge::new(
+            "exporter_backfill_remaining_records",
+            "Records left to replay, summed over all backfill partitions",
+        )
+        .unwrap();
+
+        registry.register(Box::new(ha_role.clone())).unwrap();
+        registry.register(Box::new(connection_state.clone())).unwrap();
+        registry.register(Box::new(leader_claim_age_seconds.clone())).unwrap();
+        registry.register(Box::new(records_consumed.clone())).unwrap();
+        registry.register(Box::new(records_rejected.clone())).unwrap();
+        registry.register(Box::new(records_aggregated.clone())).unwrap();
+        registry.register(Box::new(aggregates_sent.clone())).unwrap();
+        registry.register(Box::new(dead_letter_failures.clone())).unwrap();
+        registry.register(Box::new(batches_sent.clone())).unwrap();
+        registry.register(Box::new(batches_failed.clone())).unwrap();
+        registry.register(Box::new(batch_latency_seconds.clone())).unwrap();
+        registry.register(Box::new(bytes_uploaded.clone())).unwrap();
+        registry.register(Box::new(consumer_lag.clone())).unwrap();
+        registry.register(Box::new(backfill_active.clone())).unwrap();
+        registry.register(Box::new(backfill_remaining_records.clone())).unwrap();
+
+        Metrics {
+            registry,
+            ha_role,
+            connection_state,
+            leader_claim_age_seconds,
+            records_consumed,
+            records_rejected,
+            records_aggregated,
+            aggregates_sent,
+            dead_letter_failures,
+            batches_sent,
+            batches_failed,
+            batch_latency_seconds,
+            bytes_uploaded,
+            consumer_lag,
+            backfill_active,
+            backfill_remaining_records,
+        }
+    }
+
+    /// Update the gauges that mirror shared state. Called at scrape time.
+    pub fn set_role(&self, role: HaRole) {
+        self.ha_role.set(match role {
+            HaRole::Active => 1,
+            HaRole::Standby => 0,
+        });
+    }
+
+    pub fn set_connection_state(&self, conn: ConnectionState) {
+        self.connection_state.set(match conn {
+            ConnectionState::Connected => 0,
+            ConnectionState::Disconnected => 1,
+            ConnectionState::Backfilling => 2,
+        });
+    }
+
+    /// Render all registered metrics in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .expect("Prometheus text encoding cannot fail");
+        String::from_utf8(buf).expect("Prometheus text output is UTF-8")
+    }
+}
+
+/// Refresh `exporter_consumer_lag` for every partition assigned to `consumer`
+/// and return the total lag across them.
+///
+/// `fetch_watermarks` is a blocking broker round-trip, so callers should
+/// throttle this (the workers call it every few seconds, not per message).
+pub fn record_consumer_lag(metrics: &Metrics, group: &str, consumer: &StreamConsumer) -> i64 {
+    let position = match consumer.position() {
+        Ok(tpl) => tpl,
+        Err(_) => return 0,
+    };
+
+    let mut total = 0;
+    tokio::task::block_in_place(|| {
+        for elem in position.elements() {
+            let (low, high) = match consumer.fetch_watermarks(
+                elem.topic(),
+                elem.partition(),
+                Duration::from_millis(500),
+            ) {
+                Ok(w) => w,
+                Err(_) => continue,
+            };
+            // No committed position yet means everything from the low
+            // watermark is still pending.
+            let lag = match elem.offset() {
+                Offset::Offset(pos) => (high - pos).max(0),
+                _ => (high - low).max(0),
+            };
+            metrics
+                .consumer_lag
+                .with_label_values(&[group, elem.topic(), &elem.partition().to_string()])
+                .set(lag);
+            total += lag;
+        }
+    });
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_includes_instance_label() {
+        let metrics = Metrics::new("test-1");
+        metrics.set_role(HaRole::Active);
+        let text = metrics.encode();
+        assert!(text.contains("exporter_ha_role{instance=\"test-1\"} 1"));
+    }
+
+    #[test]
+    fn test_connection_state_values() {
+        let metrics = Metrics::new("test-1");
+        metrics.set_connection_state(ConnectionState::Backfilling);
+        assert_eq!(metrics.connection_state.get(), 2);
+        metrics.set_connection_state(ConnectionState::Disconnected);
+        assert_eq!(metrics.connection_state.get(), 1);
+    }
+
+    #[test]
+    fn test_path_labelled_counters() {
+        let metrics = Metrics::new("test-1");
+        metrics.batches_sent.with_label_values(&["realtime"]).inc();
+        metrics
+            .bytes_uploaded
+            .with_label_values(&["backfill"])
+            .inc_by(1024);
+        metrics
+            .batch_latency_seconds
+            .with_label_values(&["realtime"])
+            .observe(0.2);
+
+        let text = metrics.encode();
+        assert!(text.contains("exporter_batches_sent_total{path=\"realtime\",instance=\"test-1\"} 1"));
+        assert!(text.contains("exporter_bytes_uploaded_total{path=\"backfill\",instance=\"test-1\"} 1024"));
+        assert!(text.contains("exporter_batch_latency_seconds_count{path=\"realtime\",instance=\"test-1\"} 1"));
+    }
+}
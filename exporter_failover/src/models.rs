@@ -20,3 +20,33 @@ pub struct MetricsBatch {
     pub records: Vec<MetricRecord>,
     pub compressed: bool,
 }
+
+/// Summary of one `equipment_id` / `metric_id` series over a tumbling window.
+///
+/// Aggregates for the same series and window are mergeable (min of mins,
+/// max of maxes, count-weighted avg, `last` with the newest
+/// `last_timestamp`): a window cut by a batch boundary is uploaded as more
+/// than one partial aggregate.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricAggregate {
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub equipment_id: String,
+    pub metric_id: String,
+    pub unit: String,
+    pub line_id: String,
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub last: f64,
+    pub last_timestamp: DateTime<Utc>,
+}
+
+/// A batch of aggregates to be sent to the cloud (`/ingest/aggregates`).
+#[derive(Debug, Serialize)]
+pub struct AggregatesBatch {
+    pub fab_id: String,
+    pub batch_id: String,
+    pub aggregates: Vec<MetricAggregate>,
+}
@@ -6,18 +6,32 @@
 /// - Pauses when WAN is disconnected (Kafka buffers automatically)
 /// - At-least-once delivery: commit offset only after cloud confirms receipt
 /// - Adaptive batch size based on connection state
+/// - Each upload runs inside an `upload_batch` span (exported via OTLP if enabled)
+/// - Records failing validation are routed to the dead-letter topic; offsets
+///   are never committed past one whose dead letter failed
+/// - Records from `aggregate_topics` are uploaded as window aggregates
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
+use chrono::Utc;
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::error::KafkaResult;
 use rdkafka::message::Message;
-use tracing::{error, info, warn};
+use rdkafka::{Offset, TopicPartitionList};
+use tracing::{error, info, info_span, warn, Instrument};
 
+use crate::aggregation::{upload_aggregates, Aggregator};
+use crate::dlq::{accept_record, rewind, DeadLetterSink};
 use crate::leader::check_still_active;
-use crate::models::{MetricRecord, MetricsBatch};
+use crate::metrics::record_consumer_lag;
+use crate::models::{MetricAggregate, MetricRecord, MetricsBatch};
 use crate::state::{ConnectionState, SharedState};
 
+/// How often to refresh the per-partition consumer lag gauges.
+const LAG_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
 pub async fn realtime_consumer(state: Arc<SharedState>) {
     let brokers = state.config.kafka_brokers.join(",");
 
@@ -40,6 +54,10 @@ pub async fn realtime_consumer(state: Arc<SharedState>) {
         .subscribe(&["metrics.alarm", "metrics.key", "metrics.raw"])
         .expect("Failed to subscribe to metrics topics");
 
+    let rules = state.config.validation_rules();
+    let dlq = DeadLetterSink::new(&state.config.kafka_brokers, &state.config.dlq_topic)
+        .expect("Failed to create dead-letter producer");
+
     info!(instance = %state.config.instance_id, "Realtime consumer started");
 
     let http_client = reqwest::Client::builder()
@@ -48,9 +66,15 @@ pub async fn realtime_consumer(state: Arc<SharedState>) {
         .build()
         .unwrap();
 
-    let mut batch: Vec<MetricRecord> = Vec::with_capacity(state.config.normal_batch_size);
+    let mut batch: Vec<MetricRecord> = Vec::with_capacity(state.tuning().normal_batch_size);
     let mut last_flush = tokio::time::Instant::now();
     let mut was_disconnected = false;
+    let mut last_lag_refresh = tokio::time::Instant::now();
+    let mut aggregator = Aggregator::new(
+        &state.config.aggregate_topics,
+        Duration::from_secs(state.config.aggregate_window_secs),
+    );
+    let mut ready_aggregates: Vec<MetricAggregate> = Vec::new();
 
     loop {
         // Check if we are still Active
@@ -66,13 +90,31 @@ pub async fn realtime_consumer(state: Arc<SharedState>) {
 
         // IMPORTANT: Always poll Kafka to keep the consumer's fetch pipeline alive. Skipping recv() during WAN disconnect causes the internal fetch state to go stale, preventing message delivery after recovery.
         match tokio::time::timeout(Duration::from_millis(100), consumer.recv()).await {
-            Ok(Ok(msg)) => {
-                if let Some(payload) = msg.payload() {
-                    if let Ok(record) = serde_json::from_slice::<MetricRecord>(payload) {
+            Ok(Ok(msg)) => match accept_record(&msg, "realtime", &rules, &dlq, &state).await {
+                Ok(Some(record)) => {
+                    let aggregate_all = state.tuning().backfill_aggregate_only
+                        && state.get_connection_state() == ConnectionState::Backfilling;
+                    if aggregator.applies_to(msg.topic(), aggregate_all) {
+                        aggregator.add(&record, msg.topic(), msg.partition(), msg.offset());
+                        state
+                            .metrics
+                            .records_aggregated
+                            .with_label_values(&["realtime"])
+                            .inc();
+                    } else {
                         batch.push(record);
                     }
                 }
-            }
+                Ok(None) => {}
+                Err(_) => {
+                    // The dead letter was not written: consume the record again
+                    // so the next commit cannot skip it.
+                    if let Err(e) = rewind(&consumer, &msg) {
+                        error!("Rewind to unwritten dead letter failed, restarting: {}", e);
+                        return;
+                    }
+                }
+            },
             Ok(Err(e)) => {
                 warn!("Kafka recv error: {}", e);
             }
@@ -81,6 +123,11 @@ pub async fn realtime_consumer(state: Arc<SharedState>) {
             }
         }
 
+        if last_lag_refresh.elapsed() >= LAG_REFRESH_INTERVAL {
+            record_consumer_lag(&state.metrics, "rt-metrics", &consumer);
+            last_lag_refresh = tokio::time::Instant::now();
+        }
+
         // Check connection state AFTER polling Kafka
         let conn_state = state.get_connection_state();
         if conn_state == ConnectionState::Disconnected {
@@ -93,6 +140,8 @@ pub async fn realtime_consumer(state: Arc<SharedState>) {
             // them from its independent consumer group on WAN recovery.
             // We don't commit offsets, so a process restart would also replay.
             batch.clear();
+            aggregator.clear();
+            ready_aggregates.clear();
             last_flush = tokio::time::Instant::now();
             continue;
         }
@@ -108,57 +157,142 @@ pub async fn realtime_consumer(state: Arc<SharedState>) {
 
         let should_flush =
             batch.len() >= target_size || elapsed >= Duration::from_secs(target_duration);
+        if should_flush && !aggregator.is_empty() {
+            ready_aggregates.extend(aggregator.drain_closed(Utc::now()));
+        }
 
-        if should_flush && !batch.is_empty() {
-            let payload = MetricsBatch {
-                fab_id: state.config.fab_id.clone(),
-                batch_id: uuid::Uuid::new_v4().to_string(),
-                records: std::mem::take(&mut batch),
-                compressed: false,
-            };
-
-            let json = serde_json::to_vec(&payload).unwrap();
-            let url = format!("{}/ingest/metrics", state.config.aws_endpoint);
-
-            match http_client.post(&url).body(json).send().await {
-                Ok(resp) if resp.status().is_success() => {
-                    // Successfully delivered. Commit Kafka offset.
-                    if let Err(e) = consumer.commit_consumer_state(CommitMode::Async) {
-                        warn!("Failed to commit offset: {}", e);
-                    }
-                    info!(
-                        batch_size = payload.records.len(),
-                        batch_id = %payload.batch_id,
-                        "Batch sent successfully"
-                    );
-                    last_flush = tokio::time::Instant::now();
-                }
-                Ok(resp) => {
-                    error!(status = %resp.status(), "Cloud returned error, will retry");
-                    batch = payload.records;
-                }
-                Err(e) => {
-                    error!(error = %e, "Failed to send batch to cloud");
-                    batch = payload.records;
+        if should_flush && !(batch.is_empty() && ready_aggregates.is_empty()) {
+            let records_sent =
+                batch.is_empty() || send_batch(&http_client, &state, &mut batch).await;
+            let aggregates_sent = records_sent
+                && (ready_aggregates.is_empty()
+                    || match upload_aggregates(
+                        &http_client,
+                        &state,
+                        "realtime",
+                        std::mem::take(&mut ready_aggregates),
+                    )
+                    .await
+                    {
+                        Ok(_) => true,
+                        Err(aggregates) => {
+                            ready_aggregates = aggregates;
+                            false
+                        }
+                    });
+
+            if records_sent && aggregates_sent {
+                // Successfully delivered. Commit Kafka offsets, holding back
+                // partitions whose records are still in an open window.
+                if let Err(e) = commit_offsets(&consumer, &aggregator.pending_offsets()) {
+                    warn!("Failed to commit offset: {}", e);
                 }
+                last_flush = tokio::time::Instant::now();
             }
+        } else if should_flush {
+            // Nothing to send and every window still open: check again after a
+            // full interval instead of draining the aggregator on every message.
+            last_flush = tokio::time::Instant::now();
+        }
+    }
+}
+
+/// Upload `batch` to `/ingest/metrics`.  On failure the records are put back
+/// into `batch` for the next attempt.
+async fn send_batch(
+    http_client: &reqwest::Client,
+    state: &SharedState,
+    batch: &mut Vec<MetricRecord>,
+) -> bool {
+    let payload = MetricsBatch {
+        fab_id: state.config.fab_id.clone(),
+        batch_id: uuid::Uuid::new_v4().to_string(),
+        records: std::mem::take(batch),
+        compressed: false,
+    };
+
+    let json = serde_json::to_vec(&payload).unwrap();
+    let json_len = json.len() as u64;
+    let url = format!("{}/ingest/metrics", state.config.aws_endpoint);
+
+    let span = info_span!(
+        "upload_batch",
+        path = "realtime",
+        batch_id = %payload.batch_id,
+        records = payload.records.len(),
+        bytes = json_len,
+    );
+    let timer = state
+        .metrics
+        .batch_latency_seconds
+        .with_label_values(&["realtime"])
+        .start_timer();
+    let result = http_client.post(&url).body(json).send().instrument(span.clone()).await;
+    timer.observe_duration();
+
+    match result {
+        Ok(resp) if resp.status().is_success() => {
+            state.metrics.batches_sent.with_label_values(&["realtime"]).inc();
+            state
+                .metrics
+                .bytes_uploaded
+                .with_label_values(&["realtime"])
+                .inc_by(json_len);
+            info!(
+                parent: &span,
+                batch_size = payload.records.len(),
+                batch_id = %payload.batch_id,
+                "Batch sent successfully"
+            );
+            true
+        }
+        Ok(resp) => {
+            state.metrics.batches_failed.with_label_values(&["realtime"]).inc();
+            error!(parent: &span, status = %resp.status(), "Cloud returned error, will retry");
+            *batch = payload.records;
+            false
+        }
+        Err(e) => {
+            state.metrics.batches_failed.with_label_values(&["realtime"]).inc();
+            error!(parent: &span, error = %e, "Failed to send batch to cloud");
+            *batch = payload.records;
+            false
         }
     }
 }
 
+/// Commit the consumer position, except on partitions where `pending` holds
+/// an older offset (records folded into a window that is not uploaded yet).
+pub fn commit_offsets(
+    consumer: &StreamConsumer,
+    pending: &HashMap<(String, i32), i64>,
+) -> KafkaResult<()> {
+    if pending.is_empty() {
+        return consumer.commit_consumer_state(CommitMode::Async);
+    }
+    let mut offsets = TopicPartitionList::new();
+    for elem in consumer.position()?.elements() {
+        let held = pending.get(&(elem.topic().to_string(), elem.partition()));
+        let offset = match (held, elem.offset()) {
+            (Some(&held), _) => Offset::Offset(held),
+            (None, Offset::Offset(position)) => Offset::Offset(position),
+            _ => continue,
+        };
+        offsets.add_partition_offset(elem.topic(), elem.partition(), offset)?;
+    }
+    consumer.commit(&offsets, CommitMode::Async)
+}
+
 /// Get adaptive batch parameters based on current connection state.
+/// Read from the live tuning so admin/SIGHUP changes apply on the next flush.
 fn get_batch_params(state: &SharedState) -> (usize, u64) {
-    let conn_state = state.get_connection_state();
-    match conn_state {
-        ConnectionState::Connected => {
-            (state.config.normal_batch_size, state.config.normal_flush_secs)
-        }
+    let tuning = state.tuning();
+    match state.get_connection_state() {
+        ConnectionState::Connected => (tuning.normal_batch_size, tuning.normal_flush_secs),
         ConnectionState::Backfilling => {
             // During backfill, use larger batches to leave bandwidth for backfill
-            (state.config.slow_batch_size, state.config.slow_flush_secs)
-        }
-        ConnectionState::Disconnected => {
-            (state.config.normal_batch_size, state.config.normal_flush_secs)
+            (tuning.slow_batch_size, tuning.slow_flush_secs)
         }
+        ConnectionState::Disconnected => (tuning.normal_batch_size, tuning.normal_flush_secs),
     }
 }
@@ -4,7 +4,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::sync::{watch, Mutex, Notify};
 
-use crate::config::ExporterConfig;
+use crate::config::{ConfigError, ExporterConfig, TuningConfig, TuningUpdate};
+use crate::metrics::Metrics;
 
 /// Connection state machine: Connected -> Disconnected -> Backfilling -> Connected
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -40,7 +41,19 @@ impl std::fmt::Display for HaRole {
     }
 }
 
-/// JSON payload written to `__exporter_leader` for leader claim fencing.
+/// Operator command issued through `POST /admin/promote` / `/admin/demote`,
+/// consumed by the leader election loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// Promote as soon as we hold the election partition, skipping the
+    /// claim-freshness and peer checks.
+    Promote,
+    /// Step down now and release the election partition for `hold`.
+    Demote { hold: std::time::Duration },
+}
+
+/// Leader claim payload published through the election backend
+/// (e.g. written to `__exporter_leader`) for leader claim fencing.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeaderClaim {
     #[serde(rename = "type")]
@@ -49,6 +62,16 @@ pub struct LeaderClaim {
     pub ts: DateTime<Utc>,
 }
 
+impl LeaderClaim {
+    pub fn new(instance_id: &str, ts: DateTime<Utc>) -> Self {
+        LeaderClaim {
+            claim_type: "leader_claim".to_string(),
+            instance_id: instance_id.to_string(),
+            ts,
+        }
+    }
+}
+
 /// In-memory record of the most recent leader claim.
 #[derive(Debug, Clone)]
 pub struct LeaderClaimState {
@@ -62,21 +85,33 @@ pub struct SharedState {
     pub connection_rx: watch::Receiver<ConnectionState>,
     pub ha_role: Mutex<HaRole>,
     pub backfill_notify: Notify,
+    /// Startup configuration. Reloadable settings live in `tuning_tx`.
     pub config: ExporterConfig,
+    /// Current batch / backfill / bandwidth settings (hot-reloadable).
+    pub tuning_tx: watch::Sender<TuningConfig>,
+    /// Pending operator command for the leader election loop.
+    pub admin_command: Mutex<Option<AdminCommand>>,
     /// Tracks the most recent leader claim seen from any instance.
     pub last_known_leader: Mutex<Option<LeaderClaimState>>,
+    /// Prometheus registry for the data path, rendered by `GET /metrics`.
+    pub metrics: Metrics,
 }
 
 impl SharedState {
     pub fn new(config: ExporterConfig) -> Arc<Self> {
         let (conn_tx, conn_rx) = watch::channel(ConnectionState::Connected);
+        let metrics = Metrics::new(&config.instance_id);
+        let (tuning_tx, _) = watch::channel(config.tuning());
         Arc::new(SharedState {
             connection_tx: conn_tx,
             connection_rx: conn_rx,
             ha_role: Mutex::new(HaRole::Standby), // Start as Standby
             backfill_notify: Notify::new(),
             config,
+            tuning_tx,
+            admin_command: Mutex::new(None),
             last_known_leader: Mutex::new(None),
+            metrics,
         })
     }
 
@@ -88,6 +123,26 @@ impl SharedState {
         *self.connection_rx.borrow()
     }
 
+    pub fn tuning(&self) -> TuningConfig {
+        *self.tuning_tx.borrow()
+    }
+
+    /// Apply a partial tuning change. Invalid changes are rejected as a whole.
+    pub fn update_tuning(&self, update: &TuningUpdate) -> Result<TuningConfig, ConfigError> {
+        let tuning = update.apply(self.tuning())?;
+        self.tuning_tx.send_replace(tuning);
+        Ok(tuning)
+    }
+
+    /// Queue an operator command, replacing any command not yet consumed.
+    pub async fn set_admin_command(&self, cmd: AdminCommand) {
+        *self.admin_command.lock().await = Some(cmd);
+    }
+
+    pub async fn take_admin_command(&self) -> Option<AdminCommand> {
+        self.admin_command.lock().await.take()
+    }
+
     /// Update the last known leader claim. Only updates if the new claim is newer
     /// than the existing one (or if there is no existing claim).
     pub async fn update_leader_claim(&self, instance_id: &str, ts: DateTime<Utc>) {
@@ -113,6 +168,7 @@ impl SharedState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::ElectionBackend;
     use chrono::Utc;
 
     fn test_config() -> ExporterConfig {
@@ -126,12 +182,28 @@ mod tests {
             slow_batch_size: 500,
             slow_flush_secs: 15,
             backfill_bandwidth_cap_pct: 30,
+            backfill_batch_size: 1000,
+            wan_bandwidth_mbps: 1000,
+            backfill_aggregate_only: false,
+            aggregate_topics: Vec::new(),
+            aggregate_window_secs: 60,
             heartbeat_interval_secs: 5,
             failover_timeout_secs: 15,
             leader_claim_interval_secs: 3,
             http_port: 9090,
             peer_endpoint: None,
             startup_grace_secs: 8,
+            admin_token: None,
+            election_backend: ElectionBackend::Kafka,
+            election_dir: None,
+            election_lease_endpoint: None,
+            election_lease_ttl_secs: 10,
+            dlq_topic: "metrics.dlq".to_string(),
+            allowed_units: vec!["C".to_string(), "PSI".to_string()],
+            value_min: -1.0e9,
+            value_max: 1.0e9,
+            max_timestamp_age_secs: 7 * 24 * 3600,
+            max_timestamp_ahead_secs: 300,
         }
     }
 
@@ -186,6 +258,38 @@ mod tests {
         assert_eq!(state.get_role().await, HaRole::Standby);
     }
 
+    #[tokio::test]
+    async fn test_update_tuning_applies_valid_change() {
+        let state = SharedState::new(test_config());
+        let update = TuningUpdate {
+            normal_batch_size: Some(250),
+            slow_batch_size: Some(1000),
+            ..Default::default()
+        };
+        state.update_tuning(&update).unwrap();
+        assert_eq!(state.tuning().normal_batch_size, 250);
+        assert_eq!(state.tuning().slow_batch_size, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_update_tuning_rejects_invalid_change() {
+        let state = SharedState::new(test_config());
+        let update = TuningUpdate {
+            normal_batch_size: Some(0),
+            ..Default::default()
+        };
+        assert!(state.update_tuning(&update).is_err());
+        assert_eq!(state.tuning().normal_batch_size, 100);
+    }
+
+    #[tokio::test]
+    async fn test_admin_command_is_consumed_once() {
+        let state = SharedState::new(test_config());
+        state.set_admin_command(AdminCommand::Promote).await;
+        assert_eq!(state.take_admin_command().await, Some(AdminCommand::Promote));
+        assert_eq!(state.take_admin_command().await, None);
+    }
+
     #[tokio::test]
     async fn test_leader_claim_serde_roundtrip() {
         let claim = LeaderClaim {
@@ -0,0 +1,64 @@
+/// Tracing setup: console logs plus optional OpenTelemetry span export.
+///
+/// Spans are exported over OTLP/HTTP only when `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// is set (e.g. `http://otel-collector:4318`), so the default demo keeps
+/// running without a collector.
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Install the global tracing subscriber.
+///
+/// Returns the tracer provider when OTLP export is enabled; keep it alive for
+/// the life of the process and call `shutdown()` on exit to flush spans.
+pub fn init_tracing(instance_id: &str) -> Option<SdkTracerProvider> {
+    let filter = EnvFilter::from_default_env()
+        .add_directive("exporter_failover=info".parse().unwrap())
+        .add_directive("exporter=info".parse().unwrap())
+        .add_directive("rdkafka=warn".parse().unwrap());
+
+    let provider = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .and_then(|endpoint| match build_provider(&endpoint, instance_id) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                eprintln!("Failed to initialize OTLP exporter ({}): {}", endpoint, e);
+                None
+            }
+        });
+
+    let otel_layer = provider.as_ref().map(|p| {
+        tracing_opentelemetry::layer().with_tracer(p.tracer("exporter_failover"))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_target(true))
+        .with(otel_layer)
+        .init();
+
+    provider
+}
+
+fn build_provider(endpoint: &str, instance_id: &str) -> anyhow::Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+
+    let resource = Resource::builder()
+        .with_service_name("exporter")
+        .with_attribute(KeyValue::new("service.instance.id", instance_id.to_string()))
+        .build();
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build())
+}
@@ -0,0 +1,265 @@
+/// Record validation for the realtime and backfill paths.
+///
+/// A Kafka payload becomes a `MetricRecord` only if it:
+///   1. parses as JSON with every required field present,
+///   2. has non-blank `equipment_id` / `metric_id` / `unit` / `line_id`,
+///   3. uses a whitelisted unit,
+///   4. has a `value` inside the configured range,
+///   5. has a timestamp no older than `max_age` and no further than
+///      `max_ahead` in the future (clock skew on the equipment side).
+///
+/// Rejected records are routed to the dead-letter topic (see `dlq`) with the
+/// `ValidationError` as the reason.
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::models::MetricRecord;
+
+#[derive(Debug, Clone)]
+pub struct ValidationRules {
+    allowed_units: HashSet<String>,
+    value_range: RangeInclusive<f64>,
+    max_age: chrono::Duration,
+    max_ahead: chrono::Duration,
+}
+
+/// Why a record was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// Not JSON, wrong types, or a required field is missing.
+    Malformed(String),
+    EmptyField(&'static str),
+    UnknownUnit(String),
+    OutOfRange {
+        value: f64,
+        min: f64,
+        max: f64,
+    },
+    TooOld {
+        age_secs: i64,
+    },
+    InFuture {
+        ahead_secs: i64,
+    },
+}
+
+impl ValidationError {
+    /// Stable short code, used as the `reason` metric label and in the
+    /// dead-letter envelope.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ValidationError::Malformed(_) => "malformed",
+            ValidationError::EmptyField(_) => "empty_field",
+            ValidationError::UnknownUnit(_) => "unknown_unit",
+            ValidationError::OutOfRange { .. } => "out_of_range",
+            ValidationError::TooOld { .. } => "timestamp_too_old",
+            ValidationError::InFuture { .. } => "timestamp_in_future",
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Malformed(e) => write!(f, "malformed record: {}", e),
+            ValidationError::EmptyField(field) => write!(f, "field `{}` is empty", field),
+            ValidationError::UnknownUnit(unit) => write!(f, "unit '{}' is not allowed", unit),
+            ValidationError::OutOfRange { value, min, max } => {
+                write!(f, "value {} outside [{}, {}]", value, min, max)
+            }
+            ValidationError::TooOld { age_secs } => {
+                write!(f, "timestamp is {}s old", age_secs)
+            }
+            ValidationError::InFuture { ahead_secs } => {
+                write!(f, "timestamp is {}s in the future", ahead_secs)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl ValidationRules {
+    pub fn new(
+        allowed_units: &[String],
+        value_range: RangeInclusive<f64>,
+        max_age: Duration,
+        max_ahead: Duration,
+    ) -> Self {
+        ValidationRules {
+            allowed_units: allowed_units.iter().cloned().collect(),
+            value_range,
+            max_age: chrono::Duration::from_std(max_age).unwrap_or(chrono::Duration::MAX),
+            max_ahead: chrono::Duration::from_std(max_ahead).unwrap_or(chrono::Duration::MAX),
+        }
+    }
+
+    /// The same rules without the timestamp window (check 5).
+    pub fn ignoring_age(mut self) -> Self {
+        self.max_age = chrono::Duration::MAX;
+        self.max_ahead = chrono::Duration::MAX;
+        self
+    }
+
+    /// Parse and check a raw Kafka payload.
+    pub fn validate(
+        &self,
+        payload: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<MetricRecord, ValidationError> {
+        let record: MetricRecord = serde_json::from_slice(payload)
+            .map_err(|e| ValidationError::Malformed(e.to_string()))?;
+        self.check(&record, now)?;
+        Ok(record)
+    }
+
+    pub fn check(&self, record: &MetricRecord, now: DateTime<Utc>) -> Result<(), ValidationError> {
+        for (field, value) in [
+            ("equipment_id", &record.equipment_id),
+            ("metric_id", &record.metric_id),
+            ("unit", &record.unit),
+            ("line_id", &record.line_id),
+        ] {
+            if value.trim().is_empty() {
+                return Err(ValidationError::EmptyField(field));
+            }
+        }
+
+        if !self.allowed_units.contains(&record.unit) {
+            return Err(ValidationError::UnknownUnit(record.unit.clone()));
+        }
+
+        if !self.value_range.contains(&record.value) {
+            return Err(ValidationError::OutOfRange {
+                value: record.value,
+                min: *self.value_range.start(),
+                max: *self.value_range.end(),
+            });
+        }
+
+        let age = now - record.timestamp;
+        if age > self.max_age {
+            return Err(ValidationError::TooOld {
+                age_secs: age.num_seconds(),
+            });
+        }
+        if -age > self.max_ahead {
+            return Err(ValidationError::InFuture {
+                ahead_secs: (-age).num_seconds(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules() -> ValidationRules {
+        ValidationRules::new(
+            &["C".to_string(), "PSI".to_string()],
+            -100.0..=100.0,
+            Duration::from_secs(3600),
+            Duration::from_secs(60),
+        )
+    }
+
+    fn payload(now: DateTime<Utc>) -> serde_json::Value {
+        json!({
+            "timestamp": now,
+            "equipment_id": "CMP-A-001",
+            "metric_id": "temperature",
+            "value": 21.5,
+            "unit": "C",
+            "line_id": "LINE-C",
+        })
+    }
+
+    fn check(
+        value: serde_json::Value,
+        now: DateTime<Utc>,
+    ) -> Result<MetricRecord, ValidationError> {
+        rules().validate(&serde_json::to_vec(&value).unwrap(), now)
+    }
+
+    #[test]
+    fn test_valid_record_passes() {
+        let now = Utc::now();
+        let record = check(payload(now), now).unwrap();
+        assert_eq!(record.equipment_id, "CMP-A-001");
+    }
+
+    #[test]
+    fn test_missing_field_is_malformed() {
+        let now = Utc::now();
+        let mut value = payload(now);
+        value.as_object_mut().unwrap().remove("unit");
+        let err = check(value, now).unwrap_err();
+        assert_eq!(err.reason(), "malformed");
+        assert!(err.to_string().contains("unit"));
+    }
+
+    #[test]
+    fn test_not_json_is_malformed() {
+        let err = rules().validate(b"heartbeat", Utc::now()).unwrap_err();
+        assert_eq!(err.reason(), "malformed");
+    }
+
+    #[test]
+    fn test_blank_field_is_rejected() {
+        let now = Utc::now();
+        let mut value = payload(now);
+        value["equipment_id"] = json!("  ");
+        assert_eq!(
+            check(value, now).unwrap_err(),
+            ValidationError::EmptyField("equipment_id")
+        );
+    }
+
+    #[test]
+    fn test_unit_whitelist() {
+        let now = Utc::now();
+        let mut value = payload(now);
+        value["unit"] = json!("furlong");
+        assert_eq!(
+            check(value, now).unwrap_err(),
+            ValidationError::UnknownUnit("furlong".to_string())
+        );
+    }
+
+    #[test]
+    fn test_value_range_is_inclusive() {
+        let now = Utc::now();
+        let mut value = payload(now);
+        value["value"] = json!(100.0);
+        assert!(check(value.clone(), now).is_ok());
+        value["value"] = json!(100.5);
+        assert_eq!(check(value, now).unwrap_err().reason(), "out_of_range");
+    }
+
+    #[test]
+    fn test_timestamp_skew_limits() {
+        let now = Utc::now();
+        let old = payload(now - chrono::Duration::hours(2));
+        assert_eq!(
+            check(old, now).unwrap_err(),
+            ValidationError::TooOld { age_secs: 7200 }
+        );
+
+        let ahead = payload(now + chrono::Duration::seconds(30));
+        assert!(check(ahead, now).is_ok(), "small forward skew is tolerated");
+
+        let future = payload(now + chrono::Duration::minutes(5));
+        assert_eq!(
+            check(future, now).unwrap_err(),
+            ValidationError::InFuture { ahead_secs: 300 }
+        );
+    }
+}
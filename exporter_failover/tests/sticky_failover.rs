@@ -1,15 +1,24 @@
 /// Integration tests for sticky primary failover.
 ///
-/// These tests exercise multi-step failover scenarios using the pure
-/// `should_promote` logic and `SharedState` claim tracking — no Kafka
-/// broker required.
-use std::time::Duration;
+/// Scenarios 1–10 exercise the pure `should_promote` logic and `SharedState`
+/// claim tracking.  The backend scenarios at the bottom replay each of them
+/// (plus a manual handover) through `ElectionDriver` against every
+/// `LeaderElector`: file-lock and lease run in-process; Kafka needs a broker
+/// and is ignored by default:
+///
+///   KAFKA_BROKERS=localhost:9092 cargo test --test sticky_failover -- --ignored --test-threads=1
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use chrono::{Duration as ChronoDuration, Utc};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
 
-use exporter_failover::config::ExporterConfig;
-use exporter_failover::leader::should_promote;
-use exporter_failover::state::{LeaderClaimState, SharedState};
+use exporter_failover::config::{ElectionBackend, ExporterConfig};
+use exporter_failover::leader::file_lock::FileLockElector;
+use exporter_failover::leader::kafka::KafkaElector;
+use exporter_failover::lease_service::{self, LeaseStore};
+use exporter_failover::leader::lease::LeaseElector;
+use exporter_failover::leader::{should_promote, ElectionDriver, LeaderElector};
+use exporter_failover::state::{AdminCommand, HaRole, LeaderClaim, LeaderClaimState, SharedState};
 
 fn test_config(instance_id: &str) -> ExporterConfig {
     ExporterConfig {
@@ -22,12 +31,28 @@ fn test_config(instance_id: &str) -> ExporterConfig {
         slow_batch_size: 500,
         slow_flush_secs: 15,
         backfill_bandwidth_cap_pct: 30,
+        backfill_batch_size: 1000,
+        wan_bandwidth_mbps: 1000,
+        backfill_aggregate_only: false,
+        aggregate_topics: Vec::new(),
+        aggregate_window_secs: 60,
         heartbeat_interval_secs: 5,
         failover_timeout_secs: 15,
         leader_claim_interval_secs: 3,
         http_port: 9090,
         peer_endpoint: None,
         startup_grace_secs: 8,
+        admin_token: None,
+        election_backend: ElectionBackend::Kafka,
+        election_dir: None,
+        election_lease_endpoint: None,
+        election_lease_ttl_secs: 10,
+        dlq_topic: "metrics.dlq".to_string(),
+        allowed_units: vec!["C".to_string(), "PSI".to_string()],
+        value_min: -1.0e9,
+        value_max: 1.0e9,
+        max_timestamp_age_secs: 7 * 24 * 3600,
+        max_timestamp_ahead_secs: 300,
     }
 }
 
@@ -337,3 +362,505 @@ async fn scenario_both_fresh_start_no_claims_first_promotes() {
         "server-b should defer after server-a starts claiming"
     );
 }
+
+// ── Backend scenarios ───────────────────────────────────────────────────────
+
+const POLL: Duration = Duration::from_millis(10);
+/// Upper bound on waiting for a backend to hand over the slot (Kafka
+/// rebalances take seconds; the others are near-instant).
+const SLOT_WAIT: Duration = Duration::from_secs(30);
+
+trait Backend {
+    type Elector: LeaderElector;
+    fn join(&self, instance_id: &str) -> Self::Elector;
+}
+
+struct FileBackend {
+    dir: std::path::PathBuf,
+}
+
+impl FileBackend {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("exporter-election-{}", uuid::Uuid::new_v4()));
+        FileBackend { dir }
+    }
+}
+
+impl Drop for FileBackend {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Backend for FileBackend {
+    type Elector = FileLockElector;
+    fn join(&self, instance_id: &str) -> FileLockElector {
+        FileLockElector::new(&self.dir, instance_id).unwrap()
+    }
+}
+
+struct LeaseBackend {
+    endpoint: String,
+}
+
+impl LeaseBackend {
+    async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, lease_service::router(LeaseStore::new()))
+                .await
+                .unwrap();
+        });
+        LeaseBackend {
+            endpoint: format!("http://{}", addr),
+        }
+    }
+}
+
+impl Backend for LeaseBackend {
+    type Elector = LeaseElector;
+    fn join(&self, instance_id: &str) -> LeaseElector {
+        LeaseElector::new(&self.endpoint, instance_id, Duration::from_secs(1)).unwrap()
+    }
+}
+
+struct KafkaBackend {
+    brokers: Vec<String>,
+}
+
+impl KafkaBackend {
+    fn from_env() -> Self {
+        let brokers = std::env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS must be set");
+        KafkaBackend {
+            brokers: brokers.split(',').map(str::to_string).collect(),
+        }
+    }
+}
+
+impl Backend for KafkaBackend {
+    type Elector = KafkaElector;
+    fn join(&self, _instance_id: &str) -> KafkaElector {
+        KafkaElector::new(&self.brokers).unwrap()
+    }
+}
+
+/// One exporter instance: state + driver + backend connection.
+struct Node<E> {
+    state: Arc<SharedState>,
+    driver: ElectionDriver,
+    elector: E,
+}
+
+impl<E: LeaderElector> Node<E> {
+    fn start<B: Backend<Elector = E>>(backend: &B, instance_id: &str) -> Self {
+        Self::start_with_grace(backend, instance_id, 0)
+    }
+
+    fn start_with_grace<B: Backend<Elector = E>>(
+        backend: &B,
+        instance_id: &str,
+        startup_grace_secs: u64,
+    ) -> Self {
+        let mut config = test_config(instance_id);
+        config.startup_grace_secs = startup_grace_secs;
+        Node {
+            driver: ElectionDriver::new(&config, POLL),
+            elector: backend.join(instance_id),
+            state: SharedState::new(config),
+        }
+    }
+
+    async fn step(&mut self, now: DateTime<Utc>) {
+        self.driver.step(&mut self.elector, &self.state, now).await;
+    }
+
+    async fn steps(&mut self, n: usize, now: DateTime<Utc>) {
+        for _ in 0..n {
+            self.step(now).await;
+        }
+    }
+
+    async fn role(&self) -> HaRole {
+        self.state.get_role().await
+    }
+
+    async fn known_leader(&self) -> Option<String> {
+        self.state.get_leader_claim().await.map(|c| c.instance_id)
+    }
+
+    /// Poll the backend directly until we hold the slot, recording any
+    /// claims seen along the way.
+    async fn wait_for_slot(&mut self) {
+        let deadline = Instant::now() + SLOT_WAIT;
+        loop {
+            let poll = self.elector.poll(POLL).await.unwrap();
+            for claim in poll.claims {
+                self.state.update_leader_claim(&claim.instance_id, claim.ts).await;
+            }
+            if poll.holds_slot {
+                return;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for the election slot");
+        }
+    }
+}
+
+/// Start `id` alone and step it until it is Active.
+async fn start_active<B: Backend>(backend: &B, id: &str, now: DateTime<Utc>) -> Node<B::Elector> {
+    let mut node = Node::start(backend, id);
+    node.wait_for_slot().await;
+    node.step(now).await;
+    assert_eq!(node.role().await, HaRole::Active, "{} should be Active", id);
+    node
+}
+
+// Scenario 1
+async fn backend_normal_startup<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let a = start_active(&backend, "server-a", t0).await;
+    let claim = a.state.get_leader_claim().await;
+    assert_eq!(claim.map(|c| c.instance_id).as_deref(), Some("server-a"));
+}
+
+// Scenario 2
+async fn backend_standby_defers<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let _a = start_active(&backend, "server-a", t0).await;
+
+    let mut b = Node::start(&backend, "server-b");
+    b.steps(3, t0 + ChronoDuration::seconds(1)).await;
+    assert_eq!(
+        b.role().await,
+        HaRole::Standby,
+        "server-b must not promote while server-a is Active"
+    );
+}
+
+// Scenario 3
+async fn backend_failover_after_crash<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let a = start_active(&backend, "server-a", t0).await;
+    drop(a); // crash
+
+    let mut b = Node::start(&backend, "server-b");
+    b.wait_for_slot().await;
+
+    // server-a's claim is still fresh → defer
+    b.step(t0 + ChronoDuration::seconds(1)).await;
+    assert_eq!(b.role().await, HaRole::Standby, "claim still fresh, should defer");
+
+    // Claim expired → take over
+    b.step(t0 + ChronoDuration::seconds(20)).await;
+    assert_eq!(b.role().await, HaRole::Active, "should promote after claim expiry");
+}
+
+// Scenario 4
+async fn backend_sticky_primary<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let a = start_active(&backend, "server-a", t0).await;
+    drop(a);
+
+    let mut b = Node::start(&backend, "server-b");
+    b.wait_for_slot().await;
+    b.step(t0 + ChronoDuration::seconds(20)).await;
+    assert_eq!(b.role().await, HaRole::Active);
+
+    // server-a recovers with fresh state
+    let mut a = Node::start(&backend, "server-a");
+    a.steps(3, t0 + ChronoDuration::seconds(21)).await;
+    assert_eq!(
+        a.role().await,
+        HaRole::Standby,
+        "STICKY: old primary should stay Standby"
+    );
+
+    b.step(t0 + ChronoDuration::seconds(22)).await;
+    assert_eq!(b.role().await, HaRole::Active, "new primary keeps its role");
+}
+
+// Scenario 5
+async fn backend_double_failure<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let a = start_active(&backend, "server-a", t0).await;
+    drop(a);
+
+    let mut b = Node::start(&backend, "server-b");
+    b.wait_for_slot().await;
+    b.step(t0 + ChronoDuration::seconds(20)).await;
+    assert_eq!(b.role().await, HaRole::Active);
+    drop(b);
+
+    // A third instance only sees server-b's claim from T+20
+    let mut c = Node::start(&backend, "server-c");
+    c.wait_for_slot().await;
+    c.step(t0 + ChronoDuration::seconds(30)).await;
+    assert_eq!(
+        c.role().await,
+        HaRole::Standby,
+        "server-b's claim is still fresh"
+    );
+    c.step(t0 + ChronoDuration::seconds(36)).await;
+    assert_eq!(
+        c.role().await,
+        HaRole::Active,
+        "should promote once every claim has expired"
+    );
+}
+
+// Scenario 6
+async fn backend_gradual_claim_expiry<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let a = start_active(&backend, "server-a", t0).await;
+    drop(a);
+
+    let mut b = Node::start(&backend, "server-b");
+    b.wait_for_slot().await;
+    for secs in [5, 10, 14, 15] {
+        b.step(t0 + ChronoDuration::seconds(secs)).await;
+        assert_eq!(
+            b.role().await,
+            HaRole::Standby,
+            "at T+{}s server-a's claim should still be fresh",
+            secs
+        );
+    }
+    b.step(t0 + ChronoDuration::seconds(16)).await;
+    assert_eq!(
+        b.role().await,
+        HaRole::Active,
+        "at T+16s the claim has expired"
+    );
+}
+
+// Scenario 7
+async fn backend_claim_tracking<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let a = start_active(&backend, "server-a", t0).await;
+    // server-a loses the slot but keeps running (and publishing)
+    let Node {
+        elector: mut zombie,
+        ..
+    } = a;
+    zombie.release().await.unwrap();
+
+    let mut b = Node::start(&backend, "server-b");
+    b.wait_for_slot().await;
+    b.step(t0 + ChronoDuration::seconds(20)).await;
+    assert_eq!(b.role().await, HaRole::Active);
+
+    // A claim stamped before server-b took over must not replace its claim
+    zombie
+        .publish_claim(&LeaderClaim::new(
+            "server-a",
+            t0 + ChronoDuration::seconds(5),
+        ))
+        .await
+        .unwrap();
+    b.steps(3, t0 + ChronoDuration::seconds(21)).await;
+    let claim = b.state.get_leader_claim().await.unwrap();
+    assert_eq!(
+        (claim.instance_id.as_str(), claim.timestamp),
+        ("server-b", t0 + ChronoDuration::seconds(20)),
+        "Older claim should not overwrite newer claim"
+    );
+    assert_eq!(b.role().await, HaRole::Active);
+}
+
+// Scenario 8
+async fn backend_rapid_leader_transitions<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let a = start_active(&backend, "server-a", t0).await;
+    drop(a);
+
+    // server-b takes over at T+16 and crashes right away
+    let mut b = Node::start(&backend, "server-b");
+    b.wait_for_slot().await;
+    b.step(t0 + ChronoDuration::seconds(16)).await;
+    assert_eq!(b.role().await, HaRole::Active);
+    drop(b);
+
+    // server-a comes back at T+18 and defers to server-b's fresh claim
+    let mut a = Node::start(&backend, "server-a");
+    a.wait_for_slot().await;
+    a.step(t0 + ChronoDuration::seconds(18)).await;
+    assert_eq!(
+        a.role().await,
+        HaRole::Standby,
+        "server-a should defer to server-b's fresh claim"
+    );
+
+    a.step(t0 + ChronoDuration::seconds(33)).await;
+    assert_eq!(
+        a.role().await,
+        HaRole::Active,
+        "server-a should promote after server-b's claim expires"
+    );
+}
+
+// Scenario 9
+async fn backend_restart_during_grace<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let a = start_active(&backend, "server-a", t0).await;
+    drop(a);
+
+    let mut b = Node::start(&backend, "server-b");
+    b.wait_for_slot().await;
+    b.step(t0 + ChronoDuration::seconds(20)).await;
+    assert_eq!(b.role().await, HaRole::Active);
+    // The slot moves to the restarted server-a while server-b is still alive
+    b.elector.release().await.unwrap();
+
+    let mut a = Node::start_with_grace(&backend, "server-a", 8);
+    a.wait_for_slot().await;
+    a.steps(3, t0 + ChronoDuration::seconds(21)).await;
+    assert_eq!(a.role().await, HaRole::Standby, "no promotion during grace");
+    assert_eq!(a.known_leader().await.as_deref(), Some("server-b"));
+
+    // Grace over; server-b's claim (9s old) is still fresh
+    a.step(t0 + ChronoDuration::seconds(29)).await;
+    assert_eq!(
+        a.role().await,
+        HaRole::Standby,
+        "After grace period, restarted node should see fresh claim and stay standby"
+    );
+}
+
+// Scenario 10
+async fn backend_both_fresh_start<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let mut a = Node::start(&backend, "server-a");
+    let mut b = Node::start(&backend, "server-b");
+
+    // Whoever gets the slot first promotes
+    let deadline = Instant::now() + SLOT_WAIT;
+    while a.role().await == HaRole::Standby && b.role().await == HaRole::Standby {
+        assert!(Instant::now() < deadline, "timed out waiting for a leader");
+        a.step(t0).await;
+        b.step(t0).await;
+    }
+
+    // The other defers from then on
+    let t2 = t0 + ChronoDuration::seconds(2);
+    a.steps(3, t2).await;
+    b.steps(3, t2).await;
+    let (role_a, role_b) = (a.role().await, b.role().await);
+    assert_ne!(role_a, role_b, "exactly one instance should be Active");
+    let leader = if role_a == HaRole::Active { &a } else { &b };
+    assert_eq!(
+        leader.known_leader().await,
+        Some(leader.state.config.instance_id.clone())
+    );
+}
+
+async fn backend_manual_handover<B: Backend>(backend: B) {
+    let t0 = Utc::now();
+    let mut a = start_active(&backend, "server-a", t0).await;
+    let mut b = Node::start(&backend, "server-b");
+    b.step(t0).await;
+    assert_eq!(b.role().await, HaRole::Standby);
+
+    // Operator demotes server-a and promotes server-b
+    a.state
+        .set_admin_command(AdminCommand::Demote {
+            hold: Duration::from_secs(60),
+        })
+        .await;
+    a.step(t0 + ChronoDuration::seconds(1)).await;
+    assert_eq!(a.role().await, HaRole::Standby, "demoted instance steps down");
+
+    b.state.set_admin_command(AdminCommand::Promote).await;
+    b.wait_for_slot().await;
+    b.step(t0 + ChronoDuration::seconds(1)).await;
+    assert_eq!(
+        b.role().await,
+        HaRole::Active,
+        "forced promotion ignores server-a's fresh claim"
+    );
+
+    // server-a stays down for the hold period
+    a.steps(3, t0 + ChronoDuration::seconds(2)).await;
+    assert_eq!(a.role().await, HaRole::Standby);
+}
+
+macro_rules! backend_scenarios {
+    ($name:ident, $backend:expr $(, #[$attr:meta])*) => {
+        mod $name {
+            use super::*;
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn normal_startup_promotes() {
+                backend_normal_startup($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn standby_defers_to_active() {
+                backend_standby_defers($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn failover_after_crash() {
+                backend_failover_after_crash($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn sticky_primary() {
+                backend_sticky_primary($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn double_failure_eventual_promotion() {
+                backend_double_failure($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn gradual_claim_expiry() {
+                backend_gradual_claim_expiry($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn claim_tracking() {
+                backend_claim_tracking($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn rapid_leader_transitions() {
+                backend_rapid_leader_transitions($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn restart_during_grace_stays_standby() {
+                backend_restart_during_grace($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn both_fresh_start_first_promotes() {
+                backend_both_fresh_start($backend).await;
+            }
+
+            $(#[$attr])*
+            #[tokio::test]
+            async fn manual_handover() {
+                backend_manual_handover($backend).await;
+            }
+        }
+    };
+}
+
+backend_scenarios!(file_lock_backend, FileBackend::new());
+backend_scenarios!(lease_backend, LeaseBackend::start().await);
+backend_scenarios!(
+    kafka_backend,
+    KafkaBackend::from_env(),
+    #[ignore = "requires a Kafka broker (KAFKA_BROKERS)"]
+);
@@ -0,0 +1,38 @@
+//! A TCP echo server: `cargo run --example echo [addr]`, then
+//! `nc 127.0.0.1 7878`.
+
+use mini_tokio::{
+    MiniTokio,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+fn main() -> std::io::Result<()> {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:7878".to_string());
+    let rt = MiniTokio::new();
+    rt.block_on(async {
+        let listener = TcpListener::bind(&addr)?;
+        println!("echoing on {}", listener.local_addr()?);
+        loop {
+            let (mut stream, peer) = listener.accept().await?;
+            mini_tokio::spawn(async move {
+                let mut buf = [0; 4096];
+                loop {
+                    match stream.read(&mut buf).await {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            if let Err(err) = stream.write_all(&buf[..n]).await {
+                                eprintln!("{peer}: {err}");
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            eprintln!("{peer}: {err}");
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    })
+}
@@ -0,0 +1,47 @@
+//! A minimal HTTP/1.1 server answering every request with "Hello, world!":
+//! `cargo run --example http [addr]`, then `curl 127.0.0.1:8080`.
+
+use std::io;
+
+use mini_tokio::{
+    MiniTokio,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const BODY: &str = "Hello, world!\n";
+
+async fn serve(mut stream: TcpStream) -> io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        // Answer each complete request head; bodies are not supported.
+        while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            buf.drain(..end + 4);
+            let response =
+                format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{BODY}", BODY.len());
+            stream.write_all(response.as_bytes()).await?;
+        }
+        match stream.read(&mut chunk).await? {
+            0 => return Ok(()),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let rt = MiniTokio::new();
+    rt.block_on(async {
+        let listener = TcpListener::bind(&addr)?;
+        println!("listening on http://{}", listener.local_addr()?);
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            mini_tokio::spawn(async move {
+                if let Err(err) = serve(stream).await {
+                    eprintln!("{peer}: {err}");
+                }
+            });
+        }
+    })
+}
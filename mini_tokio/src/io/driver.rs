@@ -0,0 +1,332 @@
+//! The epoll driver.
+//!
+//! Every fd is added once, edge-triggered, for both directions.  Its
+//! `ScheduledIo` records the readiness seen so far and the task waiting on
+//! each direction.  An operation that fails with `WouldBlock` clears the
+//! readiness it acted on and waits for the next edge; every event bumps a
+//! tick so an edge that arrives while the operation runs is not cleared
+//! with it.
+//!
+//! Like the timers, the driver has no thread of its own.  The parked worker
+//! that drives the timers waits in `epoll_wait` instead of on the condition
+//! variable, and is woken through an eventfd when work or an earlier
+//! deadline comes in.  Busy workers also poll for events between tasks.
+
+use std::{
+    collections::HashMap,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::task::AtomicWaker;
+
+use crate::runtime::{Handle, Shared};
+
+/// Token of the eventfd; registrations start at 1.
+const WAKE_TOKEN: u64 = 0;
+const MAX_EVENTS: usize = 1024;
+
+// Readiness bits.  The closed and error bits are never cleared: once set,
+// the operation returns EOF or the error instead of `WouldBlock`.
+const READABLE: usize = 1 << 0;
+const WRITABLE: usize = 1 << 1;
+const READ_CLOSED: usize = 1 << 2;
+const WRITE_CLOSED: usize = 1 << 3;
+const ERROR: usize = 1 << 4;
+const READY_BITS: usize = 0xffff;
+/// The rest of the word counts events.
+const TICK_SHIFT: u32 = 16;
+const MAX_TICK: usize = usize::MAX >> TICK_SHIFT;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Read,
+    Write,
+}
+
+impl Direction {
+    fn mask(self) -> usize {
+        match self {
+            Direction::Read => READABLE | READ_CLOSED | ERROR,
+            Direction::Write => WRITABLE | WRITE_CLOSED | ERROR,
+        }
+    }
+}
+
+/// Readiness as seen by `poll_ready`, to be passed back to
+/// `clear_readiness`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ReadyEvent {
+    tick: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct ScheduledIo {
+    /// Ready bits in the low 16 bits, the event tick above them.
+    readiness: AtomicUsize,
+    reader: AtomicWaker,
+    writer: AtomicWaker,
+}
+
+impl ScheduledIo {
+    fn waker(&self, direction: Direction) -> &AtomicWaker {
+        match direction {
+            Direction::Read => &self.reader,
+            Direction::Write => &self.writer,
+        }
+    }
+
+    fn set_readiness(&self, ready: usize) {
+        let _ = self.readiness.fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+            let tick = ((current >> TICK_SHIFT) + 1) & MAX_TICK;
+            Some(tick << TICK_SHIFT | (current & READY_BITS) | ready)
+        });
+        for direction in [Direction::Read, Direction::Write] {
+            if ready & direction.mask() != 0 {
+                self.waker(direction).wake();
+            }
+        }
+    }
+
+    fn poll_ready(&self, direction: Direction, cx: &mut Context<'_>) -> Poll<ReadyEvent> {
+        let ready = |current: usize| {
+            (current & direction.mask() != 0).then_some(ReadyEvent { tick: current >> TICK_SHIFT })
+        };
+        if let Some(event) = ready(self.readiness.load(Ordering::Acquire)) {
+            return Poll::Ready(event);
+        }
+        self.waker(direction).register(cx.waker());
+        // An event may have come in before the waker was registered.
+        match ready(self.readiness.load(Ordering::Acquire)) {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Forget that the fd was ready in `direction`, unless another event
+    /// came in since `event` was observed.
+    fn clear_readiness(&self, event: ReadyEvent, direction: Direction) {
+        let clear = match direction {
+            Direction::Read => READABLE,
+            Direction::Write => WRITABLE,
+        };
+        let _ = self.readiness.fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+            (current >> TICK_SHIFT == event.tick).then_some(current & !clear)
+        });
+    }
+}
+
+fn readiness(events: u32) -> usize {
+    let events = events as libc::c_int;
+    let mut ready = 0;
+    if events & (libc::EPOLLIN | libc::EPOLLPRI) != 0 {
+        ready |= READABLE;
+    }
+    if events & libc::EPOLLOUT != 0 {
+        ready |= WRITABLE;
+    }
+    if events & libc::EPOLLRDHUP != 0 {
+        ready |= READ_CLOSED;
+    }
+    if events & libc::EPOLLHUP != 0 {
+        ready |= READ_CLOSED | WRITE_CLOSED;
+    }
+    if events & libc::EPOLLERR != 0 {
+        ready |= ERROR;
+    }
+    ready
+}
+
+pub(crate) struct IoDriver {
+    epoll: OwnedFd,
+    /// Written to wake the worker blocked in `epoll_wait`.
+    wake: OwnedFd,
+    ios: Mutex<HashMap<u64, Arc<ScheduledIo>>>,
+    next_token: AtomicU64,
+    /// Held by whoever is polling, so events are dispatched by one thread
+    /// at a time.
+    events: Mutex<Vec<libc::epoll_event>>,
+}
+
+impl IoDriver {
+    pub(crate) fn new() -> io::Result<Self> {
+        let epoll = syscall!(epoll_create1(libc::EPOLL_CLOEXEC))?;
+        // SAFETY: the fd was just created and is owned by nobody else.
+        let epoll = unsafe { OwnedFd::from_raw_fd(epoll) };
+        let wake = syscall!(eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK))?;
+        let wake = unsafe { OwnedFd::from_raw_fd(wake) };
+        // Level-triggered, so a wake-up sent before the driver blocks is not
+        // lost.
+        let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: WAKE_TOKEN };
+        syscall!(epoll_ctl(epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, wake.as_raw_fd(), &mut event))?;
+        Ok(IoDriver {
+            epoll,
+            wake,
+            ios: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(WAKE_TOKEN + 1),
+            events: Mutex::new(Vec::with_capacity(MAX_EVENTS)),
+        })
+    }
+
+    fn register(&self, fd: RawFd) -> io::Result<(u64, Arc<ScheduledIo>)> {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let io = Arc::new(ScheduledIo::default());
+        self.ios.lock().unwrap().insert(token, io.clone());
+        let interests = libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET;
+        let mut event = libc::epoll_event { events: interests as u32, u64: token };
+        if let Err(err) = syscall!(epoll_ctl(self.epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, fd, &mut event)) {
+            self.ios.lock().unwrap().remove(&token);
+            return Err(err);
+        }
+        Ok((token, io))
+    }
+
+    fn deregister(&self, token: u64, fd: RawFd) {
+        // Fails only if the fd is already closed, which removed it anyway.
+        let _ = syscall!(epoll_ctl(self.epoll.as_raw_fd(), libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut()));
+        self.ios.lock().unwrap().remove(&token);
+    }
+
+    pub(crate) fn has_registrations(&self) -> bool {
+        !self.ios.lock().unwrap().is_empty()
+    }
+
+    /// Make the current or next blocking `poll` return.
+    pub(crate) fn wake(&self) {
+        let one: u64 = 1;
+        // Only fails if the counter is about to overflow, in which case the
+        // driver is awake anyway.
+        let _ = syscall!(write(self.wake.as_raw_fd(), (&raw const one).cast(), size_of::<u64>()));
+    }
+
+    /// Wait up to `timeout` (forever if `None`) for events and wake the
+    /// tasks waiting on them.
+    pub(crate) fn poll(&self, timeout: Option<Duration>) {
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_nanos().div_ceil(1_000_000).min(libc::c_int::MAX as u128) as libc::c_int
+        });
+        self.dispatch(&mut self.events.lock().unwrap(), timeout);
+    }
+
+    /// Dispatch pending events without blocking, unless another thread is
+    /// already polling.
+    pub(crate) fn poll_now(&self) {
+        if let Ok(mut events) = self.events.try_lock() {
+            self.dispatch(&mut events, 0);
+        }
+    }
+
+    fn dispatch(&self, events: &mut Vec<libc::epoll_event>, timeout: libc::c_int) {
+        events.clear();
+        let n = match syscall!(epoll_wait(
+            self.epoll.as_raw_fd(),
+            events.as_mut_ptr(),
+            MAX_EVENTS as libc::c_int,
+            timeout,
+        )) {
+            Ok(n) => n as usize,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => 0,
+            Err(err) => panic!("epoll_wait failed: {err}"),
+        };
+        // SAFETY: epoll_wait initialized the first `n` events, and the
+        // capacity is `MAX_EVENTS`.
+        unsafe { events.set_len(n) };
+
+        let ready: Vec<(Arc<ScheduledIo>, usize)> = {
+            let ios = self.ios.lock().unwrap();
+            events
+                .iter()
+                .filter_map(|event| {
+                    let (token, flags) = (event.u64, event.events);
+                    if token == WAKE_TOKEN {
+                        let mut count: u64 = 0;
+                        let _ = syscall!(read(self.wake.as_raw_fd(), (&raw mut count).cast(), size_of::<u64>()));
+                        return None;
+                    }
+                    // A registration dropped after the event was queued.
+                    ios.get(&token).map(|io| (io.clone(), readiness(flags)))
+                })
+                .collect()
+        };
+        // Wake outside the lock: waking schedules tasks.
+        for (io, ready) in ready {
+            io.set_readiness(ready);
+        }
+    }
+}
+
+/// The link between an fd and the driver of the runtime it was registered
+/// with.  Deregisters on drop.
+pub(crate) struct Registration {
+    shared: Arc<Shared>,
+    token: u64,
+    fd: RawFd,
+    io: Arc<ScheduledIo>,
+}
+
+impl Registration {
+    /// Register `fd` with the current runtime.  Panics outside a runtime.
+    pub(crate) fn new(fd: RawFd) -> io::Result<Self> {
+        let shared = Handle::current().shared;
+        let (token, io) = shared.io.register(fd)?;
+        Ok(Registration { shared, token, fd, io })
+    }
+
+    pub(crate) fn poll_ready(&self, direction: Direction, cx: &mut Context<'_>) -> Poll<ReadyEvent> {
+        self.io.poll_ready(direction, cx)
+    }
+
+    pub(crate) fn clear_readiness(&self, event: ReadyEvent, direction: Direction) {
+        self.io.clear_readiness(event, direction);
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.shared.io.deregister(self.token, self.fd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker;
+
+    #[test]
+    fn clearing_keeps_readiness_from_a_newer_event() {
+        let io = ScheduledIo::default();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(io.poll_ready(Direction::Read, &mut cx).is_pending());
+
+        io.set_readiness(READABLE | WRITABLE);
+        let Poll::Ready(stale) = io.poll_ready(Direction::Read, &mut cx) else { panic!("not readable") };
+        // Another edge arrives while the read that observed `stale` runs.
+        io.set_readiness(READABLE);
+        io.clear_readiness(stale, Direction::Read);
+        let Poll::Ready(fresh) = io.poll_ready(Direction::Read, &mut cx) else { panic!("edge was lost") };
+        io.clear_readiness(fresh, Direction::Read);
+        assert!(io.poll_ready(Direction::Read, &mut cx).is_pending());
+        // Only the read side was cleared.
+        assert!(io.poll_ready(Direction::Write, &mut cx).is_ready());
+    }
+
+    #[test]
+    fn hang_up_is_never_cleared() {
+        let io = ScheduledIo::default();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        io.set_readiness(readiness(libc::EPOLLHUP as u32));
+        for direction in [Direction::Read, Direction::Write] {
+            let Poll::Ready(event) = io.poll_ready(direction, &mut cx) else { panic!("not ready") };
+            io.clear_readiness(event, direction);
+            assert!(io.poll_ready(direction, &mut cx).is_ready());
+        }
+    }
+}
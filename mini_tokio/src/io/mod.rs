@@ -0,0 +1,82 @@
+//! Asynchronous I/O.
+//!
+//! The traits are the ones from `futures::io`, so the usual extension
+//! methods (`read`, `write_all`, `read_to_end`, ...) work on our sockets.
+//! Readiness comes from the runtime's epoll driver (see `driver`).
+
+use std::{
+    future,
+    io,
+    os::fd::AsRawFd,
+    task::{Context, Poll, ready},
+};
+
+pub use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Call a libc function, turning `-1` into `io::Error::last_os_error()`.
+macro_rules! syscall {
+    ($fn: ident ( $($arg: expr),* $(,)* ) ) => {{
+        let res = unsafe { libc::$fn($($arg, )*) };
+        if res == -1 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(res)
+        }
+    }};
+}
+pub(crate) use syscall;
+
+mod driver;
+
+pub(crate) use driver::{Direction, IoDriver};
+use driver::Registration;
+
+/// A non-blocking fd registered with the current runtime's driver.
+///
+/// Only one task at a time should wait on each direction: a second one
+/// replaces the first one's waker.
+pub(crate) struct PollEvented<E: AsRawFd> {
+    // Declared first so the fd is deregistered before it is closed.
+    registration: Registration,
+    io: E,
+}
+
+impl<E: AsRawFd> PollEvented<E> {
+    /// `io` must already be in non-blocking mode.  Panics outside a runtime.
+    pub(crate) fn new(io: E) -> io::Result<Self> {
+        let registration = Registration::new(io.as_raw_fd())?;
+        Ok(PollEvented { registration, io })
+    }
+
+    pub(crate) fn get_ref(&self) -> &E {
+        &self.io
+    }
+
+    /// Wait until the fd is ready in `direction`, without doing any I/O.
+    pub(crate) async fn ready(&self, direction: Direction) {
+        future::poll_fn(|cx| self.registration.poll_ready(direction, cx).map(drop)).await
+    }
+
+    /// Run `op` once the fd is ready, until it stops failing with
+    /// `WouldBlock`.
+    pub(crate) fn poll_io<R>(
+        &self,
+        direction: Direction,
+        cx: &mut Context<'_>,
+        mut op: impl FnMut(&E) -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        loop {
+            let event = ready!(self.registration.poll_ready(direction, cx));
+            match op(&self.io) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.registration.clear_readiness(event, direction);
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    pub(crate) async fn io<R>(&self, direction: Direction, mut op: impl FnMut(&E) -> io::Result<R>) -> io::Result<R> {
+        future::poll_fn(|cx| self.poll_io(direction, cx, &mut op)).await
+    }
+}
@@ -1,143 +1,9 @@
-use std::{error::Error, pin::Pin, sync::{mpsc, Arc, Mutex}, task::{Context, Poll}, thread, time::Instant};
-use futures::task::{ self, ArcWake};
-
-pub struct Delay {
-    pub when: Instant
-}
-
-impl Future for Delay {
-    type Output = &'static str;
-
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>)
-        -> Poll<Self::Output>
-    {
-        if Instant::now() >= self.when {
-            println!("Times up");
-            Poll::Ready("done")
-        } else {
-            let waker = cx.waker().clone();
-            let when = self.when;
-            thread::spawn(move || {
-                let now = Instant::now();
-                if now < when {
-                    thread::sleep(when - now);
-                }
-
-                waker.wake();
-            });
-
-            Poll::Pending
-        }
-    }
-}
-
-
-pub struct MiniTokio {
-    scheduled: mpsc::Receiver<Arc<Task>>,
-    sender: mpsc::Sender<Arc<Task>>,
-}
-
-impl MiniTokio {
-    pub fn run(&self) {
-        while let Ok(task) = self.scheduled.recv() {
-            task.poll();
-        }
-    }
-
-    /// Initialize a new mini-tokio instance.
-    pub fn new() -> MiniTokio {
-        let (sender, scheduled) = mpsc::channel();
-
-        MiniTokio { scheduled, sender }
-    }
-
-    /// Spawn a future onto the mini-tokio instance.
-    ///
-    /// The given future is wrapped with the `Task` harness and pushed into the
-    /// `scheduled` queue. The future will be executed when `run` is called.
-    pub fn spawn<F>(&self, future: F)
-    where
-        F: Future<Output = ()> + Send + 'static,
-    {
-        Task::spawn(future, &self.sender);
-    }
-}
-
-
-pub struct Task {
-    // The `Mutex` is to make `Task` implement `Sync`. Only
-    // one thread accesses `task_future` at any given time.
-    // The `Mutex` is not required for correctness. Real Tokio
-    // does not use a mutex here, but real Tokio has
-    // more lines of code than can fit in a single tutorial
-    // page.
-    task_future: Mutex<TaskFuture>,
-    executor: mpsc::Sender<Arc<Task>>,
-}
-
-impl Task {
-    fn schedule(self: &Arc<Self>) {
-        let _ = self.executor.send(self.clone());
-    }
-
-    fn poll(self: Arc<Self>) {
-        // Create a waker from the `Task` instance. This
-        // uses the `ArcWake` impl from above.
-        let waker = task::waker(self.clone());
-        let mut cx = Context::from_waker(&waker);
-
-        // No other thread ever tries to lock the task_future
-        let mut task_future = self.task_future.try_lock().unwrap();
-        // Poll the inner future
-        task_future.poll(&mut cx);
-        // sender.send(Err(error_msg))
-    }
-
-    // Spawns a new task with the given future.
-    //
-    // Initializes a new Task harness containing the given future and pushes it
-    // onto `sender`. The receiver half of the channel will get the task and
-    // execute it.
-    fn spawn<F>(future: F, sender: &mpsc::Sender<Arc<Task>>)
-    where
-        F: Future<Output = ()> + Send + 'static,
-    {
-        let task = Arc::new(Task {
-            task_future: Mutex::new(TaskFuture::new(future)),
-            executor: sender.clone(),
-        });
-
-        let _ = sender.send(task);
-    }
-}
-
-impl ArcWake for Task {
-    fn wake_by_ref(arc_self: &Arc<Self>) {
-        arc_self.schedule();
-    }
-}
-
-struct TaskFuture {
-    future: Pin<Box<dyn Future<Output = ()> + Send>>,
-    poll: Poll<()>,
-}
-
-impl TaskFuture {
-    fn new(future: impl Future<Output = ()> + Send + 'static) -> TaskFuture {
-        TaskFuture {
-            future: Box::pin(future),
-            poll: Poll::Pending,
-        }
-    }
-
-    fn poll(&mut self, cx: &mut Context<'_>) {
-        // Spurious wake-ups are allowed, even after a future has
-        // returned `Ready`. However, polling a future which has
-        // already returned `Ready` is *not* allowed. For this
-        // reason we need to check that the future is still pending
-        // before we call it. Failure to do so can lead to a panic.
-        if self.poll.is_pending() {
-            self.poll = self.future.as_mut().poll(cx);
-        }
-    }
-}
+pub mod io;
+pub mod net;
+mod runtime;
+pub mod sync;
+mod task;
+pub mod time;
+
+pub use runtime::{spawn, Handle, MiniTokio};
+pub use task::{yield_now, JoinError, JoinHandle};
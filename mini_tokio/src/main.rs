@@ -1,23 +1,21 @@
 use std::time::{Duration, Instant};
-use mini_tokio::{Delay, MiniTokio};
-use std::net::TcpListener;
+use mini_tokio::{time, MiniTokio};
 
 fn main() {
     let mini_tokio = MiniTokio::new();
 
-    mini_tokio.spawn(async {
-        let when = Instant::now() + Duration::from_millis(10);
-        let future = Delay { when };
-
-        let out = future.await;
+    let elapsed = mini_tokio.block_on(async {
+        let handle = mini_tokio::spawn(async {
+            let start = Instant::now();
+            time::sleep(Duration::from_millis(10)).await;
+            start.elapsed()
+        });
+        handle.await.unwrap()
     });
-
-    mini_tokio.run();
+    println!("Times up after {elapsed:?}");
 }
 
 // #[tokio::main]
 // async fn main() {
-//     let when = Instant::now() + Duration::from_millis(10);
-//     let future = Delay { when };
-//     let _ = future.await;
+//     tokio::time::sleep(Duration::from_millis(10)).await;
 // }
@@ -0,0 +1,266 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{self, Shutdown, SocketAddr, ToSocketAddrs},
+    os::fd::FromRawFd,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use super::{each_addr, to_sockaddr};
+use crate::io::{AsyncRead, AsyncWrite, Direction, PollEvented, syscall};
+
+/// A TCP socket listening for connections.
+pub struct TcpListener {
+    io: PollEvented<net::TcpListener>,
+}
+
+impl TcpListener {
+    /// Bind to `addr`, trying each address it resolves to in turn.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
+        Self::from_std(net::TcpListener::bind(addr)?)
+    }
+
+    /// Register a listener created with `std`, switching it to
+    /// non-blocking mode.
+    pub fn from_std(listener: net::TcpListener) -> io::Result<TcpListener> {
+        listener.set_nonblocking(true)?;
+        Ok(TcpListener { io: PollEvented::new(listener)? })
+    }
+
+    /// Wait for the next connection.
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (stream, addr) = self.io.io(Direction::Read, |listener| listener.accept()).await?;
+        Ok((TcpStream::from_std(stream)?, addr))
+    }
+
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<(TcpStream, SocketAddr)>> {
+        self.io
+            .poll_io(Direction::Read, cx, |listener| listener.accept())
+            .map(|accepted| accepted.and_then(|(stream, addr)| Ok((TcpStream::from_std(stream)?, addr))))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().local_addr()
+    }
+}
+
+impl fmt::Debug for TcpListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.io.get_ref().fmt(f)
+    }
+}
+
+/// A TCP connection.
+///
+/// Reading and writing go through `AsyncRead` and `AsyncWrite`, which are
+/// also implemented for `&TcpStream` so one task can read while another
+/// writes.  Closing the writer shuts down the write half.
+pub struct TcpStream {
+    io: PollEvented<net::TcpStream>,
+}
+
+impl TcpStream {
+    /// Connect to `addr`, trying each address it resolves to in turn.
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+        each_addr(addr, Self::connect_addr).await
+    }
+
+    async fn connect_addr(addr: SocketAddr) -> io::Result<TcpStream> {
+        let domain = if addr.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+        let fd = syscall!(socket(domain, libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0))?;
+        // SAFETY: the fd was just created and is owned by nobody else.
+        let socket = unsafe { net::TcpStream::from_raw_fd(fd) };
+        let (storage, len) = to_sockaddr(&addr);
+        match syscall!(connect(fd, (&raw const storage).cast(), len)) {
+            Ok(_) => {}
+            Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+            Err(err) => return Err(err),
+        }
+        let stream = TcpStream { io: PollEvented::new(socket)? };
+        // The socket turns writable once the handshake finishes, whether it
+        // succeeded or not.
+        stream.io.ready(Direction::Write).await;
+        match stream.io.get_ref().take_error()? {
+            Some(err) => Err(err),
+            None => Ok(stream),
+        }
+    }
+
+    /// Register a connected stream created with `std`, switching it to
+    /// non-blocking mode.
+    pub fn from_std(stream: net::TcpStream) -> io::Result<TcpStream> {
+        stream.set_nonblocking(true)?;
+        Ok(TcpStream { io: PollEvented::new(stream)? })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().peer_addr()
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.io.get_ref().set_nodelay(nodelay)
+    }
+
+    pub fn nodelay(&self) -> io::Result<bool> {
+        self.io.get_ref().nodelay()
+    }
+}
+
+impl fmt::Debug for TcpStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.io.get_ref().fmt(f)
+    }
+}
+
+impl AsyncRead for &TcpStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.io.poll_io(Direction::Read, cx, |mut stream| stream.read(buf))
+    }
+}
+
+impl AsyncWrite for &TcpStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.io.poll_io(Direction::Write, cx, |mut stream| stream.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Writes go straight to the socket.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.io.get_ref().shutdown(Shutdown::Write))
+    }
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MiniTokio, io::{AsyncReadExt, AsyncWriteExt}, spawn};
+
+    #[test]
+    fn echo_round_trip_with_concurrent_clients() {
+        const CLIENTS: usize = 32;
+
+        let rt = MiniTokio::with_workers(4);
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            spawn(async move {
+                loop {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    spawn(async move {
+                        let mut buf = [0; 1024];
+                        loop {
+                            match stream.read(&mut buf).await.unwrap() {
+                                0 => break,
+                                n => stream.write_all(&buf[..n]).await.unwrap(),
+                            }
+                        }
+                    });
+                }
+            });
+
+            let clients: Vec<_> = (0..CLIENTS)
+                .map(|i| {
+                    spawn(async move {
+                        let mut stream = TcpStream::connect(addr).await.unwrap();
+                        let message = format!("hello from client {i}");
+                        stream.write_all(message.as_bytes()).await.unwrap();
+                        let mut echoed = vec![0; message.len()];
+                        stream.read_exact(&mut echoed).await.unwrap();
+                        assert_eq!(echoed, message.as_bytes());
+                    })
+                })
+                .collect();
+            for client in clients {
+                client.await.unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn large_writes_wait_for_the_reader() {
+        // Far more than the socket buffers hold, so the writer has to wait
+        // for write readiness.
+        const LEN: usize = 8 << 20;
+
+        let rt = MiniTokio::with_workers(2);
+        let received = rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let writer = spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let data: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+                stream.write_all(&data).await.unwrap();
+                stream.close().await.unwrap();
+            });
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            writer.await.unwrap();
+            received
+        });
+        assert_eq!(received.len(), LEN);
+        assert!(received.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+    }
+
+    #[test]
+    fn connecting_to_a_closed_port_is_refused() {
+        let rt = MiniTokio::with_workers(1);
+        let err = rt.block_on(async {
+            let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+            TcpStream::connect(addr).await.unwrap_err()
+        });
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn split_halves_by_reference() {
+        let rt = MiniTokio::with_workers(2);
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let client = spawn(async move {
+                let stream = TcpStream::connect(addr).await.unwrap();
+                let (mut reader, mut writer) = (&stream, &stream);
+                writer.write_all(b"ping").await.unwrap();
+                let mut reply = [0; 4];
+                reader.read_exact(&mut reply).await.unwrap();
+                assert_eq!(&reply, b"pong");
+            });
+            let (mut stream, peer) = listener.accept().await.unwrap();
+            assert_eq!(peer, stream.peer_addr().unwrap());
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"ping");
+            stream.write_all(b"pong").await.unwrap();
+            client.await.unwrap();
+        });
+    }
+}
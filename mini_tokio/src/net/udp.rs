@@ -0,0 +1,106 @@
+use std::{
+    fmt,
+    io,
+    net::{self, SocketAddr, ToSocketAddrs},
+    task::{Context, Poll},
+};
+
+use crate::io::{Direction, PollEvented};
+
+/// A UDP socket.
+///
+/// `send_to`/`recv_from` work on any socket; `send`/`recv` need a peer set
+/// with `connect` first.
+pub struct UdpSocket {
+    io: PollEvented<net::UdpSocket>,
+}
+
+impl UdpSocket {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+        Self::from_std(net::UdpSocket::bind(addr)?)
+    }
+
+    /// Register a socket created with `std`, switching it to non-blocking
+    /// mode.
+    pub fn from_std(socket: net::UdpSocket) -> io::Result<UdpSocket> {
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket { io: PollEvented::new(socket)? })
+    }
+
+    /// Set the only address datagrams are sent to and received from.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        self.io.get_ref().connect(addr)
+    }
+
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.io.io(Direction::Write, |socket| socket.send_to(buf, target)).await
+    }
+
+    /// Receive one datagram; the excess is discarded if it does not fit in
+    /// `buf`.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.io.io(Direction::Read, |socket| socket.recv_from(buf)).await
+    }
+
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.io.io(Direction::Write, |socket| socket.send(buf)).await
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.io(Direction::Read, |socket| socket.recv(buf)).await
+    }
+
+    pub fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>> {
+        self.io.poll_io(Direction::Write, cx, |socket| socket.send_to(buf, target))
+    }
+
+    pub fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        self.io.poll_io(Direction::Read, cx, |socket| socket.recv_from(buf))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().peer_addr()
+    }
+}
+
+impl fmt::Debug for UdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.io.get_ref().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MiniTokio, spawn};
+
+    #[test]
+    fn ping_pong() {
+        let rt = MiniTokio::with_workers(2);
+        rt.block_on(async {
+            let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let server_addr = server.local_addr().unwrap();
+            let echo = spawn(async move {
+                let mut buf = [0; 64];
+                for _ in 0..10 {
+                    let (n, from) = server.recv_from(&mut buf).await.unwrap();
+                    server.send_to(&buf[..n], from).await.unwrap();
+                }
+            });
+
+            let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+            client.connect(server_addr).unwrap();
+            let mut buf = [0; 64];
+            for i in 0..10u8 {
+                client.send(&[i; 3]).await.unwrap();
+                let n = client.recv(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], &[i; 3]);
+            }
+            echo.await.unwrap();
+        });
+    }
+}
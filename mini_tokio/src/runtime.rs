@@ -0,0 +1,484 @@
+//! The worker pool.
+//!
+//! Every worker owns a FIFO deque.  A task woken on a worker thread goes to
+//! that worker's deque; one woken anywhere else (or spawned from outside the
+//! runtime) goes to the shared injector queue.  A worker looks in its own
+//! deque first, then takes a batch from the injector, then steals half of
+//! another worker's deque.  Every 61st task is taken from the injector first
+//! so a busy worker cannot starve it.  Workers with nothing to do sleep on a
+//! condition variable until something is scheduled, except the first of
+//! them to park: it becomes the driver and waits in `epoll_wait` for I/O
+//! events and the next timer deadline (see `io` and `time`).
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    iter,
+    pin::pin,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{self, AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    thread::{self, JoinHandle as ThreadHandle, Thread},
+    time::Instant,
+};
+
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+use futures::task::{self, ArcWake};
+
+use crate::{
+    io::IoDriver,
+    task::{JoinHandle, Task},
+    time::TimerDriver,
+};
+
+thread_local! {
+    /// Runtime of the worker or `block_on` call running on this thread.
+    static CURRENT: RefCell<Option<Handle>> = const { RefCell::new(None) };
+    /// The deque of the worker running on this thread.
+    static LOCAL: RefCell<Option<Local>> = const { RefCell::new(None) };
+}
+
+struct Local {
+    shared: Arc<Shared>,
+    queue: Worker<Arc<Task>>,
+}
+
+pub(crate) struct Shared {
+    injector: Injector<Arc<Task>>,
+    stealers: Vec<Stealer<Arc<Task>>>,
+    /// Every task that has not completed, so shutdown can cancel the ones
+    /// that are not in any queue.
+    tasks: Mutex<HashMap<u64, Arc<Task>>>,
+    next_id: AtomicU64,
+    /// Workers waiting on `wakeup`; the driver is not counted.
+    sleeping: AtomicUsize,
+    idle: Mutex<()>,
+    wakeup: Condvar,
+    shutdown: AtomicBool,
+    pub(crate) timers: TimerDriver,
+    pub(crate) io: IoDriver,
+    /// Set while a parked worker is waiting for I/O and the next timer
+    /// deadline.
+    driving: AtomicBool,
+}
+
+impl Shared {
+    pub(crate) fn next_task_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn register(&self, task: &Arc<Task>) {
+        self.tasks.lock().unwrap().insert(task.id, task.clone());
+    }
+
+    pub(crate) fn unregister(&self, id: u64) {
+        self.tasks.lock().unwrap().remove(&id);
+    }
+
+    /// Queue `task`: on this thread's deque if it is one of our workers,
+    /// otherwise on the injector.
+    pub(crate) fn schedule(self: &Arc<Self>, task: Arc<Task>) {
+        let task = LOCAL.with(|local| match local.try_borrow().as_deref() {
+            Ok(Some(local)) if Arc::ptr_eq(&local.shared, self) => {
+                local.queue.push(task);
+                None
+            }
+            _ => Some(task),
+        });
+        if let Some(task) = task {
+            self.injector.push(task);
+        }
+        self.notify_one();
+    }
+
+    fn notify_one(&self) {
+        // Pairs with the fences in `park` and `drive`: either the sleeper
+        // sees the new task, or we see it sleeping and wake it.
+        atomic::fence(Ordering::SeqCst);
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let _idle = self.idle.lock().unwrap();
+            self.wakeup.notify_one();
+        } else if self.driving.load(Ordering::SeqCst) {
+            self.io.wake();
+        }
+    }
+
+    fn has_work(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|s| !s.is_empty())
+    }
+
+    /// Wake the parked driver so it re-arms for an earlier deadline.
+    pub(crate) fn unpark_driver(&self) {
+        if self.driving.load(Ordering::Acquire) {
+            self.io.wake();
+        }
+    }
+
+    fn park(&self) {
+        if !self.driving.swap(true, Ordering::SeqCst) {
+            self.drive();
+            return;
+        }
+        let mut idle = self.idle.lock().unwrap();
+        self.sleeping.fetch_add(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        if !self.has_work() && !self.shutdown.load(Ordering::SeqCst) {
+            idle = self.wakeup.wait(idle).unwrap();
+        }
+        self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        drop(idle);
+        self.timers.fire_expired();
+    }
+
+    /// Park as the driver: wait for I/O events until the next timer
+    /// deadline, or until `notify_one` or `unpark_driver` wakes us.
+    fn drive(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if !self.has_work() && !self.shutdown.load(Ordering::SeqCst) {
+            let timeout = self.timers.next_deadline().map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            self.io.poll(timeout);
+        }
+        self.driving.store(false, Ordering::SeqCst);
+        // Hand the driver role to a sleeping worker while this one goes
+        // back to work.
+        if (self.timers.next_deadline().is_some() || self.io.has_registrations())
+            && self.sleeping.load(Ordering::SeqCst) > 0
+        {
+            let _idle = self.idle.lock().unwrap();
+            self.wakeup.notify_one();
+        }
+        self.timers.fire_expired();
+    }
+
+    fn find_task(&self, local: &Worker<Arc<Task>>, index: usize, tick: u32) -> Option<Arc<Task>> {
+        if tick.is_multiple_of(61)
+            && let Some(task) = self.injector.steal_batch_and_pop(local).success()
+        {
+            return Some(task);
+        }
+        local.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.injector.steal_batch_and_pop(local).or_else(|| {
+                    let n = self.stealers.len();
+                    (1..n).map(|i| self.stealers[(index + i) % n].steal_batch_and_pop(local)).collect()
+                })
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+}
+
+fn run_worker(shared: Arc<Shared>, queue: Worker<Arc<Task>>, index: usize) {
+    let _enter = enter(Handle { shared: shared.clone() });
+    LOCAL.with(|local| *local.borrow_mut() = Some(Local { shared: shared.clone(), queue }));
+    let mut tick: u32 = 0;
+    while !shared.shutdown.load(Ordering::Acquire) {
+        tick = tick.wrapping_add(1);
+        let task = LOCAL.with(|local| {
+            let local = local.borrow();
+            shared.find_task(&local.as_ref().unwrap().queue, index, tick)
+        });
+        match task {
+            Some(task) => {
+                task.run();
+                shared.timers.fire_expired();
+                if tick.is_multiple_of(61) {
+                    shared.io.poll_now();
+                }
+            }
+            None => shared.park(),
+        }
+    }
+    LOCAL.with(|local| local.borrow_mut().take());
+}
+
+/// A handle to a runtime, for spawning onto it from anywhere.
+#[derive(Clone)]
+pub struct Handle {
+    pub(crate) shared: Arc<Shared>,
+}
+
+impl Handle {
+    /// The runtime this thread is a worker of, or is inside `block_on` of.
+    ///
+    /// Panics outside a runtime.
+    pub fn current() -> Handle {
+        Self::try_current().expect("must be called from a MiniTokio worker or inside `block_on`")
+    }
+
+    pub fn try_current() -> Option<Handle> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        Task::spawn(future, &self.shared)
+    }
+}
+
+/// Spawn onto the current runtime.  Panics outside a runtime.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Handle::current().spawn(future)
+}
+
+/// Makes `handle` current on this thread until dropped.
+struct EnterGuard;
+
+fn enter(handle: Handle) -> EnterGuard {
+    CURRENT.with(|current| {
+        let mut current = current.borrow_mut();
+        assert!(current.is_none(), "cannot start a runtime from within a runtime");
+        *current = Some(handle);
+    });
+    EnterGuard
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().take());
+    }
+}
+
+/// Wakes the thread blocked in `block_on`.
+struct Parker {
+    thread: Thread,
+    notified: AtomicBool,
+}
+
+impl ArcWake for Parker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.notified.store(true, Ordering::Release);
+        arc_self.thread.unpark();
+    }
+}
+
+/// A pool of worker threads running spawned tasks.
+///
+/// Dropping the runtime stops the workers and cancels every task that has
+/// not completed.
+pub struct MiniTokio {
+    shared: Arc<Shared>,
+    workers: Vec<ThreadHandle<()>>,
+}
+
+impl MiniTokio {
+    /// A runtime with one worker per available CPU.
+    pub fn new() -> MiniTokio {
+        let workers = thread::available_parallelism().map_or(4, |n| n.get());
+        Self::with_workers(workers)
+    }
+
+    pub fn with_workers(workers: usize) -> MiniTokio {
+        assert!(workers > 0, "a runtime needs at least one worker");
+        let queues: Vec<Worker<Arc<Task>>> = (0..workers).map(|_| Worker::new_fifo()).collect();
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: queues.iter().map(Worker::stealer).collect(),
+            tasks: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            sleeping: AtomicUsize::new(0),
+            idle: Mutex::new(()),
+            wakeup: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            timers: TimerDriver::new(),
+            io: IoDriver::new().expect("failed to create the I/O driver"),
+            driving: AtomicBool::new(false),
+        });
+        let workers = queues
+            .into_iter()
+            .enumerate()
+            .map(|(index, queue)| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("mini-tokio-worker-{index}"))
+                    .spawn(move || run_worker(shared, queue, index))
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        MiniTokio { shared, workers }
+    }
+
+    pub fn handle(&self) -> Handle {
+        Handle { shared: self.shared.clone() }
+    }
+
+    /// Spawn a future onto the worker pool.
+    ///
+    /// The task starts running right away; awaiting the returned handle
+    /// yields its output.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        Task::spawn(future, &self.shared)
+    }
+
+    /// Run `future` to completion on the calling thread, which sleeps while
+    /// the future is pending.  `spawn` inside it goes to this runtime.  A
+    /// panic in `future` propagates to the caller.
+    ///
+    /// Panics if called from a worker or from inside another `block_on`.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let _enter = enter(self.handle());
+        let mut future = pin!(future);
+        let parker = Arc::new(Parker { thread: thread::current(), notified: AtomicBool::new(false) });
+        let waker = task::waker(parker.clone());
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            while !parker.notified.swap(false, Ordering::Acquire) {
+                thread::park();
+            }
+        }
+    }
+}
+
+impl Default for MiniTokio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MiniTokio {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        {
+            let _idle = self.shared.idle.lock().unwrap();
+            self.shared.wakeup.notify_all();
+        }
+        self.shared.io.wake();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        let tasks: Vec<Arc<Task>> = self.shared.tasks.lock().unwrap().drain().map(|(_, task)| task).collect();
+        for task in tasks {
+            task.cancel();
+        }
+        while !self.shared.injector.steal().is_empty() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::{JoinError, yield_now};
+    use std::{collections::HashSet, sync::atomic::AtomicUsize, time::Duration};
+
+    #[test]
+    fn block_on_returns_spawned_output() {
+        let rt = MiniTokio::with_workers(2);
+        let value = rt.block_on(async {
+            let handle = spawn(async { 40 + 2 });
+            handle.await.unwrap()
+        });
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn tasks_run_on_several_workers() {
+        let rt = MiniTokio::with_workers(4);
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let handles: Vec<_> = (0..64)
+            .map(|_| {
+                let threads = threads.clone();
+                rt.spawn(async move {
+                    for _ in 0..10 {
+                        threads.lock().unwrap().insert(thread::current().id());
+                        // Busy enough that idle workers steal.
+                        thread::sleep(Duration::from_millis(1));
+                        yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        rt.block_on(async {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+        assert!(threads.lock().unwrap().len() > 1);
+    }
+
+    #[test]
+    fn wake_during_poll_reschedules_instead_of_panicking() {
+        let rt = MiniTokio::with_workers(4);
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        let handle = rt.spawn(std::future::poll_fn(move |cx| {
+            // Hand the waker to another thread that wakes it while this poll
+            // may still be running.
+            let waker = cx.waker().clone();
+            thread::spawn(move || waker.wake());
+            thread::sleep(Duration::from_micros(50));
+            if counter.fetch_add(1, Ordering::SeqCst) == 200 { Poll::Ready(()) } else { Poll::Pending }
+        }));
+        rt.block_on(handle).unwrap();
+        assert_eq!(polls.load(Ordering::SeqCst), 201);
+    }
+
+    #[test]
+    fn panics_are_returned_by_the_join_handle() {
+        let rt = MiniTokio::with_workers(1);
+        let err = rt.block_on(rt.spawn(async { panic!("boom") })).unwrap_err();
+        assert!(err.is_panic());
+        assert_eq!(err.to_string(), "task panicked: boom");
+        // The worker survives.
+        assert_eq!(rt.block_on(rt.spawn(async { 1 })).unwrap(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "root panic")]
+    fn block_on_propagates_panics() {
+        MiniTokio::with_workers(1).block_on(async { panic!("root panic") });
+    }
+
+    #[test]
+    fn abort_cancels_a_pending_task() {
+        let rt = MiniTokio::with_workers(2);
+        let handle = rt.spawn(std::future::pending::<()>());
+        handle.abort();
+        assert!(matches!(rt.block_on(handle), Err(JoinError::Cancelled)));
+    }
+
+    #[test]
+    fn dropping_the_runtime_cancels_pending_tasks() {
+        struct SetOnDrop(Arc<AtomicBool>);
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = SetOnDrop(dropped.clone());
+        let rt = MiniTokio::with_workers(2);
+        let handle = rt.spawn(async move {
+            let _guard = guard;
+            std::future::pending::<()>().await
+        });
+        drop(rt);
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    #[should_panic(expected = "within a runtime")]
+    fn block_on_inside_a_task_panics() {
+        let rt = MiniTokio::with_workers(1);
+        let inner = MiniTokio::with_workers(1);
+        rt.block_on(async move { inner.block_on(async {}) });
+    }
+}
@@ -0,0 +1,361 @@
+//! A multi-producer, multi-consumer channel where every receiver sees every
+//! value.
+//!
+//! The channel keeps the last `capacity` values.  Sending never waits: a
+//! receiver that falls further behind than that skips the values it missed
+//! and learns how many through `RecvError::Lagged`.  Receivers waiting for
+//! the next value are woken in the order they started waiting.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// Create a channel that keeps the last `capacity` values.
+///
+/// Panics if `capacity` is zero.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast channel capacity must be positive");
+    let shared = Arc::new(Shared {
+        capacity,
+        state: Mutex::new(State {
+            buffer: VecDeque::with_capacity(capacity),
+            tail: 0,
+            senders: 1,
+            receivers: 1,
+            next_receiver: 1,
+            waiters: VecDeque::new(),
+        }),
+    });
+    let receiver = Receiver { shared: shared.clone(), next: 0, id: 0 };
+    (Sender { shared }, receiver)
+}
+
+struct Shared<T> {
+    capacity: usize,
+    state: Mutex<State<T>>,
+}
+
+struct State<T> {
+    /// The last values sent; the newest has position `tail - 1`.
+    buffer: VecDeque<T>,
+    /// Position of the next value to be sent.
+    tail: u64,
+    senders: usize,
+    receivers: usize,
+    next_receiver: u64,
+    /// Receivers waiting for a value, by id, in the order they started
+    /// waiting.
+    waiters: VecDeque<(u64, Waker)>,
+}
+
+impl<T> State<T> {
+    fn head(&self) -> u64 {
+        self.tail - self.buffer.len() as u64
+    }
+}
+
+impl<T: Clone> State<T> {
+    /// The value at position `next`, advancing it.
+    fn take(&self, next: &mut u64) -> Result<T, TryRecvError> {
+        let head = self.head();
+        if *next < head {
+            let missed = head - *next;
+            *next = head;
+            return Err(TryRecvError::Lagged(missed));
+        }
+        if *next < self.tail {
+            let value = self.buffer[(*next - head) as usize].clone();
+            *next += 1;
+            return Ok(value);
+        }
+        if self.senders == 0 { Err(TryRecvError::Closed) } else { Err(TryRecvError::Empty) }
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Send `value` to every current receiver and return how many there
+    /// are.  Fails, handing the value back, if there are none.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let (receivers, waiters) = {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.receivers == 0 {
+                return Err(SendError(value));
+            }
+            if state.buffer.len() == self.shared.capacity {
+                state.buffer.pop_front();
+            }
+            state.buffer.push_back(value);
+            state.tail += 1;
+            (state.receivers, std::mem::take(&mut state.waiters))
+        };
+        for (_, waker) in waiters {
+            waker.wake();
+        }
+        Ok(receivers)
+    }
+
+    /// A new receiver that sees values sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receivers += 1;
+        let id = state.next_receiver;
+        state.next_receiver += 1;
+        Receiver { shared: self.shared.clone(), next: state.tail, id }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.state.lock().unwrap().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.senders -= 1;
+            if state.senders > 0 {
+                return;
+            }
+            std::mem::take(&mut state.waiters)
+        };
+        for (_, waker) in waiters {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").field("receivers", &self.receiver_count()).finish()
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// Position of the next value to receive.
+    next: u64,
+    id: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// The next value.  Fails with `Lagged` if values were overwritten
+    /// before this receiver saw them (the next call continues with the
+    /// oldest one kept), and with `Closed` once every sender is gone and
+    /// every value has been received.
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.take(&mut self.next) {
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Lagged(n)) => return Poll::Ready(Err(RecvError::Lagged(n))),
+            Err(TryRecvError::Closed) => return Poll::Ready(Err(RecvError::Closed)),
+            Ok(value) => return Poll::Ready(Ok(value)),
+        }
+        match state.waiters.iter_mut().find(|(id, _)| *id == self.id) {
+            Some((_, waker)) => waker.clone_from(cx.waker()),
+            None => state.waiters.push_back((self.id, cx.waker().clone())),
+        }
+        Poll::Pending
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.shared.state.lock().unwrap().take(&mut self.next)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receivers -= 1;
+        state.waiters.retain(|(id, _)| *id != self.id);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").field("next", &self.next).finish()
+    }
+}
+
+/// There are no receivers; the value is handed back.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvError {
+    Closed,
+    /// This many values were skipped.
+    Lagged(u64),
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Closed => write!(f, "channel closed"),
+            RecvError::Lagged(n) => write!(f, "receiver lagged by {n} values"),
+        }
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel empty"),
+            TryRecvError::Closed => write!(f, "channel closed"),
+            TryRecvError::Lagged(n) => write!(f, "receiver lagged by {n} values"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MiniTokio, sync::model::interleavings};
+    use futures::task::{self, ArcWake};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn every_receiver_sees_every_value() {
+        let rt = MiniTokio::with_workers(4);
+        let (tx, rx) = channel(16);
+        let receivers: Vec<_> = std::iter::once(rx)
+            .chain((0..3).map(|_| tx.subscribe()))
+            .map(|mut rx| {
+                rt.spawn(async move {
+                    let mut values = Vec::new();
+                    while let Ok(value) = rx.recv().await {
+                        values.push(value);
+                    }
+                    values
+                })
+            })
+            .collect();
+        rt.block_on(async move {
+            for i in 0..10 {
+                assert_eq!(tx.send(i).unwrap(), 4);
+            }
+            drop(tx);
+            for receiver in receivers {
+                assert_eq!(receiver.await.unwrap(), (0..10).collect::<Vec<_>>());
+            }
+        });
+    }
+
+    #[test]
+    fn slow_receivers_lag() {
+        let (tx, mut rx) = channel(2);
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Lagged(3)));
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Ok(4));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Closed));
+    }
+
+    #[test]
+    fn send_without_receivers_fails() {
+        let (tx, rx) = channel(1);
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+        let mut late = tx.subscribe();
+        tx.send(2).unwrap();
+        assert_eq!(late.try_recv(), Ok(2));
+    }
+
+    #[derive(Default)]
+    struct Flag(AtomicBool);
+
+    impl ArcWake for Flag {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Two receivers start polling, and poll again only when woken, while
+    /// the only sender sends once and is then dropped.  In every order both
+    /// receivers get the value and then `Closed`, so no wake-up was lost.
+    #[test]
+    fn model_send_and_close() {
+        interleavings(&[2, 2, 2], |schedule| {
+            let (tx, rx) = channel(1);
+            let mut receivers = [rx, tx.subscribe()];
+            let mut tx = Some(tx);
+            let flags = [Arc::new(Flag::default()), Arc::new(Flag::default())];
+            let mut seen: [Vec<Result<i32, RecvError>>; 2] = Default::default();
+            // Receive like a task would: until `Pending` or `Closed`.
+            let mut drive = |i: usize, force: bool| {
+                if !flags[i].0.swap(false, Ordering::SeqCst) && !force || seen[i].contains(&Err(RecvError::Closed)) {
+                    return;
+                }
+                let waker = task::waker(flags[i].clone());
+                while let Poll::Ready(result) = receivers[i].poll_recv(&mut Context::from_waker(&waker)) {
+                    let closed = result == Err(RecvError::Closed);
+                    seen[i].push(result);
+                    if closed {
+                        break;
+                    }
+                }
+            };
+            let mut step = [0; 3];
+            for &i in schedule {
+                match (i, step[i]) {
+                    (2, 0) => assert_eq!(tx.as_ref().unwrap().send(7), Ok(2)),
+                    (2, _) => tx = None,
+                    (_, 0) => drive(i, true),
+                    _ => drive(i, false),
+                }
+                step[i] += 1;
+            }
+            drive(0, false);
+            drive(1, false);
+            for seen in &seen {
+                assert_eq!(seen, &[Ok(7), Err(RecvError::Closed)], "in {schedule:?}");
+            }
+        });
+    }
+}
@@ -0,0 +1,27 @@
+//! Synchronization primitives and channels for tasks.
+//!
+//! Waiting never blocks a worker: a task that cannot proceed registers its
+//! waker and returns `Pending`.  None of these types depend on the runtime,
+//! so they work on any executor.
+//!
+//! Waiters are served first come, first served.  `Mutex` and `RwLock` are
+//! built on `Semaphore`, whose queue never lets a small acquisition overtake
+//! a larger one waiting ahead of it; that is what keeps a waiting writer from
+//! being starved by a stream of readers.  Dropping a future that is waiting
+//! gives up its place, and hands on anything it was granted in the meantime.
+
+pub mod broadcast;
+pub mod mpsc;
+mod mutex;
+mod notify;
+pub mod oneshot;
+mod rwlock;
+mod semaphore;
+
+#[cfg(test)]
+mod model;
+
+pub use mutex::{Mutex, MutexGuard, TryLockError};
+pub use notify::{Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{AcquireError, Semaphore, SemaphorePermit, TryAcquireError};
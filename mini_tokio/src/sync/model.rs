@@ -0,0 +1,95 @@
+//! Exhaustive interleaving checks for the waker bookkeeping.
+//!
+//! In the spirit of loom, but at the granularity of whole operations: a
+//! test describes a few actors as numbered steps, and `interleavings` runs
+//! it once for every order in which those steps can happen.  The wakers
+//! only record that they were woken, so a test drives a pending future only
+//! after a wake-up and a lost wake-up shows up as a future stuck forever.
+
+use std::{
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, Waker},
+};
+
+use futures::task::{self, ArcWake};
+
+/// Call `check` with every schedule that runs `steps[i]` steps of actor `i`,
+/// keeping each actor's steps in order.  A schedule lists the actor taking
+/// each step.
+pub(crate) fn interleavings(steps: &[usize], mut check: impl FnMut(&[usize])) {
+    fn go(remaining: &mut [usize], schedule: &mut Vec<usize>, check: &mut impl FnMut(&[usize])) {
+        if remaining.iter().all(|&n| n == 0) {
+            check(schedule);
+            return;
+        }
+        for actor in 0..remaining.len() {
+            if remaining[actor] > 0 {
+                remaining[actor] -= 1;
+                schedule.push(actor);
+                go(remaining, schedule, check);
+                schedule.pop();
+                remaining[actor] += 1;
+            }
+        }
+    }
+    go(&mut steps.to_vec(), &mut Vec::new(), &mut check);
+}
+
+#[derive(Default)]
+struct Flag(AtomicBool);
+
+impl ArcWake for Flag {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A future under test, with a waker of its own.
+pub(crate) struct Actor<'a, T> {
+    future: Option<Pin<Box<dyn Future<Output = T> + 'a>>>,
+    flag: Arc<Flag>,
+    waker: Waker,
+    /// Set by the first poll that returned `Pending`.
+    pub(crate) queued: bool,
+}
+
+impl<'a, T> Actor<'a, T> {
+    pub(crate) fn new(future: impl Future<Output = T> + 'a) -> Self {
+        let flag = Arc::new(Flag::default());
+        let waker = task::waker(flag.clone());
+        Actor { future: Some(Box::pin(future)), flag, waker, queued: false }
+    }
+
+    pub(crate) fn is_pending(&self) -> bool {
+        self.future.is_some()
+    }
+
+    /// Poll the future if it is still pending.  Clears the wake-up flag.
+    pub(crate) fn poll(&mut self) -> Option<T> {
+        let future = self.future.as_mut()?;
+        self.flag.0.store(false, Ordering::SeqCst);
+        match future.as_mut().poll(&mut Context::from_waker(&self.waker)) {
+            Poll::Ready(output) => {
+                self.future = None;
+                Some(output)
+            }
+            Poll::Pending => {
+                self.queued = true;
+                None
+            }
+        }
+    }
+
+    /// Poll the future only if it has been woken since the last poll.
+    pub(crate) fn poll_if_woken(&mut self) -> Option<T> {
+        if self.flag.0.load(Ordering::SeqCst) { self.poll() } else { None }
+    }
+
+    pub(crate) fn cancel(&mut self) {
+        self.future = None;
+    }
+}
@@ -0,0 +1,355 @@
+//! A bounded multi-producer, single-consumer channel.
+//!
+//! Each queued value holds one of `capacity` semaphore permits, so a full
+//! channel makes `send` wait, and waiting senders get their turn in the
+//! order they started waiting.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use futures::task::AtomicWaker;
+
+use super::semaphore::{Acquire, Semaphore};
+
+/// Create a channel that buffers up to `capacity` values.
+///
+/// Panics if `capacity` is zero.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc channel capacity must be positive");
+    let chan = Arc::new(Chan {
+        permits: Semaphore::new(capacity),
+        capacity,
+        queue: Mutex::new(VecDeque::with_capacity(capacity)),
+        rx_waker: AtomicWaker::new(),
+        senders: AtomicUsize::new(1),
+    });
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+struct Chan<T> {
+    /// Closed when the receiver goes away.
+    permits: Semaphore,
+    capacity: usize,
+    queue: Mutex<VecDeque<T>>,
+    rx_waker: AtomicWaker,
+    senders: AtomicUsize,
+}
+
+impl<T> Chan<T> {
+    fn push(&self, value: T) {
+        self.queue.lock().unwrap().push_back(value);
+        self.rx_waker.wake();
+    }
+}
+
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Wait for room in the channel and queue `value`.  Fails, handing the
+    /// value back, if the receiver is gone.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match Acquire::new(&self.chan.permits, 1).await {
+            Ok(()) => {
+                self.chan.push(value);
+                Ok(())
+            }
+            Err(_) => Err(SendError(value)),
+        }
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match self.chan.permits.try_acquire() {
+            Ok(permit) => {
+                permit.forget();
+                self.chan.push(value);
+                Ok(())
+            }
+            Err(super::TryAcquireError::Closed) => Err(TrySendError::Closed(value)),
+            Err(super::TryAcquireError::NoPermits) => Err(TrySendError::Full(value)),
+        }
+    }
+
+    /// Whether the receiver was dropped or closed.
+    pub fn is_closed(&self) -> bool {
+        self.chan.permits.is_closed()
+    }
+
+    /// How many more values fit without waiting.
+    pub fn capacity(&self) -> usize {
+        self.chan.permits.available_permits()
+    }
+
+    pub fn max_capacity(&self) -> usize {
+        self.chan.capacity
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.senders.fetch_add(1, Ordering::Relaxed);
+        Sender { chan: self.chan.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.chan.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.chan.rx_waker.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").field("capacity", &self.capacity()).finish()
+    }
+}
+
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// The next value, or `None` once every sender is gone and the buffer
+    /// is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if let Some(value) = self.pop() {
+            return Poll::Ready(Some(value));
+        }
+        self.chan.rx_waker.register(cx.waker());
+        // Checked before the queue: the last sender drops after its last
+        // push.
+        let disconnected = self.chan.senders.load(Ordering::Acquire) == 0;
+        match self.pop() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if disconnected => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let disconnected = self.chan.senders.load(Ordering::Acquire) == 0;
+        match self.pop() {
+            Some(value) => Ok(value),
+            None if disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Stop accepting values.  Pending and later sends fail; values already
+    /// queued can still be received.
+    pub fn close(&mut self) {
+        self.chan.permits.close();
+    }
+
+    fn pop(&self) -> Option<T> {
+        let value = self.chan.queue.lock().unwrap().pop_front()?;
+        self.chan.permits.add_permits(1);
+        Some(value)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+        // Nobody will receive them; drop them now rather than with the
+        // last sender.
+        let values = std::mem::take(&mut *self.chan.queue.lock().unwrap());
+        drop(values);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").field("queued", &self.chan.queue.lock().unwrap().len()).finish()
+    }
+}
+
+/// The receiver is gone; the value is handed back.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+#[derive(PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(value) | TrySendError::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full(..)"),
+            TrySendError::Closed(_) => write!(f, "Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "channel full"),
+            TrySendError::Closed(_) => write!(f, "channel closed"),
+        }
+    }
+}
+
+impl<T> std::error::Error for TrySendError<T> {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel empty"),
+            TryRecvError::Disconnected => write!(f, "channel disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MiniTokio,
+        sync::model::{Actor, interleavings},
+    };
+
+    #[test]
+    fn many_producers_one_consumer() {
+        let rt = MiniTokio::with_workers(4);
+        let (tx, mut rx) = channel(4);
+        for producer in 0..8u64 {
+            let tx = tx.clone();
+            rt.spawn(async move {
+                for i in 0..250 {
+                    tx.send(producer * 1000 + i).await.unwrap();
+                }
+            });
+        }
+        drop(tx);
+        let received = rt.block_on(async move {
+            let mut last = [None; 8];
+            let mut count = 0;
+            while let Some(value) = rx.recv().await {
+                // Each producer's values arrive in the order it sent them.
+                let producer = (value / 1000) as usize;
+                assert!(last[producer] < Some(value));
+                last[producer] = Some(value);
+                count += 1;
+            }
+            count
+        });
+        assert_eq!(received, 2000);
+    }
+
+    #[test]
+    fn full_channel_and_closed_receiver() {
+        let (tx, mut rx) = channel(1);
+        tx.try_send(1).unwrap();
+        assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(tx.capacity(), 0);
+        rx.close();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Closed(3)));
+        // Queued values survive the close.
+        assert_eq!(rx.try_recv(), Ok(1));
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn waiting_senders_fail_when_the_receiver_is_dropped() {
+        let (tx, rx) = channel(1);
+        tx.try_send(()).unwrap();
+        let mut blocked = Actor::new(tx.send(()));
+        assert!(blocked.poll().is_none());
+        drop(rx);
+        assert!(blocked.poll_if_woken().unwrap().is_err());
+    }
+
+    /// Capacity 1, two senders with one value each, and a receiver taking
+    /// a value on each of its steps if one is there.  In every order both
+    /// values arrive, with no wake-up lost on either side.
+    #[test]
+    fn model_two_senders_one_slot() {
+        interleavings(&[2, 2, 3], |schedule| {
+            let (tx, mut rx) = channel(1);
+            let mut senders = [Actor::new(tx.send(1)), Actor::new(tx.send(2))];
+            // The receiver polls on every step, so its wake-ups do not matter.
+            let waker = futures::task::noop_waker();
+            let mut received = Vec::new();
+            let mut step = [0; 3];
+            for &i in schedule {
+                match (i, step[i]) {
+                    (2, _) => {
+                        if let Poll::Ready(value) = rx.poll_recv(&mut Context::from_waker(&waker)) {
+                            received.push(value.unwrap());
+                        }
+                    }
+                    (_, 0) => assert!(senders[i].poll().is_none_or(|r| r.is_ok())),
+                    _ => assert!(senders[i].poll_if_woken().is_none_or(|r| r.is_ok())),
+                }
+                step[i] += 1;
+            }
+            // Let the senders that were woken finish, then drain.
+            loop {
+                while let Ok(value) = rx.try_recv() {
+                    received.push(value);
+                }
+                let mut progress = false;
+                for sender in &mut senders {
+                    if let Some(result) = sender.poll_if_woken() {
+                        result.unwrap();
+                        progress = true;
+                    }
+                }
+                if !progress {
+                    break;
+                }
+            }
+            assert!(senders.iter().all(|s| !s.is_pending()), "sender never woken in {schedule:?}");
+            received.sort();
+            assert_eq!(received, [1, 2], "in {schedule:?}");
+        });
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    cell::UnsafeCell,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use super::semaphore::{Acquire, Semaphore};
+
+/// A mutual exclusion lock whose `lock` waits asynchronously.
+///
+/// Tasks get the lock in the order they asked for it.  Unlike
+/// `std::sync::Mutex`, the guard may be held across an `.await`, and a
+/// panic while holding it does not poison the lock.
+pub struct Mutex<T: ?Sized> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: the semaphore hands out one permit, so at most one guard gives
+// access to `value` at a time.
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex { semaphore: Semaphore::new(1), value: UnsafeCell::new(value) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        // The semaphore is never closed.
+        let _ = Acquire::new(&self.semaphore, 1).await;
+        MutexGuard { lock: self }
+    }
+
+    /// Take the lock if it is free and nobody is waiting for it.
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, T>, TryLockError> {
+        match self.semaphore.try_acquire() {
+            Ok(permit) => {
+                permit.forget();
+                Ok(MutexGuard { lock: self })
+            }
+            Err(_) => Err(TryLockError(())),
+        }
+    }
+
+    /// No locking needed: `&mut self` proves nobody else holds the lock.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Ok(guard) => d.field("value", &&*guard),
+            Err(_) => d.field("value", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+/// Access to the value behind a `Mutex`; unlocks when dropped.
+#[must_use = "the lock is released immediately if the guard is not held"]
+pub struct MutexGuard<'a, T: ?Sized> {
+    lock: &'a Mutex<T>,
+}
+
+// SAFETY: sharing the guard shares `&T`.
+unsafe impl<T: ?Sized + Send + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the only permit.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the only permit.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(1);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// The lock was held, or other tasks were already waiting for it.
+#[derive(Debug)]
+pub struct TryLockError(pub(super) ());
+
+impl fmt::Display for TryLockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lock is held by another task")
+    }
+}
+
+impl std::error::Error for TryLockError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MiniTokio, sync::model::Actor, yield_now};
+    use std::sync::Arc;
+
+    #[test]
+    fn guards_exclude_each_other_across_awaits() {
+        let rt = MiniTokio::with_workers(4);
+        let counter = Arc::new(Mutex::new(0u64));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = counter.clone();
+                rt.spawn(async move {
+                    for _ in 0..500 {
+                        let mut guard = counter.lock().await;
+                        let value = *guard;
+                        // Another task would see the stale value here if the
+                        // lock let it in.
+                        yield_now().await;
+                        *guard = value + 1;
+                    }
+                })
+            })
+            .collect();
+        rt.block_on(async {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+        assert_eq!(*counter.try_lock().unwrap(), 4000);
+    }
+
+    #[test]
+    fn lock_is_handed_over_in_request_order() {
+        let mutex = Mutex::new(Vec::new());
+        let guard = mutex.try_lock().unwrap();
+        let mut waiters: Vec<_> = (0..3).map(|_| Actor::new(mutex.lock())).collect();
+        for waiter in &mut waiters {
+            assert!(waiter.poll().is_none());
+        }
+        assert!(mutex.try_lock().is_err());
+        drop(guard);
+        // Each waiter is woken only once the one before it unlocks.
+        for (i, waiter) in waiters.iter_mut().enumerate() {
+            let mut guard = waiter.poll_if_woken().expect("not woken in order");
+            guard.push(i);
+        }
+        drop(waiters);
+        assert_eq!(mutex.into_inner(), [0, 1, 2]);
+    }
+}
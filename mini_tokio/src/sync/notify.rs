@@ -0,0 +1,256 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU8, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use futures::task::AtomicWaker;
+
+// How a waiter was notified.
+const WAITING: u8 = 0;
+const NOTIFIED_ONE: u8 = 1;
+const NOTIFIED_ALL: u8 = 2;
+
+/// Wakes tasks waiting in `notified()`.
+///
+/// `notify_one` wakes the longest-waiting task, or, if nobody is waiting,
+/// stores a single permit that the next `notified()` consumes at once.
+/// `notify_waiters` wakes every `Notified` future created before the call
+/// and stores no permit.
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+struct State {
+    permit: bool,
+    waiters: VecDeque<Arc<Waiter>>,
+    /// Bumped by `notify_waiters`.
+    generation: u64,
+}
+
+struct Waiter {
+    /// Set under the state lock.
+    notified: AtomicU8,
+    waker: AtomicWaker,
+}
+
+impl State {
+    /// Wake the first waiter, or store the permit.
+    fn notify_one(&mut self) -> Option<Arc<Waiter>> {
+        let waiter = self.waiters.pop_front();
+        match &waiter {
+            Some(waiter) => waiter.notified.store(NOTIFIED_ONE, Ordering::Release),
+            None => self.permit = true,
+        }
+        waiter
+    }
+}
+
+impl Notify {
+    pub const fn new() -> Notify {
+        Notify { state: Mutex::new(State { permit: false, waiters: VecDeque::new(), generation: 0 }) }
+    }
+
+    /// Wait for a notification.  The future counts as waiting for
+    /// `notify_waiters` from the moment it is created; its place in the
+    /// `notify_one` queue is taken on the first poll.
+    pub fn notified(&self) -> Notified<'_> {
+        let generation = self.state.lock().unwrap().generation;
+        Notified { notify: self, generation, waiter: None }
+    }
+
+    pub fn notify_one(&self) {
+        let waiter = self.state.lock().unwrap().notify_one();
+        if let Some(waiter) = waiter {
+            waiter.waker.wake();
+        }
+    }
+
+    pub fn notify_waiters(&self) {
+        let waiters: Vec<_> = {
+            let mut state = self.state.lock().unwrap();
+            state.generation += 1;
+            state.waiters.drain(..).collect()
+        };
+        for waiter in waiters {
+            waiter.notified.store(NOTIFIED_ALL, Ordering::Release);
+            waiter.waker.wake();
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("Notify").field("permit", &state.permit).field("waiters", &state.waiters.len()).finish()
+    }
+}
+
+/// Future returned by `Notify::notified`.
+///
+/// Dropping it after `notify_one` picked it but before it completed passes
+/// the notification on to the next waiter.
+#[must_use = "futures do nothing unless polled"]
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    generation: u64,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(waiter) = &self.waiter {
+            waiter.waker.register(cx.waker());
+            if waiter.notified.load(Ordering::Acquire) == WAITING {
+                return Poll::Pending;
+            }
+            self.waiter = None;
+            return Poll::Ready(());
+        }
+
+        let mut state = self.notify.state.lock().unwrap();
+        if state.generation != self.generation {
+            return Poll::Ready(());
+        }
+        if state.permit {
+            state.permit = false;
+            return Poll::Ready(());
+        }
+        let waiter = Arc::new(Waiter { notified: AtomicU8::new(WAITING), waker: AtomicWaker::new() });
+        waiter.waker.register(cx.waker());
+        state.waiters.push_back(waiter.clone());
+        drop(state);
+        self.waiter = Some(waiter);
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+        let next = {
+            let mut state = self.notify.state.lock().unwrap();
+            match waiter.notified.load(Ordering::Acquire) {
+                WAITING => {
+                    if let Some(index) = state.waiters.iter().position(|w| Arc::ptr_eq(w, &waiter)) {
+                        state.waiters.remove(index);
+                    }
+                    None
+                }
+                NOTIFIED_ONE => state.notify_one(),
+                _ => None,
+            }
+        };
+        if let Some(next) = next {
+            next.waker.wake();
+        }
+    }
+}
+
+impl fmt::Debug for Notified<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notified").field("queued", &self.waiter.is_some()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MiniTokio,
+        sync::model::{Actor, interleavings},
+    };
+
+    #[test]
+    fn notify_one_stores_a_single_permit() {
+        let notify = Notify::new();
+        notify.notify_one();
+        notify.notify_one();
+        assert!(Actor::new(notify.notified()).poll().is_some());
+        assert!(Actor::new(notify.notified()).poll().is_none());
+    }
+
+    #[test]
+    fn notify_waiters_wakes_futures_created_before_it() {
+        let notify = Notify::new();
+        let mut polled = Actor::new(notify.notified());
+        assert!(polled.poll().is_none());
+        let mut unpolled = Actor::new(notify.notified());
+        notify.notify_waiters();
+        let mut later = Actor::new(notify.notified());
+        assert!(polled.poll_if_woken().is_some());
+        assert!(unpolled.poll().is_some());
+        assert!(later.poll().is_none());
+    }
+
+    #[test]
+    fn waiters_are_woken_in_order() {
+        let notify = Notify::new();
+        let mut waiters: Vec<_> = (0..3).map(|_| Actor::new(notify.notified())).collect();
+        for waiter in &mut waiters {
+            assert!(waiter.poll().is_none());
+        }
+        for i in 0..3 {
+            notify.notify_one();
+            assert!(waiters[i].poll_if_woken().is_some());
+            assert!(waiters[i + 1..].iter_mut().all(|w| w.poll_if_woken().is_none()));
+        }
+    }
+
+    #[test]
+    fn wakes_a_task_on_another_worker() {
+        let rt = MiniTokio::with_workers(2);
+        let notify = Arc::new(Notify::new());
+        let waiter = {
+            let notify = notify.clone();
+            rt.spawn(async move { notify.notified().await })
+        };
+        notify.notify_one();
+        rt.block_on(waiter).unwrap();
+    }
+
+    /// Two waiters, one of which gives up after its first poll, and one
+    /// `notify_one`.  In every order the notification is not swallowed: it
+    /// reaches the waiter that stays unless the other one consumed it.
+    #[test]
+    fn model_notify_one_with_cancellation() {
+        interleavings(&[2, 2, 1], |schedule| {
+            let notify = Notify::new();
+            let mut waiters = [Actor::new(notify.notified()), Actor::new(notify.notified())];
+            let mut completed = [false; 2];
+            let mut step = [0; 3];
+            for &i in schedule {
+                match (i, step[i]) {
+                    (2, _) => notify.notify_one(),
+                    (_, 0) => completed[i] = waiters[i].poll().is_some(),
+                    (1, _) => waiters[1].cancel(),
+                    _ => completed[i] |= waiters[i].poll_if_woken().is_some(),
+                }
+                step[i] += 1;
+            }
+            completed[0] |= waiters[0].poll_if_woken().is_some();
+
+            let permit = notify.state.lock().unwrap().permit;
+            if waiters[0].is_pending() {
+                assert!(!permit, "waiter 0 left waiting next to a stored permit in {schedule:?}");
+                assert!(completed[1], "notification lost in {schedule:?}");
+            }
+            assert!(notify.state.lock().unwrap().waiters.len() <= usize::from(waiters[0].is_pending()));
+        });
+    }
+}
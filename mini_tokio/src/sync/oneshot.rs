@@ -0,0 +1,253 @@
+//! A channel for sending a single value between tasks.
+
+use std::{
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// Create a connected sender and receiver.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Mutex::new(State {
+        value: None,
+        sender_dropped: false,
+        receiver_closed: false,
+        rx_waker: None,
+        tx_waker: None,
+    }));
+    (Sender { inner: inner.clone() }, Receiver { inner })
+}
+
+struct State<T> {
+    value: Option<T>,
+    sender_dropped: bool,
+    receiver_closed: bool,
+    rx_waker: Option<Waker>,
+    /// The task waiting in `Sender::closed`.
+    tx_waker: Option<Waker>,
+}
+
+fn register(slot: &mut Option<Waker>, cx: &Context<'_>) {
+    match slot {
+        Some(waker) if waker.will_wake(cx.waker()) => {}
+        _ => *slot = Some(cx.waker().clone()),
+    }
+}
+
+/// Sends the value; dropping it unsent makes the receiver fail with
+/// `RecvError`.
+pub struct Sender<T> {
+    inner: Arc<Mutex<State<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Hand `value` to the receiver, or back to the caller if the receiver
+    /// is gone.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let waker = {
+            let mut state = self.inner.lock().unwrap();
+            if state.receiver_closed {
+                return Err(value);
+            }
+            state.value = Some(value);
+            state.rx_waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Whether the receiver was dropped or closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock().unwrap().receiver_closed
+    }
+
+    /// Wait until the receiver is dropped or closed, e.g. to stop working on
+    /// a value nobody wants any more.
+    pub async fn closed(&mut self) {
+        std::future::poll_fn(|cx| {
+            let mut state = self.inner.lock().unwrap();
+            if state.receiver_closed {
+                return Poll::Ready(());
+            }
+            register(&mut state.tx_waker, cx);
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.inner.lock().unwrap();
+            state.sender_dropped = true;
+            state.rx_waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").field("closed", &self.is_closed()).finish()
+    }
+}
+
+/// Awaiting it yields the sent value, or `RecvError` if the sender was
+/// dropped without sending.
+pub struct Receiver<T> {
+    inner: Arc<Mutex<State<T>>>,
+}
+
+impl<T> Receiver<T> {
+    /// Take the value if it has been sent.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.inner.lock().unwrap();
+        match state.value.take() {
+            Some(value) => Ok(value),
+            None if state.sender_dropped => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Refuse the value: a later `send` fails.  A value sent before this can
+    /// still be received.
+    pub fn close(&mut self) {
+        let waker = {
+            let mut state = self.inner.lock().unwrap();
+            state.receiver_closed = true;
+            state.tx_waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.inner.lock().unwrap();
+        if let Some(value) = state.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if state.sender_dropped {
+            return Poll::Ready(Err(RecvError(())));
+        }
+        register(&mut state.rx_waker, cx);
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// The sender was dropped without sending.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RecvError(());
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel empty"),
+            TryRecvError::Closed => write!(f, "channel closed"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MiniTokio,
+        sync::model::{Actor, interleavings},
+    };
+
+    #[test]
+    fn sends_across_tasks() {
+        let rt = MiniTokio::with_workers(2);
+        let (tx, rx) = channel();
+        rt.spawn(async move { tx.send("done").unwrap() });
+        assert_eq!(rt.block_on(rx), Ok("done"));
+    }
+
+    #[test]
+    fn dropping_the_sender_fails_the_receiver() {
+        let (tx, mut rx) = channel::<()>();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Closed));
+    }
+
+    #[test]
+    fn closed_resolves_when_the_receiver_goes_away() {
+        let (mut tx, rx) = channel::<()>();
+        let mut closed = Actor::new(tx.closed());
+        assert!(closed.poll().is_none());
+        drop(rx);
+        assert!(closed.poll_if_woken().is_some());
+        drop(closed);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(()), Err(()));
+    }
+
+    /// The receiver polls twice, and once more at the end, only if woken
+    /// after the first poll, while the sender either sends or is dropped.
+    /// In every order the receiver ends up with what the sender did.
+    #[test]
+    fn model_send_or_drop() {
+        for send in [true, false] {
+            interleavings(&[2, 1], |schedule| {
+                let (tx, rx) = channel();
+                let mut tx = Some(tx);
+                let mut rx = Actor::new(rx);
+                let mut result = None;
+                let mut polls = 0;
+                for &i in schedule {
+                    if i == 0 {
+                        result = result.or(if polls == 0 { rx.poll() } else { rx.poll_if_woken() });
+                        polls += 1;
+                    } else if send {
+                        tx.take().unwrap().send(7).unwrap();
+                    } else {
+                        tx = None;
+                    }
+                }
+                result = result.or(rx.poll_if_woken());
+                let expected = if send { Ok(7) } else { Err(RecvError(())) };
+                assert_eq!(result, Some(expected), "in {schedule:?}");
+            });
+        }
+    }
+}
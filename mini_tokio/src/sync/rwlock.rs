@@ -0,0 +1,186 @@
+use std::{
+    cell::UnsafeCell,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use super::{
+    TryLockError,
+    semaphore::{Acquire, Semaphore},
+};
+
+/// Readers hold one permit each; a writer takes them all.
+const MAX_READS: usize = u32::MAX as usize >> 3;
+
+/// A reader-writer lock whose `read` and `write` wait asynchronously.
+///
+/// Requests are served in order, so once a writer is waiting, readers that
+/// come after it wait for it too.
+pub struct RwLock<T: ?Sized> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: readers share `&T` across threads, a writer gets `&mut T`.
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> RwLock<T> {
+        RwLock { semaphore: Semaphore::new(MAX_READS), value: UnsafeCell::new(value) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        // The semaphore is never closed.
+        let _ = Acquire::new(&self.semaphore, 1).await;
+        RwLockReadGuard { lock: self }
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let _ = Acquire::new(&self.semaphore, MAX_READS).await;
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Result<RwLockReadGuard<'_, T>, TryLockError> {
+        let permit = self.semaphore.try_acquire().map_err(|_| TryLockError(()))?;
+        permit.forget();
+        Ok(RwLockReadGuard { lock: self })
+    }
+
+    pub fn try_write(&self) -> Result<RwLockWriteGuard<'_, T>, TryLockError> {
+        let permit = self.semaphore.try_acquire_many(MAX_READS as u32).map_err(|_| TryLockError(()))?;
+        permit.forget();
+        Ok(RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        match self.try_read() {
+            Ok(guard) => d.field("value", &&*guard),
+            Err(_) => d.field("value", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+/// Shared access to the value behind an `RwLock`.
+#[must_use = "the lock is released immediately if the guard is not held"]
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: no writer holds the lock while we hold a permit.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(1);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Exclusive access to the value behind an `RwLock`.
+#[must_use = "the lock is released immediately if the guard is not held"]
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+// SAFETY: sharing the guard shares `&T`.
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds every permit.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds every permit.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(MAX_READS);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::model::Actor;
+
+    #[test]
+    fn readers_share_and_writers_exclude() {
+        let lock = RwLock::new(1);
+        let first = lock.try_read().unwrap();
+        let second = lock.try_read().unwrap();
+        assert_eq!(*first + *second, 2);
+        assert!(lock.try_write().is_err());
+        drop((first, second));
+        let mut writer = lock.try_write().unwrap();
+        *writer = 2;
+        assert!(lock.try_read().is_err());
+        drop(writer);
+        assert_eq!(*lock.try_read().unwrap(), 2);
+    }
+
+    #[test]
+    fn waiting_writer_is_not_starved_by_later_readers() {
+        let lock = RwLock::new(0);
+        let reader = lock.try_read().unwrap();
+        let mut writer = Actor::new(lock.write());
+        assert!(writer.poll().is_none());
+        // A reader arriving after the writer queues behind it.
+        let mut late_reader = Actor::new(lock.read());
+        assert!(late_reader.poll().is_none());
+        assert!(lock.try_read().is_err());
+
+        drop(reader);
+        let mut guard = writer.poll_if_woken().expect("writer not woken");
+        assert!(late_reader.poll_if_woken().is_none());
+        *guard = 1;
+        drop(guard);
+        assert_eq!(*late_reader.poll_if_woken().expect("reader not woken"), 1);
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use futures::task::AtomicWaker;
+
+/// A counting semaphore with a FIFO queue of waiters.
+///
+/// Permits go to waiters strictly in the order they started waiting: if the
+/// waiter at the head needs more permits than are available, everyone
+/// behind it waits too, even if they need fewer.
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    waiters: VecDeque<Arc<Waiter>>,
+    closed: bool,
+}
+
+struct Waiter {
+    needed: usize,
+    /// Set, under the state lock, when the permits have been handed over.
+    granted: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl State {
+    /// Hand permits to the waiters at the head of the queue.  Returns the
+    /// waiters to wake once the lock is released.
+    fn grant(&mut self) -> Vec<Arc<Waiter>> {
+        let mut granted = Vec::new();
+        while let Some(waiter) = self.waiters.front()
+            && waiter.needed <= self.permits
+        {
+            self.permits -= waiter.needed;
+            waiter.granted.store(true, Ordering::Release);
+            granted.extend(self.waiters.pop_front());
+        }
+        granted
+    }
+}
+
+fn wake_all(waiters: Vec<Arc<Waiter>>) {
+    for waiter in waiters {
+        waiter.waker.wake();
+    }
+}
+
+impl Semaphore {
+    /// The most permits a semaphore can hold.
+    pub const MAX_PERMITS: usize = usize::MAX >> 3;
+
+    pub const fn new(permits: usize) -> Semaphore {
+        assert!(permits <= Self::MAX_PERMITS, "a semaphore holds at most `MAX_PERMITS` permits");
+        Semaphore { state: Mutex::new(State { permits, waiters: VecDeque::new(), closed: false }) }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    /// Add `n` permits, waking the waiters they are enough for.
+    pub fn add_permits(&self, n: usize) {
+        let granted = {
+            let mut state = self.state.lock().unwrap();
+            state.permits += n;
+            assert!(state.permits <= Self::MAX_PERMITS, "a semaphore holds at most `MAX_PERMITS` permits");
+            state.grant()
+        };
+        wake_all(granted);
+    }
+
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, AcquireError> {
+        self.acquire_many(1).await
+    }
+
+    /// Wait for `n` permits at once.  Fails once the semaphore is closed.
+    pub async fn acquire_many(&self, n: u32) -> Result<SemaphorePermit<'_>, AcquireError> {
+        Acquire::new(self, n as usize).await?;
+        Ok(SemaphorePermit { semaphore: self, permits: n as usize })
+    }
+
+    /// Take a permit if one is available and nobody is waiting.
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: u32) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(TryAcquireError::Closed);
+        }
+        if !state.waiters.is_empty() || state.permits < n as usize {
+            return Err(TryAcquireError::NoPermits);
+        }
+        state.permits -= n as usize;
+        Ok(SemaphorePermit { semaphore: self, permits: n as usize })
+    }
+
+    /// Fail every pending and future acquisition.  Permits already handed
+    /// out stay valid.
+    pub fn close(&self) {
+        let waiters: Vec<_> = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            state.waiters.drain(..).collect()
+        };
+        wake_all(waiters);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("Semaphore").field("permits", &state.permits).field("closed", &state.closed).finish()
+    }
+}
+
+/// Waits for permits; gives up its place in the queue when dropped.
+pub(crate) struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    needed: usize,
+    /// Our entry in the queue, once we are in it.
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl<'a> Acquire<'a> {
+    pub(crate) fn new(semaphore: &'a Semaphore, needed: usize) -> Self {
+        Acquire { semaphore, needed, waiter: None }
+    }
+}
+
+impl Future for Acquire<'_> {
+    type Output = Result<(), AcquireError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(waiter) = &self.waiter {
+            waiter.waker.register(cx.waker());
+            if waiter.granted.load(Ordering::Acquire) {
+                self.waiter = None;
+                return Poll::Ready(Ok(()));
+            }
+            if self.semaphore.is_closed() {
+                // `close` already took us out of the queue.
+                self.waiter = None;
+                return Poll::Ready(Err(AcquireError(())));
+            }
+            return Poll::Pending;
+        }
+
+        let mut state = self.semaphore.state.lock().unwrap();
+        if state.closed {
+            return Poll::Ready(Err(AcquireError(())));
+        }
+        if state.waiters.is_empty() && state.permits >= self.needed {
+            state.permits -= self.needed;
+            return Poll::Ready(Ok(()));
+        }
+        let waiter = Arc::new(Waiter { needed: self.needed, granted: AtomicBool::new(false), waker: AtomicWaker::new() });
+        waiter.waker.register(cx.waker());
+        state.waiters.push_back(waiter.clone());
+        drop(state);
+        self.waiter = Some(waiter);
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+        let granted = {
+            let mut state = self.semaphore.state.lock().unwrap();
+            if waiter.granted.load(Ordering::Acquire) {
+                // Granted after our last poll: pass the permits on.
+                state.permits += waiter.needed;
+            } else if let Some(index) = state.waiters.iter().position(|w| Arc::ptr_eq(w, &waiter)) {
+                state.waiters.remove(index);
+            }
+            // Leaving the head of the queue may unblock those behind us.
+            state.grant()
+        };
+        wake_all(granted);
+    }
+}
+
+/// Permits taken from a `Semaphore`; they are returned when this is dropped.
+#[must_use = "the permits are released immediately if the permit is not held"]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// Keep the permits out of the semaphore for good.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+
+    pub fn num_permits(&self) -> usize {
+        self.permits
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+impl fmt::Debug for SemaphorePermit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SemaphorePermit").field("permits", &self.permits).finish()
+    }
+}
+
+/// The semaphore was closed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AcquireError(());
+
+impl fmt::Display for AcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "semaphore closed")
+    }
+}
+
+impl std::error::Error for AcquireError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryAcquireError {
+    Closed,
+    NoPermits,
+}
+
+impl fmt::Display for TryAcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryAcquireError::Closed => write!(f, "semaphore closed"),
+            TryAcquireError::NoPermits => write!(f, "no permits available"),
+        }
+    }
+}
+
+impl std::error::Error for TryAcquireError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MiniTokio,
+        sync::model::{Actor, interleavings},
+        time,
+    };
+    use std::{sync::atomic::AtomicUsize, time::Duration};
+
+    #[test]
+    fn limits_concurrency() {
+        let rt = MiniTokio::with_workers(4);
+        let semaphore = Arc::new(Semaphore::new(3));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..32)
+            .map(|_| {
+                let (semaphore, running, peak) = (semaphore.clone(), running.clone(), peak.clone());
+                rt.spawn(async move {
+                    let _permit = semaphore.acquire().await.unwrap();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    time::sleep(Duration::from_millis(2)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        rt.block_on(async {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+
+    #[test]
+    fn small_acquisitions_do_not_overtake_a_large_one() {
+        let semaphore = Semaphore::new(2);
+        let held = semaphore.try_acquire().unwrap();
+        let mut big = Actor::new(semaphore.acquire_many(2));
+        let mut small = Actor::new(semaphore.acquire());
+        assert!(big.poll().is_none());
+        // One permit is free, but `big` is ahead in the queue.
+        assert!(small.poll().is_none());
+        assert_eq!(semaphore.try_acquire().unwrap_err(), TryAcquireError::NoPermits);
+        drop(held);
+        let big_permit = big.poll_if_woken().unwrap().unwrap();
+        assert!(small.poll_if_woken().is_none());
+        drop(big_permit);
+        assert_eq!(small.poll_if_woken().unwrap().unwrap().num_permits(), 1);
+    }
+
+    #[test]
+    fn close_fails_waiters() {
+        let semaphore = Semaphore::new(0);
+        let mut waiter = Actor::new(semaphore.acquire());
+        assert!(waiter.poll().is_none());
+        semaphore.close();
+        assert_eq!(waiter.poll_if_woken().unwrap().unwrap_err(), AcquireError(()));
+        assert_eq!(semaphore.try_acquire().unwrap_err(), TryAcquireError::Closed);
+    }
+
+    /// Three tasks compete for one permit; the third gives up after its
+    /// first poll.  In every order: no wake-up is lost, permits are not
+    /// leaked, and queued waiters are served in the order they queued.
+    #[test]
+    fn model_acquire_release_cancel() {
+        interleavings(&[3, 3, 2], |schedule| {
+            let semaphore = Semaphore::new(1);
+            let mut actors: Vec<_> = (0..3).map(|_| Actor::new(semaphore.acquire())).collect();
+            let mut held: Vec<Option<SemaphorePermit<'_>>> = (0..3).map(|_| None).collect();
+            let mut step = [0; 3];
+            let mut queue_order = Vec::new();
+            let mut acquired = Vec::new();
+            for &i in schedule {
+                let result = match (i, step[i]) {
+                    (_, 0) => {
+                        let result = actors[i].poll();
+                        if actors[i].queued {
+                            queue_order.push(i);
+                        }
+                        result
+                    }
+                    (2, _) => {
+                        actors[2].cancel();
+                        held[2] = None;
+                        None
+                    }
+                    // Hold the permit for one step, then release it.
+                    _ if held[i].is_some() => {
+                        held[i] = None;
+                        None
+                    }
+                    _ => actors[i].poll_if_woken(),
+                };
+                if let Some(result) = result {
+                    held[i] = Some(result.unwrap());
+                    acquired.push(i);
+                }
+                step[i] += 1;
+            }
+            held.fill_with(|| None);
+            // Run whatever was woken until nothing changes.
+            loop {
+                let mut progress = false;
+                for (i, actor) in actors.iter_mut().enumerate().take(2) {
+                    if let Some(result) = actor.poll_if_woken() {
+                        drop(result.unwrap());
+                        acquired.push(i);
+                        progress = true;
+                    }
+                }
+                if !progress {
+                    break;
+                }
+            }
+
+            assert!(!actors[0].is_pending() && !actors[1].is_pending(), "lost wake-up in {schedule:?}");
+            assert_eq!(semaphore.available_permits(), 1, "leaked permits in {schedule:?}");
+            let served: Vec<_> = acquired.iter().filter(|&&i| i != 2 && queue_order.contains(&i)).collect();
+            let queued: Vec<_> = queue_order.iter().filter(|&&i| i != 2).collect();
+            assert_eq!(served, queued, "unfair order in {schedule:?}");
+        });
+    }
+}
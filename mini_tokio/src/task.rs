@@ -0,0 +1,323 @@
+//! The task harness run by the workers and the `JoinHandle` that waits for
+//! its output.
+
+use std::{
+    any::Any,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+    },
+    task::{Context, Poll, Waker},
+};
+
+use futures::task::{self, ArcWake};
+
+use crate::runtime::Shared;
+
+// Task states.  A wake-up only queues an `IDLE` task; one that arrives while
+// the task is being polled moves it to `NOTIFIED` and the worker queues it
+// again once the poll returns, so a task is never polled by two workers at
+// once.
+const IDLE: u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+const NOTIFIED: u8 = 3;
+const COMPLETE: u8 = 4;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+pub(crate) struct Task {
+    pub(crate) id: u64,
+    // Only the worker that moved `state` to `RUNNING` locks this, so the lock
+    // is never contended; it is what makes `Task` `Sync`.
+    future: Mutex<Option<BoxFuture>>,
+    state: AtomicU8,
+    aborted: AtomicBool,
+    shared: Weak<Shared>,
+}
+
+impl Task {
+    /// Wrap `future` in a harness that reports its output to the returned
+    /// handle, and queue it on `shared`.
+    pub(crate) fn spawn<F>(future: F, shared: &Arc<Shared>) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let join = Arc::new(JoinState::default());
+        let task = Arc::new(Task {
+            id: shared.next_task_id(),
+            future: Mutex::new(Some(Box::pin(Harness { future, join: join.clone() }))),
+            state: AtomicU8::new(SCHEDULED),
+            aborted: AtomicBool::new(false),
+            shared: Arc::downgrade(shared),
+        });
+        shared.register(&task);
+        shared.schedule(task.clone());
+        JoinHandle { join, task }
+    }
+
+    fn schedule(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) if next == SCHEDULED => break,
+                Ok(_) => return,
+                Err(actual) => state = actual,
+            }
+        }
+        // A task outliving its runtime is never polled again.
+        if let Some(shared) = self.shared.upgrade() {
+            shared.schedule(self.clone());
+        }
+    }
+
+    /// Poll the task once.  Called by a worker that took it off a queue.
+    pub(crate) fn run(self: Arc<Self>) {
+        if self
+            .state
+            .compare_exchange(SCHEDULED, RUNNING, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return;
+        }
+        let mut slot = self.future.lock().unwrap();
+        let done = match slot.as_mut() {
+            Some(future) if !self.aborted.load(Ordering::Acquire) => {
+                let waker = task::waker(self.clone());
+                let mut cx = Context::from_waker(&waker);
+                future.as_mut().poll(&mut cx).is_ready()
+            }
+            _ => true,
+        };
+        if done {
+            // Dropping the harness completes the join handle with
+            // `Cancelled` if the future did not finish.
+            *slot = None;
+            self.state.store(COMPLETE, Ordering::Release);
+            drop(slot);
+            if let Some(shared) = self.shared.upgrade() {
+                shared.unregister(self.id);
+            }
+            return;
+        }
+        drop(slot);
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            // Woken during the poll.
+            self.state.store(SCHEDULED, Ordering::Release);
+            if let Some(shared) = self.shared.upgrade() {
+                shared.schedule(self.clone());
+            }
+        }
+    }
+
+    /// Drop the future without polling it again; used at shutdown.
+    pub(crate) fn cancel(&self) {
+        self.aborted.store(true, Ordering::Release);
+        let future = self.future.lock().unwrap().take();
+        self.state.store(COMPLETE, Ordering::Release);
+        drop(future);
+    }
+}
+
+impl ArcWake for Task {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.schedule();
+    }
+}
+
+/// Polls the user's future, catching panics, and hands the result to the
+/// `JoinHandle`.
+struct Harness<F: Future> {
+    future: F,
+    join: Arc<JoinState<F::Output>>,
+}
+
+impl<F: Future> Future for Harness<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // SAFETY: `future` is never moved out of the harness, and the
+        // harness itself is pinned; `join` is not structurally pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => return Poll::Pending,
+            Ok(Poll::Ready(output)) => Ok(output),
+            Err(payload) => Err(JoinError::Panicked(payload)),
+        };
+        this.join.complete(result);
+        Poll::Ready(())
+    }
+}
+
+impl<F: Future> Drop for Harness<F> {
+    fn drop(&mut self) {
+        // No-op if the future already completed.
+        self.join.complete(Err(JoinError::Cancelled));
+    }
+}
+
+struct JoinState<T> {
+    inner: Mutex<JoinInner<T>>,
+}
+
+struct JoinInner<T> {
+    result: Option<Result<T, JoinError>>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+impl<T> Default for JoinState<T> {
+    fn default() -> Self {
+        JoinState { inner: Mutex::new(JoinInner { result: None, finished: false, waker: None }) }
+    }
+}
+
+impl<T> JoinState<T> {
+    fn complete(&self, result: Result<T, JoinError>) {
+        let waker = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.finished {
+                return;
+            }
+            inner.finished = true;
+            inner.result = Some(result);
+            inner.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// An owned permission to await a spawned task's output.
+///
+/// Dropping the handle detaches the task; it keeps running.  Awaiting it
+/// yields `Err(JoinError::Panicked(..))` if the task panicked and
+/// `Err(JoinError::Cancelled)` if it was aborted or the runtime shut down
+/// first.
+pub struct JoinHandle<T> {
+    join: Arc<JoinState<T>>,
+    task: Arc<Task>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Cancel the task.  Its future is dropped the next time a worker picks
+    /// it up, without being polled again; a task that already finished is
+    /// not affected.
+    pub fn abort(&self) {
+        self.task.aborted.store(true, Ordering::Release);
+        self.task.schedule();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.join.inner.lock().unwrap().finished
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.join.inner.lock().unwrap();
+        if let Some(result) = inner.result.take() {
+            return Poll::Ready(result);
+        }
+        assert!(!inner.finished, "JoinHandle polled after completion");
+        match &inner.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => inner.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle").field("id", &self.task.id).finish()
+    }
+}
+
+/// Why a task did not produce its output.
+pub enum JoinError {
+    Cancelled,
+    /// The task panicked; the payload can be re-raised with
+    /// `std::panic::resume_unwind`.
+    Panicked(Box<dyn Any + Send + 'static>),
+}
+
+impl JoinError {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panicked(_))
+    }
+
+    /// The panic payload.  Panics if the task was cancelled instead.
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        match self {
+            JoinError::Panicked(payload) => payload,
+            JoinError::Cancelled => panic!("`into_panic` called on a cancelled task's JoinError"),
+        }
+    }
+
+    fn panic_message(&self) -> Option<&str> {
+        let JoinError::Panicked(payload) = self else {
+            return None;
+        };
+        payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "Cancelled"),
+            JoinError::Panicked(_) => write!(f, "Panicked({:?})", self.panic_message().unwrap_or("..")),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self, self.panic_message()) {
+            (JoinError::Cancelled, _) => write!(f, "task was cancelled"),
+            (JoinError::Panicked(_), Some(message)) => write!(f, "task panicked: {message}"),
+            (JoinError::Panicked(_), None) => write!(f, "task panicked"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// Yield to other tasks: returns `Pending` once after waking itself.
+pub async fn yield_now() {
+    let mut yielded = false;
+    std::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
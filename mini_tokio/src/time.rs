@@ -0,0 +1,549 @@
+//! Timers: `sleep`, `sleep_until`, `interval` and `timeout`.
+//!
+//! All timers of a runtime live in one hierarchical timing wheel with a
+//! resolution of one millisecond.  Level 0 has 64 one-millisecond slots,
+//! level 1 has 64 slots of 64 ms, and so on up to level 5 (about 2.2 years
+//! in total).  A timer goes in the lowest level whose range still covers its
+//! deadline; when the wheel reaches a slot on a higher level, the timers in
+//! it are re-inserted and cascade down until they fire from level 0.
+//!
+//! Nobody runs a timer thread.  A worker with nothing to do becomes the
+//! driver: it sleeps until the earliest deadline, fires what has expired and
+//! goes back to work.  Busy workers also fire expired timers between tasks.
+//! Dropping a timer only flags its entry; the wheel discards it when its
+//! slot comes up.
+
+use std::{
+    fmt,
+    mem,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::task::AtomicWaker;
+
+use crate::runtime::Handle;
+
+const LEVELS: usize = 6;
+const SLOTS: usize = 64;
+const SLOT_BITS: u32 = 6;
+/// Deadlines further out than this are clamped to it.
+const MAX_TICKS: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+#[derive(Debug)]
+pub(crate) struct TimerEntry {
+    /// Deadline in wheel ticks.
+    when: u64,
+    fired: AtomicBool,
+    cancelled: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl TimerEntry {
+    fn fire(&self) {
+        self.fired.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+struct Level {
+    /// Bit `i` is set when `slots[i]` is non-empty.
+    occupied: u64,
+    slots: [Vec<Arc<TimerEntry>>; SLOTS],
+}
+
+/// The slot of `level` to process next and the tick at which it is due.
+struct Expiration {
+    level: usize,
+    slot: usize,
+    deadline: u64,
+}
+
+fn slot_range(level: usize) -> u64 {
+    1 << (SLOT_BITS * level as u32)
+}
+
+impl Level {
+    fn new() -> Self {
+        Level { occupied: 0, slots: std::array::from_fn(|_| Vec::new()) }
+    }
+
+    fn next_expiration(&self, level: usize, now: u64) -> Option<Expiration> {
+        if self.occupied == 0 {
+            return None;
+        }
+        // The first occupied slot at or after the current one, wrapping.
+        let now_slot = (now / slot_range(level)) % SLOTS as u64;
+        let slot = (self.occupied.rotate_right(now_slot as u32).trailing_zeros() as u64 + now_slot) % SLOTS as u64;
+        let level_range = slot_range(level + 1);
+        let mut deadline = (now & !(level_range - 1)) + slot * slot_range(level);
+        if deadline <= now {
+            // Only the top level wraps: its timers were clamped to the wheel's
+            // span and belong to the next rotation.
+            deadline += level_range;
+        }
+        Some(Expiration { level, slot: slot as usize, deadline })
+    }
+}
+
+pub(crate) struct Wheel {
+    /// Ticks processed so far.
+    elapsed: u64,
+    levels: Vec<Level>,
+}
+
+impl Wheel {
+    pub(crate) fn new() -> Self {
+        Wheel { elapsed: 0, levels: (0..LEVELS).map(|_| Level::new()).collect() }
+    }
+
+    /// The lowest level where `elapsed` and `when` agree on every higher
+    /// slot, i.e. the level whose current rotation contains `when`.
+    fn level_for(&self, when: u64) -> usize {
+        let masked = ((self.elapsed ^ when) | (SLOTS as u64 - 1)).min(MAX_TICKS);
+        let significant = 63 - masked.leading_zeros() as usize;
+        significant / SLOT_BITS as usize
+    }
+
+    /// Insert `entry`, or hand it back if its deadline has already passed.
+    fn insert(&mut self, entry: Arc<TimerEntry>) -> Result<(), Arc<TimerEntry>> {
+        if entry.when <= self.elapsed {
+            return Err(entry);
+        }
+        let level = self.level_for(entry.when);
+        let slot = ((entry.when >> (SLOT_BITS * level as u32)) % SLOTS as u64) as usize;
+        self.levels[level].slots[slot].push(entry);
+        self.levels[level].occupied |= 1 << slot;
+        Ok(())
+    }
+
+    fn next_expiration(&self) -> Option<Expiration> {
+        self.levels
+            .iter()
+            .enumerate()
+            .find_map(|(level, slots)| slots.next_expiration(level, self.elapsed))
+    }
+
+    /// Earliest tick at which `poll` has something to do.
+    fn next_deadline(&self) -> Option<u64> {
+        self.next_expiration().map(|expiration| expiration.deadline)
+    }
+
+    /// Advance to `now`, pushing every entry that expired onto `fired`.
+    /// Cancelled entries are dropped on the way.
+    pub(crate) fn poll(&mut self, now: u64, fired: &mut Vec<Arc<TimerEntry>>) {
+        while let Some(expiration) = self.next_expiration()
+            && expiration.deadline <= now
+        {
+            self.elapsed = expiration.deadline;
+            let level = &mut self.levels[expiration.level];
+            level.occupied &= !(1 << expiration.slot);
+            for entry in mem::take(&mut level.slots[expiration.slot]) {
+                if entry.cancelled.load(Ordering::Acquire) {
+                    continue;
+                }
+                if let Err(entry) = self.insert(entry) {
+                    fired.push(entry);
+                }
+            }
+        }
+        self.elapsed = self.elapsed.max(now);
+    }
+}
+
+/// The runtime's timers; lives in the runtime's shared state.
+pub(crate) struct TimerDriver {
+    start: Instant,
+    wheel: Mutex<Wheel>,
+    /// Mirrors `wheel.next_deadline()` (`u64::MAX` for none) so workers can
+    /// skip the lock when nothing is due.
+    next: AtomicU64,
+}
+
+impl TimerDriver {
+    pub(crate) fn new() -> Self {
+        TimerDriver { start: Instant::now(), wheel: Mutex::new(Wheel::new()), next: AtomicU64::new(u64::MAX) }
+    }
+
+    /// Ticks since the driver started, rounded up so a timer never fires
+    /// early.
+    fn ticks_at(&self, deadline: Instant) -> u64 {
+        let since = deadline.saturating_duration_since(self.start);
+        let ticks = since.as_millis() as u64 + u64::from(!since.subsec_nanos().is_multiple_of(1_000_000));
+        ticks.min(MAX_TICKS)
+    }
+
+    fn now_ticks(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    /// Add a timer.  Returns `true` if it is now the earliest one, so a
+    /// parked driver has to wake up and re-arm.
+    fn register(&self, entry: Arc<TimerEntry>) -> bool {
+        let when = entry.when;
+        let mut wheel = self.wheel.lock().unwrap();
+        if let Err(entry) = wheel.insert(entry) {
+            drop(wheel);
+            entry.fire();
+            return false;
+        }
+        let earliest = when < self.next.load(Ordering::Acquire);
+        if earliest {
+            self.next.store(wheel.next_deadline().unwrap_or(u64::MAX), Ordering::Release);
+        }
+        earliest
+    }
+
+    /// Instant at which the earliest timer is due.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        match self.next.load(Ordering::Acquire) {
+            u64::MAX => None,
+            ticks => Some(self.start + Duration::from_millis(ticks)),
+        }
+    }
+
+    /// Fire every expired timer.  Returns without waiting if another worker
+    /// is already doing it.
+    pub(crate) fn fire_expired(&self) {
+        let now = self.now_ticks();
+        if now < self.next.load(Ordering::Acquire) {
+            return;
+        }
+        let mut fired = Vec::new();
+        {
+            let Ok(mut wheel) = self.wheel.try_lock() else {
+                return;
+            };
+            wheel.poll(now, &mut fired);
+            self.next.store(wheel.next_deadline().unwrap_or(u64::MAX), Ordering::Release);
+        }
+        // Wake outside the lock: waking schedules tasks.
+        for entry in fired {
+            entry.fire();
+        }
+    }
+}
+
+/// A future that completes at a deadline.  Created by `sleep` and
+/// `sleep_until`.
+///
+/// The timer is registered on the first poll, with the runtime of the
+/// polling thread.  Later polls only replace the stored waker, so the task
+/// that polled last is the one woken.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Sleep {
+    deadline: Instant,
+    entry: Option<Arc<TimerEntry>>,
+}
+
+/// Wait until `duration` has elapsed.
+///
+/// Panics when polled outside a runtime.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Wait until `deadline`.
+///
+/// Panics when polled outside a runtime.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, entry: None }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        self.entry.as_ref().is_some_and(|entry| entry.fired.load(Ordering::Acquire))
+    }
+
+    /// Move the deadline.  The old timer is dropped and a new one registered
+    /// on the next poll.
+    pub fn reset(&mut self, deadline: Instant) {
+        self.cancel();
+        self.deadline = deadline;
+    }
+
+    fn cancel(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            entry.cancelled.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let entry = match &self.entry {
+            Some(entry) => entry.clone(),
+            None => {
+                if Instant::now() >= self.deadline {
+                    return Poll::Ready(());
+                }
+                let shared = Handle::current().shared;
+                let entry = Arc::new(TimerEntry {
+                    when: shared.timers.ticks_at(self.deadline),
+                    fired: AtomicBool::new(false),
+                    cancelled: AtomicBool::new(false),
+                    waker: AtomicWaker::new(),
+                });
+                // Set the waker before the entry becomes visible to a driver.
+                entry.waker.register(cx.waker());
+                self.entry = Some(entry.clone());
+                if shared.timers.register(entry.clone()) {
+                    shared.unpark_driver();
+                }
+                entry
+            }
+        };
+        entry.waker.register(cx.waker());
+        // Checked after registering, so a fire between the two is not lost.
+        if entry.fired.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl fmt::Debug for Sleep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sleep").field("deadline", &self.deadline).finish()
+    }
+}
+
+/// Ticks every `period`; see `interval`.
+#[derive(Debug)]
+pub struct Interval {
+    sleep: Sleep,
+    period: Duration,
+}
+
+/// A stream of ticks `period` apart.  The first tick completes
+/// immediately.  If a tick is late by more than a whole period, the missed
+/// ticks are skipped and the schedule restarts from the late one.
+///
+/// Panics if `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "`interval` period must be non-zero");
+    Interval { sleep: sleep_until(Instant::now()), period }
+}
+
+impl Interval {
+    /// Wait for the next tick and return the instant it was scheduled for.
+    pub async fn tick(&mut self) -> Instant {
+        std::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let scheduled = self.sleep.deadline();
+        let mut next = scheduled + self.period;
+        let now = Instant::now();
+        if next <= now {
+            next = now + self.period;
+        }
+        self.sleep.reset(next);
+        Poll::Ready(scheduled)
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+/// Run `future`, giving up after `duration`.
+///
+/// Panics when polled outside a runtime.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout { future, sleep: sleep(duration) }
+}
+
+/// The future returned by `timeout`.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is structurally pinned and never moved; `sleep`
+        // is `Unpin`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        // The inner future wins a tie.
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed(()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Returned by `timeout` when the deadline passes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Waker;
+    use crate::MiniTokio;
+    use std::sync::atomic::AtomicUsize;
+
+    /// A waker that counts how often it was woken.
+    fn counting_waker() -> (Waker, Arc<AtomicUsize>) {
+        struct Counter(Arc<AtomicUsize>);
+        impl futures::task::ArcWake for Counter {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let count = Arc::new(AtomicUsize::new(0));
+        (futures::task::waker(Arc::new(Counter(count.clone()))), count)
+    }
+
+    fn entry(when: u64) -> Arc<TimerEntry> {
+        Arc::new(TimerEntry {
+            when,
+            fired: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        })
+    }
+
+    #[test]
+    fn wheel_cascades_across_levels() {
+        let mut wheel = Wheel::new();
+        let deadlines = [1, 63, 64, 65, 4095, 4096, 300_000, 20_000_000];
+        for &when in deadlines.iter().rev() {
+            wheel.insert(entry(when)).unwrap();
+        }
+        assert_eq!(wheel.next_deadline(), Some(1));
+
+        let mut fired = Vec::new();
+        let mut order = Vec::new();
+        for now in [0, 1, 64, 100, 4096, 299_999, 300_000, 30_000_000] {
+            wheel.poll(now, &mut fired);
+            order.extend(fired.drain(..).map(|e| (now, e.when)));
+        }
+        assert_eq!(
+            order,
+            [(1, 1), (64, 63), (64, 64), (100, 65), (4096, 4095), (4096, 4096), (300_000, 300_000), (30_000_000, 20_000_000)]
+        );
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn wheel_skips_cancelled_and_fires_past_deadlines_on_insert() {
+        let mut wheel = Wheel::new();
+        let cancelled = entry(10);
+        cancelled.cancelled.store(true, Ordering::SeqCst);
+        wheel.insert(cancelled).unwrap();
+        wheel.insert(entry(10)).unwrap();
+        let mut fired = Vec::new();
+        wheel.poll(10, &mut fired);
+        assert_eq!(fired.len(), 1);
+        assert!(wheel.insert(entry(5)).is_err());
+    }
+
+    #[test]
+    fn sleep_waits_at_least_the_duration() {
+        let rt = MiniTokio::with_workers(2);
+        let start = Instant::now();
+        rt.block_on(sleep(Duration::from_millis(30)));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn repoll_replaces_the_waker() {
+        let rt = MiniTokio::with_workers(1);
+        let (first, first_wakes) = counting_waker();
+        let (second, second_wakes) = counting_waker();
+        rt.block_on(async {
+            let mut sleep = sleep(Duration::from_millis(20));
+            assert!(Pin::new(&mut sleep).poll(&mut Context::from_waker(&first)).is_pending());
+            assert!(Pin::new(&mut sleep).poll(&mut Context::from_waker(&second)).is_pending());
+            // Wait on a second timer so this thread does not poll `sleep`.
+            super::sleep(Duration::from_millis(60)).await;
+            assert!(sleep.is_elapsed());
+        });
+        assert_eq!(first_wakes.load(Ordering::SeqCst), 0);
+        assert_eq!(second_wakes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn interval_ticks_on_schedule() {
+        let rt = MiniTokio::with_workers(2);
+        let ticks = rt.block_on(async {
+            let mut interval = interval(Duration::from_millis(10));
+            let mut ticks = Vec::new();
+            for _ in 0..4 {
+                ticks.push(interval.tick().await);
+            }
+            ticks
+        });
+        for pair in ticks.windows(2) {
+            assert_eq!(pair[1] - pair[0], Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn timeout_returns_output_or_elapsed() {
+        let rt = MiniTokio::with_workers(2);
+        rt.block_on(async {
+            assert_eq!(timeout(Duration::from_millis(50), async { 7 }).await, Ok(7));
+            let slow = timeout(Duration::from_millis(10), sleep(Duration::from_secs(10))).await;
+            assert_eq!(slow, Err(Elapsed(())));
+            assert_eq!(slow.unwrap_err().to_string(), "deadline has elapsed");
+        });
+    }
+
+    #[test]
+    fn timers_fire_while_all_workers_are_parked() {
+        let rt = MiniTokio::with_workers(3);
+        let handles: Vec<_> = (1..=20u64)
+            .map(|i| rt.spawn(async move {
+                sleep(Duration::from_millis(i * 3)).await;
+                i
+            }))
+            .collect();
+        let total: u64 = rt.block_on(async {
+            let mut total = 0;
+            for handle in handles {
+                total += handle.await.unwrap();
+            }
+            total
+        });
+        assert_eq!(total, 210);
+    }
+}
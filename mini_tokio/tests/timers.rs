@@ -0,0 +1,53 @@
+//! Runs in its own process so the thread count is not disturbed by other
+//! tests.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use mini_tokio::{MiniTokio, time};
+
+fn thread_count() -> usize {
+    std::fs::read_dir("/proc/self/task").map_or(0, |tasks| tasks.count())
+}
+
+#[test]
+fn many_concurrent_timers_use_a_bounded_number_of_threads() {
+    const TIMERS: u64 = 100_000;
+    const WORKERS: usize = 4;
+
+    let before = thread_count();
+    let rt = MiniTokio::with_workers(WORKERS);
+    let done = Arc::new(AtomicUsize::new(0));
+    let handles: Vec<_> = (0..TIMERS)
+        .map(|i| {
+            let done = done.clone();
+            rt.spawn(async move {
+                // Deadlines spread over 50..250 ms.
+                time::sleep(Duration::from_millis(50 + i % 200)).await;
+                done.fetch_add(1, Ordering::Relaxed);
+            })
+        })
+        .collect();
+
+    let peak = rt.block_on(async {
+        let mut peak = 0;
+        while done.load(Ordering::Relaxed) < TIMERS as usize {
+            peak = peak.max(thread_count());
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        peak
+    });
+
+    assert_eq!(done.load(Ordering::Relaxed), TIMERS as usize);
+    if before > 0 {
+        assert!(peak <= before + WORKERS, "{peak} threads with {before} before the runtime started");
+    }
+}
@@ -0,0 +1,51 @@
+use std::hint::black_box;
+use std::str::FromStr;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use parse_example::{bytecode::Program, Environment, Expression};
+
+// Evaluate one formula over many bindings, the way a batch job would:
+// tree-walking `eval` looks every variable up in the environment's HashMap
+// (and rebinding is a map insert) and recomputes the repeated sqrt, while
+// the compiled program reads slots and computes it once.
+const FORMULA: &str = "sqrt(x^2 + y^2) / (1 + sqrt(x^2 + y^2)) + k * sin(x * y) - k * sin(x * y) ^ 2";
+
+fn bench_eval(c: &mut Criterion) {
+    let expr = Expression::from_str(FORMULA).unwrap();
+    let mut env = Environment::new();
+    env.set_var("k", 0.5);
+    env.set_var("x", 0.0);
+    env.set_var("y", 0.0);
+    let program = Program::compile(&expr, &env).unwrap();
+    let (x, y) = (program.slot("x").unwrap(), program.slot("y").unwrap());
+    let n = 10_000;
+
+    c.bench_function("tree-walking eval", |b| {
+        b.iter(|| {
+            let mut sum = 0.0;
+            for i in 0..n {
+                env.set_var("x", i as f64 * 0.001);
+                env.set_var("y", 1.0 - i as f64 * 0.001);
+                sum += expr.eval(black_box(&env)).unwrap().as_number().unwrap();
+            }
+            sum
+        })
+    });
+
+    c.bench_function("bytecode run", |b| {
+        let mut vars = program.bind(&env).unwrap();
+        let mut scratch = Vec::new();
+        b.iter(|| {
+            let mut sum = 0.0;
+            for i in 0..n {
+                vars[x] = i as f64 * 0.001;
+                vars[y] = 1.0 - i as f64 * 0.001;
+                sum += program.run_with(black_box(&vars), &mut scratch).unwrap();
+            }
+            sum
+        })
+    });
+}
+
+criterion_group!(benches, bench_eval);
+criterion_main!(benches);
@@ -0,0 +1,531 @@
+//! Stack-machine bytecode for evaluating one formula many times.
+//!
+//! `Program::compile` lowers an `Expression` in three steps:
+//!
+//! 1. user-defined functions are inlined and constants (`pi`, literals,
+//!    builtins applied to constants) are folded;
+//! 2. the tree is hash-consed into a DAG, so repeated subexpressions such as
+//!    the two `sqrt(x^2 + y^2)` in `sqrt(x^2 + y^2) / (1 + sqrt(x^2 + y^2))`
+//!    become one node;
+//! 3. the DAG is emitted in post-order.  A node used more than once is
+//!    computed the first time it is reached, kept in a temp with `Tee` and
+//!    re-read with `LoadTemp` afterwards.
+//!
+//! Only numeric expressions compile: comparisons, logic, `if` and string or
+//! list values are left to `Expression::eval`.
+//!
+//! Free variables are resolved to slots at compile time; `run` takes their
+//! values as a slice in `variables()` order instead of doing a map lookup per
+//! use.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    BUILTINS, Builtin, Environment, EvalError, EvalErrorKind, Expression, MAX_CALL_DEPTH, Span, Value,
+    apply_binary, builtin, constant, precedence, value_builtin,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Const(f64),
+    /// Push the value of variable slot `n`.
+    Load(u32),
+    /// Push temp `n`, written earlier by `Tee`.
+    LoadTemp(u32),
+    /// Copy the top of the stack into temp `n` without popping it.
+    Tee(u32),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    /// Apply `BUILTINS[builtin]` to the top `argc` values.
+    Call { builtin: u16, argc: u16 },
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    code: Vec<Op>,
+    /// Source span of each instruction, for runtime errors.
+    spans: Vec<Span>,
+    variables: Vec<String>,
+    temps: usize,
+    max_stack: usize,
+}
+
+impl Program {
+    /// Compile `expr`, inlining calls to functions defined in `env`.  Every
+    /// other free name that is not a constant becomes a variable slot.
+    pub fn compile(expr: &Expression, env: &Environment) -> Result<Program, EvalError> {
+        let mut lowering = Lowering { env, variables: Vec::new(), dag: Dag::default() };
+        let root = lowering.lower(expr, &HashMap::new(), 0)?;
+        let Lowering { variables, dag, .. } = lowering;
+        Ok(Emitter::new(dag, root).emit(variables))
+    }
+
+    /// Names of the variable slots, in the order `run` expects their values.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.variables.iter().position(|v| v == name)
+    }
+
+    pub fn code(&self) -> &[Op] {
+        &self.code
+    }
+
+    /// Values for `variables()` taken from `env`; each must be a number.
+    pub fn bind(&self, env: &Environment) -> Result<Vec<f64>, EvalError> {
+        self.variables
+            .iter()
+            .map(|name| match env.var(name) {
+                Some(Value::Number(n)) => Ok(*n),
+                Some(value) => Err(EvalError::new(
+                    EvalErrorKind::NotCompilable(format!("{} variable '{name}'", value.type_name())),
+                    Span::default(),
+                )),
+                None => Err(EvalError::new(EvalErrorKind::UndefinedVariable(name.clone()), Span::default())),
+            })
+            .collect()
+    }
+
+    pub fn run(&self, vars: &[f64]) -> Result<f64, EvalError> {
+        self.run_with(vars, &mut Vec::new())
+    }
+
+    /// Like `run`, but reuses `scratch` for temps and the stack so a hot loop
+    /// does not allocate.
+    pub fn run_with(&self, vars: &[f64], scratch: &mut Vec<f64>) -> Result<f64, EvalError> {
+        assert_eq!(vars.len(), self.variables.len(), "one value per variable slot");
+        scratch.clear();
+        scratch.resize(self.temps, 0.0);
+        scratch.reserve(self.max_stack);
+        let (temps, stack) = (self.temps, scratch);
+
+        for (pc, op) in self.code.iter().enumerate() {
+            match *op {
+                Op::Const(n) => stack.push(n),
+                Op::Load(slot) => stack.push(vars[slot as usize]),
+                Op::LoadTemp(temp) => stack.push(stack[temp as usize]),
+                Op::Tee(temp) => stack[temp as usize] = stack[stack.len() - 1],
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    let value = apply_binary(op_symbol(*op), left, right)
+                        .map_err(|kind| EvalError::new(kind, self.spans[pc]))?;
+                    stack.push(value);
+                }
+                Op::Call { builtin, argc } => {
+                    let start = stack.len() - argc as usize;
+                    let value = (BUILTINS[builtin as usize].apply)(&stack[start..]);
+                    stack.truncate(start);
+                    stack.push(value);
+                }
+            }
+        }
+        debug_assert_eq!(stack.len(), temps + 1);
+        Ok(stack[temps])
+    }
+}
+
+fn op_symbol(op: Op) -> &'static str {
+    match op {
+        Op::Add => "+",
+        Op::Sub => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+        Op::Pow => "^",
+        _ => unreachable!("not a binary operator"),
+    }
+}
+
+fn literal_type(expr: &Expression) -> &'static str {
+    match expr {
+        Expression::Bool(_) => "bool",
+        Expression::Str(_) => "string",
+        _ => "list",
+    }
+}
+
+fn binary_op(symbol: &str) -> Option<Op> {
+    match symbol {
+        "+" => Some(Op::Add),
+        "-" => Some(Op::Sub),
+        "*" => Some(Op::Mul),
+        "/" => Some(Op::Div),
+        "^" => Some(Op::Pow),
+        _ => None,
+    }
+}
+
+/// Structural identity of a DAG node; children are node ids.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    /// `f64::to_bits`, so `0.0` and `-0.0` stay distinct.
+    Const(u64),
+    Load(u32),
+    Binary(&'static str, usize, usize),
+    Call(u16, Vec<usize>),
+}
+
+#[derive(Default)]
+struct Dag {
+    nodes: Vec<(Key, Span)>,
+    ids: HashMap<Key, usize>,
+}
+
+impl Dag {
+    fn intern(&mut self, key: Key, span: Span) -> usize {
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        self.nodes.push((key.clone(), span));
+        self.ids.insert(key, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn constant_value(&self, id: usize) -> Option<f64> {
+        match self.nodes[id].0 {
+            Key::Const(bits) => Some(f64::from_bits(bits)),
+            _ => None,
+        }
+    }
+}
+
+struct Lowering<'a> {
+    env: &'a Environment,
+    variables: Vec<String>,
+    dag: Dag,
+}
+
+impl Lowering<'_> {
+    /// `params` maps the parameters of the function being inlined to the
+    /// nodes of its arguments.
+    fn lower(&mut self, expr: &Expression, params: &HashMap<String, usize>, depth: usize) -> Result<usize, EvalError> {
+        match expr {
+            Expression::Number(n) => Ok(self.constant(*n)),
+            Expression::Bool(_) | Expression::Str(_) | Expression::List(_) => Err(EvalError::new(
+                EvalErrorKind::NotCompilable(format!("{} literal", literal_type(expr))),
+                Span::default(),
+            )),
+            Expression::Not(_, span) => Err(EvalError::new(EvalErrorKind::NotCompilable("'!'".to_string()), *span)),
+            Expression::If(.., span) => Err(EvalError::new(EvalErrorKind::NotCompilable("'if'".to_string()), *span)),
+            Expression::Variable(name, span) => {
+                if let Some(&id) = params.get(name) {
+                    return Ok(id);
+                }
+                if self.env.var(name).is_none()
+                    && let Some(value) = constant(name)
+                {
+                    return Ok(self.constant(value));
+                }
+                let slot = match self.variables.iter().position(|v| v == name) {
+                    Some(slot) => slot,
+                    None => {
+                        self.variables.push(name.clone());
+                        self.variables.len() - 1
+                    }
+                };
+                Ok(self.dag.intern(Key::Load(slot as u32), *span))
+            }
+            Expression::Operator(op, exprs, span) => {
+                if binary_op(op).is_none() {
+                    let kind = match *op {
+                        "=" => EvalErrorKind::InvalidAssignment,
+                        op if precedence(op).is_some() => EvalErrorKind::NotCompilable(format!("'{op}'")),
+                        op => EvalErrorKind::UnknownOperator(op),
+                    };
+                    return Err(EvalError::new(kind, *span));
+                }
+                let left = self.lower(&exprs[0], params, depth)?;
+                let right = self.lower(&exprs[1], params, depth)?;
+                if let (Some(l), Some(r)) = (self.dag.constant_value(left), self.dag.constant_value(right))
+                    && let Ok(value) = apply_binary(op, l, r)
+                {
+                    return Ok(self.constant(value));
+                }
+                // A folding error (e.g. a negative exponent) is left for run
+                // time, where it is reported like the tree-walker does.
+                Ok(self.dag.intern(Key::Binary(op, left, right), *span))
+            }
+            Expression::Call(name, args, span) => {
+                if value_builtin(name).is_some() {
+                    return Err(EvalError::new(EvalErrorKind::NotCompilable(format!("'{name}'")), *span));
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| self.lower(arg, params, depth))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let arity_error = |min, max| {
+                    EvalError::new(EvalErrorKind::ArityMismatch { name: name.clone(), min, max, found: args.len() }, *span)
+                };
+                if let Some(builtin) = builtin(name) {
+                    if !(builtin.min_args..=builtin.max_args).contains(&args.len()) {
+                        return Err(arity_error(builtin.min_args, builtin.max_args));
+                    }
+                    return Ok(self.call_builtin(builtin, args, *span));
+                }
+                let Some(function) = self.env.function(name) else {
+                    return Err(EvalError::new(EvalErrorKind::UndefinedFunction(name.clone()), *span));
+                };
+                if function.params.len() != args.len() {
+                    return Err(arity_error(function.params.len(), function.params.len()));
+                }
+                if depth >= MAX_CALL_DEPTH {
+                    return Err(EvalError::new(EvalErrorKind::RecursionLimit(name.clone()), *span));
+                }
+                let params = function.params.iter().cloned().zip(args).collect();
+                self.lower(&function.body, &params, depth + 1)
+            }
+        }
+    }
+
+    fn constant(&mut self, value: f64) -> usize {
+        self.dag.intern(Key::Const(value.to_bits()), Span::default())
+    }
+
+    fn call_builtin(&mut self, builtin: &Builtin, args: Vec<usize>, span: Span) -> usize {
+        let constants: Option<Vec<f64>> = args.iter().map(|&id| self.dag.constant_value(id)).collect();
+        if let Some(values) = constants {
+            return self.constant((builtin.apply)(&values));
+        }
+        let index = BUILTINS.iter().position(|b| b.name == builtin.name).unwrap() as u16;
+        self.dag.intern(Key::Call(index, args), span)
+    }
+}
+
+struct Emitter {
+    dag: Dag,
+    root: usize,
+    uses: Vec<usize>,
+    /// Temp holding each shared node once it has been computed.
+    temps: Vec<Option<u32>>,
+    next_temp: u32,
+    code: Vec<Op>,
+    spans: Vec<Span>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Emitter {
+    fn new(dag: Dag, root: usize) -> Self {
+        let mut uses = vec![0; dag.nodes.len()];
+        uses[root] += 1;
+        // Children always have smaller ids than their parents, but only
+        // nodes reachable from the root count.
+        let mut reachable = vec![false; dag.nodes.len()];
+        reachable[root] = true;
+        for id in (0..dag.nodes.len()).rev() {
+            if !reachable[id] {
+                continue;
+            }
+            for child in children(&dag.nodes[id].0) {
+                uses[child] += 1;
+                reachable[child] = true;
+            }
+        }
+        let temps = vec![None; dag.nodes.len()];
+        Emitter { dag, root, uses, temps, next_temp: 0, code: Vec::new(), spans: Vec::new(), depth: 0, max_depth: 0 }
+    }
+
+    fn emit(mut self, variables: Vec<String>) -> Program {
+        self.node(self.root);
+        Program {
+            code: self.code,
+            spans: self.spans,
+            variables,
+            temps: self.next_temp as usize,
+            max_stack: self.max_depth,
+        }
+    }
+
+    fn node(&mut self, id: usize) {
+        if let Some(temp) = self.temps[id] {
+            self.push(Op::LoadTemp(temp), Span::default(), 1);
+            return;
+        }
+        let (key, span) = self.dag.nodes[id].clone();
+        match key {
+            Key::Const(bits) => self.push(Op::Const(f64::from_bits(bits)), span, 1),
+            Key::Load(slot) => self.push(Op::Load(slot), span, 1),
+            Key::Binary(op, left, right) => {
+                self.node(left);
+                self.node(right);
+                self.push(binary_op(op).unwrap(), span, -1);
+            }
+            Key::Call(builtin, args) => {
+                for &arg in &args {
+                    self.node(arg);
+                }
+                self.push(Op::Call { builtin, argc: args.len() as u16 }, span, 1 - args.len() as isize);
+            }
+        }
+        // Constants and loads are as cheap to repeat as a temp read.
+        let shared = self.uses[id] > 1 && !matches!(self.dag.nodes[id].0, Key::Const(_) | Key::Load(_));
+        if shared {
+            let temp = self.next_temp;
+            self.next_temp += 1;
+            self.temps[id] = Some(temp);
+            self.push(Op::Tee(temp), Span::default(), 0);
+        }
+    }
+
+    fn push(&mut self, op: Op, span: Span, stack_effect: isize) {
+        self.code.push(op);
+        self.spans.push(span);
+        self.depth = self.depth.wrapping_add_signed(stack_effect);
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+}
+
+fn children(key: &Key) -> Vec<usize> {
+    match key {
+        Key::Const(_) | Key::Load(_) => Vec::new(),
+        Key::Binary(_, left, right) => vec![*left, *right],
+        Key::Call(_, args) => args.clone(),
+    }
+}
+
+impl fmt::Display for Program {
+    /// One instruction per line, e.g. `load x`, `tee t0`, `call sqrt/1`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, op) in self.code.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match *op {
+                Op::Const(n) => write!(f, "const {n}")?,
+                Op::Load(slot) => write!(f, "load {}", self.variables[slot as usize])?,
+                Op::LoadTemp(temp) => write!(f, "load t{temp}")?,
+                Op::Tee(temp) => write!(f, "tee t{temp}")?,
+                Op::Call { builtin, argc } => write!(f, "call {}/{argc}", BUILTINS[builtin as usize].name)?,
+                op => write!(f, "{}", op_symbol(op))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn compile(input: &str, env: &Environment) -> Program {
+        Program::compile(&Expression::from_str(input).unwrap(), env).unwrap()
+    }
+
+    #[test]
+    fn variables_are_resolved_to_slots_in_order() {
+        let program = compile("y * x + y", &Environment::new());
+        assert_eq!(program.variables(), ["y", "x"]);
+        assert_eq!(program.slot("x"), Some(1));
+        assert_eq!(program.run(&[3.0, 2.0]).unwrap(), 9.0);
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        let program = compile("2 * pi * r + sqrt(16) - 2 ^ 3", &Environment::new());
+        assert_eq!(
+            program.to_string(),
+            format!("const {}\nload r\n*\nconst 4\n+\nconst 8\n-", 2.0 * std::f64::consts::PI)
+        );
+    }
+
+    #[test]
+    fn common_subexpressions_are_computed_once() {
+        let program = compile("sqrt(x^2 + y^2) / (1 + sqrt(x^2 + y^2))", &Environment::new());
+        assert_eq!(
+            program.to_string(),
+            "load x\nconst 2\n^\nload y\nconst 2\n^\n+\ncall sqrt/1\ntee t0\nconst 1\nload t0\n+\n/"
+        );
+        let value = program.run(&[3.0, 4.0]).unwrap();
+        assert_eq!(value, 5.0 / 6.0);
+    }
+
+    #[test]
+    fn nested_shared_subexpressions() {
+        // (a*b) appears inside a shared (a*b + c) and once on its own.
+        let program = compile("(a*b + c) * (a*b + c) - a*b", &Environment::new());
+        assert_eq!(program.code().iter().filter(|op| **op == Op::Mul).count(), 2);
+        assert_eq!(program.run(&[2.0, 3.0, 1.0]).unwrap(), 49.0 - 6.0);
+    }
+
+    #[test]
+    fn user_functions_are_inlined() {
+        let mut env = Environment::new();
+        env.execute(Expression::from_str("sq(v) = v * v").unwrap()).unwrap();
+        env.execute(Expression::from_str("norm(a, b) = sqrt(sq(a) + sq(b))").unwrap()).unwrap();
+        let program = compile("norm(x, 2 * 2)", &env);
+        assert_eq!(program.variables(), ["x"]);
+        assert!(!program.to_string().contains("norm"));
+        assert_eq!(program.run(&[3.0]).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn matches_tree_walking_eval() {
+        let mut env = Environment::new();
+        env.execute(Expression::from_str("f(t) = t^2 - 3*t").unwrap()).unwrap();
+        env.set_var("x", 1.5);
+        env.set_var("y", -0.25);
+        for input in [
+            "x + y * 2",
+            "-(x - y) ^ 2",
+            "max(x, y, f(x)) / min(1, y)",
+            "f(x) * f(x) + f(y)",
+            "log(1e3) + atan2(y, x) + e",
+            "10 - 4 - 3 / x / 2",
+        ] {
+            let expr = Expression::from_str(input).unwrap();
+            let program = Program::compile(&expr, &env).unwrap();
+            let vars = program.bind(&env).unwrap();
+            assert_eq!(program.run(&vars).unwrap(), expr.eval(&env).unwrap().as_number().unwrap(), "{input}");
+        }
+    }
+
+    #[test]
+    fn runtime_errors_keep_spans() {
+        let input = "2 ^ (x - 5)";
+        let program = compile(input, &Environment::new());
+        let err = program.run(&[1.0]).unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::NegativeExponent);
+        assert_eq!(err.span, Span::new(2, 3));
+
+        // Folding a constant that would fail still fails at run time.
+        let program = compile("2 ^ -1", &Environment::new());
+        assert_eq!(program.run(&[]).unwrap_err().kind, EvalErrorKind::NegativeExponent);
+    }
+
+    #[test]
+    fn compile_errors() {
+        let err = Program::compile(&Expression::from_str("g(1)").unwrap(), &Environment::new()).unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::UndefinedFunction("g".to_string()));
+
+        let err = Program::compile(&Expression::from_str("x = 1").unwrap(), &Environment::new()).unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::InvalidAssignment);
+
+        let err = Program::compile(&Expression::from_str("x > 1").unwrap(), &Environment::new()).unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::NotCompilable("'>'".to_string()));
+        assert_eq!(err.span, Span::new(2, 3));
+
+        let err = Program::compile(&Expression::from_str("len(\"ab\")").unwrap(), &Environment::new()).unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::NotCompilable("'len'".to_string()));
+
+        let mut env = Environment::new();
+        env.set_var("host", "db-1");
+        let program = compile("host * 2", &env);
+        assert_eq!(program.bind(&env).unwrap_err().to_string(), "string variable 'host' cannot be compiled to bytecode");
+    }
+
+    #[test]
+    fn scratch_buffer_is_reused() {
+        let program = compile("(x + 1) * (x + 1)", &Environment::new());
+        let mut scratch = Vec::new();
+        for i in 0..10 {
+            let x = i as f64;
+            assert_eq!(program.run_with(&[x], &mut scratch).unwrap(), (x + 1.0) * (x + 1.0));
+        }
+    }
+}
@@ -1,67 +1,444 @@
-use std::{collections::HashMap, fmt};
+use std::{collections::HashMap, f64::consts, fmt, str::FromStr};
 
-#[derive(Debug, PartialEq)]
+pub mod bytecode;
+pub mod symbolic;
+
+/// Byte range `start..end` of a token in the source text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Number(f32),
-    Variable(char),
-    Operator(char),
+    Number(f64),
+    /// A `"..."` literal with its escapes resolved.
+    Str(String),
+    Ident(String),
+    Operator(&'static str),
+    /// A character that starts no token, e.g. `%`.
+    Unknown(char),
     Eof,
 }
 
+/// Variables, operators and calls keep the span of their token so
+/// evaluation errors can point back into the source.
+#[derive(Debug, Clone)]
 pub enum Expression {
-    Number(f32),
-    Variable(char),
-    Operator(char, Vec<Expression>),
+    Number(f64),
+    Bool(bool),
+    Str(String),
+    List(Vec<Expression>),
+    Variable(String, Span),
+    Operator(&'static str, Vec<Expression>, Span),
+    /// Prefix `!`.
+    Not(Box<Expression>, Span),
+    /// `if cond then a else b`; only the chosen branch is evaluated.
+    If(Box<Expression>, Box<Expression>, Box<Expression>, Span),
+    Call(String, Vec<Expression>, Span),
+}
+
+/// The result of evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+    Str(String),
+    List(Vec<Value>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// A literal, identifier, `-`, `!`, `(`, `[` or `if` was expected.
+    ExpectedOperand(Token),
+    /// Two operands in a row, e.g. `1 2`.
+    ExpectedOperator(Token),
+    UnknownOperator(char),
+    /// The span points at the `(` that was never closed.
+    UnclosedParen,
+    UnmatchedParen,
+    /// The span points at the `[` that was never closed.
+    UnclosedBracket,
+    UnmatchedBracket,
+    /// `then` or `else` missing from an `if`.
+    ExpectedKeyword(&'static str),
+    /// The span runs from the opening `"` to the end of input.
+    UnterminatedString,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalErrorKind {
+    UndefinedVariable(String),
+    UndefinedFunction(String),
+    ArityMismatch {
+        name: String,
+        min: usize,
+        max: usize,
+        found: usize,
+    },
+    NegativeExponent,
+    UnknownOperator(&'static str),
+    /// Left of `=` is neither a name nor `name(params)`.
+    InvalidAssignment,
+    /// A function definition whose parameters are not plain names.
+    InvalidParameters(String),
+    /// Assigning to a constant or redefining a built-in function.
+    ReservedName(String),
+    RecursionLimit(String),
+    /// `derive` reached a function or operator it has no rule for.
+    NotDifferentiable(String),
+    /// An operator or function got operands of the wrong type; `found` lists
+    /// their types, e.g. `"number and string"`.
+    TypeMismatch { operation: String, found: String },
+    /// The operand of `!`, `&&`, `||` or an `if` condition is not a bool.
+    ExpectedBool { context: &'static str, found: &'static str },
+    /// The bytecode compiler only handles numeric expressions.
+    NotCompilable(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError {
+    pub kind: EvalErrorKind,
+    pub span: Span,
+}
+
+/// Binary operators as `(symbol, left binding power, right binding power)`.
+/// Adding an operator is a new row here plus a case in `apply_binary` or
+/// `apply_value`.
+const BINARY_OPERATORS: &[(&str, f32, f32)] = &[
+    ("=", 0.0, 0.1),
+    ("||", 0.3, 0.4),
+    ("&&", 0.5, 0.6),
+    ("==", 0.7, 0.8),
+    ("!=", 0.7, 0.8),
+    ("<", 0.7, 0.8),
+    ("<=", 0.7, 0.8),
+    (">", 0.7, 0.8),
+    (">=", 0.7, 0.8),
+    ("+", 1.0, 1.1),
+    ("-", 1.0, 1.1),
+    ("*", 2.0, 2.1),
+    ("/", 2.0, 2.1),
+    ("^", 3.0, 3.1),
+];
+
+/// Every symbol the lexer knows, two-character ones first so `<=` is not
+/// read as `<` followed by `=`.
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "^", "=", "<", ">", "!", "(", ")", "[", "]", ",",
+];
+
+/// Words that cannot be used as names.
+const KEYWORDS: &[&str] = &["if", "then", "else", "true", "false"];
+
+/// Binding power of prefix `-`: binds tighter than `+`/`-`, looser than `*`.
+const PREFIX_MINUS_PD: f32 = 1.1;
+
+/// Binding power of prefix `!`: takes in a comparison but stops at `&&` and
+/// `||`, so `!x > 3 && y` is `(!(x > 3)) && y`.
+const PREFIX_NOT_PD: f32 = 0.6;
+
+const CONSTANTS: &[(&str, f64)] = &[
+    ("pi", consts::PI),
+    ("e", consts::E),
+    ("tau", consts::TAU),
+];
+
+struct Builtin {
+    name: &'static str,
+    min_args: usize,
+    max_args: usize,
+    apply: fn(&[f64]) -> f64,
+}
+
+const fn unary(name: &'static str, apply: fn(&[f64]) -> f64) -> Builtin {
+    Builtin { name, min_args: 1, max_args: 1, apply }
+}
+
+const BUILTINS: &[Builtin] = &[
+    unary("sin", |a| a[0].sin()),
+    unary("cos", |a| a[0].cos()),
+    unary("tan", |a| a[0].tan()),
+    unary("asin", |a| a[0].asin()),
+    unary("acos", |a| a[0].acos()),
+    unary("atan", |a| a[0].atan()),
+    unary("sqrt", |a| a[0].sqrt()),
+    unary("abs", |a| a[0].abs()),
+    unary("exp", |a| a[0].exp()),
+    unary("ln", |a| a[0].ln()),
+    unary("floor", |a| a[0].floor()),
+    unary("ceil", |a| a[0].ceil()),
+    unary("round", |a| a[0].round()),
+    // log(x) is base 10, log(x, b) is base b.
+    Builtin { name: "log", min_args: 1, max_args: 2, apply: |a| if a.len() == 1 { a[0].log10() } else { a[0].log(a[1]) } },
+    Builtin { name: "atan2", min_args: 2, max_args: 2, apply: |a| a[0].atan2(a[1]) },
+    Builtin { name: "min", min_args: 1, max_args: usize::MAX, apply: |a| a.iter().copied().fold(f64::INFINITY, f64::min) },
+    Builtin { name: "max", min_args: 1, max_args: usize::MAX, apply: |a| a.iter().copied().fold(f64::NEG_INFINITY, f64::max) },
+];
+
+/// Built-in functions over strings and lists.  `apply` returns `None` when
+/// the arguments have the wrong types.
+struct ValueBuiltin {
+    name: &'static str,
+    args: usize,
+    apply: fn(&[Value]) -> Option<Value>,
+}
+
+const VALUE_BUILTINS: &[ValueBuiltin] = &[
+    ValueBuiltin {
+        name: "len",
+        args: 1,
+        apply: |a| match &a[0] {
+            Value::Str(s) => Some(Value::Number(s.chars().count() as f64)),
+            Value::List(items) => Some(Value::Number(items.len() as f64)),
+            _ => None,
+        },
+    },
+    // contains(list, item) or contains(string, substring).
+    ValueBuiltin {
+        name: "contains",
+        args: 2,
+        apply: |a| match (&a[0], &a[1]) {
+            (Value::List(items), item) => Some(Value::Bool(items.contains(item))),
+            (Value::Str(s), Value::Str(sub)) => Some(Value::Bool(s.contains(sub.as_str()))),
+            _ => None,
+        },
+    },
+];
+
+/// Nesting limit for calls to user-defined functions.
+const MAX_CALL_DEPTH: usize = 256;
+
+fn constant(name: &str) -> Option<f64> {
+    CONSTANTS.iter().find(|(n, _)| *n == name).map(|&(_, value)| value)
+}
+
+fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+fn value_builtin(name: &str) -> Option<&'static ValueBuiltin> {
+    VALUE_BUILTINS.iter().find(|b| b.name == name)
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Self {
+        Value::List(items)
+    }
+}
+
+impl PartialEq<f64> for Value {
+    fn eq(&self, other: &f64) -> bool {
+        self.as_number() == Some(*other)
+    }
+}
+
+/// A function defined with `name(params) = body`.
+#[derive(Debug, Clone)]
+pub struct UserFunction {
+    pub params: Vec<String>,
+    pub body: Expression,
+}
+
+/// Variables and user-defined functions visible to `eval`.  Constants and
+/// built-in functions are always available and cannot be redefined.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    variables: HashMap<String, Value>,
+    functions: HashMap<String, UserFunction>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_var(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.variables.insert(name.into(), value.into());
+    }
+
+    pub fn var(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name)
+    }
+
+    pub fn function(&self, name: &str) -> Option<&UserFunction> {
+        self.functions.get(name)
+    }
+
+    /// Run one line of input: `name = expr` assigns a variable,
+    /// `name(a, b) = expr` defines a function, anything else is evaluated
+    /// and its value returned.
+    pub fn execute(&mut self, expr: Expression) -> Result<Option<Value>, EvalError> {
+        let (target, body, span) = match expr {
+            Expression::Operator("=", mut sides, span) if sides.len() == 2 => {
+                let body = sides.pop().unwrap();
+                (sides.pop().unwrap(), body, span)
+            }
+            expr => return expr.eval(self).map(Some),
+        };
+        match target {
+            Expression::Variable(name, name_span) => {
+                if constant(&name).is_some() {
+                    return Err(EvalError::new(EvalErrorKind::ReservedName(name), name_span));
+                }
+                let value = body.eval(self)?;
+                self.variables.insert(name, value);
+                Ok(None)
+            }
+            Expression::Call(name, args, name_span) => {
+                if builtin(&name).is_some() || value_builtin(&name).is_some() {
+                    return Err(EvalError::new(EvalErrorKind::ReservedName(name), name_span));
+                }
+                let mut params: Vec<String> = Vec::with_capacity(args.len());
+                for arg in args {
+                    match arg {
+                        Expression::Variable(param, _) if !params.contains(&param) => params.push(param),
+                        _ => return Err(EvalError::new(EvalErrorKind::InvalidParameters(name), name_span)),
+                    }
+                }
+                self.functions.insert(name, UserFunction { params, body });
+                Ok(None)
+            }
+            _ => Err(EvalError::new(EvalErrorKind::InvalidAssignment, span)),
+        }
+    }
 }
 
 pub struct Lexer {
     index : usize,
-    tokens: Vec<Token>
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+    /// An unterminated string stops tokenizing; parsing reports it first.
+    error: Option<ParseError>,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
+        let (tokens, spans, error) = Self::tokenize(input);
         Lexer {
             index: 0,
-            tokens: Self::tokenize(input),
+            tokens,
+            spans,
+            error,
         }
     }
 
-    fn tokenize(input : &str) -> Vec<Token> {
+    fn tokenize(input : &str) -> (Vec<Token>, Vec<Span>, Option<ParseError>) {
         let mut tokens = Vec::new();
-        let mut chars = input.chars().peekable();
-        while let Some(c) = chars.next() {
+        let mut spans = Vec::new();
+        let mut error = None;
+        let mut chars = input.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
             if c.is_whitespace() {
                 continue;
             }
-            match c {
-                '0'..='9' => {
-                    let mut number = c.to_digit(10).unwrap() as f32;
-                    while let Some(&next) = chars.peek() {
-                        if next.is_digit(10) {
-                            chars.next();
-                            number = number * 10.0 + next.to_digit(10).unwrap() as f32;
-                        } else {
-                            break;
-                        }
+            let starts_number = c.is_ascii_digit()
+                || (c == '.' && chars.peek().is_some_and(|&(_, next)| next.is_ascii_digit()));
+            let token = if starts_number {
+                let end = scan_number(input, start);
+                while chars.peek().is_some_and(|&(i, _)| i < end) {
+                    chars.next();
+                }
+                Token::Number(input[start..end].parse().expect("scan_number yields a valid float"))
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let mut name = String::from(c);
+                while let Some(&(_, next)) = chars.peek() {
+                    if next.is_ascii_alphanumeric() || next == '_' {
+                        chars.next();
+                        name.push(next);
+                    } else {
+                        break;
                     }
-
-                    float_calculator(&mut chars, &mut number);
-
-                    tokens.push(Token::Number(number));
-                },
-                'a'..='z' | 'A'..='Z' => {
-                    tokens.push(Token::Variable(c));
-                },
-                _ => {
-                    tokens.push(Token::Operator(c));
                 }
-            }
+                Token::Ident(name)
+            } else if c == '"' {
+                match scan_string(&mut chars) {
+                    Some(s) => Token::Str(s),
+                    None => {
+                        error = Some(ParseError::new(ParseErrorKind::UnterminatedString, Span::new(start, input.len())));
+                        break;
+                    }
+                }
+            } else if let Some(symbol) = SYMBOLS.iter().find(|s| input[start..].starts_with(**s)) {
+                if symbol.len() > 1 {
+                    chars.next();
+                }
+                Token::Operator(symbol)
+            } else {
+                Token::Unknown(c)
+            };
+            let end = chars.peek().map_or(input.len(), |&(i, _)| i);
+            tokens.push(token);
+            spans.push(Span::new(start, end));
         }
         tokens.push(Token::Eof);
-        tokens
+        spans.push(Span::new(input.len(), input.len()));
+        (tokens, spans, error)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> &Token {
         if self.index >= self.tokens.len() {
             return &Token::Eof;
@@ -79,96 +456,270 @@ impl Lexer {
 
         &self.tokens[self.index]
     }
+
+    /// Span of the token `peek` returns.
+    pub fn peek_span(&self) -> Span {
+        let last = self.spans.len() - 1;
+        self.spans[self.index.min(last)]
+    }
 }
 
-fn float_calculator(chars: &mut std::iter::Peekable<std::str::Chars<'_>>, number: &mut f32) {
-    if chars.peek() == Some(&'.') {
-        chars.next(); // consume the '.'
-        let mut decimal_place = 0.1;
-        while let Some(&next_digit) = chars.peek() {
-            if next_digit.is_digit(10) {
-                chars.next();
-                *number += (next_digit.to_digit(10).unwrap() as f32) * decimal_place;
-                decimal_place *= 0.1;
-            } else {
-                break;
-            }
+/// End of the number literal starting at byte `start`: digits, an optional
+/// fraction and an optional exponent (`1.5e-3`).  An `e` not followed by
+/// digits is left for the next token.
+fn scan_number(input: &str, start: usize) -> usize {
+    let bytes = input.as_bytes();
+    let digits = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    };
+    let mut end = digits(start);
+    if bytes.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut exp = end + 1;
+        if matches!(bytes.get(exp), Some(b'+' | b'-')) {
+            exp += 1;
+        }
+        if bytes.get(exp).is_some_and(u8::is_ascii_digit) {
+            end = digits(exp);
         }
     }
+    end
 }
 
-impl Expression {
-    pub fn from_str(input: &str) -> Self {
+/// Body of a string literal whose opening `"` has been consumed, up to and
+/// including the closing `"`.  `\"`, `\\`, `\n` and `\t` are escapes; any
+/// other backslash is kept as is.  `None` if the input ends first.
+fn scan_string(chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>) -> Option<String> {
+    let mut s = String::new();
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '"' => return Some(s),
+            '\\' => match chars.next()?.1 {
+                'n' => s.push('\n'),
+                't' => s.push('\t'),
+                escaped @ ('"' | '\\') => s.push(escaped),
+                other => {
+                    s.push('\\');
+                    s.push(other);
+                }
+            },
+            c => s.push(c),
+        }
+    }
+    None
+}
+
+impl FromStr for Expression {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, ParseError> {
         let mut lexer = Lexer::new(input);
-        Self::parse_expression(&mut lexer, 0.0)
+        if let Some(error) = lexer.error.take() {
+            return Err(error);
+        }
+        let expr = Self::parse_expression(&mut lexer, 0.0)?;
+        // The operator loop also stops at closing delimiters and keywords.
+        match lexer.peek() {
+            Token::Eof => Ok(expr),
+            Token::Operator(")") => Err(ParseError::new(ParseErrorKind::UnmatchedParen, lexer.peek_span())),
+            Token::Operator("]") => Err(ParseError::new(ParseErrorKind::UnmatchedBracket, lexer.peek_span())),
+            token => Err(ParseError::new(ParseErrorKind::ExpectedOperator(token.clone()), lexer.peek_span())),
+        }
     }
+}
 
-    fn parse_expression(lexer: &mut Lexer,min_pd : f32) -> Self {
+impl Expression {
+    fn parse_expression(lexer: &mut Lexer,min_pd : f32) -> Result<Self, ParseError> {
+        let span = lexer.peek_span();
         let mut left_expr = match lexer.next() {
             Token::Number(n) => Expression::Number(*n),
-            Token::Variable(v) => Expression::Variable(*v),
-            Token::Operator('-') => Expression::Operator('-', vec![Expression::Number(0.0), Self::parse_expression(lexer, 1.1)]),
-            Token::Operator('(') => {
-                let inner_expr = Self::parse_expression(lexer, 0.0);
-                assert_eq!(lexer.next(), &Token::Operator(')'));
+            Token::Str(s) => Expression::Str(s.clone()),
+            Token::Ident(name) if name == "true" || name == "false" => Expression::Bool(name == "true"),
+            Token::Ident(name) if name == "if" => {
+                let condition = Self::parse_expression(lexer, 0.0)?;
+                Self::expect_keyword(lexer, "then")?;
+                let then_branch = Self::parse_expression(lexer, 0.0)?;
+                Self::expect_keyword(lexer, "else")?;
+                let else_branch = Self::parse_expression(lexer, 0.0)?;
+                Expression::If(Box::new(condition), Box::new(then_branch), Box::new(else_branch), span)
+            }
+            Token::Ident(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                if lexer.peek() == &Token::Operator("(") {
+                    let open = lexer.peek_span();
+                    lexer.next();
+                    let args = Self::parse_list(lexer, open, ")", ParseErrorKind::UnclosedParen)?;
+                    Expression::Call(name, args, span)
+                } else {
+                    Expression::Variable(name, span)
+                }
+            },
+            Token::Operator("-") => Expression::Operator("-", vec![Expression::Number(0.0), Self::parse_expression(lexer, PREFIX_MINUS_PD)?], span),
+            Token::Operator("!") => Expression::Not(Box::new(Self::parse_expression(lexer, PREFIX_NOT_PD)?), span),
+            Token::Operator("(") => {
+                let inner_expr = Self::parse_expression(lexer, 0.0)?;
+                if lexer.next() != &Token::Operator(")") {
+                    return Err(ParseError::new(ParseErrorKind::UnclosedParen, span));
+                }
                 inner_expr
             },
-            _ => panic!("left_expr unexpected token"),
+            Token::Operator("[") => {
+                Expression::List(Self::parse_list(lexer, span, "]", ParseErrorKind::UnclosedBracket)?)
+            },
+            token => return Err(ParseError::new(ParseErrorKind::ExpectedOperand(token.clone()), span)),
         };
 
         loop {
+            let span = lexer.peek_span();
             let op = match lexer.peek() {
-                Token::Operator(')') | Token::Eof => break,
-                Token::Operator(op) => *op,
-                _ => panic!("op unexpected token")
+                Token::Operator(")" | "]" | ",") | Token::Eof => break,
+                Token::Ident(word) if word == "then" || word == "else" => break,
+                Token::Unknown(c) => return Err(ParseError::new(ParseErrorKind::UnknownOperator(*c), span)),
+                Token::Operator(op) if precedence(op).is_some() => *op,
+                token => return Err(ParseError::new(ParseErrorKind::ExpectedOperator(token.clone()), span)),
             };
-            let (left_pd, right_pd) = precedence(op);
+            let (left_pd, right_pd) = precedence(op).unwrap();
             if left_pd < min_pd {
                 break;
             }
             lexer.next();
-            let right_expr = Self::parse_expression(lexer, right_pd);
-            left_expr = Expression::Operator(op, vec![left_expr, right_expr]);
+            let right_expr = Self::parse_expression(lexer, right_pd)?;
+            left_expr = Expression::Operator(op, vec![left_expr, right_expr], span);
         }
 
-        left_expr
+        Ok(left_expr)
+    }
+
+    /// Parse the items of `(a, b, ...)` after a function name or of
+    /// `[a, b, ...]`; the opening delimiter at `open` has been consumed.
+    /// `unclosed` is reported there if `close` never comes.
+    fn parse_list(lexer: &mut Lexer, open: Span, close: &'static str, unclosed: ParseErrorKind) -> Result<Vec<Self>, ParseError> {
+        let mut items = Vec::new();
+        if lexer.peek() == &Token::Operator(close) {
+            lexer.next();
+            return Ok(items);
+        }
+        loop {
+            items.push(Self::parse_expression(lexer, 0.0)?);
+            match lexer.next() {
+                Token::Operator(",") => continue,
+                Token::Operator(op) if *op == close => return Ok(items),
+                _ => return Err(ParseError::new(unclosed, open)),
+            }
+        }
     }
 
-    pub fn eval_no_vars(&self) -> f32 {
-        let empty_vars = HashMap::new();
-        self.eval(&empty_vars)
+    fn expect_keyword(lexer: &mut Lexer, keyword: &'static str) -> Result<(), ParseError> {
+        let span = lexer.peek_span();
+        match lexer.next() {
+            Token::Ident(word) if word == keyword => Ok(()),
+            _ => Err(ParseError::new(ParseErrorKind::ExpectedKeyword(keyword), span)),
+        }
     }
 
-    pub fn eval(&self, vars: &HashMap<char, f32>) -> f32 {
+    pub fn eval_no_vars(&self) -> Result<Value, EvalError> {
+        self.eval(&Environment::new())
+    }
+
+    pub fn eval(&self, env: &Environment) -> Result<Value, EvalError> {
+        self.eval_in(env, &HashMap::new(), 0)
+    }
+
+    /// `locals` holds the parameters of the user function being evaluated;
+    /// they shadow global variables.
+    fn eval_in(&self, env: &Environment, locals: &HashMap<String, Value>, depth: usize) -> Result<Value, EvalError> {
+        let eval_bool = |expr: &Expression, context: &'static str, span: Span| {
+            let value = expr.eval_in(env, locals, depth)?;
+            value.as_bool().ok_or_else(|| {
+                EvalError::new(EvalErrorKind::ExpectedBool { context, found: value.type_name() }, span)
+            })
+        };
         match self {
-            Expression::Number(n) => *n,
-            Expression::Variable(name) => {
-                *vars.get(name).expect("Variable not found")
-            },
-            Expression::Operator(op, exprs) => {
-                let left_expr = exprs[0].eval(vars);
-                let right_expr = exprs[1].eval(vars);
-                match op {
-                    '+' => left_expr + right_expr,
-                    '-' => left_expr - right_expr,
-                    '*' => left_expr * right_expr,
-                    '/' => left_expr / right_expr,
-                    '^' => {
-                        if right_expr < 0.0 {
-                            panic!("Negative exponent not supported");
-                        }
-                        left_expr.powf(right_expr)
-                    },
-                    _ => panic!("Unknown operator {:?}", op),
+            Expression::Number(n) => Ok(Value::Number(*n)),
+            Expression::Bool(b) => Ok(Value::Bool(*b)),
+            Expression::Str(s) => Ok(Value::Str(s.clone())),
+            Expression::List(items) => items
+                .iter()
+                .map(|item| item.eval_in(env, locals, depth))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::List),
+            Expression::Variable(name, span) => locals
+                .get(name)
+                .or_else(|| env.variables.get(name))
+                .cloned()
+                .or_else(|| constant(name).map(Value::Number))
+                .ok_or_else(|| EvalError::new(EvalErrorKind::UndefinedVariable(name.clone()), *span)),
+            Expression::Operator("=", _, span) => Err(EvalError::new(EvalErrorKind::InvalidAssignment, *span)),
+            // Short-circuit: the right side is only evaluated when needed.
+            Expression::Operator(op @ ("&&" | "||"), exprs, span) => {
+                let left = eval_bool(&exprs[0], op, *span)?;
+                if left == (*op == "||") {
+                    return Ok(Value::Bool(left));
                 }
+                eval_bool(&exprs[1], op, *span).map(Value::Bool)
+            }
+            Expression::Operator(op, exprs, span) => {
+                let left_expr = exprs[0].eval_in(env, locals, depth)?;
+                let right_expr = exprs[1].eval_in(env, locals, depth)?;
+                apply_value(op, left_expr, right_expr).map_err(|kind| EvalError::new(kind, *span))
+            }
+            Expression::Not(inner, span) => eval_bool(inner, "!", *span).map(|b| Value::Bool(!b)),
+            Expression::If(condition, then_branch, else_branch, span) => {
+                if eval_bool(condition, "if", *span)? {
+                    then_branch.eval_in(env, locals, depth)
+                } else {
+                    else_branch.eval_in(env, locals, depth)
+                }
+            }
+            Expression::Call(name, args, span) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval_in(env, locals, depth))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let arity_error = |min, max| {
+                    EvalError::new(EvalErrorKind::ArityMismatch { name: name.clone(), min, max, found: args.len() }, *span)
+                };
+                let type_error = || {
+                    let found = args.iter().map(Value::type_name).collect::<Vec<_>>().join(", ");
+                    EvalError::new(EvalErrorKind::TypeMismatch { operation: name.clone(), found }, *span)
+                };
+                if let Some(builtin) = builtin(name) {
+                    if !(builtin.min_args..=builtin.max_args).contains(&args.len()) {
+                        return Err(arity_error(builtin.min_args, builtin.max_args));
+                    }
+                    let numbers: Option<Vec<f64>> = args.iter().map(Value::as_number).collect();
+                    return numbers.map(|numbers| Value::Number((builtin.apply)(&numbers))).ok_or_else(type_error);
+                }
+                if let Some(builtin) = value_builtin(name) {
+                    if args.len() != builtin.args {
+                        return Err(arity_error(builtin.args, builtin.args));
+                    }
+                    return (builtin.apply)(&args).ok_or_else(type_error);
+                }
+                let Some(function) = env.functions.get(name) else {
+                    return Err(EvalError::new(EvalErrorKind::UndefinedFunction(name.clone()), *span));
+                };
+                if function.params.len() != args.len() {
+                    return Err(arity_error(function.params.len(), function.params.len()));
+                }
+                if depth >= MAX_CALL_DEPTH {
+                    return Err(EvalError::new(EvalErrorKind::RecursionLimit(name.clone()), *span));
+                }
+                let locals = function.params.iter().cloned().zip(args).collect();
+                function.body.eval_in(env, &locals, depth + 1)
             }
         }
     }
 
-    pub fn is_asign(&self) -> Option<(char,&Expression)>{
+    pub fn is_asign(&self) -> Option<(&str,&Expression)>{
         match self {
-            Expression::Operator('=',exprs) if exprs.len() == 2 => {
-                if let Expression::Variable(var_name) = exprs[0] {
+            Expression::Operator("=",exprs, _) if exprs.len() == 2 => {
+                if let Expression::Variable(var_name, _) = &exprs[0] {
                     Some((var_name, &exprs[1]))
                 } else {
                     None
@@ -179,32 +730,298 @@ impl Expression {
     }
 }
 
-fn precedence(op: char) -> (f32,f32) {
+fn precedence(op: &str) -> Option<(f32,f32)> {
+    BINARY_OPERATORS
+        .iter()
+        .find(|(symbol, _, _)| *symbol == op)
+        .map(|&(_, left_pd, right_pd)| (left_pd, right_pd))
+}
+
+/// Arithmetic on numbers; shared with the bytecode VM and the simplifier.
+fn apply_binary(op: &'static str, left: f64, right: f64) -> Result<f64, EvalErrorKind> {
     match op {
-        '=' => (0.0, 0.1),
-        '+' | '-' => (1.0,1.1),
-        '*' | '/' => (2.0,2.1),
-        '^' => (3.0,3.1),
-        _ => panic!("Unknown operator {:?}",op),
+        "+" => Ok(left + right),
+        "-" => Ok(left - right),
+        "*" => Ok(left * right),
+        "/" => Ok(left / right),
+        "^" => {
+            if right < 0.0 {
+                return Err(EvalErrorKind::NegativeExponent);
+            }
+            Ok(left.powf(right))
+        },
+        _ => Err(EvalErrorKind::UnknownOperator(op)),
+    }
+}
+
+/// Every binary operator except `=`, `&&` and `||`, which `eval_in` handles
+/// itself.  `+` also concatenates strings and lists, `<` and friends also
+/// compare strings, and `==`/`!=` need both sides to have the same type.
+fn apply_value(op: &'static str, left: Value, right: Value) -> Result<Value, EvalErrorKind> {
+    let ordering = match (&left, &right) {
+        (Value::Number(l), Value::Number(r)) => l.partial_cmp(r),
+        (Value::Str(l), Value::Str(r)) => Some(l.cmp(r)),
+        _ => None,
+    };
+    match (op, left, right) {
+        (_, Value::Number(l), Value::Number(r)) if !matches!(op, "==" | "!=" | "<" | "<=" | ">" | ">=") => {
+            apply_binary(op, l, r).map(Value::Number)
+        }
+        ("==" | "!=", l, r) if l.type_name() == r.type_name() => Ok(Value::Bool((l == r) == (op == "=="))),
+        // NaN compares false to everything.
+        ("<" | "<=" | ">" | ">=", Value::Number(_), Value::Number(_)) | ("<" | "<=" | ">" | ">=", Value::Str(_), Value::Str(_)) => {
+            Ok(Value::Bool(ordering.is_some_and(|ordering| match op {
+                "<" => ordering.is_lt(),
+                "<=" => ordering.is_le(),
+                ">" => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            })))
+        }
+        ("+", Value::Str(l), Value::Str(r)) => Ok(Value::Str(l + &r)),
+        ("+", Value::List(mut l), Value::List(r)) => {
+            l.extend(r);
+            Ok(Value::List(l))
+        }
+        (op, l, r) if precedence(op).is_some() => Err(EvalErrorKind::TypeMismatch {
+            operation: op.to_string(),
+            found: format!("{} and {}", l.type_name(), r.type_name()),
+        }),
+        (op, ..) => Err(EvalErrorKind::UnknownOperator(op)),
+    }
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span) -> Self {
+        ParseError { kind, span }
+    }
+
+    /// Caret-underlined diagnostic for `source`, the text that was parsed.
+    pub fn render(&self, source: &str) -> String {
+        render_diagnostic(source, self.span, self)
+    }
+}
+
+impl EvalError {
+    pub fn new(kind: EvalErrorKind, span: Span) -> Self {
+        EvalError { kind, span }
+    }
+
+    /// Caret-underlined diagnostic for `source`, the text the expression was
+    /// parsed from.
+    pub fn render(&self, source: &str) -> String {
+        render_diagnostic(source, self.span, self)
+    }
+}
+
+/// Format `message` followed by the source line with `span` underlined:
+///
+/// ```text
+/// error: unknown operator '%'
+///   | 1 % 2
+///   |   ^
+/// ```
+fn render_diagnostic(source: &str, span: Span, message: &dyn fmt::Display) -> String {
+    let line = source.trim_end();
+    let start = span.start.min(line.len());
+    let end = span.end.clamp(start, line.len());
+    let column = line[..start].chars().count();
+    let width = line[start..end].chars().count().max(1);
+    format!("error: {message}\n  | {line}\n  | {}{}", " ".repeat(column), "^".repeat(width))
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "number {n}"),
+            Token::Str(s) => write!(f, "string {s:?}"),
+            Token::Ident(name) => write!(f, "'{name}'"),
+            Token::Operator(op) => write!(f, "'{op}'"),
+            Token::Unknown(c) => write!(f, "'{c}'"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::ExpectedOperand(token) => write!(f, "expected a value, name or '(', found {token}"),
+            ParseErrorKind::ExpectedOperator(token) => write!(f, "expected an operator, found {token}"),
+            ParseErrorKind::UnknownOperator(op) => write!(f, "unknown operator '{op}'"),
+            ParseErrorKind::UnclosedParen => write!(f, "unclosed '('"),
+            ParseErrorKind::UnmatchedParen => write!(f, "unmatched ')'"),
+            ParseErrorKind::UnclosedBracket => write!(f, "unclosed '['"),
+            ParseErrorKind::UnmatchedBracket => write!(f, "unmatched ']'"),
+            ParseErrorKind::ExpectedKeyword(keyword) => write!(f, "expected '{keyword}'"),
+            ParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            EvalErrorKind::UndefinedVariable(name) => write!(f, "variable '{name}' is not defined"),
+            EvalErrorKind::UndefinedFunction(name) => write!(f, "function '{name}' is not defined"),
+            EvalErrorKind::ArityMismatch { name, min, max, found } => {
+                let expected = if min == max {
+                    min.to_string()
+                } else if *max == usize::MAX {
+                    format!("at least {min}")
+                } else {
+                    format!("{min} to {max}")
+                };
+                write!(f, "'{name}' takes {expected} argument(s), got {found}")
+            }
+            EvalErrorKind::NegativeExponent => write!(f, "negative exponent not supported"),
+            EvalErrorKind::UnknownOperator(op) => write!(f, "unknown operator '{op}'"),
+            EvalErrorKind::InvalidAssignment => write!(f, "can only assign to a name or define `name(params) = ...`"),
+            EvalErrorKind::InvalidParameters(name) => write!(f, "parameters of '{name}' must be distinct names"),
+            EvalErrorKind::ReservedName(name) => write!(f, "'{name}' is built in and cannot be redefined"),
+            EvalErrorKind::RecursionLimit(name) => write!(f, "call depth exceeded in '{name}'"),
+            EvalErrorKind::NotDifferentiable(name) => write!(f, "cannot differentiate '{name}'"),
+            EvalErrorKind::TypeMismatch { operation, found } => write!(f, "'{operation}' cannot be applied to {found}"),
+            EvalErrorKind::ExpectedBool { context, found } => write!(f, "'{context}' needs a bool, found {found}"),
+            EvalErrorKind::NotCompilable(what) => write!(f, "{what} cannot be compiled to bytecode"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl Expression {
+    /// Fully parenthesised prefix form, e.g. `(+ 1 (* 2 x))`; shows the
+    /// tree exactly as parsed.
+    pub fn to_sexpr(&self) -> String {
+        match self {
+            Expression::Number(_) | Expression::Bool(_) | Expression::Str(_) => self.to_string(),
+            Expression::List(items) => sexpr_list("list", items),
+            Expression::Variable(name, _) => name.clone(),
+            Expression::Operator(op, exprs, _) => sexpr_list(op, exprs),
+            Expression::Not(inner, _) => format!("(! {})", inner.to_sexpr()),
+            Expression::If(condition, then_branch, else_branch, _) => format!(
+                "(if {} {} {})",
+                condition.to_sexpr(),
+                then_branch.to_sexpr(),
+                else_branch.to_sexpr()
+            ),
+            Expression::Call(name, args, _) => sexpr_list(name, args),
+        }
+    }
+
+    /// Prefix `-e`, parsed as `0 - e`; negative literals print the same way.
+    fn is_negation(&self) -> bool {
+        match self {
+            Expression::Number(n) => n.is_sign_negative() && *n != 0.0,
+            Expression::Operator("-", exprs, _) => matches!(exprs[0], Expression::Number(z) if z == 0.0),
+            _ => false,
+        }
     }
+
+    /// Write `self` as an operand that will be re-parsed with binding power
+    /// `min_pd`, adding parentheses only where the parse would differ.
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, min_pd: f32) -> fmt::Result {
+        let needs_parens = if self.is_negation() {
+            // `a * -b ^ c` would parse as `a * -(b ^ c)`.
+            min_pd > PREFIX_MINUS_PD
+        } else {
+            match self {
+                Expression::Operator(op, _, _) => precedence(op).is_some_and(|(left_pd, _)| left_pd < min_pd),
+                Expression::Not(..) => min_pd > PREFIX_NOT_PD,
+                // The else branch would swallow whatever follows.
+                Expression::If(..) => true,
+                _ => false,
+            }
+        };
+        if needs_parens {
+            write!(f, "({self})")
+        } else {
+            write!(f, "{self}")
+        }
+    }
+}
+
+fn sexpr_list(head: &str, items: &[Expression]) -> String {
+    let mut out = format!("({head}");
+    for item in items {
+        out.push(' ');
+        out.push_str(&item.to_sexpr());
+    }
+    out.push(')');
+    out
+}
+
+fn fmt_items<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{item}")?;
+    }
+    Ok(())
 }
 
+/// Infix with the fewest parentheses that still parse back to the same tree.
 impl fmt::Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Expression::Number(n) => write!(f, "{n}"),
-            Expression::Variable(name) => write!(f, "{name}"),
-            Expression::Operator(op, exprs) => {
-                write!(f, "({op}")?;
-                for s in exprs {
-                    write!(f, " {}", s)?;
+            Expression::Bool(b) => write!(f, "{b}"),
+            Expression::Str(s) => write!(f, "{s:?}"),
+            Expression::List(items) => {
+                write!(f, "[")?;
+                fmt_items(f, items)?;
+                write!(f, "]")
+            }
+            Expression::Variable(name, _) => write!(f, "{name}"),
+            Expression::Operator("-", exprs, _) if self.is_negation() => {
+                write!(f, "-")?;
+                exprs[1].fmt_operand(f, PREFIX_MINUS_PD)
+            }
+            Expression::Operator(op, exprs, _) => {
+                let (left_pd, right_pd) = precedence(op).unwrap_or((0.0, 0.0));
+                exprs[0].fmt_operand(f, left_pd)?;
+                if *op == "^" {
+                    write!(f, "^")?;
+                } else {
+                    write!(f, " {op} ")?;
                 }
+                exprs[1].fmt_operand(f, right_pd)
+            }
+            Expression::Not(inner, _) => {
+                write!(f, "!")?;
+                inner.fmt_operand(f, PREFIX_NOT_PD)
+            }
+            Expression::If(condition, then_branch, else_branch, _) => {
+                write!(f, "if {condition} then {then_branch} else {else_branch}")
+            }
+            Expression::Call(name, args, _) => {
+                write!(f, "{name}(")?;
+                fmt_items(f, args)?;
                 write!(f, ")")
             }
         }
     }
 }
 
+/// Strings print quoted, as they would be written in an expression.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Str(s) => write!(f, "{s:?}"),
+            Value::List(items) => {
+                write!(f, "[")?;
+                fmt_items(f, items)?;
+                write!(f, "]")
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -223,7 +1040,7 @@ mod tests {
         }
 
         match &lexer.tokens[1] {
-            Token::Operator(op) => assert_eq!(*op, '+'),
+            Token::Operator(op) => assert_eq!(*op, "+"),
             _ => panic!("Expected Operator(+)"),
         }
 
@@ -248,7 +1065,7 @@ mod tests {
     #[test]
     fn tokenize_all_operators() {
         let lexer = Lexer::new("1 + 2 - 3 * 4 / 5 ^ 6");
-        let expected_ops = ['+', '-', '*', '/', '^'];
+        let expected_ops = ["+", "-", "*", "/", "^"];
         let mut op_index = 0;
 
         for (i, token) in lexer.tokens.iter().enumerate() {
@@ -264,8 +1081,8 @@ mod tests {
     #[test]
     fn tokenize_expression_with_parentheses() {
         let lexer = Lexer::new("(1 + 2) * 3");
-        assert_eq!(lexer.tokens[0], Token::Operator('('));
-        assert_eq!(lexer.tokens[4], Token::Operator(')'));
+        assert_eq!(lexer.tokens[0], Token::Operator("("));
+        assert_eq!(lexer.tokens[4], Token::Operator(")"));
     }
 
     #[test]
@@ -273,7 +1090,7 @@ mod tests {
         let lexer = Lexer::new("  1   +   2   ");
         assert_eq!(lexer.tokens.len(), 4); // Should ignore whitespace
         assert_eq!(lexer.tokens[0], Token::Number(1.0));
-        assert_eq!(lexer.tokens[1], Token::Operator('+'));
+        assert_eq!(lexer.tokens[1], Token::Operator("+"));
         assert_eq!(lexer.tokens[2], Token::Number(2.0));
     }
 
@@ -281,310 +1098,390 @@ mod tests {
 
     #[test]
     fn parse_single_number() {
-        let expr = Expression::from_str("1");
+        let expr = Expression::from_str("1").unwrap();
         assert_eq!(expr.to_string(), "1");
     }
 
     #[test]
     fn parse_large_single_number() {
-        let expr = Expression::from_str("12345");
+        let expr = Expression::from_str("12345").unwrap();
         assert_eq!(expr.to_string(), "12345");
     }
 
     #[test]
     fn parse_multiplication_precedence_over_addition() {
-        let expr = Expression::from_str("1 + 2 * 3");
-        assert_eq!(expr.to_string(), "(+ 1 (* 2 3))");
+        let expr = Expression::from_str("1 + 2 * 3").unwrap();
+        assert_eq!(expr.to_sexpr(), "(+ 1 (* 2 3))");
     }
 
     #[test]
     fn parse_left_associative_multiplication() {
-        let expr = Expression::from_str("1 * 2 * 3");
-        assert_eq!(expr.to_string(), "(* (* 1 2) 3)");
+        let expr = Expression::from_str("1 * 2 * 3").unwrap();
+        assert_eq!(expr.to_sexpr(), "(* (* 1 2) 3)");
     }
 
     #[test]
     fn parse_left_associative_addition() {
-        let expr = Expression::from_str("1 + 2 + 3");
-        assert_eq!(expr.to_string(), "(+ (+ 1 2) 3)");
+        let expr = Expression::from_str("1 + 2 + 3").unwrap();
+        assert_eq!(expr.to_sexpr(), "(+ (+ 1 2) 3)");
     }
 
     #[test]
     fn parse_left_associative_subtraction() {
-        let expr = Expression::from_str("10 - 5 - 2");
-        assert_eq!(expr.to_string(), "(- (- 10 5) 2)");
+        let expr = Expression::from_str("10 - 5 - 2").unwrap();
+        assert_eq!(expr.to_sexpr(), "(- (- 10 5) 2)");
     }
 
     #[test]
     fn parse_left_associative_division() {
-        let expr = Expression::from_str("20 / 4 / 2");
-        assert_eq!(expr.to_string(), "(/ (/ 20 4) 2)");
+        let expr = Expression::from_str("20 / 4 / 2").unwrap();
+        assert_eq!(expr.to_sexpr(), "(/ (/ 20 4) 2)");
     }
 
     #[test]
     fn parse_left_associative_exponentiation() {
-        let expr = Expression::from_str("2 ^ 3 ^ 4");
-        assert_eq!(expr.to_string(), "(^ (^ 2 3) 4)");
+        let expr = Expression::from_str("2 ^ 3 ^ 4").unwrap();
+        assert_eq!(expr.to_sexpr(), "(^ (^ 2 3) 4)");
     }
 
     #[test]
     fn parse_complex_precedence_with_multiple_operations() {
-        let expr = Expression::from_str("22 + 33 * 2 * 44 + 1 / 4");
-        assert_eq!(expr.to_string(), "(+ (+ 22 (* (* 33 2) 44)) (/ 1 4))");
+        let expr = Expression::from_str("22 + 33 * 2 * 44 + 1 / 4").unwrap();
+        assert_eq!(expr.to_sexpr(), "(+ (+ 22 (* (* 33 2) 44)) (/ 1 4))");
     }
 
     #[test]
     fn parse_mixed_operations_with_precedence() {
-        let expr = Expression::from_str("2 + 2 * 5 - 3 / 5 + 5 - 3");
-        assert_eq!(expr.to_string(), "(- (+ (- (+ 2 (* 2 5)) (/ 3 5)) 5) 3)");
+        let expr = Expression::from_str("2 + 2 * 5 - 3 / 5 + 5 - 3").unwrap();
+        assert_eq!(expr.to_sexpr(), "(- (+ (- (+ 2 (* 2 5)) (/ 3 5)) 5) 3)");
     }
 
     #[test]
     fn parse_parentheses_override_precedence() {
-        let expr = Expression::from_str("(2 + 444) * 5");
-        assert_eq!(expr.to_string(), "(* (+ 2 444) 5)");
+        let expr = Expression::from_str("(2 + 444) * 5").unwrap();
+        assert_eq!(expr.to_sexpr(), "(* (+ 2 444) 5)");
     }
 
     #[test]
     fn parse_nested_parentheses() {
-        let expr = Expression::from_str("(((11)))");
-        assert_eq!(expr.to_string(), "11");
+        let expr = Expression::from_str("(((11)))").unwrap();
+        assert_eq!(expr.to_sexpr(), "11");
     }
 
     #[test]
     fn parse_complex_expression_with_all_operators() {
-        let expr = Expression::from_str("13 + 5 * 211 - 8 / 4");
-        assert_eq!(expr.to_string(), "(- (+ 13 (* 5 211)) (/ 8 4))");
+        let expr = Expression::from_str("13 + 5 * 211 - 8 / 4").unwrap();
+        assert_eq!(expr.to_sexpr(), "(- (+ 13 (* 5 211)) (/ 8 4))");
     }
 
     #[test]
     fn parse_exponentiation_with_other_operations() {
-        let expr = Expression::from_str("2 + 3 ^ 2 * 4");
-        assert_eq!(expr.to_string(), "(+ 2 (* (^ 3 2) 4))");
+        let expr = Expression::from_str("2 + 3 ^ 2 * 4").unwrap();
+        assert_eq!(expr.to_sexpr(), "(+ 2 (* (^ 3 2) 4))");
     }
 
     #[test]
     fn parse_complex_parenthetical_expression() {
-        let expr = Expression::from_str("(1 + 2) * (3 + 4) / (5 - 3)");
-        assert_eq!(expr.to_string(), "(/ (* (+ 1 2) (+ 3 4)) (- 5 3))");
+        let expr = Expression::from_str("(1 + 2) * (3 + 4) / (5 - 3)").unwrap();
+        assert_eq!(expr.to_sexpr(), "(/ (* (+ 1 2) (+ 3 4)) (- 5 3))");
     }
 
     #[test]
     fn parse_deeply_nested_parentheses() {
-        let expr = Expression::from_str("((1 + 2) * (3 + (4 * 5)))");
-        assert_eq!(expr.to_string(), "(* (+ 1 2) (+ 3 (* 4 5)))");
+        let expr = Expression::from_str("((1 + 2) * (3 + (4 * 5)))").unwrap();
+        assert_eq!(expr.to_sexpr(), "(* (+ 1 2) (+ 3 (* 4 5)))");
     }
 
     // ===== EVALUATION TESTS =====
 
     #[test]
     fn evaluate_single_number() {
-        let expr = Expression::from_str("42");
-        assert_eq!(expr.eval_no_vars(), 42.0);
+        let expr = Expression::from_str("42").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 42.0);
     }
 
     #[test]
     fn evaluate_simple_addition() {
-        let expr = Expression::from_str("2 + 3");
-        assert_eq!(expr.eval_no_vars(), 5.0);
+        let expr = Expression::from_str("2 + 3").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 5.0);
     }
 
     #[test]
     fn evaluate_simple_subtraction() {
-        let expr = Expression::from_str("10 - 4");
-        assert_eq!(expr.eval_no_vars(), 6.0);
+        let expr = Expression::from_str("10 - 4").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 6.0);
     }
 
     #[test]
     fn evaluate_simple_multiplication() {
-        let expr = Expression::from_str("6 * 7");
-        assert_eq!(expr.eval_no_vars(), 42.0);
+        let expr = Expression::from_str("6 * 7").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 42.0);
     }
 
     #[test]
     fn evaluate_simple_division() {
-        let expr = Expression::from_str("15 / 3");
-        assert_eq!(expr.eval_no_vars(), 5.0);
+        let expr = Expression::from_str("15 / 3").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 5.0);
     }
 
     #[test]
     fn evaluate_simple_exponentiation() {
-        let expr = Expression::from_str("2 ^ 3");
-        assert_eq!(expr.eval_no_vars(), 8.0);
+        let expr = Expression::from_str("2 ^ 3").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 8.0);
     }
 
     #[test]
     fn evaluate_precedence_multiplication_over_addition() {
-        let expr = Expression::from_str("2 + 3 * 4");
-        assert_eq!(expr.eval_no_vars(), 14.0); // 2 + (3 * 4) = 2 + 12 = 14
+        let expr = Expression::from_str("2 + 3 * 4").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 14.0); // 2 + (3 * 4) = 2 + 12 = 14
     }
 
     #[test]
     fn evaluate_precedence_exponentiation_over_multiplication() {
-        let expr = Expression::from_str("2 * 3 ^ 2");
-        assert_eq!(expr.eval_no_vars(), 18.0); // 2 * (3 ^ 2) = 2 * 9 = 18
+        let expr = Expression::from_str("2 * 3 ^ 2").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 18.0); // 2 * (3 ^ 2) = 2 * 9 = 18
     }
 
     #[test]
     fn evaluate_left_associative_subtraction() {
-        let expr = Expression::from_str("10 - 3 - 2");
-        assert_eq!(expr.eval_no_vars(), 5.0); // (10 - 3) - 2 = 7 - 2 = 5
+        let expr = Expression::from_str("10 - 3 - 2").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 5.0); // (10 - 3) - 2 = 7 - 2 = 5
     }
 
     #[test]
     fn evaluate_left_associative_division() {
-        let expr = Expression::from_str("20 / 4 / 2");
-        assert_eq!(expr.eval_no_vars(), 2.5); // (20 / 4) / 2 = 5 / 2 = 2.5 (float division)
+        let expr = Expression::from_str("20 / 4 / 2").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 2.5); // (20 / 4) / 2 = 5 / 2 = 2.5 (float division)
     }
 
     #[test]
     fn evaluate_parentheses_override_precedence() {
-        let expr = Expression::from_str("(2 + 3) * 4");
-        assert_eq!(expr.eval_no_vars(), 20.0); // (2 + 3) * 4 = 5 * 4 = 20
+        let expr = Expression::from_str("(2 + 3) * 4").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 20.0); // (2 + 3) * 4 = 5 * 4 = 20
     }
 
     #[test]
     fn evaluate_complex_expression() {
-        let expr = Expression::from_str("2 + 3 * 4 - 6 / 2");
-        assert_eq!(expr.eval_no_vars(), 11.0); // 2 + (3 * 4) - (6 / 2) = 2 + 12 - 3 = 11
+        let expr = Expression::from_str("2 + 3 * 4 - 6 / 2").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 11.0); // 2 + (3 * 4) - (6 / 2) = 2 + 12 - 3 = 11
     }
 
     #[test]
     fn evaluate_nested_parentheses() {
-        let expr = Expression::from_str("((2 + 3) * (4 + 1))");
-        assert_eq!(expr.eval_no_vars(), 25.0); // (5 * 5) = 25
+        let expr = Expression::from_str("((2 + 3) * (4 + 1))").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 25.0); // (5 * 5) = 25
     }
 
     #[test]
     fn evaluate_zero_exponent() {
-        let expr = Expression::from_str("5 ^ 0");
-        assert_eq!(expr.eval_no_vars(), 1.0); // Any number to the power of 0 is 1
+        let expr = Expression::from_str("5 ^ 0").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 1.0); // Any number to the power of 0 is 1
     }
 
     #[test]
     fn evaluate_one_exponent() {
-        let expr = Expression::from_str("42 ^ 1");
-        assert_eq!(expr.eval_no_vars(), 42.0); // Any number to the power of 1 is itself
+        let expr = Expression::from_str("42 ^ 1").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 42.0); // Any number to the power of 1 is itself
     }
 
     // ===== ERROR HANDLING TESTS =====
 
+    fn parse_err(input: &str) -> ParseError {
+        Expression::from_str(input).expect_err("expected a parse error")
+    }
+
+    #[test]
+    fn evaluate_negative_exponent_is_error() {
+        let expr = Expression::from_str("2 ^ (0 - 1)").unwrap();
+        let err = expr.eval_no_vars().unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::NegativeExponent);
+        assert_eq!(err.span, Span::new(2, 3));
+    }
+
+    #[test]
+    fn parse_invalid_starting_token_is_error() {
+        let err = parse_err("+ 1 2");
+        assert_eq!(err.kind, ParseErrorKind::ExpectedOperand(Token::Operator("+")));
+        assert_eq!(err.span, Span::new(0, 1));
+    }
+
+    #[test]
+    fn parse_invalid_operator_position_is_error() {
+        let err = parse_err("1 2 + 3");
+        assert_eq!(err.kind, ParseErrorKind::ExpectedOperator(Token::Number(2.0)));
+        assert_eq!(err.span, Span::new(2, 3));
+    }
+
+    #[test]
+    fn parse_unknown_operator_is_error() {
+        let err = parse_err("1 % 2");
+        assert_eq!(err.kind, ParseErrorKind::UnknownOperator('%'));
+        assert_eq!(err.span, Span::new(2, 3));
+    }
+
     #[test]
-    #[should_panic(expected = "Negative exponent not supported")]
-    fn evaluate_negative_exponent_panics() {
-        let expr = Expression::from_str("2 ^ (0 - 1)");
-        expr.eval_no_vars(); // Should panic on negative exponent
+    fn parse_unclosed_paren_points_at_open_paren() {
+        let err = parse_err("2 * (1 + 3");
+        assert_eq!(err.kind, ParseErrorKind::UnclosedParen);
+        assert_eq!(err.span, Span::new(4, 5));
     }
 
     #[test]
-    #[should_panic(expected = "left_expr unexpected token")]
-    fn parse_invalid_starting_token_panics() {
-        Expression::from_str("+ 1 2");
+    fn parse_unmatched_close_paren_is_error() {
+        let err = parse_err("1 + 2)");
+        assert_eq!(err.kind, ParseErrorKind::UnmatchedParen);
+        assert_eq!(err.span, Span::new(5, 6));
     }
 
     #[test]
-    #[should_panic(expected = "op unexpected token")]
-    fn parse_invalid_operator_position_panics() {
-        Expression::from_str("1 2 + 3");
+    fn parse_empty_input_is_error() {
+        let err = parse_err("  ");
+        assert_eq!(err.kind, ParseErrorKind::ExpectedOperand(Token::Eof));
+        assert_eq!(err.span, Span::new(2, 2));
+    }
+
+    #[test]
+    fn parse_truncated_expression_points_past_end() {
+        let err = parse_err("1 +");
+        assert_eq!(err.kind, ParseErrorKind::ExpectedOperand(Token::Eof));
+        assert_eq!(err.span, Span::new(3, 3));
+    }
+
+    #[test]
+    fn tokenize_spans_are_byte_ranges() {
+        let lexer = Lexer::new(" 12.5 *x");
+        assert_eq!(lexer.spans, vec![Span::new(1, 5), Span::new(6, 7), Span::new(7, 8), Span::new(8, 8)]);
+    }
+
+    #[test]
+    fn render_underlines_offending_token() {
+        let input = "1 + 23 $ 4\n";
+        let err = parse_err(input);
+        assert_eq!(
+            err.render(input),
+            "error: unknown operator '$'\n  | 1 + 23 $ 4\n  |        ^"
+        );
+    }
+
+    #[test]
+    fn render_underlines_whole_token_and_end_of_input() {
+        let err = parse_err("1 2345");
+        assert!(err.render("1 2345").ends_with("  |   ^^^^"));
+
+        let err = parse_err("7 *");
+        assert!(err.render("7 *").ends_with("  |    ^"));
+    }
+
+    #[test]
+    fn render_counts_columns_in_chars() {
+        // Two no-break spaces: 4 bytes, but 2 columns on screen.
+        let input = "\u{a0}\u{a0}1 $ 2";
+        let err = parse_err(input);
+        assert_eq!(err.span, Span::new(6, 7));
+        assert!(err.render(input).ends_with("  |     ^"));
     }
 
     // ===== EDGE CASES =====
 
     #[test]
     fn parse_expression_with_trailing_whitespace() {
-        let expr = Expression::from_str("1 + 2   ");
-        assert_eq!(expr.eval_no_vars(), 3.0);
+        let expr = Expression::from_str("1 + 2   ").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 3.0);
     }
 
     #[test]
     fn parse_expression_with_leading_whitespace() {
-        let expr = Expression::from_str("   1 + 2");
-        assert_eq!(expr.eval_no_vars(), 3.0);
+        let expr = Expression::from_str("   1 + 2").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 3.0);
     }
 
     #[test]
     fn evaluate_large_numbers() {
-        let expr = Expression::from_str("999 + 1");
-        assert_eq!(expr.eval_no_vars(), 1000.0);
+        let expr = Expression::from_str("999 + 1").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 1000.0);
     }
 
     #[test]
     fn evaluate_division_with_integer_result() {
-        let expr = Expression::from_str("9 / 3");
-        assert_eq!(expr.eval_no_vars(), 3.0);
+        let expr = Expression::from_str("9 / 3").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 3.0);
     }
 
     #[test]
     fn evaluate_division_with_decimal_result() {
-        let expr = Expression::from_str("7 / 2");
-        assert_eq!(expr.eval_no_vars(), 3.5); // Float division gives precise result
+        let expr = Expression::from_str("7 / 2").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 3.5); // Float division gives precise result
     }
 
     // ===== VARIABLE TESTS =====
 
     #[test]
     fn evaluate_single_variable() {
-        let expr = Expression::from_str("x");
-        let mut vars = HashMap::new();
-        vars.insert('x', 42.0);
-        assert_eq!(expr.eval(&vars), 42.0);
+        let expr = Expression::from_str("x").unwrap();
+        let mut env = Environment::new();
+        env.set_var("x", 42.0);
+        assert_eq!(expr.eval(&env).unwrap(), 42.0);
     }
 
     #[test]
     fn evaluate_variable_in_expression() {
-        let expr = Expression::from_str("x + 10");
-        let mut vars = HashMap::new();
-        vars.insert('x', 5.0);
-        assert_eq!(expr.eval(&vars), 15.0);
+        let expr = Expression::from_str("x + 10").unwrap();
+        let mut env = Environment::new();
+        env.set_var("x", 5.0);
+        assert_eq!(expr.eval(&env).unwrap(), 15.0);
     }
 
     #[test]
     fn evaluate_multiple_variables() {
-        let expr = Expression::from_str("x * y + z");
-        let mut vars = HashMap::new();
-        vars.insert('x', 3.0);
-        vars.insert('y', 4.0);
-        vars.insert('z', 2.0);
-        assert_eq!(expr.eval(&vars), 14.0); // 3 * 4 + 2 = 14
+        let expr = Expression::from_str("x * y + z").unwrap();
+        let mut env = Environment::new();
+        env.set_var("x", 3.0);
+        env.set_var("y", 4.0);
+        env.set_var("z", 2.0);
+        assert_eq!(expr.eval(&env).unwrap(), 14.0); // 3 * 4 + 2 = 14
     }
 
     #[test]
     fn evaluate_assignment_expression() {
-        let expr = Expression::from_str("x = 5 + 3");
+        let expr = Expression::from_str("x = 5 + 3").unwrap();
         if let Some((var_name, value_expr)) = expr.is_asign() {
-            assert_eq!(var_name, 'x');
-            assert_eq!(value_expr.eval_no_vars(), 8.0);
+            assert_eq!(var_name, "x");
+            assert_eq!(value_expr.eval_no_vars().unwrap(), 8.0);
         } else {
             panic!("Expected assignment expression");
         }
     }
 
     #[test]
-    #[should_panic(expected = "Variable not found")]
-    fn evaluate_undefined_variable_panics() {
-        let expr = Expression::from_str("x + 1");
-        let vars = HashMap::new(); // Empty variables map
-        expr.eval(&vars); // Should panic
+    fn evaluate_undefined_variable_is_error() {
+        let input = "2 * (x + 1)";
+        let expr = Expression::from_str(input).unwrap();
+        let err = expr.eval_no_vars().unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::UndefinedVariable("x".to_string()));
+        assert_eq!(
+            err.render(input),
+            "error: variable 'x' is not defined\n  | 2 * (x + 1)\n  |      ^"
+        );
     }
 
     // ===== UNARY MINUS TESTS =====
 
     #[test]
     fn evaluate_unary_minus() {
-        let expr = Expression::from_str("-(5)");
-        assert_eq!(expr.eval_no_vars(), -5.0);
+        let expr = Expression::from_str("-(5)").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), -5.0);
     }
 
     #[test]
     fn evaluate_unary_minus_with_expression() {
-        let expr = Expression::from_str("-(2 + 3)");
-        assert_eq!(expr.eval_no_vars(), -5.0);
+        let expr = Expression::from_str("-(2 + 3)").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), -5.0);
     }
 
     #[test]
     fn evaluate_double_unary_minus() {
-        let expr = Expression::from_str("-(-5)");
-        assert_eq!(expr.eval_no_vars(), 5.0);
+        let expr = Expression::from_str("-(-5)").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 5.0);
     }
 
     // ===== ADDITIONAL EDGE CASE TESTS =====
@@ -597,49 +1494,50 @@ mod tests {
 
     #[test]
     fn evaluate_division_by_one() {
-        let expr = Expression::from_str("42 / 1");
-        assert_eq!(expr.eval_no_vars(), 42.0);
+        let expr = Expression::from_str("42 / 1").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 42.0);
     }
 
     #[test]
     fn evaluate_multiplication_by_zero() {
-        let expr = Expression::from_str("999 * 0");
-        assert_eq!(expr.eval_no_vars(), 0.0);
+        let expr = Expression::from_str("999 * 0").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 0.0);
     }
 
     #[test]
     fn evaluate_addition_with_zero() {
-        let expr = Expression::from_str("42 + 0");
-        assert_eq!(expr.eval_no_vars(), 42.0);
+        let expr = Expression::from_str("42 + 0").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 42.0);
     }
 
     #[test]
     fn evaluate_subtraction_with_zero() {
-        let expr = Expression::from_str("42 - 0");
-        assert_eq!(expr.eval_no_vars(), 42.0);
+        let expr = Expression::from_str("42 - 0").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 42.0);
     }
 
     #[test]
     fn parse_variable_names() {
-        let expr = Expression::from_str("a + B + z");
-        assert_eq!(expr.to_string(), "(+ (+ a B) z)");
+        let expr = Expression::from_str("a + B + z").unwrap();
+        assert_eq!(expr.to_sexpr(), "(+ (+ a B) z)");
     }
 
     #[test]
-    #[should_panic(expected = "Unknown operator")]
-    fn evaluate_unknown_operator_panics() {
+    fn evaluate_unknown_operator_is_error() {
         // This would require manually creating an invalid operator expression
         // since the parser doesn't allow unknown operators
-        let invalid_expr = Expression::Operator('%', vec![
+        let invalid_expr = Expression::Operator("%", vec![
             Expression::Number(5.0),
             Expression::Number(3.0),
-        ]);
-        invalid_expr.eval_no_vars();
+        ], Span::new(2, 3));
+        let err = invalid_expr.eval_no_vars().unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::UnknownOperator("%"));
     }
 
     // ===== FLOATING POINT SPECIFIC TESTS =====
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn tokenize_decimal_numbers() {
         let lexer = Lexer::new("3.14 + 2.5");
         // Check that we have decimal numbers, allowing for float precision
@@ -653,50 +1551,319 @@ mod tests {
 
     #[test]
     fn evaluate_decimal_arithmetic() {
-        let expr = Expression::from_str("3.5 + 2.25");
-        assert_eq!(expr.eval_no_vars(), 5.75);
+        let expr = Expression::from_str("3.5 + 2.25").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 5.75);
     }
 
     #[test]
     fn evaluate_precise_division() {
-        let expr = Expression::from_str("22 / 7");
-        let result = expr.eval_no_vars();
+        let expr = Expression::from_str("22 / 7").unwrap();
+        let result = expr.eval_no_vars().unwrap().as_number().unwrap();
         assert!((result - 3.142857).abs() < 0.0001); // Approximately pi
     }
 
     #[test]
     fn evaluate_fractional_exponentiation() {
-        let expr = Expression::from_str("4 ^ 0.5");
-        assert_eq!(expr.eval_no_vars(), 2.0); // Square root of 4
+        let expr = Expression::from_str("4 ^ 0.5").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 2.0); // Square root of 4
     }
 
     #[test]
     fn evaluate_mixed_int_float_operations() {
-        let expr = Expression::from_str("5 + 3.5 * 2");
-        assert_eq!(expr.eval_no_vars(), 12.0); // 5 + (3.5 * 2) = 5 + 7 = 12
+        let expr = Expression::from_str("5 + 3.5 * 2").unwrap();
+        assert_eq!(expr.eval_no_vars().unwrap(), 12.0); // 5 + (3.5 * 2) = 5 + 7 = 12
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn evaluate_float_variables() {
-        let expr = Expression::from_str("p * r * r");
-        let mut vars = HashMap::new();
-        vars.insert('p', 3.14159);
-        vars.insert('r', 2.5);
-        let result = expr.eval(&vars);
+        let expr = Expression::from_str("p * r * r").unwrap();
+        let mut env = Environment::new();
+        env.set_var("p", 3.14159);
+        env.set_var("r", 2.5);
+        let result = expr.eval(&env).unwrap().as_number().unwrap();
         // 3.14159 * 2.5 * 2.5 = 19.634375
         assert!((result - 19.634375).abs() < 0.001); // More lenient precision check
     }
 
     #[test]
     fn evaluate_negative_first() {
-        let expr = Expression::from_str("-2 + 1");
-        let result = expr.eval_no_vars();
+        let expr = Expression::from_str("-2 + 1").unwrap();
+        let result = expr.eval_no_vars().unwrap();
         // -2 + 1 = -1
         assert_eq!(result, -1.0);
 
-        let expr = Expression::from_str("1 - 3");
-        let result = expr.eval_no_vars();
+        let expr = Expression::from_str("1 - 3").unwrap();
+        let result = expr.eval_no_vars().unwrap();
         assert_eq!(result, -2.0);
 
     }
+
+    // ===== IDENTIFIER, FUNCTION AND F64 TESTS =====
+
+    fn run(env: &mut Environment, line: &str) -> Result<Option<Value>, EvalError> {
+        env.execute(Expression::from_str(line).unwrap())
+    }
+
+    #[test]
+    fn tokenize_multi_character_identifiers() {
+        let lexer = Lexer::new("rate_2 * dt");
+        assert_eq!(lexer.tokens[0], Token::Ident("rate_2".to_string()));
+        assert_eq!(lexer.tokens[2], Token::Ident("dt".to_string()));
+        assert_eq!(lexer.spans[0], Span::new(0, 6));
+    }
+
+    #[test]
+    fn tokenize_scientific_notation() {
+        let lexer = Lexer::new("1.5e3 + 2E-2 + .5 + 7e");
+        assert_eq!(lexer.tokens[0], Token::Number(1500.0));
+        assert_eq!(lexer.tokens[2], Token::Number(0.02));
+        assert_eq!(lexer.tokens[4], Token::Number(0.5));
+        // `7e` is the number 7 followed by the name `e`.
+        assert_eq!(lexer.tokens[6], Token::Number(7.0));
+        assert_eq!(lexer.tokens[7], Token::Ident("e".to_string()));
+    }
+
+    #[test]
+    fn tokenize_decimals_exactly_like_std() {
+        let lexer = Lexer::new("0.1 12345.6789");
+        assert_eq!(lexer.tokens[0], Token::Number(0.1));
+        assert_eq!(lexer.tokens[1], Token::Number(12345.6789));
+    }
+
+    #[test]
+    fn evaluate_long_variable_names() {
+        let mut env = Environment::new();
+        env.set_var("width", 3.0);
+        env.set_var("height", 4.0);
+        let expr = Expression::from_str("width * height").unwrap();
+        assert_eq!(expr.eval(&env).unwrap(), 12.0);
+    }
+
+    #[test]
+    fn parse_function_calls() {
+        let expr = Expression::from_str("max(1, 2 * x, sqrt(y)) + f()").unwrap();
+        assert_eq!(expr.to_sexpr(), "(+ (max 1 (* 2 x) (sqrt y)) (f))");
+    }
+
+    #[test]
+    fn parse_call_errors() {
+        assert_eq!(parse_err("sin(1").kind, ParseErrorKind::UnclosedParen);
+        assert_eq!(parse_err("sin(1").span, Span::new(3, 4));
+        assert_eq!(parse_err("min(1,)").kind, ParseErrorKind::ExpectedOperand(Token::Operator(")")));
+        assert_eq!(parse_err("1, 2").kind, ParseErrorKind::ExpectedOperator(Token::Operator(",")));
+    }
+
+    #[test]
+    fn evaluate_builtin_functions_and_constants() {
+        let eval = |s: &str| Expression::from_str(s).unwrap().eval_no_vars().unwrap().as_number().unwrap();
+        assert_eq!(eval("sqrt(16) + abs(-2)"), 6.0);
+        assert_eq!(eval("min(3, 1, 2) + max(3, 1, 2)"), 4.0);
+        assert_eq!(eval("log(1000)"), 3.0);
+        assert!((eval("log(8, 2)") - 3.0).abs() < 1e-12);
+        assert_eq!(eval("ln(e)"), 1.0);
+        assert!(eval("sin(pi)").abs() < 1e-12);
+        assert_eq!(eval("tau / pi"), 2.0);
+    }
+
+    #[test]
+    fn evaluate_builtin_arity_is_checked() {
+        let err = Expression::from_str("1 + sqrt(1, 2)").unwrap().eval_no_vars().unwrap_err();
+        assert_eq!(err.span, Span::new(4, 8));
+        assert_eq!(err.to_string(), "'sqrt' takes 1 argument(s), got 2");
+
+        let err = Expression::from_str("max()").unwrap().eval_no_vars().unwrap_err();
+        assert_eq!(err.to_string(), "'max' takes at least 1 argument(s), got 0");
+    }
+
+    #[test]
+    fn evaluate_user_defined_function() {
+        let mut env = Environment::new();
+        assert_eq!(run(&mut env, "f(x) = x^2 + 1"), Ok(None));
+        assert_eq!(run(&mut env, "hyp(a, b) = sqrt(a^2 + b^2)"), Ok(None));
+        assert_eq!(run(&mut env, "f(3)"), Ok(Some(Value::Number(10.0))));
+        assert_eq!(run(&mut env, "hyp(3, f(2) - 1)"), Ok(Some(Value::Number(5.0))));
+        assert_eq!(env.function("hyp").unwrap().params, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn user_function_params_shadow_globals() {
+        let mut env = Environment::new();
+        run(&mut env, "x = 100").unwrap();
+        run(&mut env, "k = 2").unwrap();
+        run(&mut env, "scale(x) = k * x").unwrap();
+        assert_eq!(run(&mut env, "scale(5)"), Ok(Some(Value::Number(10.0))));
+        assert_eq!(env.var("x"), Some(&Value::Number(100.0)));
+    }
+
+    #[test]
+    fn user_function_errors() {
+        let mut env = Environment::new();
+        run(&mut env, "f(x) = x").unwrap();
+        let err = run(&mut env, "f(1, 2)").unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::ArityMismatch { name: "f".to_string(), min: 1, max: 1, found: 2 });
+
+        let err = run(&mut env, "g(2)").unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::UndefinedFunction("g".to_string()));
+
+        let err = run(&mut env, "h(x, 1) = x").unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::InvalidParameters("h".to_string()));
+
+        run(&mut env, "loop(x) = loop(x)").unwrap();
+        let err = run(&mut env, "loop(1)").unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::RecursionLimit("loop".to_string()));
+    }
+
+    #[test]
+    fn builtins_cannot_be_redefined() {
+        let mut env = Environment::new();
+        let err = run(&mut env, "pi = 3").unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::ReservedName("pi".to_string()));
+        assert_eq!(err.span, Span::new(0, 2));
+
+        let err = run(&mut env, "sin(x) = x").unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::ReservedName("sin".to_string()));
+
+        let err = run(&mut env, "2 = 3").unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::InvalidAssignment);
+    }
+
+    // ===== COMPARISON, LOGIC, CONDITIONAL AND VALUE TESTS =====
+
+    fn eval_str(input: &str) -> Result<Value, EvalError> {
+        Expression::from_str(input).unwrap().eval_no_vars()
+    }
+
+    #[test]
+    fn tokenize_two_character_operators() {
+        let lexer = Lexer::new("a<=b==c!=!d&&e||f>=g");
+        let ops: Vec<_> = lexer.tokens.iter().filter_map(|t| match t {
+            Token::Operator(op) => Some(*op),
+            _ => None,
+        }).collect();
+        assert_eq!(ops, ["<=", "==", "!=", "!", "&&", "||", ">="]);
+        assert_eq!(lexer.spans[1], Span::new(1, 3));
+    }
+
+    #[test]
+    fn tokenize_string_literals() {
+        let lexer = Lexer::new(r#""db-1" + "say \"hi\"\n""#);
+        assert_eq!(lexer.tokens[0], Token::Str("db-1".to_string()));
+        assert_eq!(lexer.spans[0], Span::new(0, 6));
+        assert_eq!(lexer.tokens[2], Token::Str("say \"hi\"\n".to_string()));
+
+        let err = parse_err("1 + \"open");
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedString);
+        assert_eq!(err.span, Span::new(4, 9));
+    }
+
+    #[test]
+    fn parse_logic_precedence() {
+        let sexpr = |s: &str| Expression::from_str(s).unwrap().to_sexpr();
+        assert_eq!(sexpr("a || b && c"), "(|| a (&& b c))");
+        assert_eq!(sexpr("x + 1 > y * 2 && y != 0"), "(&& (> (+ x 1) (* y 2)) (!= y 0))");
+        assert_eq!(sexpr("!x > 3 && y"), "(&& (! (> x 3)) y)");
+        assert_eq!(sexpr("if a then 1 else 2 + 3"), "(if a 1 (+ 2 3))");
+        assert_eq!(sexpr("[1, \"a\", [true]]"), "(list 1 \"a\" (list true))");
+    }
+
+    #[test]
+    fn parse_conditional_and_list_errors() {
+        assert_eq!(parse_err("if x else 2").kind, ParseErrorKind::ExpectedKeyword("then"));
+        assert_eq!(parse_err("if x then 1").kind, ParseErrorKind::ExpectedKeyword("else"));
+        assert_eq!(parse_err("if x then 1").span, Span::new(11, 11));
+        assert_eq!(parse_err("then + 1").kind, ParseErrorKind::ExpectedOperand(Token::Ident("then".to_string())));
+        assert_eq!(parse_err("[1, 2").kind, ParseErrorKind::UnclosedBracket);
+        assert_eq!(parse_err("[1, 2").span, Span::new(0, 1));
+        assert_eq!(parse_err("1]").kind, ParseErrorKind::UnmatchedBracket);
+        assert_eq!(parse_err("1 ! 2").kind, ParseErrorKind::ExpectedOperator(Token::Operator("!")));
+    }
+
+    #[test]
+    fn evaluate_comparisons_and_logic() {
+        assert_eq!(eval_str("1 + 1 == 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("3 <= 2 || 2 >= 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("!(1 < 2) && true"), Ok(Value::Bool(false)));
+        assert_eq!(eval_str("\"abc\" < \"abd\""), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("[1, \"a\"] == [1, \"a\"]"), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("\"a\" + \"b\""), Ok(Value::from("ab")));
+        assert_eq!(eval_str("[1] + [2]"), Ok(Value::List(vec![1.0.into(), 2.0.into()])));
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        // The right side would be an undefined variable.
+        assert_eq!(eval_str("false && missing"), Ok(Value::Bool(false)));
+        assert_eq!(eval_str("true || missing"), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("if true then 1 else missing"), Ok(Value::Number(1.0)));
+        assert_eq!(
+            eval_str("true && missing").unwrap_err().kind,
+            EvalErrorKind::UndefinedVariable("missing".to_string())
+        );
+    }
+
+    #[test]
+    fn evaluate_type_errors() {
+        let err = eval_str("1 + \"a\"").unwrap_err();
+        assert_eq!(
+            err.kind,
+            EvalErrorKind::TypeMismatch { operation: "+".to_string(), found: "number and string".to_string() }
+        );
+        assert_eq!(err.span, Span::new(2, 3));
+        assert_eq!(err.to_string(), "'+' cannot be applied to number and string");
+
+        let err = eval_str("if 1 then 2 else 3").unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::ExpectedBool { context: "if", found: "number" });
+        assert_eq!(err.span, Span::new(0, 2));
+        assert_eq!(err.to_string(), "'if' needs a bool, found number");
+
+        assert!(matches!(eval_str("1 == true").unwrap_err().kind, EvalErrorKind::TypeMismatch { .. }));
+        assert!(matches!(eval_str("[1] < [2]").unwrap_err().kind, EvalErrorKind::TypeMismatch { .. }));
+        assert!(matches!(eval_str("!1").unwrap_err().kind, EvalErrorKind::ExpectedBool { context: "!", .. }));
+        assert_eq!(
+            eval_str("sqrt(\"4\")").unwrap_err().kind,
+            EvalErrorKind::TypeMismatch { operation: "sqrt".to_string(), found: "string".to_string() }
+        );
+    }
+
+    #[test]
+    fn evaluate_value_builtins() {
+        assert_eq!(eval_str("len(\"héllo\") + len([1, 2])"), Ok(Value::Number(7.0)));
+        assert_eq!(eval_str("contains([\"a\", \"b\"], \"b\")"), Ok(Value::Bool(true)));
+        assert_eq!(eval_str("contains(\"db-primary\", \"db-\")"), Ok(Value::Bool(true)));
+        assert!(matches!(eval_str("len(3)").unwrap_err().kind, EvalErrorKind::TypeMismatch { .. }));
+
+        let mut env = Environment::new();
+        assert_eq!(run(&mut env, "len(x) = x").unwrap_err().kind, EvalErrorKind::ReservedName("len".to_string()));
+    }
+
+    #[test]
+    fn threshold_alert_rule() {
+        let mut env = Environment::new();
+        run(&mut env, "critical = [\"db-1\", \"db-2\"]").unwrap();
+        run(&mut env, "severity(cpu, host) = if cpu > 95 || (cpu > 80 && contains(critical, host)) then \"page\" else \"ok\"").unwrap();
+        assert_eq!(run(&mut env, "severity(85, \"db-1\")"), Ok(Some(Value::from("page"))));
+        assert_eq!(run(&mut env, "severity(85, \"web-1\")"), Ok(Some(Value::from("ok"))));
+
+        env.set_var("host", "web-1");
+        env.set_var("cpu", 97.5);
+        assert_eq!(run(&mut env, "severity(cpu, host)"), Ok(Some(Value::from("page"))));
+    }
+
+    #[test]
+    fn display_round_trips_new_syntax() {
+        for input in [
+            "!(a && b) || c",
+            "!a && b",
+            "a == (!b)",
+            "(if a then 1 else 2) + 3",
+            "if a > 1 then \"hi\\n\" else [1, 2]",
+            "contains([1, 2], x) != false",
+        ] {
+            let expr = Expression::from_str(input).unwrap();
+            assert_eq!(expr.to_string(), input);
+            assert_eq!(Expression::from_str(&expr.to_string()).unwrap().to_sexpr(), expr.to_sexpr());
+        }
+        assert_eq!(Value::List(vec![1.5.into(), "a".into(), true.into()]).to_string(), "[1.5, \"a\", true]");
+    }
 }
@@ -1,28 +1,53 @@
-use std::{collections::HashMap, io::{self, Write}};
+use std::io::{self, Write};
 use parse_example::*;
 
 fn main() {
-    let mut variables: HashMap<char, f32> = HashMap::new();
+    let mut env = Environment::new();
 	loop{
 		print!(">> ");
 		io::stdout().flush().unwrap();
 		let input = {
 			let mut buf = String::new();
-			std::io::stdin().read_line(&mut buf).unwrap();
+			if std::io::stdin().read_line(&mut buf).unwrap() == 0 {
+				break; // EOF
+			}
 			buf
 		};
 		if input.trim() == "exit" {
 			break;
 		}
-		let expr = Expression::from_str(&input);
-		println!("Parsed expression: {}", expr.to_string());
-		if let Some((var_name, lhs)) = expr.is_asign(){
-			let value = lhs.eval(&variables);
-			variables.insert(var_name, value);
+		if input.trim().is_empty() {
 			continue;
 		}
-		let value = expr.eval(&variables);
-		println!("{}", value);
+		if let Some((var, body)) = derivative_command(&input) {
+			match body.parse::<Expression>().map_err(|e| e.render(body)).and_then(|expr| expr.derive(var).map_err(|e| e.render(body))) {
+				Ok(derivative) => println!("{}", derivative),
+				Err(diagnostic) => eprintln!("{}", diagnostic),
+			}
+			continue;
+		}
+		let expr = match input.parse::<Expression>() {
+			Ok(expr) => expr,
+			Err(e) => {
+				eprintln!("{}", e.render(&input));
+				continue;
+			}
+		};
+		println!("Parsed expression: {}", expr.to_sexpr());
+		// Assignments and function definitions print nothing.
+		match env.execute(expr) {
+			Ok(Some(value)) => println!("{}", value),
+			Ok(None) => {}
+			Err(e) => eprintln!("{}", e.render(&input)),
+		}
 	}
 }
 
+/// `d/dx <expr>`: the variable and the expression to differentiate.
+fn derivative_command(input: &str) -> Option<(&str, &str)> {
+	let rest = input.trim_start().strip_prefix("d/d")?;
+	let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))?;
+	let (var, body) = rest.split_at(end);
+	let starts_with_letter = var.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+	(starts_with_letter && body.starts_with(char::is_whitespace) && !body.trim().is_empty()).then_some((var, body.trim_start()))
+}
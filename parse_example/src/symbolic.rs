@@ -0,0 +1,531 @@
+//! Symbolic differentiation and algebraic simplification.
+//!
+//! `simplify` works bottom-up and is purely syntactic: it folds constants,
+//! drops identities (`x + 0`, `x * 1`, `x ^ 1`, ...) and collects like terms
+//! in sums (`2*x + 3*x` -> `5 * x`) and like bases in products
+//! (`x * y * x` -> `x^2 * y`).  Like terms are matched on their S-expression,
+//! so `x*y` and `y*x` only match because product factors are kept sorted.
+//!
+//! Builtins applied to constants are folded only when the result is an
+//! integer, so `ln(2)` stays exact.
+//!
+//! Rewrites that are only valid where the expression is defined, such as
+//! `x * 0 -> 0` or `x / x -> 1`, are applied anyway, as a CAS would.
+
+use std::collections::HashMap;
+
+use crate::{EvalError, EvalErrorKind, Expression, Span, apply_binary, builtin};
+
+fn num(n: f64) -> Expression {
+    Expression::Number(n)
+}
+
+fn binary(op: &'static str, left: Expression, right: Expression) -> Expression {
+    Expression::Operator(op, vec![left, right], Span::default())
+}
+
+fn neg(expr: Expression) -> Expression {
+    binary("-", num(0.0), expr)
+}
+
+fn call(name: &str, arg: Expression) -> Expression {
+    Expression::Call(name.to_string(), vec![arg], Span::default())
+}
+
+impl Expression {
+    /// Simplified derivative of `self` with respect to `var`.  Calls to
+    /// user-defined functions and to non-smooth builtins (`min`, `floor`,
+    /// ...) that depend on `var` are rejected.
+    pub fn derive(&self, var: &str) -> Result<Expression, EvalError> {
+        Ok(self.derivative(var)?.simplify())
+    }
+
+    pub fn depends_on(&self, var: &str) -> bool {
+        match self {
+            Expression::Number(_) | Expression::Bool(_) | Expression::Str(_) => false,
+            Expression::Variable(name, _) => name == var,
+            Expression::List(exprs) | Expression::Operator(_, exprs, _) | Expression::Call(_, exprs, _) => {
+                exprs.iter().any(|e| e.depends_on(var))
+            }
+            Expression::Not(inner, _) => inner.depends_on(var),
+            Expression::If(condition, then_branch, else_branch, _) => {
+                condition.depends_on(var) || then_branch.depends_on(var) || else_branch.depends_on(var)
+            }
+        }
+    }
+
+    fn derivative(&self, var: &str) -> Result<Expression, EvalError> {
+        if !self.depends_on(var) {
+            return Ok(num(0.0));
+        }
+        let d = match self {
+            Expression::Number(_) | Expression::Bool(_) | Expression::Str(_) => num(0.0),
+            Expression::Variable(..) => num(1.0),
+            // Piecewise: the condition only picks the branch.
+            Expression::If(condition, then_branch, else_branch, span) => Expression::If(
+                condition.clone(),
+                Box::new(then_branch.derivative(var)?),
+                Box::new(else_branch.derivative(var)?),
+                *span,
+            ),
+            Expression::List(_) => {
+                return Err(EvalError::new(EvalErrorKind::NotDifferentiable("list".to_string()), Span::default()));
+            }
+            Expression::Not(_, span) => {
+                return Err(EvalError::new(EvalErrorKind::NotDifferentiable("!".to_string()), *span));
+            }
+            Expression::Operator(op, exprs, span) => {
+                let (u, v) = (&exprs[0], &exprs[1]);
+                match *op {
+                    "+" | "-" => binary(op, u.derivative(var)?, v.derivative(var)?),
+                    "*" => binary(
+                        "+",
+                        binary("*", u.derivative(var)?, v.clone()),
+                        binary("*", u.clone(), v.derivative(var)?),
+                    ),
+                    "/" => binary(
+                        "/",
+                        binary(
+                            "-",
+                            binary("*", u.derivative(var)?, v.clone()),
+                            binary("*", u.clone(), v.derivative(var)?),
+                        ),
+                        binary("^", v.clone(), num(2.0)),
+                    ),
+                    "^" => power_derivative(u, v, var)?,
+                    _ => {
+                        return Err(EvalError::new(EvalErrorKind::NotDifferentiable(op.to_string()), *span));
+                    }
+                }
+            }
+            Expression::Call(name, args, span) => {
+                let not_differentiable = || EvalError::new(EvalErrorKind::NotDifferentiable(name.clone()), *span);
+                let [u] = args.as_slice() else {
+                    return Err(not_differentiable());
+                };
+                let du = u.derivative(var)?;
+                // d/du f(u); the chain rule multiplies by du below.
+                let outer = match name.as_str() {
+                    "sin" => call("cos", u.clone()),
+                    "cos" => neg(call("sin", u.clone())),
+                    "tan" => binary("/", num(1.0), binary("^", call("cos", u.clone()), num(2.0))),
+                    "exp" => call("exp", u.clone()),
+                    "ln" => binary("/", num(1.0), u.clone()),
+                    "log" => binary("/", num(1.0), binary("*", u.clone(), call("ln", num(10.0)))),
+                    "sqrt" => binary("/", num(1.0), binary("*", num(2.0), call("sqrt", u.clone()))),
+                    "asin" | "acos" => {
+                        let d = binary(
+                            "/",
+                            num(1.0),
+                            call("sqrt", binary("-", num(1.0), binary("^", u.clone(), num(2.0)))),
+                        );
+                        if name == "acos" { neg(d) } else { d }
+                    }
+                    "atan" => binary("/", num(1.0), binary("+", num(1.0), binary("^", u.clone(), num(2.0)))),
+                    "abs" => binary("/", u.clone(), call("abs", u.clone())),
+                    _ => return Err(not_differentiable()),
+                };
+                binary("*", outer, du)
+            }
+        };
+        Ok(d)
+    }
+
+    /// Constant folding, identity elimination and like-term collection.
+    pub fn simplify(&self) -> Expression {
+        match self {
+            Expression::Number(_) | Expression::Bool(_) | Expression::Str(_) | Expression::Variable(..) => {
+                self.clone()
+            }
+            Expression::List(items) => Expression::List(items.iter().map(Expression::simplify).collect()),
+            Expression::Not(inner, span) => match inner.simplify() {
+                Expression::Bool(b) => Expression::Bool(!b),
+                inner => Expression::Not(Box::new(inner), *span),
+            },
+            // A literal condition picks its branch.
+            Expression::If(condition, then_branch, else_branch, span) => match condition.simplify() {
+                Expression::Bool(true) => then_branch.simplify(),
+                Expression::Bool(false) => else_branch.simplify(),
+                condition => Expression::If(
+                    Box::new(condition),
+                    Box::new(then_branch.simplify()),
+                    Box::new(else_branch.simplify()),
+                    *span,
+                ),
+            },
+            Expression::Call(name, args, span) => {
+                let args: Vec<Expression> = args.iter().map(Expression::simplify).collect();
+                // Only exact results are folded: `sqrt(16)` becomes 4 but
+                // `ln(2)` stays symbolic.
+                let values: Option<Vec<f64>> = args.iter().map(as_number).collect();
+                if let (Some(b), Some(values)) = (builtin(name), values)
+                    && (b.min_args..=b.max_args).contains(&values.len())
+                    && (b.apply)(&values).fract() == 0.0
+                {
+                    return num((b.apply)(&values));
+                }
+                Expression::Call(name.clone(), args, *span)
+            }
+            Expression::Operator(op, exprs, span) => {
+                let left = exprs[0].simplify();
+                let right = exprs[1].simplify();
+                if let (Some(l), Some(r)) = (as_number(&left), as_number(&right))
+                    && let Ok(value) = apply_binary(op, l, r)
+                {
+                    return num(value);
+                }
+                match *op {
+                    "+" | "-" => {
+                        let mut terms = Terms::default();
+                        terms.add(&binary(op, left, right), 1.0);
+                        terms.build()
+                    }
+                    "*" => {
+                        let mut product = Product::default();
+                        product.add(&left);
+                        product.add(&right);
+                        product.build()
+                    }
+                    "/" => simplify_division(left, right),
+                    "^" => match (as_number(&left), as_number(&right)) {
+                        (_, Some(1.0)) => left,
+                        (_, Some(0.0)) => num(1.0),
+                        (Some(1.0), _) => num(1.0),
+                        _ => binary("^", left, right),
+                    },
+                    _ => Expression::Operator(op, vec![left, right], *span),
+                }
+            }
+        }
+    }
+}
+
+/// d/dx u^v: the power rule when `v` is constant, `a^v * ln(a) * v'` when
+/// the base is constant, and the general rule otherwise.
+fn power_derivative(u: &Expression, v: &Expression, var: &str) -> Result<Expression, EvalError> {
+    if !v.depends_on(var) {
+        let du = u.derivative(var)?;
+        // Negative exponents don't evaluate, so n * u^(n-1) with n < 1 is
+        // written as n / u^(1-n).
+        if let Some(n) = as_number(&v.simplify())
+            && n < 1.0
+        {
+            return Ok(binary("/", binary("*", num(n), du), binary("^", u.clone(), num(1.0 - n))));
+        }
+        let exponent = binary("-", v.clone(), num(1.0));
+        return Ok(binary("*", binary("*", v.clone(), binary("^", u.clone(), exponent)), du));
+    }
+    let power = binary("^", u.clone(), v.clone());
+    let dv = v.derivative(var)?;
+    if !u.depends_on(var) {
+        return Ok(binary("*", binary("*", power, call("ln", u.clone())), dv));
+    }
+    let du = u.derivative(var)?;
+    Ok(binary(
+        "*",
+        power,
+        binary("+", binary("*", dv, call("ln", u.clone())), binary("/", binary("*", v.clone(), du), u.clone())),
+    ))
+}
+
+fn simplify_division(left: Expression, right: Expression) -> Expression {
+    match (as_number(&left), as_number(&right)) {
+        (_, Some(1.0)) => left,
+        (Some(0.0), _) => num(0.0),
+        (Some(l), _) if l < 0.0 => neg(simplify_division(num(-l), right)),
+        _ if left.to_sexpr() == right.to_sexpr() => num(1.0),
+        // (c * x) / d -> (c/d) * x
+        (_, Some(d)) => {
+            let mut product = Product::default();
+            product.add(&left);
+            product.coefficient /= d;
+            product.build()
+        }
+        _ => binary("/", left, right),
+    }
+}
+
+fn as_number(expr: &Expression) -> Option<f64> {
+    match expr {
+        Expression::Number(n) => Some(*n),
+        // A folded negation, `0 - 3`, is just -3.
+        Expression::Operator("-", exprs, _) => match (&exprs[0], &exprs[1]) {
+            (Expression::Number(z), Expression::Number(n)) if *z == 0.0 => Some(-n),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A sum as `coefficient * term` pairs plus a constant, keyed by the term's
+/// S-expression and kept in first-seen order.
+#[derive(Default)]
+struct Terms {
+    constant: f64,
+    terms: Vec<(f64, Expression)>,
+    index: HashMap<String, usize>,
+}
+
+impl Terms {
+    fn add(&mut self, expr: &Expression, sign: f64) {
+        match expr {
+            Expression::Operator(op @ ("+" | "-"), exprs, _) => {
+                self.add(&exprs[0], sign);
+                self.add(&exprs[1], if *op == "-" { -sign } else { sign });
+            }
+            _ => {
+                let mut product = Product::default();
+                product.add(expr);
+                let coefficient = product.coefficient * sign;
+                product.coefficient = 1.0;
+                if product.factors.is_empty() {
+                    self.constant += coefficient;
+                    return;
+                }
+                let term = product.build();
+                let key = term.to_sexpr();
+                match self.index.get(&key) {
+                    Some(&i) => self.terms[i].0 += coefficient,
+                    None => {
+                        self.index.insert(key, self.terms.len());
+                        self.terms.push((coefficient, term));
+                    }
+                }
+            }
+        }
+    }
+
+    /// `a + b - c`, with the constant last and a leading `-` if the first
+    /// surviving term is negative.
+    fn build(self) -> Expression {
+        let mut parts: Vec<(f64, Option<Expression>)> = self
+            .terms
+            .into_iter()
+            .filter(|(c, _)| *c != 0.0)
+            .map(|(c, term)| (c, Some(term)))
+            .collect();
+        if self.constant != 0.0 {
+            parts.push((self.constant, None));
+        }
+        let scaled = |c: f64, term: Option<Expression>| match term {
+            None => num(c),
+            Some(term) if c == 1.0 => term,
+            Some(term) => {
+                let mut product = Product { coefficient: c, ..Product::default() };
+                product.add(&term);
+                product.build()
+            }
+        };
+        let mut parts = parts.into_iter();
+        let Some((c, term)) = parts.next() else {
+            return num(0.0);
+        };
+        let mut sum = if c < 0.0 { neg(scaled(-c, term)) } else { scaled(c, term) };
+        for (c, term) in parts {
+            sum = if c < 0.0 {
+                binary("-", sum, scaled(-c, term))
+            } else {
+                binary("+", sum, scaled(c, term))
+            };
+        }
+        sum
+    }
+}
+
+/// A product as a numeric coefficient times `base ^ exponent` factors, with
+/// like bases merged and factors sorted by their S-expression.
+struct Product {
+    coefficient: f64,
+    factors: Vec<(Expression, f64)>,
+}
+
+impl Default for Product {
+    fn default() -> Self {
+        Product { coefficient: 1.0, factors: Vec::new() }
+    }
+}
+
+impl Product {
+    fn add(&mut self, expr: &Expression) {
+        if let Some(n) = as_number(expr) {
+            self.coefficient *= n;
+            return;
+        }
+        match expr {
+            Expression::Operator("*", exprs, _) => {
+                self.add(&exprs[0]);
+                self.add(&exprs[1]);
+            }
+            Expression::Operator("-", exprs, _) if expr.is_negation() => {
+                self.coefficient = -self.coefficient;
+                self.add(&exprs[1]);
+            }
+            Expression::Operator("^", exprs, _) if as_number(&exprs[1]).is_some() => {
+                self.add_factor(exprs[0].clone(), as_number(&exprs[1]).unwrap());
+            }
+            _ => self.add_factor(expr.clone(), 1.0),
+        }
+    }
+
+    fn add_factor(&mut self, base: Expression, exponent: f64) {
+        let key = base.to_sexpr();
+        match self.factors.iter_mut().find(|(b, _)| b.to_sexpr() == key) {
+            Some((_, e)) => *e += exponent,
+            None => self.factors.push((base, exponent)),
+        }
+    }
+
+    fn build(mut self) -> Expression {
+        if self.coefficient == 0.0 {
+            return num(0.0);
+        }
+        self.factors.retain(|(_, e)| *e != 0.0);
+        self.factors.sort_by_cached_key(|(base, _)| base.to_sexpr());
+        let mut factors = self.factors.into_iter().map(|(base, e)| {
+            if e == 1.0 { base } else { binary("^", base, num(e)) }
+        });
+        let magnitude = self.coefficient.abs();
+        let mut product = match factors.next() {
+            None => return num(self.coefficient),
+            Some(first) if magnitude == 1.0 => first,
+            Some(first) => binary("*", num(magnitude), first),
+        };
+        for factor in factors {
+            product = binary("*", product, factor);
+        }
+        if self.coefficient < 0.0 { neg(product) } else { product }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Environment;
+    use std::str::FromStr;
+
+    fn parse(input: &str) -> Expression {
+        Expression::from_str(input).unwrap()
+    }
+
+    fn simplified(input: &str) -> String {
+        parse(input).simplify().to_string()
+    }
+
+    fn derived(input: &str) -> String {
+        parse(input).derive("x").unwrap().to_string()
+    }
+
+    #[test]
+    fn infix_uses_minimal_parentheses() {
+        for (input, printed) in [
+            ("1 + 2 * 3", "1 + 2 * 3"),
+            ("(1 + 2) * 3", "(1 + 2) * 3"),
+            ("10 - (5 - 2)", "10 - (5 - 2)"),
+            ("(10 - 5) - 2", "10 - 5 - 2"),
+            ("2 ^ (3 ^ 4)", "2^(3^4)"),
+            ("(2 ^ 3) ^ 4", "2^3^4"),
+            ("-(a + b) * c", "-(a + b) * c"),
+            ("(-a) ^ 2", "(-a)^2"),
+            ("a * (-b)", "a * (-b)"),
+            ("a - -b", "a - -b"),
+            ("max(a, (b))", "max(a, b)"),
+            ("f(x) = x ^ 2 + 1", "f(x) = x^2 + 1"),
+        ] {
+            assert_eq!(parse(input).to_string(), printed, "{input}");
+        }
+    }
+
+    #[test]
+    fn infix_output_parses_back_to_the_same_tree() {
+        for input in [
+            "-(a + b) * c",
+            "(-a) ^ 2",
+            "-a ^ 2",
+            "a * (-b) ^ c",
+            "a / (b / c) / d",
+            "-(-(x))",
+            "x - (y + z) - -w",
+        ] {
+            let expr = parse(input);
+            assert_eq!(parse(&expr.to_string()).to_sexpr(), expr.to_sexpr(), "{input}");
+        }
+    }
+
+    #[test]
+    fn simplify_identities_and_constants() {
+        assert_eq!(simplified("x + 0"), "x");
+        assert_eq!(simplified("0 + x * 1"), "x");
+        assert_eq!(simplified("x ^ 1 - 0"), "x");
+        assert_eq!(simplified("y * 0 + 3"), "3");
+        assert_eq!(simplified("x ^ 0"), "1");
+        assert_eq!(simplified("x / 1"), "x");
+        assert_eq!(simplified("2 * 3 + sqrt(16) * x"), "4 * x + 6");
+        assert_eq!(simplified("(x + 1) / (x + 1)"), "1");
+    }
+
+    #[test]
+    fn simplify_collects_like_terms() {
+        assert_eq!(simplified("2*x + 3*x"), "5 * x");
+        assert_eq!(simplified("x + y - x"), "y");
+        assert_eq!(simplified("x*y + y*x"), "2 * x * y");
+        assert_eq!(simplified("a - 2*a + 1 - 3"), "-a - 2");
+        assert_eq!(simplified("x * x * 3 * x"), "3 * x^3");
+        assert_eq!(simplified("x^2 * y * x^-1"), "x * y");
+        assert_eq!(simplified("-x * -y"), "x * y");
+        assert_eq!(simplified("(6 * x) / 2"), "3 * x");
+    }
+
+    #[test]
+    fn derive_polynomials() {
+        assert_eq!(derived("x^2 * y"), "2 * x * y");
+        assert_eq!(derived("3 * x^3 - 2 * x + 7"), "9 * x^2 - 2");
+        assert_eq!(derived("y"), "0");
+        assert_eq!(derived("x^0.5"), "0.5 / x^0.5");
+    }
+
+    #[test]
+    fn derive_quotients_and_functions() {
+        assert_eq!(derived("sin(x)^2"), "2 * cos(x) * sin(x)");
+        assert_eq!(derived("exp(2 * x)"), "2 * exp(2 * x)");
+        assert_eq!(derived("ln(x)"), "1 / x");
+        assert_eq!(derived("cos(x)"), "-sin(x)");
+        assert_eq!(derived("1 / x"), "-1 / x^2");
+        assert_eq!(derived("2 ^ x"), "2^x * ln(2)");
+    }
+
+    #[test]
+    fn derivative_matches_finite_difference() {
+        let h = 1e-6;
+        for input in ["x^x", "sqrt(1 + x^2) / x", "atan(x) * log(x)", "tan(x) - acos(x / 2)", "abs(x) * asin(x / 3)"] {
+            let expr = parse(input);
+            let derivative = expr.derive("x").unwrap();
+            let at = |e: &Expression, x: f64| {
+                let mut env = Environment::new();
+                env.set_var("x", x);
+                e.eval(&env).unwrap().as_number().unwrap()
+            };
+            let x = 0.7;
+            let numeric = (at(&expr, x + h) - at(&expr, x - h)) / (2.0 * h);
+            assert!((at(&derivative, x) - numeric).abs() < 1e-5, "{input}: {derivative}");
+        }
+    }
+
+    #[test]
+    fn derive_rejects_non_smooth_and_user_functions() {
+        let err = parse("1 + floor(x)").derive("x").unwrap_err();
+        assert_eq!(err.kind, EvalErrorKind::NotDifferentiable("floor".to_string()));
+        assert_eq!(err.span, Span::new(4, 9));
+
+        assert!(parse("f(x)").derive("x").is_err());
+        // Independent of `x`, so the rule is never needed.
+        assert_eq!(parse("max(y, 2)").derive("x").unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn derive_conditionals_piecewise() {
+        assert_eq!(parse("if x > 0 then x^2 else -x").derive("x").unwrap().to_string(), "if x > 0 then 2 * x else -1");
+        assert_eq!(parse("if true then 3 * x else y").simplify().to_string(), "3 * x");
+        assert_eq!(parse("!(1 < 2) || x").simplify().to_sexpr(), "(|| (! (< 1 2)) x)");
+        assert!(matches!(parse("!x").derive("x").unwrap_err().kind, EvalErrorKind::NotDifferentiable(_)));
+    }
+}
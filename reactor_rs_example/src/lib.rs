@@ -1,5 +1,6 @@
-pub mod poll;  
+pub mod poll;
 pub mod utility;
 pub mod reactor;
 pub mod registry;
-pub mod executor;
\ No newline at end of file
+pub mod timer;
+pub mod waker;
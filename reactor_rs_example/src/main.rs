@@ -1,203 +1,280 @@
-use std::{collections::HashMap, io::{self, Read, Write}, net::{TcpListener, TcpStream}, os::fd::AsRawFd, sync::{mpsc::channel, Mutex}};
+use std::{
+    io::{self, Read, Write},
+    mem,
+    net::{Shutdown, TcpListener, TcpStream},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    time::Duration,
+};
 use log::{debug, error, info, warn};
-use reactor_rs_example::{executor::Executor, reactor::Reactor , utility::EventId};
-use lazy_static::lazy_static;
-use rand::prelude::*;
-
-lazy_static! {
-    static ref EXECUTOR: Mutex<Executor> = Mutex::new(Executor::new());
-    static ref REACTOR: Mutex<Reactor> = Mutex::new(Reactor::new());
-    static ref CONTEXTS: Mutex<HashMap<EventId, RequestContext>> = Mutex::new(HashMap::new());
+use reactor_rs_example::{
+    poll::Event,
+    reactor::{Context, Handler, Reactor},
+    registry::{Interest, Mode},
+    syscall,
+    timer::TimerId,
+};
+
+const HTTP_RESP: &[u8] = b"HTTP/1.1 200 OK\r\n\
+Content-Type: text/plain\r\n\
+Content-Length: 11\r\n\
+Connection: close\r\n\
+\r\n\
+Hello Rust!";
+
+const BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\n\
+Content-Length: 0\r\n\
+Connection: close\r\n\
+\r\n";
+
+const TOO_LARGE: &[u8] = b"HTTP/1.1 413 Content Too Large\r\n\
+Content-Length: 0\r\n\
+Connection: close\r\n\
+\r\n";
+
+const MAX_REQUEST: usize = 1 << 20;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts connections until the server shuts down.
+struct Listener {
+    listener: TcpListener,
+}
+
+impl Handler for Listener {
+    fn ready(&mut self, cx: &mut Context<'_>, _event: &Event) {
+        // Edge-triggered: drain the accept queue or miss connections.
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        error!("can't make stream to {} nonblocking: {}", addr, e);
+                        continue;
+                    }
+                    let fd = stream.as_raw_fd();
+                    match cx.register(fd, Interest::READ, Mode::OneShot, Connection::new(stream)) {
+                        Ok(event_id) => debug!("new client: {}, event_id: {}, raw fd: {}", addr, event_id, fd),
+                        Err(e) => error!("can't register stream to {}: {}", addr, e),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    error!("couldn't accept: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn shutdown(&mut self, cx: &mut Context<'_>) {
+        info!("no longer accepting connections");
+        cx.close();
+    }
 }
 
-#[derive(Debug)]
-pub struct RequestContext {
-    pub stream: TcpStream,
-    pub content_length: usize,
-    pub buf: Vec<u8>,
+enum State {
+    Reading(Vec<u8>),
+    Writing { response: &'static [u8], written: usize },
 }
 
-impl RequestContext {
+/// One client: reads a request, answers it and closes.
+struct Connection {
+    stream: TcpStream,
+    state: State,
+    idle: Option<TimerId>,
+}
+
+impl Connection {
     fn new(stream: TcpStream) -> Self {
-        Self {
-            stream,
-            buf: Vec::new(),
-            content_length: 0,
+        Connection { stream, state: State::Reading(Vec::new()), idle: None }
+    }
+
+    fn reset_idle_timer(&mut self, cx: &mut Context<'_>) {
+        if let Some(timer) = self.idle.take() {
+            cx.cancel_timer(timer);
         }
+        self.idle = Some(cx.set_timer(IDLE_TIMEOUT));
     }
 
-    fn read_cb(&mut self, event_id: EventId, exec: &mut Executor) -> io::Result<()> {
-        let mut buf = [0u8; 4096];
-        match self.stream.read(&mut buf) {
-            Ok(_) => {
-                if let Ok(data) = std::str::from_utf8(&buf) {
-                    self.parse_and_set_content_length(data);
-                }
+    /// Read what is available.  Returns the response once the request is
+    /// complete, or `None` to wait for more.
+    fn read(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<&'static [u8]>> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
             }
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
-            Err(e) => {
-                return Err(e);
+            if buf.len() > MAX_REQUEST {
+                return Ok(Some(TOO_LARGE));
             }
+        }
+
+        let Some(head_end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            return Ok(None);
+        };
+        let Some(content_length) = std::str::from_utf8(&buf[..head_end]).ok().and_then(content_length) else {
+            return Ok(Some(BAD_REQUEST));
         };
-        self.buf.extend_from_slice(&buf);
-        if self.buf.len() >= self.content_length {
-            REACTOR
-                .lock()
-                .expect("can get reactor lock")
-                .write_interest(self.stream.as_raw_fd(), event_id)
-                .expect("can set write interest");
-
-            write_cb(exec, event_id);
-        } else {
-            REACTOR
-                .lock()
-                .expect("can get reactor lock")
-                .read_interest(self.stream.as_raw_fd(), event_id)
-                .expect("can set write interest");
-            read_cb(exec, event_id);
+        debug!("content length: {} bytes", content_length);
+        if head_end + 4 + content_length > MAX_REQUEST {
+            return Ok(Some(TOO_LARGE));
         }
-        Ok(())
-    }
-
-    fn parse_and_set_content_length(&mut self, data: &str) {
-        if data.contains("HTTP") {
-            if let Some(content_length) = data
-                .lines()
-                .find(|l| l.to_lowercase().starts_with("content-length: "))
-            {
-                if let Some(len) = content_length
-                    .to_lowercase()
-                    .strip_prefix("content-length: ")
-                {
-                    self.content_length = len.parse::<usize>().expect("content-length is valid");
-                    debug!("set content length: {} bytes", self.content_length);
-                }
+        Ok((buf.len() >= head_end + 4 + content_length).then_some(HTTP_RESP))
+    }
+
+    /// Write what the socket takes.  Returns whether the whole response is out.
+    fn write(&mut self, response: &[u8], written: &mut usize) -> io::Result<bool> {
+        while *written < response.len() {
+            match self.stream.write(&response[*written..]) {
+                Ok(n) => *written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
             }
         }
+        Ok(true)
     }
 
-    fn write_cb(&mut self, event_id: EventId) -> io::Result<()> {
-        debug!("in write event of stream with event id: {}", event_id);
-        self.stream.write_all(HTTP_RESP)?;
-       
-        if let Err(e) = self.stream.shutdown(std::net::Shutdown::Both) {
-            if e.kind() != io::ErrorKind::NotConnected {
-                warn!("warning: can't shutdown stream: {}", e);
+    fn step(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        match mem::replace(&mut self.state, State::Reading(Vec::new())) {
+            State::Reading(mut buf) => match self.read(&mut buf)? {
+                Some(response) => {
+                    self.state = State::Writing { response, written: 0 };
+                    // Usually the socket has room, so try right away.
+                    self.step(cx)
+                }
+                None => {
+                    self.state = State::Reading(buf);
+                    self.reset_idle_timer(cx);
+                    cx.rearm()
+                }
+            },
+            State::Writing { response, mut written } => {
+                if self.write(response, &mut written)? {
+                    if let Err(e) = self.stream.shutdown(Shutdown::Write)
+                        && e.kind() != io::ErrorKind::NotConnected
+                    {
+                        warn!("warning: can't shutdown stream: {}", e);
+                    }
+                    info!("answered request {}", cx.id());
+                    cx.close();
+                    return Ok(());
+                }
+                self.state = State::Writing { response, written };
+                self.reset_idle_timer(cx);
+                cx.reregister(Interest::WRITE, Mode::OneShot)
             }
         }
-
-        REACTOR
-            .lock()
-            .expect("can't get reactor lock")
-            .close(self.stream.as_raw_fd())
-            .expect("can't close fd and clean up reactor");
-        info!("answered from request {}", event_id);
-        Ok(())
     }
 }
 
-const HTTP_RESP: &[u8] =b"HTTP/1.1 200 OK\r\n\
-Content-Type: text/plain\r\n\
-Content-Length: 11\r\n\
-Connection: close\r\n\
-\r\n\
-Hello Rust!";
-
-fn main() -> io::Result<()> {
-    env_logger::init(); 
-    let addr = "127.0.0.1:8000";
-    info!("listening : http://{} ...",addr);
-    let listener_event_id = 100;
-    let listener = TcpListener::bind(addr)?;
-    listener.set_nonblocking(true)?;
-    let listener_fd = listener.as_raw_fd();
-
-    let (sender, receiver) = channel();
-
-    match REACTOR.lock() {
-        Ok(mut re) => re.run(sender),
-        Err(e) => panic!("error running reactor, {}", e),
-    };
-
-    REACTOR
-        .lock()
-        .expect("can't get reactor lock")
-        .read_interest(listener_fd, listener_event_id)?;
+impl Handler for Connection {
+    fn init(&mut self, cx: &mut Context<'_>) {
+        self.reset_idle_timer(cx);
+    }
 
-    listener_cb(listener, listener_event_id);
+    fn ready(&mut self, cx: &mut Context<'_>, _event: &Event) {
+        if let Err(e) = self.step(cx) {
+            if e.kind() != io::ErrorKind::UnexpectedEof {
+                warn!("connection {} failed: {}", cx.id(), e);
+            }
+            cx.close();
+        }
+    }
 
-    while let Ok(event_id) = receiver.recv() {
-        EXECUTOR
-            .lock()
-            .expect("can't get an executor lock")
-            .run(event_id);
+    fn timeout(&mut self, cx: &mut Context<'_>, timer: TimerId) {
+        if self.idle == Some(timer) {
+            debug!("connection {} timed out", cx.id());
+            cx.close();
+        }
     }
 
-    Ok(())
+    fn shutdown(&mut self, cx: &mut Context<'_>) {
+        // A request that has started gets its answer; idle clients are dropped.
+        if let State::Reading(buf) = &self.state
+            && buf.is_empty()
+        {
+            cx.close();
+        }
+    }
 }
 
+/// The `Content-Length` of a request head, 0 if there is none, or `None`
+/// if the head is malformed.
+fn content_length(head: &str) -> Option<usize> {
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next()?;
+    if !request_line.ends_with("HTTP/1.1") && !request_line.ends_with("HTTP/1.0") {
+        return None;
+    }
+    let mut length = 0;
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.trim().parse().ok()?;
+        }
+    }
+    Some(length)
+}
 
+/// Turns SIGINT and SIGTERM into a graceful shutdown.
+struct Signals {
+    fd: OwnedFd,
+    mask: libc::sigset_t,
+}
 
-fn listener_cb(listener: TcpListener, event_id: EventId) {
-    let mut exec_lock = EXECUTOR.lock().expect("can get executor lock");
-    exec_lock.await_keep(event_id, move |exec| {
-        match listener.accept() {
-            Ok((stream, addr)) => {
-                let event_id: EventId = random();
-                stream.set_nonblocking(true).expect("nonblocking works");
-                debug!(
-                    "new client: {}, event_id: {}, raw fd: {}",
-                    addr,
-                    event_id,
-                    stream.as_raw_fd()
-                );
-                REACTOR
-                    .lock()
-                    .expect("can get reactor lock")
-                    .read_interest(stream.as_raw_fd(), event_id)
-                    .expect("can set read interest");
-                CONTEXTS
-                    .lock()
-                    .expect("can lock request contests")
-                    .insert(event_id, RequestContext::new(stream));
-                read_cb(exec, event_id);
-            }
-            Err(e) => error!("couldn't accept: {}", e),
-        };
-        REACTOR
-            .lock()
-            .expect("can get reactor lock")
-            .read_interest(listener.as_raw_fd(), event_id)
-            .expect("re-register works");
-    });
-    drop(exec_lock);
+impl Signals {
+    fn new() -> io::Result<Self> {
+        // SAFETY: sigset_t is plain data and is initialized by sigemptyset.
+        let mut mask: libc::sigset_t = unsafe { mem::zeroed() };
+        syscall!(sigemptyset(&mut mask))?;
+        syscall!(sigaddset(&mut mask, libc::SIGINT))?;
+        syscall!(sigaddset(&mut mask, libc::SIGTERM))?;
+        // Blocked signals are only delivered through the signalfd.
+        syscall!(sigprocmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut()))?;
+        let fd = syscall!(signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC))?;
+        // SAFETY: the fd was just created and nothing else owns it.
+        Ok(Signals { fd: unsafe { OwnedFd::from_raw_fd(fd) }, mask })
+    }
 }
 
-fn read_cb(exec: &mut Executor, event_id: EventId) {
-    exec.await_once(event_id, move |write_exec| {
-        if let Some(ctx) = CONTEXTS
-            .lock()
-            .expect("can lock request_contexts")
-            .get_mut(&event_id)
-        {
-            ctx.read_cb(event_id, write_exec)
-                .expect("read callback works");
+impl Handler for Signals {
+    fn ready(&mut self, cx: &mut Context<'_>, _event: &Event) {
+        // SAFETY: signalfd_siginfo is plain data.
+        let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+        let size = mem::size_of::<libc::signalfd_siginfo>();
+        while syscall!(read(self.fd.as_raw_fd(), &mut info as *mut _ as *mut libc::c_void, size)).is_ok() {
+            info!("received signal {}, shutting down", info.ssi_signo);
+            cx.shutdown();
         }
-    });
-}
+    }
 
-fn write_cb(exec: &mut Executor, event_id: EventId) {
-    exec.await_once(event_id, move |_| {
-        if let Some(ctx) = CONTEXTS
-            .lock()
-            .expect("can lock request_contexts")
-            .get_mut(&event_id)
-        {
-            ctx.write_cb(event_id).expect("write callback works");
+    fn shutdown(&mut self, cx: &mut Context<'_>) {
+        // Let a second signal kill the process while connections drain.
+        if let Err(e) = syscall!(sigprocmask(libc::SIG_UNBLOCK, &self.mask, std::ptr::null_mut())) {
+            warn!("can't unblock signals: {}", e);
         }
-        CONTEXTS
-            .lock()
-            .expect("can't lock request contexts")
-            .remove(&event_id);
-    });
-}
\ No newline at end of file
+        cx.close();
+    }
+}
+
+fn main() -> io::Result<()> {
+    env_logger::init();
+    let addr = "127.0.0.1:8000";
+    info!("listening : http://{} ...", addr);
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+
+    let mut reactor = Reactor::new()?;
+    let signals = Signals::new()?;
+    let fd = signals.fd.as_raw_fd();
+    reactor.register(fd, Interest::READ, Mode::Level, signals)?;
+    let fd = listener.as_raw_fd();
+    reactor.register(fd, Interest::READ, Mode::Edge, Listener { listener })?;
+
+    reactor.run()?;
+    info!("server stopped");
+    Ok(())
+}
@@ -1,38 +1,83 @@
-use std::os::fd::RawFd;
-use crate::{registry::Registry, syscall};
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    time::Duration,
+};
+use crate::{registry::Registry, syscall, utility::EventId};
 
 pub struct Poll {
-    epoll_fd: RawFd,
+    epoll_fd: OwnedFd,
 }
 
 impl Poll {
-    pub fn new() -> Self {
-        let epoll_fd = syscall!(epoll_create1(0)).expect("failed create epoll");
-        
-        if let Ok(flag) = syscall!(fcntl(epoll_fd, libc::F_GETFD)) {
-            let _ = syscall!(fcntl(epoll_fd, libc::F_SETFD, flag | libc::FD_CLOEXEC));
-        }
-
-        Poll {epoll_fd }
+    pub fn new() -> io::Result<Self> {
+        let epoll_fd = syscall!(epoll_create1(libc::EPOLL_CLOEXEC))?;
+        // SAFETY: the fd was just created and nothing else owns it.
+        let epoll_fd = unsafe { OwnedFd::from_raw_fd(epoll_fd) };
+        Ok(Poll { epoll_fd })
     }
 
     pub fn get_registry(&self) -> Registry {
-        Registry::new(self.epoll_fd)
+        Registry::new(self.epoll_fd.as_raw_fd())
     }
 
-    pub fn poll(&self, events: &mut Vec<libc::epoll_event>) {
+    /// Wait up to `timeout` (forever if `None`) for events, filling `events`
+    /// up to its capacity.  An interrupted wait returns no events.
+    pub fn poll(&self, events: &mut Vec<libc::epoll_event>, timeout: Option<Duration>) -> io::Result<()> {
         events.clear();
+        // Round up so a timer is never woken for just before its deadline.
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_nanos().div_ceil(1_000_000).min(libc::c_int::MAX as u128) as libc::c_int
+        });
         let res = match syscall!(epoll_wait(
-            self.epoll_fd,
-            events.as_mut_ptr() as *mut libc::epoll_event,
-            1024,
-            -1 as libc::c_int,
+            self.epoll_fd.as_raw_fd(),
+            events.as_mut_ptr(),
+            events.capacity().min(libc::c_int::MAX as usize) as libc::c_int,
+            timeout,
         )) {
             Ok(v) => v,
-            Err(e) => panic!("error during epoll wait: {}", e),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => 0,
+            Err(e) => return Err(e),
         };
 
+        // SAFETY: epoll_wait initialized the first `res` entries.
         unsafe { events.set_len(res as usize) };
+        Ok(())
+    }
+}
+
+/// Readiness of one registered fd.
+#[derive(Clone, Copy, Debug)]
+pub struct Event {
+    id: EventId,
+    flags: u32,
+}
+
+impl Event {
+    pub fn id(&self) -> EventId {
+        self.id
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.flags & (libc::EPOLLIN | libc::EPOLLPRI) as u32 != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.flags & libc::EPOLLOUT as u32 != 0
+    }
+
+    /// The peer shut down its writing half, or the connection is gone.
+    pub fn is_read_closed(&self) -> bool {
+        self.flags & (libc::EPOLLRDHUP | libc::EPOLLHUP) as u32 != 0
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.flags & libc::EPOLLERR as u32 != 0
     }
 }
 
+impl From<&libc::epoll_event> for Event {
+    fn from(event: &libc::epoll_event) -> Self {
+        Event { id: event.u64 as EventId, flags: event.events }
+    }
+}
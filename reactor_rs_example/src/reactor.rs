@@ -1,53 +1,538 @@
-use std::{io, os::fd::RawFd, sync::mpsc::Sender};
-use crate::{poll::Poll, registry::Registry, utility::EventId};
+use std::{
+    collections::HashMap,
+    io,
+    os::fd::RawFd,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use log::{debug, warn};
+use crate::{
+    poll::{Event, Poll},
+    registry::{Interest, Mode, Registry},
+    timer::{TimerId, Timers},
+    utility::EventId,
+    waker::{ShutdownHandle, WakeQueue, Waker},
+};
 
-pub struct Reactor {
-    pub registry: Option<Registry>,
+/// Event id of the reactor's own eventfd.
+const WAKE_ID: EventId = EventId::MAX;
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Owns the state behind one registration and reacts to its events.  A
+/// handler only ever runs on the reactor thread, so it needs neither `Send`
+/// nor `Sync`.
+pub trait Handler {
+    /// Called once, right after the handler is registered.
+    fn init(&mut self, _cx: &mut Context<'_>) {}
+
+    /// The registered fd is ready.
+    fn ready(&mut self, cx: &mut Context<'_>, event: &Event);
+
+    /// A timer set through `cx.set_timer` expired.
+    fn timeout(&mut self, _cx: &mut Context<'_>, _timer: TimerId) {}
+
+    /// A `Waker` for this handler was woken.
+    fn woken(&mut self, _cx: &mut Context<'_>) {}
+
+    /// The reactor is shutting down.  The handler keeps receiving events
+    /// until it closes or the shutdown timeout runs out; by default it closes
+    /// right away.
+    fn shutdown(&mut self, cx: &mut Context<'_>) {
+        cx.close();
+    }
+}
+
+struct Entry {
+    fd: Option<RawFd>,
+    interest: Interest,
+    mode: Mode,
+    /// `None` while the handler runs.
+    handler: Option<Box<dyn Handler>>,
+}
+
+struct Core {
+    registry: Registry,
+    entries: HashMap<EventId, Entry>,
+    next_id: EventId,
+    timers: Timers,
+    wake: Arc<WakeQueue>,
+    shutting_down: bool,
+}
+
+impl Core {
+    fn insert(&mut self, fd: Option<(RawFd, Interest, Mode)>, handler: Box<dyn Handler>) -> io::Result<EventId> {
+        let id = self.next_id;
+        let (fd, interest, mode) = match fd {
+            Some((fd, interest, mode)) => {
+                self.registry.register(fd, id, interest, mode)?;
+                (Some(fd), interest, mode)
+            }
+            None => (None, Interest::READ, Mode::Level),
+        };
+        self.next_id += 1;
+        self.entries.insert(id, Entry { fd, interest, mode, handler: Some(handler) });
+        self.dispatch(id, |handler, cx| handler.init(cx));
+        Ok(id)
+    }
+
+    /// Run one callback of the handler registered as `id`, then put the
+    /// handler back, or deregister and drop it if it closed itself.
+    fn dispatch(&mut self, id: EventId, f: impl FnOnce(&mut dyn Handler, &mut Context<'_>)) {
+        let Some(mut handler) = self.entries.get_mut(&id).and_then(|entry| entry.handler.take()) else {
+            return;
+        };
+        let mut cx = Context { core: self, id, closed: false };
+        f(&mut *handler, &mut cx);
+
+        if cx.closed {
+            if let Some(Entry { fd: Some(fd), .. }) = self.entries.remove(&id)
+                && let Err(e) = self.registry.deregister(fd)
+            {
+                warn!("can't deregister fd {} of handler {}: {}", fd, id, e);
+            }
+            debug!("closed handler {}", id);
+        } else if let Some(entry) = self.entries.get_mut(&id) {
+            entry.handler = Some(handler);
+        }
+    }
+}
+
+/// What a handler can do to the reactor while it runs.
+pub struct Context<'a> {
+    core: &'a mut Core,
+    id: EventId,
+    closed: bool,
+}
+
+impl Context<'_> {
+    /// Event id of the running handler.
+    pub fn id(&self) -> EventId {
+        self.id
+    }
+
+    /// Register another fd with its own handler, which owns the fd.
+    pub fn register(
+        &mut self,
+        fd: RawFd,
+        interest: Interest,
+        mode: Mode,
+        handler: impl Handler + 'static,
+    ) -> io::Result<EventId> {
+        self.core.insert(Some((fd, interest, mode)), Box::new(handler))
+    }
+
+    /// Add a handler without an fd, driven only by timers and wakers.
+    pub fn add(&mut self, handler: impl Handler + 'static) -> EventId {
+        self.core.insert(None, Box::new(handler)).expect("adding a handler without an fd can't fail")
+    }
+
+    /// Change what the running handler's fd is watched for.
+    pub fn reregister(&mut self, interest: Interest, mode: Mode) -> io::Result<()> {
+        let entry = self.core.entries.get_mut(&self.id).expect("running handler has an entry");
+        let fd = entry.fd.ok_or_else(|| io::Error::other("handler has no fd"))?;
+        self.core.registry.reregister(fd, self.id, interest, mode)?;
+        entry.interest = interest;
+        entry.mode = mode;
+        Ok(())
+    }
+
+    /// Re-arm a one-shot registration with its current interest.
+    pub fn rearm(&mut self) -> io::Result<()> {
+        let entry = &self.core.entries[&self.id];
+        let (interest, mode) = (entry.interest, entry.mode);
+        self.reregister(interest, mode)
+    }
+
+    /// Call `Handler::timeout` on the running handler after `delay`.
+    pub fn set_timer(&mut self, delay: Duration) -> TimerId {
+        self.core.timers.insert(Instant::now() + delay, self.id)
+    }
+
+    pub fn cancel_timer(&mut self, timer: TimerId) {
+        self.core.timers.cancel(timer);
+    }
+
+    /// A waker for the running handler, to be handed to other threads.
+    pub fn waker(&self) -> Waker {
+        Waker::new(self.core.wake.clone(), self.id)
+    }
+
+    /// Deregister and drop the running handler once it returns.  Later
+    /// events and timers for it are ignored.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Start a graceful shutdown of the whole reactor.
+    pub fn shutdown(&mut self) {
+        self.core.wake.shutdown();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.core.shutting_down
+    }
 }
 
+/// A single-threaded event loop that dispatches epoll events, timers and
+/// cross-thread wakeups to the handlers registered with it.
+pub struct Reactor {
+    poll: Poll,
+    core: Core,
+    events: Vec<libc::epoll_event>,
+    shutdown_timeout: Duration,
+}
 
 impl Reactor {
-    pub fn new() -> Self {
-        Self { registry: None }
+    pub fn new() -> io::Result<Self> {
+        let poll = Poll::new()?;
+        let registry = poll.get_registry();
+        let wake = Arc::new(WakeQueue::new()?);
+        registry.register(wake.as_raw_fd(), WAKE_ID, Interest::READ, Mode::Level)?;
+
+        Ok(Reactor {
+            poll,
+            core: Core {
+                registry,
+                entries: HashMap::new(),
+                next_id: 0,
+                timers: Timers::default(),
+                wake,
+                shutting_down: false,
+            },
+            events: Vec::with_capacity(1024),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        })
+    }
+
+    /// Register `fd` with the handler that owns it.  The fd must stay open
+    /// until the handler is dropped.
+    pub fn register(
+        &mut self,
+        fd: RawFd,
+        interest: Interest,
+        mode: Mode,
+        handler: impl Handler + 'static,
+    ) -> io::Result<EventId> {
+        self.core.insert(Some((fd, interest, mode)), Box::new(handler))
+    }
+
+    /// Add a handler without an fd, driven only by timers and wakers.
+    pub fn add(&mut self, handler: impl Handler + 'static) -> EventId {
+        self.core.insert(None, Box::new(handler)).expect("adding a handler without an fd can't fail")
+    }
+
+    pub fn waker(&self, id: EventId) -> Waker {
+        Waker::new(self.core.wake.clone(), id)
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(self.core.wake.clone())
+    }
+
+    /// How long handlers get to close after a shutdown starts before the
+    /// reactor drops them anyway.
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
+    /// Run until every handler has closed, or until a shutdown has drained or
+    /// timed out.
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut drain_deadline = None;
+        loop {
+            if drain_deadline.is_none() && self.core.wake.is_shutdown() {
+                debug!("shutting down {} handlers", self.core.entries.len());
+                self.core.shutting_down = true;
+                drain_deadline = Some(Instant::now() + self.shutdown_timeout);
+                let ids: Vec<_> = self.core.entries.keys().copied().collect();
+                for id in ids {
+                    self.core.dispatch(id, |handler, cx| handler.shutdown(cx));
+                }
+            }
+            if self.core.entries.is_empty() {
+                return Ok(());
+            }
+            if let Some(deadline) = drain_deadline
+                && Instant::now() >= deadline
+            {
+                warn!("shutdown timed out, dropping {} handlers", self.core.entries.len());
+                self.core.entries.clear();
+                return Ok(());
+            }
+
+            let deadline = match (self.core.timers.next_deadline(), drain_deadline) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            self.poll.poll(&mut self.events, timeout)?;
+
+            for event in &self.events {
+                let event = Event::from(event);
+                if event.id() == WAKE_ID {
+                    for id in self.core.wake.take() {
+                        self.core.dispatch(id, |handler, cx| handler.woken(cx));
+                    }
+                } else {
+                    self.core.dispatch(event.id(), |handler, cx| handler.ready(cx, &event));
+                }
+            }
+
+            let now = Instant::now();
+            while let Some((timer, id)) = self.core.timers.pop_expired(now) {
+                self.core.dispatch(id, |handler, cx| handler.timeout(cx, timer));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::RefCell,
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        os::{fd::AsRawFd, unix::net::UnixStream},
+        rc::Rc,
+        thread,
+    };
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    struct Timed {
+        log: Log,
+        timers: Vec<(TimerId, &'static str)>,
     }
 
-    pub fn run(&mut self, sender: Sender<EventId>) {
-        let poller = Poll::new();
-        let registry = poller.get_registry();
+    impl Handler for Timed {
+        fn init(&mut self, cx: &mut Context<'_>) {
+            for (delay, name) in [(30, "c"), (10, "a"), (20, "b"), (15, "cancelled")] {
+                self.timers.push((cx.set_timer(Duration::from_millis(delay)), name));
+            }
+            let cancelled = self.timers.pop().unwrap().0;
+            cx.cancel_timer(cancelled);
+        }
 
-        self.registry = Some(registry);
+        fn ready(&mut self, _cx: &mut Context<'_>, _event: &Event) {}
 
-        std::thread::spawn(move || {
-            let mut events: Vec<libc::epoll_event> = Vec::with_capacity(1024);
+        fn timeout(&mut self, cx: &mut Context<'_>, timer: TimerId) {
+            let name = self.timers.iter().find(|(id, _)| *id == timer).unwrap().1;
+            self.log.borrow_mut().push(name.to_string());
+            if name == "c" {
+                cx.close();
+            }
+        }
+    }
 
+    #[test]
+    fn timers_fire_in_deadline_order() {
+        let log = Log::default();
+        let mut reactor = Reactor::new().unwrap();
+        reactor.add(Timed { log: log.clone(), timers: Vec::new() });
+        let start = Instant::now();
+        reactor.run().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(*log.borrow(), ["a", "b", "c"]);
+    }
+
+    struct Counter {
+        woken: Rc<RefCell<usize>>,
+        until: usize,
+    }
+
+    impl Handler for Counter {
+        fn ready(&mut self, _cx: &mut Context<'_>, _event: &Event) {}
+
+        fn woken(&mut self, cx: &mut Context<'_>) {
+            *self.woken.borrow_mut() += 1;
+            if *self.woken.borrow() == self.until {
+                cx.close();
+            }
+        }
+    }
+
+    #[test]
+    fn wakers_work_from_other_threads() {
+        let woken = Rc::new(RefCell::new(0));
+        let mut reactor = Reactor::new().unwrap();
+        let id = reactor.add(Counter { woken: woken.clone(), until: 3 });
+        let waker = reactor.waker(id);
+        let thread = thread::spawn(move || {
+            for _ in 0..3 {
+                thread::sleep(Duration::from_millis(5));
+                waker.wake();
+            }
+        });
+        reactor.run().unwrap();
+        thread.join().unwrap();
+        assert_eq!(*woken.borrow(), 3);
+    }
+
+    struct EchoListener {
+        listener: TcpListener,
+    }
+
+    impl Handler for EchoListener {
+        fn ready(&mut self, cx: &mut Context<'_>, _event: &Event) {
             loop {
-                poller.poll(&mut events);
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        stream.set_nonblocking(true).unwrap();
+                        let fd = stream.as_raw_fd();
+                        cx.register(fd, Interest::READ, Mode::Edge, Echo { stream }).unwrap();
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => panic!("accept failed: {e}"),
+                }
+            }
+        }
+    }
+
+    struct Echo {
+        stream: TcpStream,
+    }
 
-                for e in &events {
-                    sender.send(e.u64 as EventId).expect("channel works");
+    impl Handler for Echo {
+        fn ready(&mut self, cx: &mut Context<'_>, _event: &Event) {
+            let mut buf = [0; 4];
+            loop {
+                match self.stream.read(&mut buf) {
+                    Ok(0) => return cx.close(),
+                    Ok(n) => self.stream.write_all(&buf[..n]).unwrap(),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                    Err(e) => panic!("read failed: {e}"),
                 }
             }
+        }
+    }
+
+    #[test]
+    fn edge_triggered_echo_until_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut reactor = Reactor::new().unwrap();
+        let fd = listener.as_raw_fd();
+        reactor.register(fd, Interest::READ, Mode::Edge, EchoListener { listener }).unwrap();
+        let shutdown = reactor.shutdown_handle();
+        let client = thread::spawn(move || {
+            for _ in 0..2 {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                // Longer than the read buffer, so the edge is only seen once
+                // for data that takes several reads.
+                stream.write_all(b"hello, reactor").unwrap();
+                let mut buf = [0; 14];
+                stream.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf, b"hello, reactor");
+            }
+            shutdown.shutdown();
         });
+        reactor.run().unwrap();
+        client.join().unwrap();
+    }
+
+    struct OneShot {
+        _stream: UnixStream,
+        events: Rc<RefCell<usize>>,
     }
 
-    pub fn read_interest(&mut self, fd: RawFd, event_id: EventId) -> io::Result<()> {
-        self.registry
-            .as_mut()
-            .expect("registry is set")
-            .register_read(fd, event_id)
+    impl Handler for OneShot {
+        fn ready(&mut self, cx: &mut Context<'_>, _event: &Event) {
+            *self.events.borrow_mut() += 1;
+            match *self.events.borrow() {
+                // Read nothing; a level-triggered fd would fire again at once.
+                1 => {
+                    cx.set_timer(Duration::from_millis(20));
+                }
+                _ => cx.close(),
+            }
+        }
+
+        fn timeout(&mut self, cx: &mut Context<'_>, _timer: TimerId) {
+            assert_eq!(*self.events.borrow(), 1);
+            cx.rearm().unwrap();
+        }
     }
 
-    pub fn write_interest(&mut self, fd: RawFd, event_id: EventId) -> io::Result<()> {
-        self.registry
-            .as_mut()
-            .expect("registry is set")
-            .register_write(fd, event_id)
+    #[test]
+    fn one_shot_fires_once_until_rearmed() {
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        peer.write_all(b"x").unwrap();
+        let events = Rc::new(RefCell::new(0));
+        let mut reactor = Reactor::new().unwrap();
+        let fd = stream.as_raw_fd();
+        reactor.register(fd, Interest::READ, Mode::OneShot, OneShot { _stream: stream, events: events.clone() }).unwrap();
+        reactor.run().unwrap();
+        assert_eq!(*events.borrow(), 2);
     }
 
-    pub fn close(&mut self, fd: RawFd) -> io::Result<()> {
-        self.registry
-            .as_mut()
-            .expect("registry is set")
-            .remove_interests(fd)
+    struct Lingering {
+        delay: Option<Duration>,
+        dropped: Rc<RefCell<usize>>,
     }
-}
\ No newline at end of file
+
+    impl Handler for Lingering {
+        fn ready(&mut self, _cx: &mut Context<'_>, _event: &Event) {}
+
+        fn timeout(&mut self, cx: &mut Context<'_>, _timer: TimerId) {
+            cx.close();
+        }
+
+        fn shutdown(&mut self, cx: &mut Context<'_>) {
+            assert!(cx.is_shutting_down());
+            match self.delay {
+                Some(delay) => {
+                    cx.set_timer(delay);
+                }
+                None => cx.close(),
+            }
+        }
+    }
+
+    impl Drop for Lingering {
+        fn drop(&mut self) {
+            *self.dropped.borrow_mut() += 1;
+        }
+    }
+
+    struct Trigger;
+
+    impl Handler for Trigger {
+        fn init(&mut self, cx: &mut Context<'_>) {
+            cx.shutdown();
+        }
+
+        fn ready(&mut self, _cx: &mut Context<'_>, _event: &Event) {}
+    }
+
+    #[test]
+    fn shutdown_waits_for_handlers_to_close() {
+        let dropped = Rc::new(RefCell::new(0));
+        let mut reactor = Reactor::new().unwrap();
+        for delay in [None, Some(Duration::from_millis(20))] {
+            reactor.add(Lingering { delay, dropped: dropped.clone() });
+        }
+        reactor.add(Trigger);
+        let start = Instant::now();
+        reactor.run().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(*dropped.borrow(), 2);
+    }
+
+    #[test]
+    fn shutdown_drops_handlers_after_the_timeout() {
+        let dropped = Rc::new(RefCell::new(0));
+        let mut reactor = Reactor::new().unwrap();
+        reactor.set_shutdown_timeout(Duration::from_millis(20));
+        reactor.add(Lingering { delay: Some(Duration::from_secs(60)), dropped: dropped.clone() });
+        reactor.shutdown_handle().shutdown();
+        let start = Instant::now();
+        reactor.run().unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20) && elapsed < Duration::from_secs(10), "{elapsed:?}");
+        assert_eq!(*dropped.borrow(), 1);
+    }
+}
@@ -1,80 +1,88 @@
-use std::{collections::{HashMap, HashSet}, io, os::fd::RawFd};
+use std::{io, ops::BitOr, os::fd::RawFd};
 use crate::{syscall, utility::EventId};
 
-const READ_FLAGS: i32 = libc::EPOLLONESHOT | libc::EPOLLIN;
-const WRITE_FLAGS: i32 = libc::EPOLLONESHOT | libc::EPOLLOUT;
+/// Which readiness to be notified of; combine with `|`.
+#[derive(Clone, Copy, Debug, PartialEq, Hash, Eq)]
+pub struct Interest(u32);
 
-pub struct Registry {
-    epoll_fd: RawFd,
-    io_sources: HashMap<RawFd, HashSet<Interest>>,
-}
+impl Interest {
+    pub const READ: Interest = Interest(libc::EPOLLIN as u32);
+    pub const WRITE: Interest = Interest(libc::EPOLLOUT as u32);
+
+    pub fn is_readable(self) -> bool {
+        self.0 & Self::READ.0 != 0
+    }
 
-#[derive(PartialEq, Hash, Eq)]
-pub enum Interest {
-    READ,
-    WRITE,
+    pub fn is_writable(self) -> bool {
+        self.0 & Self::WRITE.0 != 0
+    }
 }
 
-impl Registry {
-    pub fn new(epoll_fd:RawFd) -> Self {
-        Registry {
-            epoll_fd,
-            io_sources : HashMap::new()
-        }
+impl BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, other: Interest) -> Interest {
+        Interest(self.0 | other.0)
     }
+}
 
-    pub fn register_read(&mut self, fd: RawFd, event_id: EventId) -> io::Result<()> {
-        let interests = self.io_sources.entry(fd).or_insert(HashSet::new());
+/// When epoll reports an fd.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Hash, Eq)]
+pub enum Mode {
+    /// On every wait, as long as the fd is ready.
+    #[default]
+    Level,
+    /// Only when the fd becomes ready.  The handler must read or write until
+    /// `WouldBlock`, or it will not hear about the fd again.
+    Edge,
+    /// Once; after that the fd stays registered but silent until it is
+    /// re-armed with `reregister`.
+    OneShot,
+}
 
-        if interests.is_empty() {
-            syscall!(epoll_ctl(
-                self.epoll_fd,
-                libc::EPOLL_CTL_ADD,
-                fd,
-                &mut read_event(event_id)
-            ))?;
-        } else {
-            syscall!(epoll_ctl(
-                self.epoll_fd,
-                libc::EPOLL_CTL_MOD,
-                fd,
-                &mut read_event(event_id)
-            ))?;
+impl Mode {
+    fn flags(self) -> u32 {
+        match self {
+            Mode::Level => 0,
+            Mode::Edge => libc::EPOLLET as u32,
+            Mode::OneShot => libc::EPOLLONESHOT as u32,
         }
+    }
+}
 
-        interests.clear();
-        interests.insert(Interest::READ);
-
-        Ok(())
+fn event(event_id: EventId, interest: Interest, mode: Mode) -> libc::epoll_event {
+    libc::epoll_event {
+        // Hang-ups are always reported so a handler waiting to write still
+        // notices that the peer went away.
+        events: interest.0 | libc::EPOLLRDHUP as u32 | mode.flags(),
+        u64: event_id as u64,
     }
+}
 
-    pub fn register_write(&mut self, fd: RawFd, event_id: EventId) -> io::Result<()> {
-        let interests = self.io_sources.entry(fd).or_insert(HashSet::new());
+/// Adds, changes and removes the fds a `Poll` watches.
+#[derive(Clone, Copy, Debug)]
+pub struct Registry {
+    epoll_fd: RawFd,
+}
 
-        if interests.is_empty() {
-            syscall!(epoll_ctl(
-                self.epoll_fd,
-                libc::EPOLL_CTL_ADD,
-                fd,
-                &mut write_event(event_id)
-            ))?;
-        } else {
-            syscall!(epoll_ctl(
-                self.epoll_fd,
-                libc::EPOLL_CTL_MOD,
-                fd,
-                &mut write_event(event_id)
-            ))?;
-        }
+impl Registry {
+    pub fn new(epoll_fd: RawFd) -> Self {
+        Registry { epoll_fd }
+    }
 
-        interests.clear();
-        interests.insert(Interest::WRITE);
+    pub fn register(&self, fd: RawFd, event_id: EventId, interest: Interest, mode: Mode) -> io::Result<()> {
+        syscall!(epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_ADD, fd, &mut event(event_id, interest, mode)))?;
+        Ok(())
+    }
 
+    /// Change the interest or mode of a registered fd.  This is also how a
+    /// one-shot registration is re-armed.
+    pub fn reregister(&self, fd: RawFd, event_id: EventId, interest: Interest, mode: Mode) -> io::Result<()> {
+        syscall!(epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_MOD, fd, &mut event(event_id, interest, mode)))?;
         Ok(())
     }
 
-    pub fn remove_interests(&mut self, fd: RawFd) -> io::Result<()> {
-        self.io_sources.remove(&fd);
+    pub fn deregister(&self, fd: RawFd) -> io::Result<()> {
         syscall!(epoll_ctl(
             self.epoll_fd,
             libc::EPOLL_CTL_DEL,
@@ -85,18 +93,3 @@ impl Registry {
         Ok(())
     }
 }
-
-
-pub fn read_event(event_id: EventId) -> libc::epoll_event {
-    libc::epoll_event {
-        events: READ_FLAGS as u32,
-        u64: event_id as u64,
-    }
-}
-
-pub fn write_event(event_id: EventId) -> libc::epoll_event {
-    libc::epoll_event {
-        events: WRITE_FLAGS as u32,
-        u64: event_id as u64,
-    }
-}
\ No newline at end of file
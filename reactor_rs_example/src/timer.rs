@@ -0,0 +1,59 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet},
+    time::Instant,
+};
+use crate::utility::EventId;
+
+/// Identifies a timer set through `Context::set_timer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+/// Pending timers, earliest first.  Cancelling only marks a timer; it is
+/// discarded when it reaches the front.
+#[derive(Default)]
+pub(crate) struct Timers {
+    heap: BinaryHeap<Reverse<(Instant, TimerId, EventId)>>,
+    cancelled: HashSet<TimerId>,
+    next_id: u64,
+}
+
+impl Timers {
+    pub(crate) fn insert(&mut self, deadline: Instant, owner: EventId) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.heap.push(Reverse((deadline, id, owner)));
+        id
+    }
+
+    pub(crate) fn cancel(&mut self, id: TimerId) {
+        if self.heap.iter().any(|Reverse((_, timer, _))| *timer == id) {
+            self.cancelled.insert(id);
+        }
+    }
+
+    pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
+        self.discard_cancelled();
+        self.heap.peek().map(|Reverse((deadline, _, _))| *deadline)
+    }
+
+    /// The next timer due at `now`, with the handler that set it.
+    pub(crate) fn pop_expired(&mut self, now: Instant) -> Option<(TimerId, EventId)> {
+        self.discard_cancelled();
+        match self.heap.peek() {
+            Some(Reverse((deadline, _, _))) if *deadline <= now => {
+                self.heap.pop().map(|Reverse((_, id, owner))| (id, owner))
+            }
+            _ => None,
+        }
+    }
+
+    fn discard_cancelled(&mut self) {
+        while let Some(Reverse((_, id, _))) = self.heap.peek() {
+            if !self.cancelled.remove(id) {
+                break;
+            }
+            self.heap.pop();
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use crate::{syscall, utility::EventId};
+
+/// Requests from other threads, signalled to the reactor through an eventfd.
+pub(crate) struct WakeQueue {
+    fd: OwnedFd,
+    pending: Mutex<Vec<EventId>>,
+    shutdown: AtomicBool,
+}
+
+impl WakeQueue {
+    pub(crate) fn new() -> io::Result<Self> {
+        let fd = syscall!(eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK))?;
+        // SAFETY: the fd was just created and nothing else owns it.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(WakeQueue { fd, pending: Mutex::new(Vec::new()), shutdown: AtomicBool::new(false) })
+    }
+
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    pub(crate) fn wake(&self, id: EventId) {
+        let mut pending = self.pending.lock().expect("wake queue lock");
+        if !pending.contains(&id) {
+            pending.push(id);
+        }
+        drop(pending);
+        self.notify();
+    }
+
+    pub(crate) fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Release);
+        self.notify();
+    }
+
+    pub(crate) fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+
+    /// Reset the eventfd and take the ids woken so far.  The reset comes
+    /// first, so a wake that misses this batch leaves the eventfd readable.
+    pub(crate) fn take(&self) -> Vec<EventId> {
+        let mut count = 0u64;
+        // EAGAIN only means nobody wrote since the last reset.
+        let _ = syscall!(read(self.fd.as_raw_fd(), &mut count as *mut u64 as *mut libc::c_void, 8));
+        std::mem::take(&mut *self.pending.lock().expect("wake queue lock"))
+    }
+
+    fn notify(&self) {
+        let one = 1u64;
+        // EAGAIN means the counter is saturated, which is as readable as it gets.
+        let _ = syscall!(write(self.fd.as_raw_fd(), &one as *const u64 as *const libc::c_void, 8));
+    }
+}
+
+/// Calls `Handler::woken` for one handler, from any thread.  Wakes that
+/// arrive before the handler runs are coalesced.
+#[derive(Clone)]
+pub struct Waker {
+    queue: Arc<WakeQueue>,
+    id: EventId,
+}
+
+impl Waker {
+    pub(crate) fn new(queue: Arc<WakeQueue>, id: EventId) -> Self {
+        Waker { queue, id }
+    }
+
+    pub fn id(&self) -> EventId {
+        self.id
+    }
+
+    pub fn wake(&self) {
+        self.queue.wake(self.id);
+    }
+}
+
+/// Starts a graceful shutdown of the reactor from any thread.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    queue: Arc<WakeQueue>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(queue: Arc<WakeQueue>) -> Self {
+        ShutdownHandle { queue }
+    }
+
+    pub fn shutdown(&self) {
+        self.queue.shutdown();
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+/// Header fields in the order they were added.  Names compare
+/// case-insensitively but keep the case they were given in; a name may
+/// appear more than once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// First value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.entries.iter().filter(move |(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    /// Comma-separated elements of every `name` field, trimmed, as in
+    /// `Connection: keep-alive, Upgrade`.
+    pub fn get_list<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.get_all(name).flat_map(|v| v.split(',')).map(str::trim).filter(|v| !v.is_empty())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Replace every `name` field with one holding `value`.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Add a `name` field, keeping any already there.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Remove every `name` field, returning the first value.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+        self.entries.retain_mut(|(n, v)| {
+            if !n.eq_ignore_ascii_case(name) {
+                return true;
+            }
+            if removed.is_none() {
+                removed = Some(std::mem::take(v));
+            }
+            false
+        });
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Headers {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Headers { entries: iter.into_iter().map(|(k, v)| (k.into(), v.into())).collect() }
+    }
+}
+
+impl fmt::Display for Headers {
+    /// Wire format, each field ending in CRLF.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.iter() {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_case_insensitive_and_repeatable() {
+        let mut headers = Headers::new();
+        headers.append("Accept", "text/html");
+        headers.append("accept", "application/json");
+        headers.append("Connection", "keep-alive, Upgrade");
+        assert_eq!(headers.get("ACCEPT"), Some("text/html"));
+        assert_eq!(headers.get_all("accept").collect::<Vec<_>>(), ["text/html", "application/json"]);
+        assert_eq!(headers.get_list("connection").collect::<Vec<_>>(), ["keep-alive", "Upgrade"]);
+
+        headers.insert("ACCEPT", "*/*");
+        assert_eq!(headers.get_all("accept").collect::<Vec<_>>(), ["*/*"]);
+        assert_eq!(headers.remove("connection").as_deref(), Some("keep-alive, Upgrade"));
+        assert_eq!(headers.to_string(), "ACCEPT: */*\r\n");
+    }
+}
@@ -1,106 +1,262 @@
-use std::collections::HashMap;
+use std::{fmt, str::FromStr};
 
-#[derive(Debug,PartialEq)]
+use crate::headers::Headers;
+use crate::parse::{self, is_token, percent_decode, percent_encode, Framing, ParseError};
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum Method {
     GET,
+    HEAD,
     POST,
-    Uninitalized
+    PUT,
+    DELETE,
+    CONNECT,
+    OPTIONS,
+    TRACE,
+    PATCH,
+    /// Any other method token.  Methods are case-sensitive, so `get` lands here.
+    Extension(String),
 }
 
-impl From<&str> for Method {
-    fn from(s: &str) -> Self {
-        match s {
+impl Method {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::GET => "GET",
+            Method::HEAD => "HEAD",
+            Method::POST => "POST",
+            Method::PUT => "PUT",
+            Method::DELETE => "DELETE",
+            Method::CONNECT => "CONNECT",
+            Method::OPTIONS => "OPTIONS",
+            Method::TRACE => "TRACE",
+            Method::PATCH => "PATCH",
+            Method::Extension(s) => s,
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
             "GET" => Method::GET,
+            "HEAD" => Method::HEAD,
             "POST" => Method::POST,
-            _ => Method::Uninitalized
-        }
+            "PUT" => Method::PUT,
+            "DELETE" => Method::DELETE,
+            "CONNECT" => Method::CONNECT,
+            "OPTIONS" => Method::OPTIONS,
+            "TRACE" => Method::TRACE,
+            "PATCH" => Method::PATCH,
+            s if is_token(s) => Method::Extension(s.to_string()),
+            _ => return Err(ParseError::InvalidMethod),
+        })
     }
 }
 
-#[derive(Debug,PartialEq)]
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Version {
+    HTTP1_0,
     HTTP1_1,
-    HTTP2,
-    Uninitalized
 }
 
-impl From<&str> for Version {
-    fn from(s: &str) -> Self {
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::HTTP1_0 => "HTTP/1.0",
+            Version::HTTP1_1 => "HTTP/1.1",
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "HTTP/1.1" => Version::HTTP1_1,
-            "HTTP/2" => Version::HTTP2,
-            _ => Version::Uninitalized
+            "HTTP/1.1" => Ok(Version::HTTP1_1),
+            "HTTP/1.0" => Ok(Version::HTTP1_0),
+            _ => Err(ParseError::UnsupportedVersion),
         }
     }
 }
 
-#[derive(Debug,PartialEq)]
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a request is for, with any query split off into `HttpRequest::query`.
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Resource {
+    /// A percent-decoded absolute path.  The path of an absolute-form target
+    /// (`http://host/path`) ends up here too.
     Path(String),
-    Uninitalized
+    /// `OPTIONS *`.
+    Asterisk,
+    /// `CONNECT host:port`.
+    Authority(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct HttpRequest {
     pub method: Method,
     pub version: Version,
     pub resource: Resource,
-    pub headers: HashMap<String,String>,
-    pub body: String
+    /// Decoded query parameters in order; a name may repeat.
+    pub query: Vec<(String, String)>,
+    pub headers: Headers,
+    pub body: Vec<u8>,
 }
 
-impl From<String> for HttpRequest {
-    fn from(req: String) -> Self {
-        let mut pasred_method = Method::Uninitalized;
-        let mut pasred_version = Version::Uninitalized;
-        let mut pasred_resource = Resource::Uninitalized;
-        let mut pasred_header = HashMap::new();
-        let mut pasred_body = "";
-
-        for line in req.lines() {
-            if line.contains("HTTP") {
-                let (method, resource, version) = parse_request_line(line);
-                pasred_method = method;
-                pasred_resource = resource;
-                pasred_version = version;
-            } else if line.contains(":") {
-                let (key, value) = parse_header_line(line);
-                pasred_header.insert(key, value);
-            } else if line.is_empty() {
-            } else {
-                pasred_body = line;
-            }
+impl HttpRequest {
+    /// An HTTP/1.1 request without headers or body.
+    pub fn new(method: Method, path: impl Into<String>) -> Self {
+        HttpRequest {
+            method,
+            version: Version::HTTP1_1,
+            resource: Resource::Path(path.into()),
+            query: Vec::new(),
+            headers: Headers::new(),
+            body: Vec::new(),
         }
+    }
 
-        HttpRequest {
-            method: pasred_method,
-            version: pasred_version,
-            resource: pasred_resource,
-            headers: pasred_header,
-            body: pasred_body.to_string()
+    /// Parse one request from the start of `buf`, returning it with the
+    /// number of bytes it took up; pipelined requests follow.  Returns
+    /// `ParseError::Incomplete` until the whole request has arrived.
+    pub fn parse(buf: &[u8]) -> Result<(HttpRequest, usize), ParseError> {
+        let head = parse::parse_head(buf)?;
+        let mut parts = head.start_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::InvalidRequestLine);
+        };
+        let method: Method = method.parse()?;
+        let version = match version.parse() {
+            Err(_) if !version.starts_with("HTTP/") => return Err(ParseError::InvalidRequestLine),
+            version => version?,
+        };
+        let (resource, query) = parse_target(&method, target)?;
+
+        // Requests without a length have no body.
+        let framing = parse::framing(&head.headers, Framing::Length(0))?;
+        let (body, body_len) = parse::parse_body(&buf[head.len..], framing)?;
+        let request = HttpRequest { method, version, resource, query, headers: head.headers, body };
+        Ok((request, head.len + body_len))
+    }
+
+    /// First value of the header `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// First value of the query parameter `name`.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn body_text(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
+
+    /// Whether the client will take another response on this connection.
+    pub fn keep_alive(&self) -> bool {
+        let has = |option: &str| self.headers.get_list("connection").any(|v| v.eq_ignore_ascii_case(option));
+        match self.version {
+            Version::HTTP1_1 => !has("close"),
+            Version::HTTP1_0 => has("keep-alive"),
+        }
+    }
+
+    /// The request target as sent on the wire, re-encoded.
+    pub fn target(&self) -> String {
+        let mut target = match &self.resource {
+            Resource::Path(path) => percent_encode(path, b"/"),
+            Resource::Asterisk => return "*".to_string(),
+            Resource::Authority(authority) => return authority.clone(),
+        };
+        for (i, (name, value)) in self.query.iter().enumerate() {
+            target.push(if i == 0 { '?' } else { '&' });
+            target.push_str(&percent_encode(name, b""));
+            target.push('=');
+            target.push_str(&percent_encode(value, b""));
         }
+        target
+    }
+
+    /// Wire format.  `Content-Length` is set from the body, replacing any
+    /// framing headers.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut headers = self.headers.clone();
+        headers.remove("transfer-encoding");
+        headers.remove("content-length");
+        if !self.body.is_empty() {
+            headers.append("Content-Length", self.body.len().to_string());
+        }
+        let mut out = format!("{} {} {}\r\n{}\r\n", self.method, self.target(), self.version, headers).into_bytes();
+        out.extend_from_slice(&self.body);
+        out
     }
 }
 
-fn parse_request_line(s:&str) -> (Method, Resource, Version) {
-    let mut iter = s.split_whitespace();
-    let method = Method::from(iter.next().unwrap());
-    let resource = Resource::Path(iter.next().unwrap().to_string());
-    let version = Version::from(iter.next().unwrap());
-    (method, resource, version)
+impl TryFrom<&[u8]> for HttpRequest {
+    type Error = ParseError;
+
+    /// Parse a buffer holding exactly one request.
+    fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
+        match HttpRequest::parse(buf)? {
+            (request, used) if used == buf.len() => Ok(request),
+            _ => Err(ParseError::InvalidContentLength),
+        }
+    }
 }
 
-fn parse_header_line(s:&str) -> (String, String) {
-    let mut header_items = s.split(":");
-    let mut key = String::from("");
-    let mut value = String::from("");
-    if let Some(k) = header_items.next() {
-        key = k.to_string();
+fn parse_target(method: &Method, target: &str) -> Result<(Resource, Vec<(String, String)>), ParseError> {
+    if target.is_empty() || target.bytes().any(|b| b.is_ascii_control() || b >= 0x80) {
+        return Err(ParseError::InvalidTarget);
     }
-    if let Some(v) = header_items.next() {
-        value = v.to_string();
+    if target == "*" && *method == Method::OPTIONS {
+        return Ok((Resource::Asterisk, Vec::new()));
     }
-    (key, value)
+    if *method == Method::CONNECT {
+        return Ok((Resource::Authority(target.to_string()), Vec::new()));
+    }
+
+    let origin = match target.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") => {
+            match rest.find(['/', '?']) {
+                Some(i) if rest[i..].starts_with('/') => &rest[i..],
+                Some(i) => return Ok((Resource::Path("/".to_string()), parse_query(&rest[i + 1..])?)),
+                None => "/",
+            }
+        }
+        _ if target.starts_with('/') => target,
+        _ => return Err(ParseError::InvalidTarget),
+    };
+    let origin = origin.split_once('#').map_or(origin, |(origin, _)| origin);
+    let (path, query) = origin.split_once('?').unwrap_or((origin, ""));
+    Ok((Resource::Path(percent_decode(path)?), parse_query(query)?))
+}
+
+/// Decode an `application/x-www-form-urlencoded` query string.
+pub fn parse_query(query: &str) -> Result<Vec<(String, String)>, ParseError> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(&name.replace('+', " "))?, percent_decode(&value.replace('+', " "))?))
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -109,50 +265,92 @@ mod tests {
 
     #[test]
     fn test_method_from_str() {
-        assert_eq!(Method::from("GET"), Method::GET);
-        assert_eq!(Method::from("POST"), Method::POST);
-        assert_eq!(Method::from("PUT"), Method::Uninitalized);
+        assert_eq!("GET".parse(), Ok(Method::GET));
+        assert_eq!("PATCH".parse(), Ok(Method::PATCH));
+        assert_eq!("PURGE".parse(), Ok(Method::Extension("PURGE".to_string())));
+        assert_eq!("get".parse(), Ok(Method::Extension("get".to_string())));
+        assert_eq!("GE T".parse::<Method>(), Err(ParseError::InvalidMethod));
     }
 
     #[test]
     fn test_version_from_str() {
-        assert_eq!(Version::from("HTTP/1.1"), Version::HTTP1_1);
-        assert_eq!(Version::from("HTTP/2"), Version::HTTP2);
-        assert_eq!(Version::from("HTTP/3"), Version::Uninitalized);
+        assert_eq!("HTTP/1.1".parse(), Ok(Version::HTTP1_1));
+        assert_eq!("HTTP/1.0".parse(), Ok(Version::HTTP1_0));
+        assert_eq!("HTTP/2".parse::<Version>(), Err(ParseError::UnsupportedVersion));
     }
 
     #[test]
-    fn test_parse_request_line() {
-        let line = "GET / HTTP/1.1";
-        let (method, resource, version) = parse_request_line(line);
-        assert_eq!(method, Method::GET);
-        assert_eq!(resource, Resource::Path("/".to_string()));
-        assert_eq!(version, Version::HTTP1_1);
-    }
-    
-    #[test]
-    fn test_parse_header_line() {
-        let line = "Host: localhost:8080";
-        let (key, value) = parse_header_line(line);
-        assert_eq!(key, "Host");
-        assert_eq!(value, " localhost");
+    fn test_parse_target() {
+        let path = |p: &str| Resource::Path(p.to_string());
+        let pairs = |pairs: &[(&str, &str)]| pairs.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect();
+        assert_eq!(
+            parse_target(&Method::GET, "/a%20b/c?x=1&y=a+b%26c&flag&&x=2"),
+            Ok((path("/a b/c"), pairs(&[("x", "1"), ("y", "a b&c"), ("flag", ""), ("x", "2")])))
+        );
+        assert_eq!(parse_target(&Method::GET, "http://example.com/p?q=1"), Ok((path("/p"), pairs(&[("q", "1")]))));
+        assert_eq!(parse_target(&Method::GET, "http://example.com"), Ok((path("/"), vec![])));
+        assert_eq!(parse_target(&Method::OPTIONS, "*"), Ok((Resource::Asterisk, vec![])));
+        assert_eq!(parse_target(&Method::CONNECT, "example.com:443"), Ok((Resource::Authority("example.com:443".into()), vec![])));
+        assert_eq!(parse_target(&Method::GET, "relative"), Err(ParseError::InvalidTarget));
+        assert_eq!(parse_target(&Method::GET, "/%E9"), Err(ParseError::InvalidEncoding));
     }
 
     #[test]
-    fn test_http_request_from_string() {
-        let req = "GET /greeting HTTP/1.1\r\nHost: localhost:8080\r\nUser-Agent: curl/7.64.1\r\nAccept: */*\r\n\r\nHello, World!";
-        
-        let http_req = HttpRequest::from(req.to_string());
-        let mut expect_headers = HashMap::new();
-        expect_headers.insert("Host".to_string(), " localhost".to_string());
-        expect_headers.insert("User-Agent".to_string(), " curl/7.64.1".to_string());
-        expect_headers.insert("Accept".to_string(), " */*".to_string());
+    fn test_http_request_parse() {
+        let req = b"GET /greeting?name=Rust HTTP/1.1\r\nHost: localhost:8080\r\nUser-Agent: curl/7.64.1\r\nAccept: */*\r\nAccept: text/html\r\n\r\n";
+
+        let (http_req, used) = HttpRequest::parse(req).unwrap();
+        assert_eq!(used, req.len());
         assert_eq!(http_req.method, Method::GET);
         assert_eq!(http_req.version, Version::HTTP1_1);
         assert_eq!(http_req.resource, Resource::Path("/greeting".to_string()));
-        assert_eq!(http_req.headers, expect_headers);
-        assert_eq!(http_req.body, "Hello, World!");
+        assert_eq!(http_req.query_param("name"), Some("Rust"));
+        assert_eq!(http_req.header("host"), Some("localhost:8080"));
+        assert_eq!(http_req.headers.get_all("ACCEPT").collect::<Vec<_>>(), ["*/*", "text/html"]);
+        assert!(http_req.body.is_empty());
+        assert!(http_req.keep_alive());
     }
 
+    #[test]
+    fn test_binary_and_pipelined_bodies() {
+        let mut buf = b"POST /upload HTTP/1.1\r\nContent-Length: 4\r\n\r\n\x00\xff\r\n".to_vec();
+        buf.extend_from_slice(b"PUT /chunks HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n0\r\n\r\n");
+
+        let (first, used) = HttpRequest::parse(&buf).unwrap();
+        assert_eq!(first.body, b"\x00\xff\r\n");
+        let (second, rest) = HttpRequest::parse(&buf[used..]).unwrap();
+        assert_eq!(used + rest, buf.len());
+        assert_eq!((second.keep_alive(), second.method, second.body), (false, Method::PUT, b"ab".to_vec()));
+
+        for end in 0..used {
+            assert_eq!(HttpRequest::parse(&buf[..end]), Err(ParseError::Incomplete), "{end}");
+        }
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_malformed_requests() {
+        let cases: &[(&[u8], ParseError)] = &[
+            (b"GET /\r\n\r\n", ParseError::InvalidRequestLine),
+            (b"GET  / HTTP/1.1\r\n\r\n", ParseError::InvalidRequestLine),
+            (b"GET / HTTP/2.0\r\n\r\n", ParseError::UnsupportedVersion),
+            (b"GET / FTP/1.1\r\n\r\n", ParseError::InvalidRequestLine),
+            (b"GET / HTTP/1.1\r\nHost : x\r\n\r\n", ParseError::InvalidHeader),
+            (b"GET / HTTP/1.1\r\nA: b\r\n folded\r\n\r\n", ParseError::InvalidHeader),
+            (b"GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n", ParseError::InvalidContentLength),
+        ];
+        for (input, error) in cases {
+            assert_eq!(HttpRequest::parse(input), Err(*error), "{}", String::from_utf8_lossy(input));
+        }
+        assert_eq!(HttpRequest::parse(&vec![b'a'; parse::MAX_HEAD_BYTES + 1]), Err(ParseError::HeadTooLarge));
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let mut req = HttpRequest::new(Method::POST, "/a b");
+        req.query.push(("q".into(), "x&y".into()));
+        req.headers.append("Host", "example.com");
+        req.headers.append("Content-Length", "999");
+        req.body = b"hi".to_vec();
+        assert_eq!(req.to_bytes(), b"POST /a%20b?q=x%26y HTTP/1.1\r\nHost: example.com\r\nContent-Length: 2\r\n\r\nhi");
+    }
+}
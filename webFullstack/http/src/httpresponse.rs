@@ -1,163 +1,350 @@
-use std::{collections::HashMap, io::Write};
-
-#[derive(Debug,PartialEq,Clone)]
-pub struct HttpResponse<'a> {
-    version: &'a str,
-    status_code: &'a str,
-    status_text: &'a str,
-    headers: Option<HashMap<&'a str, &'a str>>,
-    body: Option<String>,
-}
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::{fmt, path::PathBuf};
 
-impl<'a> Default for HttpResponse<'a> {
-    fn default() -> Self {
-        HttpResponse {
-            version: "HTTP/1.1",
-            status_code: "200",
-            status_text: "OK",
-            headers: None,
-            body: None,
+use crate::headers::Headers;
+use crate::httprequest::Version;
+use crate::parse::{self, Framing, ParseError};
+
+macro_rules! status_codes {
+    ($($variant:ident = $code:literal, $reason:literal;)*) => {
+        #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+        pub enum StatusCode {
+            $($variant,)*
         }
+
+        impl StatusCode {
+            pub const ALL: &'static [StatusCode] = &[$(StatusCode::$variant,)*];
+
+            pub fn code(self) -> u16 {
+                match self {
+                    $(StatusCode::$variant => $code,)*
+                }
+            }
+
+            pub fn reason(self) -> &'static str {
+                match self {
+                    $(StatusCode::$variant => $reason,)*
+                }
+            }
+        }
+
+        impl TryFrom<u16> for StatusCode {
+            type Error = ParseError;
+
+            fn try_from(code: u16) -> Result<Self, Self::Error> {
+                match code {
+                    $($code => Ok(StatusCode::$variant),)*
+                    _ => Err(ParseError::InvalidStatusLine),
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NoContent = 204, "No Content";
+    PartialContent = 206, "Partial Content";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    UnprocessableContent = 422, "Unprocessable Content";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+}
+
+impl StatusCode {
+    /// 1xx, 204 and 304 responses never have a body.
+    pub fn allows_body(self) -> bool {
+        !(self.code() < 200 || self == StatusCode::NoContent || self == StatusCode::NotModified)
+    }
+
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.code())
     }
 }
 
-impl<'a> From<HttpResponse<'a>> for String{
-    fn from(value: HttpResponse<'a>) -> Self {
-        //let res = value.clone();
-        let response_string = format!(
-            "{} {} {}\r\n{}Content-Length:{}\r\n\r\n{}",
-            value.version(),
-            value.status_code(),
-            value.status_text(),
-            value.headers(),
-            value.body().len(),
-            value.body()
-        );
-        response_string
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
     }
 }
 
-impl<'a> HttpResponse<'a>{
-    pub fn new(
-        status_code : &'a str,
-        headers: Option<HashMap<&'a str,&'a str>>,
-        body:Option<String>) 
-    -> HttpResponse<'a> {
-        let mut res: HttpResponse<'a> = HttpResponse::default();
-        if status_code != "200" {
-            res.status_code = status_code;
+impl From<ParseError> for StatusCode {
+    /// What to answer a request that failed to parse with.
+    fn from(error: ParseError) -> Self {
+        match error {
+            ParseError::HeadTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+            ParseError::BodyTooLarge => StatusCode::ContentTooLarge,
+            ParseError::UnsupportedVersion => StatusCode::HttpVersionNotSupported,
+            ParseError::UnsupportedTransferEncoding => StatusCode::NotImplemented,
+            _ => StatusCode::BadRequest,
         }
-        
-        res.headers = headers.or_else(|| {
-            let mut h = HashMap::new();
-            h.insert("Content-Type", "text/html");
-            Some(h)
-        });
-
-        res.status_text = match res.status_code {
-            "200" => "OK",
-            "400" => "Bad Request",
-            "404" => "Not Found",
-            "500" => "Internal Server Error",
-            _ => "Not Found!"
-        };
-        res.body = body;
-        res
+    }
+}
+
+/// Part of a file sent as the body when the response is written, rather
+/// than held in memory.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FileBody {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HttpResponse {
+    version: Version,
+    status: StatusCode,
+    headers: Headers,
+    body: Vec<u8>,
+    file: Option<FileBody>,
+}
+
+impl Default for HttpResponse {
+    fn default() -> Self {
+        HttpResponse::new(StatusCode::Ok)
+    }
+}
+
+impl HttpResponse {
+    pub fn new(status: StatusCode) -> HttpResponse {
+        HttpResponse { version: Version::HTTP1_1, status, headers: Headers::new(), body: Vec::new(), file: None }
+    }
+
+    pub fn html(status: StatusCode, body: impl Into<String>) -> HttpResponse {
+        HttpResponse::new(status).with_header("Content-Type", "text/html; charset=utf-8").with_body(body.into())
+    }
+
+    pub fn text(status: StatusCode, body: impl Into<String>) -> HttpResponse {
+        HttpResponse::new(status).with_header("Content-Type", "text/plain; charset=utf-8").with_body(body.into())
+    }
+
+    /// Replace any `name` header with `value`.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> HttpResponse {
+        self.headers.insert(name, value);
+        self
     }
 
-    pub fn send_response(&self, write_steam: &mut impl Write) -> Result<(), std::io::Error> {
-        let res = self.clone();
-        let response_string:String = String::from(res);
-        let _ = write!(write_steam, "{}", response_string);
-        Ok(())
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> HttpResponse {
+        self.body = body.into();
+        self.file = None;
+        self
     }
 
-    fn version(&self) -> &str {
+    /// Send `file` as the body, replacing any other.
+    pub fn with_file(mut self, file: FileBody) -> HttpResponse {
+        self.body = Vec::new();
+        self.file = Some(file);
+        self
+    }
+
+    pub fn version(&self) -> Version {
         self.version
     }
 
-    fn status_code(&self) -> &str {
-        self.status_code
+    pub fn status(&self) -> StatusCode {
+        self.status
     }
 
-    fn status_text(&self) -> &str {
-        self.status_text
+    pub fn headers(&self) -> &Headers {
+        &self.headers
     }
 
-    fn headers(&self) -> String {
-        let map = self.headers.clone().unwrap();
-        let mut header_string = String::new();
-        for (key, value) in map {
-            header_string.push_str(&format!("{}: {}\r\n", key, value));
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// The in-memory body; empty when the body is a file.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn file(&self) -> Option<&FileBody> {
+        self.file.as_ref()
+    }
+
+    /// Length of the body, whichever kind it is.
+    pub fn content_length(&self) -> u64 {
+        self.file.as_ref().map_or(self.body.len() as u64, |file| file.len)
+    }
+
+    /// The status line and headers.  `Content-Length` is set from the body,
+    /// replacing any framing headers, unless the status forbids a body.
+    pub fn head_bytes(&self) -> Vec<u8> {
+        let mut headers = self.headers.clone();
+        headers.remove("transfer-encoding");
+        headers.remove("content-length");
+        if self.status.allows_body() {
+            headers.append("Content-Length", self.content_length().to_string());
+        }
+        format!("{} {}\r\n{}\r\n", self.version, self.status, headers).into_bytes()
+    }
+
+    /// Wire format with the in-memory body; a file body is left out, so use
+    /// `write_to` for those.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.head_bytes();
+        if self.status.allows_body() {
+            out.extend_from_slice(&self.body);
+        }
+        out
+    }
+
+    /// Write the response, streaming any file body.  Without `with_body`
+    /// only the head goes out, as the answer to a `HEAD` request.
+    pub fn write_to(&self, out: &mut impl Write, with_body: bool) -> io::Result<()> {
+        let with_body = with_body && self.status.allows_body();
+        let file = match &self.file {
+            // Opened first so a missing file fails before anything is sent.
+            Some(body) if with_body => {
+                let mut file = File::open(&body.path)?;
+                file.seek(SeekFrom::Start(body.offset))?;
+                Some(file.take(body.len))
+            }
+            _ => None,
+        };
+
+        out.write_all(&self.head_bytes())?;
+        if let Some(mut file) = file {
+            let len = self.content_length();
+            if io::copy(&mut file, out)? != len {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while being sent"));
+            }
+        } else if with_body {
+            out.write_all(&self.body)?;
         }
-        header_string
+        out.flush()
+    }
+
+    pub fn send_response(&self, write_stream: &mut impl Write) -> Result<(), std::io::Error> {
+        self.write_to(write_stream, true)
     }
 
-    pub fn body(&self) -> &str {
-        match &self.body {
-            Some(b) => b.as_str(),
-            None => "",
+    /// Parse one response from the start of `buf`, returning it with the
+    /// number of bytes it took up.  A response with neither `Content-Length`
+    /// nor chunked encoding takes the rest of `buf` as its body, so `buf`
+    /// must then hold everything up to the end of the connection.
+    pub fn parse(buf: &[u8]) -> Result<(HttpResponse, usize), ParseError> {
+        let head = parse::parse_head(buf)?;
+        let mut parts = head.start_line.splitn(3, ' ');
+        let (Some(version), Some(code), _reason) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(ParseError::InvalidStatusLine);
+        };
+        let version = version.parse()?;
+        if code.len() != 3 {
+            return Err(ParseError::InvalidStatusLine);
         }
+        let status = code.parse::<u16>().map_err(|_| ParseError::InvalidStatusLine)?.try_into()?;
+
+        let framing = match status {
+            status if !StatusCode::allows_body(status) => Framing::Length(0),
+            _ => parse::framing(&head.headers, Framing::UntilClose)?,
+        };
+        let (body, body_len) = parse::parse_body(&buf[head.len..], framing)?;
+        Ok((HttpResponse { version, status, headers: head.headers, body, file: None }, head.len + body_len))
     }
 }
 
+impl From<HttpResponse> for Vec<u8> {
+    fn from(value: HttpResponse) -> Self {
+        value.to_bytes()
+    }
+}
 
 #[cfg(test)]
-mod tests{
+mod tests {
     use super::*;
 
     #[test]
     fn test_default() {
         let res = HttpResponse::default();
-        assert_eq!(res.version, "HTTP/1.1");
-        assert_eq!(res.status_code, "200");
-        assert_eq!(res.status_text, "OK");
-        assert_eq!(res.headers, None);
-        assert_eq!(res.body, None);
+        assert_eq!(res.version(), Version::HTTP1_1);
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert!(res.headers().is_empty());
+        assert!(res.body().is_empty());
     }
 
     #[test]
-    fn test_response_struct_creation_200(){
-        let response_act = HttpResponse::new("200", None, Some("Hello World".to_string()));
-
-        let response_expect = HttpResponse{
-            version: "HTTP/1.1",
-            status_code: "200",
-            status_text: "OK",
-            headers: Some({
-                let mut h = HashMap::new();
-                h.insert("Content-Type", "text/html");
-                h
-            }),
-            body: Some("Hello World".to_string())
-        };
+    fn test_status_codes() {
+        assert_eq!(StatusCode::NotFound.to_string(), "404 Not Found");
+        assert_eq!(StatusCode::try_from(503), Ok(StatusCode::ServiceUnavailable));
+        assert_eq!(StatusCode::try_from(299), Err(ParseError::InvalidStatusLine));
+        assert_eq!(StatusCode::from(ParseError::BodyTooLarge), StatusCode::ContentTooLarge);
+    }
+
+    #[test]
+    fn test_http_response_creation() {
+        let response = HttpResponse::html(StatusCode::Ok, "Hello World");
+        let expect_response = "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: 11\r\n\r\nHello World";
+        assert_eq!(Vec::from(response), expect_response.as_bytes());
 
-        assert_eq!(response_act, response_expect);
+        let response = HttpResponse::new(StatusCode::NotModified).with_header("ETag", "\"1\"").with_body("ignored");
+        assert_eq!(response.to_bytes(), b"HTTP/1.1 304 Not Modified\r\nETag: \"1\"\r\n\r\n");
     }
+
     #[test]
-    fn test_response_struct_creation_404(){
-        let response_act = HttpResponse::new("404", None, Some("Hello World".to_string()));
-
-        let response_expect = HttpResponse{
-            version: "HTTP/1.1",
-            status_code: "404",
-            status_text: "Not Found",
-            headers: Some({
-                let mut h = HashMap::new();
-                h.insert("Content-Type", "text/html");
-                h
-            }),
-            body: Some("Hello World".to_string())
-        };
+    fn test_file_body() {
+        let path = std::env::temp_dir().join(format!("http-file-body-{}", std::process::id()));
+        std::fs::write(&path, b"0123456789").unwrap();
+        let response = HttpResponse::new(StatusCode::PartialContent)
+            .with_body("replaced")
+            .with_file(FileBody { path: path.clone(), offset: 2, len: 5 });
+        assert_eq!(response.content_length(), 5);
+
+        let mut out = Vec::new();
+        response.write_to(&mut out, true).unwrap();
+        assert_eq!(out, b"HTTP/1.1 206 Partial Content\r\nContent-Length: 5\r\n\r\n23456");
+        out.clear();
+        response.write_to(&mut out, false).unwrap();
+        assert_eq!(out, response.head_bytes());
 
-        assert_eq!(response_act, response_expect);
+        let past_end = response.with_file(FileBody { path: path.clone(), offset: 8, len: 5 });
+        assert_eq!(past_end.write_to(&mut Vec::new(), true).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(past_end.write_to(&mut out, true).unwrap_err().kind(), io::ErrorKind::NotFound);
     }
 
     #[test]
-    fn test_http_response_creation(){
-        let response = HttpResponse::new("200", None, Some("Hello World".to_string()));
-        let response_string = String::from(response);
-        let expect_response = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length:11\r\n\r\nHello World";
-        assert_eq!(response_string, expect_response);
+    fn test_http_response_parse() {
+        let buf = b"HTTP/1.1 201 Created\r\nLocation: /orders/1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n\x00\x01\x02\r\n0\r\n\r\n";
+        let (res, used) = HttpResponse::parse(buf).unwrap();
+        assert_eq!(used, buf.len());
+        assert_eq!(res.status(), StatusCode::Created);
+        assert_eq!(res.headers().get("location"), Some("/orders/1"));
+        assert_eq!(res.body(), b"\x00\x01\x02");
+
+        let (res, _) = HttpResponse::parse(b"HTTP/1.0 200 OK\r\n\r\nuntil close").unwrap();
+        assert_eq!((res.version(), res.body()), (Version::HTTP1_0, &b"until close"[..]));
+        assert_eq!(HttpResponse::parse(b"HTTP/1.1 20 OK\r\n\r\n"), Err(ParseError::InvalidStatusLine));
     }
-}
\ No newline at end of file
+}
@@ -1,2 +1,8 @@
+pub mod date;
+pub mod headers;
 pub mod httprequest;
-pub mod httpresponse;
\ No newline at end of file
+pub mod httpresponse;
+mod parse;
+
+pub use headers::Headers;
+pub use parse::{percent_decode, percent_encode, ParseError, MAX_BODY_BYTES, MAX_HEAD_BYTES};
@@ -0,0 +1,299 @@
+use std::{error::Error, fmt};
+
+use crate::headers::Headers;
+
+/// Most bytes accepted for a start line plus headers, and again for the
+/// trailers of a chunked body.
+pub const MAX_HEAD_BYTES: usize = 64 * 1024;
+/// Most bytes accepted for a decoded body.
+pub const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The buffer ends before the message does; read more and try again.
+    Incomplete,
+    InvalidRequestLine,
+    InvalidStatusLine,
+    InvalidMethod,
+    InvalidTarget,
+    UnsupportedVersion,
+    InvalidHeader,
+    InvalidContentLength,
+    InvalidChunk,
+    UnsupportedTransferEncoding,
+    /// A bad `%` escape, or one that doesn't decode to UTF-8.
+    InvalidEncoding,
+    HeadTooLarge,
+    BodyTooLarge,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ParseError::Incomplete => "incomplete message",
+            ParseError::InvalidRequestLine => "malformed request line",
+            ParseError::InvalidStatusLine => "malformed status line",
+            ParseError::InvalidMethod => "invalid method",
+            ParseError::InvalidTarget => "invalid request target",
+            ParseError::UnsupportedVersion => "unsupported HTTP version",
+            ParseError::InvalidHeader => "malformed header field",
+            ParseError::InvalidContentLength => "invalid Content-Length",
+            ParseError::InvalidChunk => "malformed chunked body",
+            ParseError::UnsupportedTransferEncoding => "unsupported Transfer-Encoding",
+            ParseError::InvalidEncoding => "invalid percent-encoding",
+            ParseError::HeadTooLarge => "header section too large",
+            ParseError::BodyTooLarge => "body too large",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Start line and header fields of a message.
+pub(crate) struct Head<'a> {
+    pub start_line: &'a str,
+    pub headers: Headers,
+    /// Bytes up to and including the empty line.
+    pub len: usize,
+}
+
+/// How the body of a message is delimited (RFC 9112 section 6.3).
+pub(crate) enum Framing {
+    Length(usize),
+    Chunked,
+    /// Responses only: the body runs until the connection closes.
+    UntilClose,
+}
+
+pub(crate) fn parse_head(buf: &[u8]) -> Result<Head<'_>, ParseError> {
+    // Empty lines before the start line are ignored.
+    let mut start = 0;
+    while let Some(n) = line_break(&buf[start..]) {
+        start += n;
+    }
+    let Some(end) = find_empty_line(&buf[start..]) else {
+        return Err(if buf.len() - start > MAX_HEAD_BYTES { ParseError::HeadTooLarge } else { ParseError::Incomplete });
+    };
+    if end > MAX_HEAD_BYTES {
+        return Err(ParseError::HeadTooLarge);
+    }
+    let head = std::str::from_utf8(&buf[start..start + end]).map_err(|_| ParseError::InvalidHeader)?;
+    let mut lines = lines(head);
+    let start_line = lines.next().unwrap_or_default();
+    let headers = parse_fields(lines)?;
+    Ok(Head { start_line, headers, len: start + end })
+}
+
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).take_while(|line| !line.is_empty())
+}
+
+fn parse_fields<'a>(lines: impl Iterator<Item = &'a str>) -> Result<Headers, ParseError> {
+    let mut headers = Headers::new();
+    for line in lines {
+        // Whitespace before the colon and folded lines are both rejected.
+        let (name, value) = line.split_once(':').ok_or(ParseError::InvalidHeader)?;
+        if !is_token(name) {
+            return Err(ParseError::InvalidHeader);
+        }
+        let value = value.trim_matches([' ', '\t']);
+        if value.chars().any(|c| c.is_ascii_control() && c != '\t') {
+            return Err(ParseError::InvalidHeader);
+        }
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+pub(crate) fn framing(headers: &Headers, otherwise: Framing) -> Result<Framing, ParseError> {
+    if headers.contains("transfer-encoding") {
+        // Both at once is how requests get smuggled past proxies.
+        if headers.contains("content-length") {
+            return Err(ParseError::InvalidContentLength);
+        }
+        let codings: Vec<_> = headers.get_list("transfer-encoding").collect();
+        return match codings[..] {
+            [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(Framing::Chunked),
+            _ => Err(ParseError::UnsupportedTransferEncoding),
+        };
+    }
+
+    let mut length = None;
+    for value in headers.get_all("content-length").flat_map(|v| v.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::InvalidContentLength);
+        }
+        let value: usize = value.parse().map_err(|_| ParseError::BodyTooLarge)?;
+        if length.is_some_and(|length| length != value) {
+            return Err(ParseError::InvalidContentLength);
+        }
+        length = Some(value);
+    }
+    match length {
+        Some(length) if length > MAX_BODY_BYTES => Err(ParseError::BodyTooLarge),
+        Some(length) => Ok(Framing::Length(length)),
+        None => Ok(otherwise),
+    }
+}
+
+/// Decode the body at the start of `buf`.  Returns it with the number of
+/// bytes it took up.
+pub(crate) fn parse_body(buf: &[u8], framing: Framing) -> Result<(Vec<u8>, usize), ParseError> {
+    match framing {
+        Framing::Length(n) if buf.len() < n => Err(ParseError::Incomplete),
+        Framing::Length(n) => Ok((buf[..n].to_vec(), n)),
+        Framing::UntilClose if buf.len() > MAX_BODY_BYTES => Err(ParseError::BodyTooLarge),
+        Framing::UntilClose => Ok((buf.to_vec(), buf.len())),
+        Framing::Chunked => parse_chunked(buf),
+    }
+}
+
+fn parse_chunked(buf: &[u8]) -> Result<(Vec<u8>, usize), ParseError> {
+    let mut body = Vec::new();
+    let mut pos = 0;
+    loop {
+        let Some(end) = buf[pos..].iter().position(|&b| b == b'\n') else {
+            // A size line is a few hex digits plus extensions.
+            return Err(if buf.len() - pos > 1024 { ParseError::InvalidChunk } else { ParseError::Incomplete });
+        };
+        let line = std::str::from_utf8(&buf[pos..pos + end]).map_err(|_| ParseError::InvalidChunk)?;
+        let size = line.trim_end_matches('\r').split(';').next().unwrap_or_default().trim_end_matches([' ', '\t']);
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseError::InvalidChunk);
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::BodyTooLarge)?;
+        pos += end + 1;
+
+        if size == 0 {
+            // Trailer fields are checked and dropped.
+            if let Some(n) = line_break(&buf[pos..]) {
+                return Ok((body, pos + n));
+            }
+            let Some(end) = find_empty_line(&buf[pos..]) else {
+                return Err(if buf.len() - pos > MAX_HEAD_BYTES { ParseError::HeadTooLarge } else { ParseError::Incomplete });
+            };
+            let trailers = std::str::from_utf8(&buf[pos..pos + end]).map_err(|_| ParseError::InvalidHeader)?;
+            parse_fields(lines(trailers))?;
+            return Ok((body, pos + end));
+        }
+
+        if size > MAX_BODY_BYTES - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+        let data = buf.get(pos..pos + size).ok_or(ParseError::Incomplete)?;
+        body.extend_from_slice(data);
+        pos += size;
+        match line_break(&buf[pos..]) {
+            Some(n) => pos += n,
+            None if b"\r\n".starts_with(&buf[pos..]) => return Err(ParseError::Incomplete),
+            None => return Err(ParseError::InvalidChunk),
+        }
+    }
+}
+
+/// Length of the line break at the start of `buf`, if there is one.
+fn line_break(buf: &[u8]) -> Option<usize> {
+    match buf {
+        [b'\r', b'\n', ..] => Some(2),
+        [b'\n', ..] => Some(1),
+        _ => None,
+    }
+}
+
+/// Length of `buf` up to and including the first empty line.
+fn find_empty_line(buf: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    while let Some(n) = buf[start..].iter().position(|&b| b == b'\n') {
+        let line = &buf[start..start + n];
+        if line.is_empty() || line == b"\r" {
+            return Some(start + n + 1);
+        }
+        start += n + 1;
+    }
+    None
+}
+
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Decode `%XX` escapes.
+pub fn percent_decode(s: &str) -> Result<String, ParseError> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            out.push(b);
+            continue;
+        }
+        let hex = |b: Option<u8>| b.and_then(|b| (b as char).to_digit(16)).ok_or(ParseError::InvalidEncoding);
+        let (hi, lo) = (hex(bytes.next())?, hex(bytes.next())?);
+        out.push((hi * 16 + lo) as u8);
+    }
+    String::from_utf8(out).map_err(|_| ParseError::InvalidEncoding)
+}
+
+/// Escape every byte of `s` except unreserved characters and those in `keep`.
+pub fn percent_encode(s: &str, keep: &[u8]) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) || keep.contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("/caf%C3%A9%20bar%2f").unwrap(), "/café bar/");
+        assert_eq!(percent_decode("100%"), Err(ParseError::InvalidEncoding));
+        assert_eq!(percent_decode("%zz"), Err(ParseError::InvalidEncoding));
+        assert_eq!(percent_decode("%FF"), Err(ParseError::InvalidEncoding));
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(percent_encode("/a b/é?", b"/"), "/a%20b/%C3%A9%3F");
+    }
+
+    #[test]
+    fn test_chunked_body() {
+        let buf = b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\nNEXT";
+        let (body, used) = parse_body(buf, Framing::Chunked).unwrap();
+        assert_eq!(body, b"Wikipedia");
+        assert_eq!(&buf[used..], b"NEXT");
+        for end in 0..used {
+            assert!(matches!(parse_body(&buf[..end], Framing::Chunked), Err(ParseError::Incomplete)), "{end}");
+        }
+        assert!(matches!(parse_body(b"4\r\nWikiXX", Framing::Chunked), Err(ParseError::InvalidChunk)));
+        assert!(matches!(parse_body(b"g\r\n", Framing::Chunked), Err(ParseError::InvalidChunk)));
+    }
+
+    #[test]
+    fn test_framing() {
+        let headers = |fields: &[(&str, &str)]| fields.iter().copied().collect::<Headers>();
+        let framing = |fields| framing(&headers(fields), Framing::Length(0)).map(|f| match f {
+            Framing::Length(n) => Some(n),
+            _ => None,
+        });
+        assert_eq!(framing(&[("Content-Length", "5")]), Ok(Some(5)));
+        assert_eq!(framing(&[("content-length", "5"), ("Content-Length", "5")]), Ok(Some(5)));
+        assert_eq!(framing(&[("Content-Length", "5, 6")]), Err(ParseError::InvalidContentLength));
+        assert_eq!(framing(&[("Content-Length", "+5")]), Err(ParseError::InvalidContentLength));
+        assert_eq!(framing(&[("Transfer-Encoding", "Chunked")]), Ok(None));
+        assert_eq!(framing(&[("Transfer-Encoding", "gzip, chunked")]), Err(ParseError::UnsupportedTransferEncoding));
+        assert_eq!(
+            framing(&[("Transfer-Encoding", "chunked"), ("Content-Length", "5")]),
+            Err(ParseError::InvalidContentLength)
+        );
+    }
+}
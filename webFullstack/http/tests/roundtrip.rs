@@ -0,0 +1,140 @@
+use http::httprequest::{HttpRequest, Method, Resource, Version};
+use http::httpresponse::{HttpResponse, StatusCode};
+use http::{percent_decode, percent_encode, Headers, ParseError};
+use proptest::prelude::*;
+
+fn method() -> impl Strategy<Value = Method> {
+    prop_oneof![
+        Just(Method::GET),
+        Just(Method::HEAD),
+        Just(Method::POST),
+        Just(Method::PUT),
+        Just(Method::DELETE),
+        Just(Method::OPTIONS),
+        Just(Method::TRACE),
+        Just(Method::PATCH),
+        "[A-Z][A-Z-]{0,10}".prop_map(|m| m.parse().unwrap()),
+    ]
+}
+
+fn version() -> impl Strategy<Value = Version> {
+    prop_oneof![Just(Version::HTTP1_0), Just(Version::HTTP1_1)]
+}
+
+/// Header fields other than the framing ones, which serialisation owns.
+fn headers() -> impl Strategy<Value = Headers> {
+    let name = "[A-Za-z0-9!#$%&'*+.^_`|~-]{1,20}"
+        .prop_filter("framing header", |n| !n.eq_ignore_ascii_case("content-length") && !n.eq_ignore_ascii_case("transfer-encoding"));
+    // Visible characters with inner spaces; no surrounding whitespace.
+    let value = "([!-~]([ -~]{0,30}[!-~])?)?";
+    prop::collection::vec((name, value), 0..8).prop_map(Headers::from_iter)
+}
+
+fn request() -> impl Strategy<Value = HttpRequest> {
+    let path = prop::collection::vec(any::<String>(), 0..4).prop_map(|segments| format!("/{}", segments.join("/")));
+    let query = prop::collection::vec((".+", ".*"), 0..4);
+    (method(), version(), path, query, headers(), prop::collection::vec(any::<u8>(), 0..200)).prop_map(
+        |(method, version, path, query, headers, body)| HttpRequest {
+            method,
+            version,
+            resource: Resource::Path(path),
+            query,
+            headers,
+            body,
+        },
+    )
+}
+
+fn without_length(mut headers: Headers) -> Headers {
+    headers.remove("content-length");
+    headers
+}
+
+/// Re-frame a serialised message's body as chunks of the given sizes.
+fn rechunk(wire: &[u8], body: &[u8], sizes: &[usize]) -> Vec<u8> {
+    let head_end = wire.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(wire[..head_end].to_vec()).unwrap();
+    let head: Vec<_> = head.split("\r\n").filter(|l| !l.starts_with("Content-Length:")).collect();
+    let mut out = format!("{}\r\nTransfer-Encoding: chunked\r\n\r\n", head.join("\r\n")).into_bytes();
+    let mut rest = body;
+    for &size in sizes.iter().chain(std::iter::once(&usize::MAX)) {
+        let (chunk, tail) = rest.split_at(size.min(rest.len()));
+        if !chunk.is_empty() {
+            out.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            out.extend_from_slice(chunk);
+            out.extend_from_slice(b"\r\n");
+        }
+        rest = tail;
+    }
+    out.extend_from_slice(b"0\r\n\r\n");
+    out
+}
+
+proptest! {
+    #[test]
+    fn percent_encoding_round_trips(s in any::<String>()) {
+        prop_assert_eq!(percent_decode(&percent_encode(&s, b"/")).unwrap(), s.clone());
+        prop_assert!(percent_encode(&s, b"").bytes().all(|b| b.is_ascii_graphic()));
+    }
+
+    #[test]
+    fn request_round_trips(request in request(), split in any::<prop::sample::Index>()) {
+        let wire = request.to_bytes();
+        let (parsed, used) = HttpRequest::parse(&wire).unwrap();
+        prop_assert_eq!(used, wire.len());
+        prop_assert_eq!(&parsed.method, &request.method);
+        prop_assert_eq!(parsed.version, request.version);
+        prop_assert_eq!(&parsed.resource, &request.resource);
+        prop_assert_eq!(&parsed.query, &request.query);
+        prop_assert_eq!(without_length(parsed.headers.clone()), request.headers.clone());
+        prop_assert_eq!(&parsed.body, &request.body);
+
+        // Any strict prefix is incomplete rather than wrong.
+        let end = split.index(wire.len());
+        prop_assert_eq!(HttpRequest::parse(&wire[..end]), Err(ParseError::Incomplete));
+    }
+
+    #[test]
+    fn chunked_request_matches_content_length(request in request(), sizes in prop::collection::vec(1usize..50, 0..6)) {
+        let chunked = rechunk(&request.to_bytes(), &request.body, &sizes);
+        let (parsed, used) = HttpRequest::parse(&chunked).unwrap();
+        prop_assert_eq!(used, chunked.len());
+        prop_assert_eq!(&parsed.body, &request.body);
+    }
+
+    #[test]
+    fn pipelined_requests_parse_in_order(requests in prop::collection::vec(request(), 1..4)) {
+        let wire: Vec<u8> = requests.iter().flat_map(HttpRequest::to_bytes).collect();
+        let mut rest = &wire[..];
+        for request in &requests {
+            let (parsed, used) = HttpRequest::parse(rest).unwrap();
+            prop_assert_eq!(&parsed.body, &request.body);
+            prop_assert_eq!(&parsed.resource, &request.resource);
+            rest = &rest[used..];
+        }
+        prop_assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn response_round_trips(
+        status in prop::sample::select(StatusCode::ALL),
+        headers in headers(),
+        body in prop::collection::vec(any::<u8>(), 0..200),
+    ) {
+        let mut response = HttpResponse::new(status).with_body(body.clone());
+        *response.headers_mut() = headers.clone();
+        let wire = response.to_bytes();
+        let (parsed, used) = HttpResponse::parse(&wire).unwrap();
+        prop_assert_eq!(used, wire.len());
+        prop_assert_eq!(parsed.status(), status);
+        prop_assert_eq!(without_length(parsed.headers().clone()), headers);
+        let expected: &[u8] = if status.allows_body() { &body } else { &[] };
+        prop_assert_eq!(parsed.body(), expected);
+    }
+
+    #[test]
+    fn parser_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..300)) {
+        let _ = HttpRequest::parse(&bytes);
+        let _ = HttpResponse::parse(&bytes);
+    }
+}
@@ -0,0 +1,392 @@
+use std::env;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http::date::{format_http_date, parse_http_date};
+use http::httprequest::{HttpRequest, Method, Resource};
+use http::httpresponse::{FileBody, HttpResponse, StatusCode};
+use http::percent_encode;
+
+use super::router::{Handler, Params};
+
+/// Files up to this size are read into memory, where middleware such as
+/// compression can see them; bigger ones are streamed.
+const IN_MEMORY_BYTES: u64 = 64 * 1024;
+
+/// Serves the files under a directory.  Paths can't leave it, whether by
+/// `..` or by symlink; a directory is served by its `index.html`, and a path
+/// without an extension may name an `.html` file, so `/health` serves
+/// `health.html`.  Responses carry `ETag` and `Last-Modified` for
+/// conditional requests and honour single byte ranges.
+pub struct StaticFiles {
+    root: PathBuf,
+    max_age: u32,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl AsRef<Path>) -> io::Result<StaticFiles> {
+        Ok(StaticFiles { root: root.as_ref().canonicalize()?, max_age: 0 })
+    }
+
+    /// The directory named by `PUBLIC_PATH`, or the one shipped in `public/`.
+    pub fn open_default() -> io::Result<StaticFiles> {
+        let default_path = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
+        StaticFiles::new(env::var("PUBLIC_PATH").unwrap_or(default_path))
+    }
+
+    /// How long clients may cache files without revalidating.  By default
+    /// they revalidate every time, which the `ETag` makes cheap.
+    pub fn max_age(mut self, secs: u32) -> Self {
+        self.max_age = secs;
+        self
+    }
+
+    /// The file `url_path` names, if it is under the root.
+    fn resolve(&self, url_path: &str) -> Option<(PathBuf, Metadata)> {
+        let mut path = self.root.clone();
+        for segment in url_path.split('/') {
+            // Segments were percent-decoded, so look at them as a path would.
+            match Path::new(segment).components().collect::<Vec<_>>()[..] {
+                [] | [Component::CurDir] => {}
+                [Component::Normal(name)] if !segment.contains('\\') => path.push(name),
+                _ => return None,
+            }
+        }
+        let candidates = [Some(path.clone()), (path.extension().is_none()).then(|| path.with_extension("html"))];
+        candidates.into_iter().flatten().find_map(|candidate| {
+            let path = candidate.canonicalize().ok()?;
+            let metadata = fs::metadata(&path).ok()?;
+            path.starts_with(&self.root).then_some((path, metadata))
+        })
+    }
+
+    fn not_found(&self) -> HttpResponse {
+        let page = fs::read(self.root.join("404.html")).unwrap_or_default();
+        HttpResponse::new(StatusCode::NotFound).with_header("Content-Type", "text/html; charset=utf-8").with_body(page)
+    }
+
+    fn serve_file(&self, req: &HttpRequest, path: PathBuf, metadata: &Metadata) -> HttpResponse {
+        let len = metadata.len();
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let etag = etag(len, modified);
+        let last_modified = format_http_date(modified);
+        let cache_control = match self.max_age {
+            0 => "no-cache".to_string(),
+            secs => format!("public, max-age={}", secs),
+        };
+        let validators = |response: HttpResponse| {
+            response
+                .with_header("ETag", etag.as_str())
+                .with_header("Last-Modified", last_modified.as_str())
+                .with_header("Cache-Control", cache_control.as_str())
+        };
+
+        if is_not_modified(req, &etag, modified) {
+            return validators(HttpResponse::new(StatusCode::NotModified));
+        }
+
+        let (status, offset, part_len) = match requested_range(req, &etag, modified, len) {
+            None => (StatusCode::Ok, 0, len),
+            Some(Ok((start, end))) => (StatusCode::PartialContent, start, end - start + 1),
+            Some(Err(())) => {
+                return HttpResponse::new(StatusCode::RangeNotSatisfiable)
+                    .with_header("Content-Range", format!("bytes */{}", len))
+                    .with_header("Accept-Ranges", "bytes");
+            }
+        };
+        let mut response = validators(HttpResponse::new(status))
+            .with_header("Content-Type", content_type(&path))
+            .with_header("Accept-Ranges", "bytes");
+        if status == StatusCode::PartialContent {
+            let range = format!("bytes {}-{}/{}", offset, offset + part_len - 1, len);
+            response = response.with_header("Content-Range", range);
+        }
+
+        if len <= IN_MEMORY_BYTES {
+            match fs::read(&path) {
+                Ok(bytes) if bytes.len() as u64 == len => {
+                    let part = bytes[offset as usize..(offset + part_len) as usize].to_vec();
+                    response.with_body(part)
+                }
+                // Changed since we looked; the client can try again.
+                Ok(_) => HttpResponse::text(StatusCode::ServiceUnavailable, "File changed while being read\n"),
+                Err(_) => self.not_found(),
+            }
+        } else {
+            response.with_file(FileBody { path, offset, len: part_len })
+        }
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, req: &HttpRequest, _params: &Params) -> HttpResponse {
+        if req.method != Method::GET && req.method != Method::HEAD {
+            return HttpResponse::text(StatusCode::MethodNotAllowed, format!("{} is not allowed here\n", req.method))
+                .with_header("Allow", "GET, HEAD");
+        }
+        let Resource::Path(url_path) = &req.resource else {
+            return self.not_found();
+        };
+        let Some((path, metadata)) = self.resolve(url_path) else {
+            return self.not_found();
+        };
+
+        if !metadata.is_dir() {
+            return self.serve_file(req, path, &metadata);
+        }
+        // Relative links in the index resolve against the directory only
+        // when its URL ends in a slash.
+        if !url_path.ends_with('/') {
+            let mut location = percent_encode(&format!("{}/", url_path), b"/");
+            if let Some(query) = req.target().split_once('?').map(|(_, query)| query.to_string()) {
+                location = format!("{}?{}", location, query);
+            }
+            return HttpResponse::new(StatusCode::MovedPermanently).with_header("Location", location);
+        }
+        match fs::metadata(path.join("index.html")) {
+            Ok(metadata) if metadata.is_file() => self.serve_file(req, path.join("index.html"), &metadata),
+            _ => self.not_found(),
+        }
+    }
+}
+
+/// A strong validator from the size and modification time, as most servers
+/// make them; cheap, and it changes whenever the file is rewritten.
+fn etag(len: u64, modified: SystemTime) -> String {
+    let nanos = modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    format!("\"{:x}-{:x}\"", len, nanos)
+}
+
+/// `If-None-Match` wins over `If-Modified-Since` when both are sent.
+fn is_not_modified(req: &HttpRequest, etag: &str, modified: SystemTime) -> bool {
+    if let Some(tags) = req.header("if-none-match") {
+        // Weak comparison: `W/"x"` matches `"x"`.
+        return tags.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    match req.header("if-modified-since").and_then(parse_http_date) {
+        Some(since) => truncate_to_secs(modified) <= since,
+        None => false,
+    }
+}
+
+/// The inclusive byte range asked for, if the request has a `Range` we
+/// honour: one range, in bytes, with any `If-Range` still matching.  Several
+/// ranges get the whole file, which is allowed and simpler than multipart.
+fn requested_range(req: &HttpRequest, etag: &str, modified: SystemTime, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = req.header("range")?.strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    if let Some(if_range) = req.header("if-range") {
+        let current = match parse_http_date(if_range) {
+            Some(date) => truncate_to_secs(modified) == date,
+            // Strong comparison only.
+            None => if_range == etag,
+        };
+        if !current {
+            return None;
+        }
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return None,
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return Some(Err(()));
+            }
+            (len.saturating_sub(suffix), len.checked_sub(1))
+        }
+        (start, "") => (start.parse().ok()?, len.checked_sub(1)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, Some(end.min(len.saturating_sub(1))))
+        }
+    };
+    match end {
+        Some(end) if start < len => Some(Ok((start, end))),
+        _ => Some(Err(())),
+    }
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    UNIX_EPOCH + std::time::Duration::from_secs(time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()))
+}
+
+/// Media type by extension; anything unknown is opaque bytes.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture {
+        dir: PathBuf,
+        files: StaticFiles,
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn fixture() -> Fixture {
+        let name = format!("httpserver-files-{}-{:?}", std::process::id(), std::thread::current().id());
+        let dir = env::temp_dir().join(name);
+        let public = dir.join("public");
+        fs::create_dir_all(public.join("docs/empty")).unwrap();
+        fs::write(public.join("index.html"), "<h1>home</h1>").unwrap();
+        fs::write(public.join("404.html"), "<h1>missing</h1>").unwrap();
+        fs::write(public.join("health.html"), "ok").unwrap();
+        fs::write(public.join("docs/index.html"), "docs").unwrap();
+        fs::write(public.join("logo.png"), [0x89, b'P', b'N', b'G', 0, 0xff]).unwrap();
+        fs::write(public.join("big.bin"), vec![7; IN_MEMORY_BYTES as usize + 1]).unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("secret.txt"), public.join("link.txt")).unwrap();
+        Fixture { files: StaticFiles::new(&public).unwrap(), dir }
+    }
+
+    fn get(files: &StaticFiles, path: &str, headers: &[(&str, &str)]) -> HttpResponse {
+        let mut req = HttpRequest::new(Method::GET, path);
+        for (name, value) in headers {
+            req.headers.append(*name, *value);
+        }
+        files.handle(&req, &Params::new())
+    }
+
+    #[test]
+    fn stays_inside_the_root() {
+        let fixture = fixture();
+        let files = &fixture.files;
+        for path in ["/../secret.txt", "/docs/../../secret.txt", "/..\\secret.txt", "/link.txt", "/nope"] {
+            let response = get(files, path, &[]);
+            assert_eq!(response.status(), StatusCode::NotFound, "{path}");
+            assert_eq!(response.body(), b"<h1>missing</h1>");
+        }
+        assert_eq!(get(files, "/docs/./../logo.png", &[]).status(), StatusCode::NotFound);
+        assert_eq!(get(files, "/./logo.png", &[]).status(), StatusCode::Ok);
+    }
+
+    #[test]
+    fn serves_files_indexes_and_html_without_extension() {
+        let fixture = fixture();
+        let files = &fixture.files;
+        let response = get(files, "/logo.png", &[]);
+        assert_eq!(response.body(), [0x89, b'P', b'N', b'G', 0, 0xff]);
+        assert_eq!(response.headers().get("content-type"), Some("image/png"));
+        assert_eq!(get(files, "/", &[]).body(), b"<h1>home</h1>");
+        assert_eq!(get(files, "/health", &[]).body(), b"ok");
+        assert_eq!(get(files, "/docs/", &[]).body(), b"docs");
+        assert_eq!(get(files, "/docs/empty/", &[]).status(), StatusCode::NotFound);
+
+        let mut req = HttpRequest::new(Method::GET, "/docs");
+        req.query.push(("v".to_string(), "1".to_string()));
+        let redirect = files.handle(&req, &Params::new());
+        assert_eq!(redirect.status(), StatusCode::MovedPermanently);
+        assert_eq!(redirect.headers().get("location"), Some("/docs/?v=1"));
+
+        let big = get(files, "/big.bin", &[]);
+        assert!(big.body().is_empty());
+        assert_eq!(big.content_length(), IN_MEMORY_BYTES + 1);
+        assert_eq!(big.headers().get("content-type"), Some("application/octet-stream"));
+
+        let post = files.handle(&HttpRequest::new(Method::POST, "/"), &Params::new());
+        assert_eq!(post.status(), StatusCode::MethodNotAllowed);
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let fixture = fixture();
+        let files = &fixture.files;
+        let response = get(files, "/logo.png", &[]);
+        let etag = response.headers().get("etag").unwrap();
+        let last_modified = response.headers().get("last-modified").unwrap();
+
+        let not_modified = get(files, "/logo.png", &[("If-None-Match", &format!("\"x\", W/{}", etag))]);
+        assert_eq!(not_modified.status(), StatusCode::NotModified);
+        assert_eq!(not_modified.headers().get("etag"), Some(etag));
+        assert_eq!(get(files, "/logo.png", &[("If-Modified-Since", last_modified)]).status(), StatusCode::NotModified);
+
+        // A matching date doesn't count when the tag doesn't match.
+        let headers = [("If-None-Match", "\"other\""), ("If-Modified-Since", last_modified)];
+        assert_eq!(get(files, "/logo.png", &headers).status(), StatusCode::Ok);
+        let headers = [("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")];
+        assert_eq!(get(files, "/logo.png", &headers).status(), StatusCode::Ok);
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let fixture = fixture();
+        let files = &fixture.files;
+        let etag = get(files, "/logo.png", &[]).headers().get("etag").unwrap().to_string();
+        let range = |spec: &str, if_range: Option<&str>| {
+            let mut headers = vec![("Range", spec)];
+            headers.extend(if_range.map(|tag| ("If-Range", tag)));
+            let response = get(files, "/logo.png", &headers);
+            (response.status(), response.headers().get("content-range").map(str::to_string), response.body().to_vec())
+        };
+
+        let partial = |range: &str, body: &[u8]| (StatusCode::PartialContent, Some(range.to_string()), body.to_vec());
+        assert_eq!(range("bytes=1-3", None), partial("bytes 1-3/6", b"PNG"));
+        assert_eq!(range("bytes=4-", None), partial("bytes 4-5/6", &[0, 0xff]));
+        assert_eq!(range("bytes=-2", None), partial("bytes 4-5/6", &[0, 0xff]));
+        assert_eq!(range("bytes=-10", None), partial("bytes 0-5/6", &[0x89, b'P', b'N', b'G', 0, 0xff]));
+        assert_eq!(range("bytes=5-100", Some(&etag)), partial("bytes 5-5/6", &[0xff]));
+
+        let unsatisfiable = (StatusCode::RangeNotSatisfiable, Some("bytes */6".to_string()), Vec::new());
+        assert_eq!(range("bytes=6-", None), unsatisfiable);
+        assert_eq!(range("bytes=-0", None), unsatisfiable);
+
+        let ignored = [("bytes=0-1,3-4", None), ("bytes=3-1", None), ("lines=1-2", None)];
+        for (spec, if_range) in ignored.into_iter().chain([("bytes=0-1", Some("\"old\""))]) {
+            assert_eq!(range(spec, if_range).0, StatusCode::Ok, "{spec}");
+        }
+
+        let big = get(files, "/big.bin", &[("Range", "bytes=10-19")]);
+        assert_eq!(big.file().map(|file| (file.offset, file.len)), Some((10, 10)));
+    }
+}
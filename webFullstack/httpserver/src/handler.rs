@@ -1,93 +1,271 @@
-use http::httprequest::{self, Resource};
-use http::{httprequest::HttpRequest, httpresponse::HttpResponse};
+use http::httprequest::HttpRequest;
+use http::httpresponse::{HttpResponse, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-pub trait Handler {
-    fn handle(req: &HttpRequest) -> HttpResponse;
-    fn load_file(file_path: &str) -> Option<String> {
-        let default_path = format!("{}/public",env!("CARGO_MANIFEST_DIR"));
-        let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-        let full_path = format!("{}/{}", public_path, file_path);
+use super::router::{Handler, Params};
 
-        let contents = fs::read_to_string(full_path);
-        contents.ok()
-    }
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderStatus {
+    pub id: i32,
+    pub date: String,
+    pub status: String,
 }
 
-pub struct WebServiceHandler;
-pub struct PageNotFoundHandler;
-pub struct StaticPageHandler;
-
-#[derive(Serialize, Deserialize)]  
-pub  struct OrderStatus {
-    pub id: i32,
-    pub date:String,
+/// An order as clients send it; the id comes from the URL or the store.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewOrder {
+    pub id: Option<i32>,
+    pub date: String,
     pub status: String,
 }
 
+/// The orders, kept in memory and written back to their JSON file after
+/// every change.
+pub struct OrderStore {
+    path: PathBuf,
+    orders: Mutex<Vec<OrderStatus>>,
+}
+
+impl OrderStore {
+    /// A missing file is an empty store.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<OrderStore> {
+        let path = path.into();
+        let orders = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(OrderStore { path, orders: Mutex::new(orders) })
+    }
+
+    /// The file named by `DATA_PATH`, or the one shipped in `data/`.
+    pub fn open_default() -> io::Result<OrderStore> {
+        let default_path = format!("{}/data", env!("CARGO_MANIFEST_DIR"));
+        let data_path = env::var("DATA_PATH").unwrap_or(default_path);
+        OrderStore::open(Path::new(&data_path).join("orders.json"))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<OrderStatus>> {
+        // Every change is saved before the guard is released, so a panic
+        // while it is held leaves nothing half-written.
+        self.orders.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Write through a temporary file so a crash never leaves a torn one.
+    fn save(&self, orders: &[OrderStatus]) -> io::Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(orders)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    pub fn list(&self) -> Vec<OrderStatus> {
+        self.lock().clone()
+    }
 
-impl Handler for PageNotFoundHandler{
-    fn handle(req: &HttpRequest) -> HttpResponse {
-        HttpResponse::new("404", None, Self::load_file("404.html"))
+    pub fn get(&self, id: i32) -> Option<OrderStatus> {
+        self.lock().iter().find(|o| o.id == id).cloned()
+    }
+
+    /// `Ok(None)` if the id is taken.  Without an id the order gets one
+    /// past the highest.
+    pub fn create(&self, order: NewOrder) -> io::Result<Option<OrderStatus>> {
+        let mut orders = self.lock();
+        let id = order.id.unwrap_or_else(|| orders.iter().map(|o| o.id).max().unwrap_or(0) + 1);
+        if orders.iter().any(|o| o.id == id) {
+            return Ok(None);
+        }
+        let order = OrderStatus { id, date: order.date, status: order.status };
+        orders.push(order.clone());
+        if let Err(e) = self.save(&orders) {
+            orders.pop();
+            return Err(e);
+        }
+        Ok(Some(order))
+    }
+
+    /// `Ok(None)` if there is no such order.
+    pub fn update(&self, id: i32, order: NewOrder) -> io::Result<Option<OrderStatus>> {
+        let mut orders = self.lock();
+        let Some(i) = orders.iter().position(|o| o.id == id) else { return Ok(None) };
+        let updated = OrderStatus { id, date: order.date, status: order.status };
+        let previous = std::mem::replace(&mut orders[i], updated.clone());
+        if let Err(e) = self.save(&orders) {
+            orders[i] = previous;
+            return Err(e);
+        }
+        Ok(Some(updated))
+    }
+
+    /// Whether there was such an order.
+    pub fn delete(&self, id: i32) -> io::Result<bool> {
+        let mut orders = self.lock();
+        let Some(i) = orders.iter().position(|o| o.id == id) else { return Ok(false) };
+        let removed = orders.remove(i);
+        if let Err(e) = self.save(&orders) {
+            orders.insert(i, removed);
+            return Err(e);
+        }
+        Ok(true)
     }
 }
 
-impl Handler for StaticPageHandler{
-    fn handle(req: &HttpRequest) -> HttpResponse {
-        let Resource::Path(s) = &req.resource else{todo!()};
-        let route:Vec<&str> = s.split("/").collect();
-        match route[1]{
-            "" => HttpResponse::new("200", None, Self::load_file("index.html")),
-            "health" => HttpResponse::new("200", None, Self::load_file("health.html")),
-            path => match Self::load_file(path){
-                Some(content) => {
-                    let mut map: HashMap<&str, &str> = HashMap::new();
-                    if path.ends_with(".css") {
-                        map.insert("Content-Type", "text/css");
-                    } else if path.ends_with(".js") {
-                        map.insert("Content-Type", "application/javascript");
-                    } else {
-                        map.insert("Content-Type", "text/html");
-                    }
-
-                    HttpResponse::new("200",Some(map),Some(content))
-                },
-                None => HttpResponse::new("404", None, Self::load_file("404.html")),
+/// The `/api/shipping/orders` resource.
+pub struct OrdersHandler;
+
+impl OrdersHandler {
+    pub fn list(store: Arc<OrderStore>) -> impl Handler {
+        move |_: &HttpRequest, _: &Params| json(StatusCode::Ok, &store.list())
+    }
+
+    pub fn get(store: Arc<OrderStore>) -> impl Handler {
+        move |_: &HttpRequest, params: &Params| match order_id(params) {
+            Ok(id) => store.get(id).map_or_else(|| not_found(id), |order| json(StatusCode::Ok, &order)),
+            Err(response) => response,
+        }
+    }
+
+    pub fn create(store: Arc<OrderStore>) -> impl Handler {
+        move |req: &HttpRequest, _: &Params| {
+            let order = match parse_order(req) {
+                Ok(order) => order,
+                Err(response) => return response,
+            };
+            match store.create(order) {
+                Ok(Some(order)) => json(StatusCode::Created, &order)
+                    .with_header("Location", format!("/api/shipping/orders/{}", order.id)),
+                Ok(None) => error(StatusCode::Conflict, "an order with that id already exists"),
+                Err(e) => storage_error(e),
             }
         }
     }
-}
 
-impl Handler for WebServiceHandler{
-    fn handle(req: &HttpRequest) -> HttpResponse {
-        let httprequest::Resource::Path(s) = &req.resource else{todo!()};
-        let route : Vec<&str> = s.split("/").collect();
-
-        match route[2]{
-            "shipping" if route.len() > 2 && route[3] == "orders" => {
-                let orders = Self::load_json();
-                let json = serde_json::to_string(&orders).unwrap();
-                let mut map: HashMap<&str, &str> = HashMap::new();
-                map.insert("Content-Type", "application/json");
-                HttpResponse::new("200", Some(map), Some(json))
-            },
-            _ => HttpResponse::new("404", None, Self::load_file("404.html")),
+    pub fn update(store: Arc<OrderStore>) -> impl Handler {
+        move |req: &HttpRequest, params: &Params| {
+            let (id, order) = match order_id(params).and_then(|id| Ok((id, parse_order(req)?))) {
+                Ok(parsed) => parsed,
+                Err(response) => return response,
+            };
+            if order.id.is_some_and(|body_id| body_id != id) {
+                return error(StatusCode::BadRequest, "the id in the body does not match the URL");
+            }
+            match store.update(id, order) {
+                Ok(Some(order)) => json(StatusCode::Ok, &order),
+                Ok(None) => not_found(id),
+                Err(e) => storage_error(e),
+            }
+        }
+    }
+
+    pub fn delete(store: Arc<OrderStore>) -> impl Handler {
+        move |_: &HttpRequest, params: &Params| {
+            let id = match order_id(params) {
+                Ok(id) => id,
+                Err(response) => return response,
+            };
+            match store.delete(id) {
+                Ok(true) => HttpResponse::new(StatusCode::NoContent),
+                Ok(false) => not_found(id),
+                Err(e) => storage_error(e),
+            }
         }
     }
 }
 
+fn json(status: StatusCode, value: &impl Serialize) -> HttpResponse {
+    match serde_json::to_string(value) {
+        Ok(body) => HttpResponse::new(status).with_header("Content-Type", "application/json").with_body(body),
+        Err(e) => storage_error(e.into()),
+    }
+}
 
-impl WebServiceHandler{
-    fn load_json() -> Vec<OrderStatus>{
-        let default_path = format!("{}/public",env!("CARGO_MANIFEST_DIR"));
-        let data_path = env::var("DATA_PATH").unwrap_or(default_path);
-        let full_path = format!("{}/{}", data_path, "orders.json");
-        let json_contents = fs::read_to_string(full_path).expect("Unable to read file");
-        let order : Vec<OrderStatus> = serde_json::from_str(&json_contents.as_str()).expect("Unable to parse json");
-        order
+fn error(status: StatusCode, message: &str) -> HttpResponse {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+fn not_found(id: i32) -> HttpResponse {
+    error(StatusCode::NotFound, &format!("no order {}", id))
+}
+
+fn storage_error(e: io::Error) -> HttpResponse {
+    eprintln!("order store: {}", e);
+    error(StatusCode::InternalServerError, "the orders could not be saved")
+}
+
+fn order_id(params: &Params) -> Result<i32, HttpResponse> {
+    let id = params.get("id").map(String::as_str).unwrap_or_default();
+    id.parse().map_err(|_| error(StatusCode::BadRequest, &format!("{:?} is not an order id", id)))
+}
+
+fn parse_order(req: &HttpRequest) -> Result<NewOrder, HttpResponse> {
+    let content_type = req.header("content-type").unwrap_or_default();
+    if !content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("application/json") {
+        return Err(error(StatusCode::UnsupportedMediaType, "expected application/json"));
+    }
+    serde_json::from_slice(&req.body).map_err(|e| error(StatusCode::UnprocessableContent, &e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> (OrderStore, PathBuf) {
+        let name = format!("httpserver-orders-{}-{:?}", std::process::id(), std::thread::current().id());
+        let dir = env::temp_dir().join(name);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("orders.json");
+        fs::write(&path, r#"[{"id": 1, "date": "2024-08-09", "status": "Shipped"}]"#).unwrap();
+        (OrderStore::open(&path).unwrap(), path)
+    }
+
+    fn new_order(id: Option<i32>, status: &str) -> NewOrder {
+        NewOrder { id, date: "2024-08-10".to_string(), status: status.to_string() }
+    }
+
+    #[test]
+    fn changes_are_saved() {
+        let (store, path) = store();
+        assert_eq!(store.create(new_order(None, "Processing")).unwrap().unwrap().id, 2);
+        assert!(store.create(new_order(Some(1), "Processing")).unwrap().is_none());
+        assert_eq!(store.update(2, new_order(None, "Shipped")).unwrap().unwrap().status, "Shipped");
+        assert!(store.update(9, new_order(None, "Shipped")).unwrap().is_none());
+        assert!(store.delete(1).unwrap());
+        assert!(!store.delete(1).unwrap());
+
+        let reopened = OrderStore::open(&path).unwrap();
+        assert_eq!(reopened.list(), store.list());
+        let expected = OrderStatus { id: 2, date: "2024-08-10".to_string(), status: "Shipped".to_string() };
+        assert_eq!(reopened.list(), [expected]);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let (store, path) = store();
+        let create = OrdersHandler::create(Arc::new(store));
+        let mut req = HttpRequest::new(http::httprequest::Method::POST, "/api/shipping/orders");
+        req.body = br#"{"date": "2024-08-10", "status": "New"}"#.to_vec();
+        assert_eq!(create.handle(&req, &Params::new()).status(), StatusCode::UnsupportedMediaType);
+
+        req.headers.insert("Content-Type", "application/json; charset=utf-8");
+        req.body = br#"{"date": "2024-08-10"}"#.to_vec();
+        assert_eq!(create.handle(&req, &Params::new()).status(), StatusCode::UnprocessableContent);
+
+        req.body = br#"{"date": "2024-08-10", "status": "New"}"#.to_vec();
+        let response = create.handle(&req, &Params::new());
+        assert_eq!(response.status(), StatusCode::Created);
+        assert_eq!(response.headers().get("location"), Some("/api/shipping/orders/2"));
+
+        let store = Arc::new(OrderStore::open(&path).unwrap());
+        let params = Params::from([("id".to_string(), "two".to_string())]);
+        assert_eq!(OrdersHandler::get(Arc::clone(&store)).handle(&req, &params).status(), StatusCode::BadRequest);
+        let params = Params::from([("id".to_string(), "2".to_string())]);
+        assert_eq!(OrdersHandler::get(store).handle(&req, &params).status(), StatusCode::Ok);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
     }
-    
-}
\ No newline at end of file
+}
@@ -1,9 +1,39 @@
+use std::sync::Arc;
+
+use files::StaticFiles;
+use handler::{OrderStore, OrdersHandler};
+use middleware::{App, Compression, Cors, Logger, RequestId};
+use router::Router;
 use server::Server;
 
-mod server;
+mod files;
 mod handler;
+mod middleware;
+mod pool;
 mod router;
+mod server;
 
 fn main() {
-    Server::new("127.0.0.1:3001").run();
+    let orders = Arc::new(OrderStore::open_default().expect("Unable to load orders"));
+    let mut files = StaticFiles::open_default().expect("Unable to open the public directory");
+    if let Some(secs) = std::env::var("STATIC_MAX_AGE").ok().and_then(|s| s.parse().ok()) {
+        files = files.max_age(secs);
+    }
+    let router = Router::new(files)
+        .get("/api/shipping/orders", OrdersHandler::list(Arc::clone(&orders)))
+        .post("/api/shipping/orders", OrdersHandler::create(Arc::clone(&orders)))
+        .get("/api/shipping/orders/:id", OrdersHandler::get(Arc::clone(&orders)))
+        .put("/api/shipping/orders/:id", OrdersHandler::update(Arc::clone(&orders)))
+        .delete("/api/shipping/orders/:id", OrdersHandler::delete(orders));
+
+    let cors = match std::env::var("CORS_ORIGINS") {
+        Ok(origins) => Cors::new(origins.split(',').map(str::trim).filter(|o| !o.is_empty())),
+        Err(_) => Cors::any(),
+    };
+    let app = App::new(router).wrap(RequestId::new()).wrap(Logger).wrap(cors).wrap(Compression::new());
+    let mut server = Server::new("127.0.0.1:3001", app);
+    if let Some(workers) = std::env::var("WORKERS").ok().and_then(|w| w.parse().ok()) {
+        server = server.workers(workers);
+    }
+    server.run();
 }
@@ -0,0 +1,308 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use flate2::{write::GzEncoder, Compression as Level};
+use http::httprequest::{HttpRequest, Method};
+use http::httpresponse::{HttpResponse, StatusCode};
+
+use super::router::Router;
+
+/// A layer around the router.  Each gets the request before the layers
+/// after it and may answer it itself or pass it on through `next`, and sees
+/// their response on the way back out.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse;
+}
+
+/// The rest of the chain after the current layer.
+pub struct Next<'a> {
+    layers: &'a [Box<dyn Middleware>],
+    router: &'a Router,
+}
+
+impl Next<'_> {
+    pub fn run(self, req: &mut HttpRequest) -> HttpResponse {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.handle(req, Next { layers, router: self.router }),
+            None => self.router.dispatch(req),
+        }
+    }
+}
+
+/// A router with its middleware, outermost first.
+pub struct App {
+    layers: Vec<Box<dyn Middleware>>,
+    router: Router,
+}
+
+impl App {
+    pub fn new(router: Router) -> Self {
+        App { layers: Vec::new(), router }
+    }
+
+    /// Add a layer inside those already added.
+    pub fn wrap(mut self, layer: impl Middleware + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    pub fn handle(&self, req: &mut HttpRequest) -> HttpResponse {
+        Next { layers: &self.layers, router: &self.router }.run(req)
+    }
+}
+
+/// Tags each request and its response with `X-Request-Id`, keeping the
+/// client's own when it sends a sensible one.
+pub struct RequestId {
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl RequestId {
+    pub const HEADER: &'static str = "X-Request-Id";
+
+    pub fn new() -> Self {
+        // Distinguishes restarts; the counter distinguishes requests.
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        RequestId { prefix: format!("{:x}", started), next: AtomicU64::new(1) }
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        let id = match req.header(Self::HEADER) {
+            Some(id) if id.len() <= 64 && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b)) => {
+                id.to_string()
+            }
+            _ => format!("{}-{}", self.prefix, self.next.fetch_add(1, Ordering::Relaxed)),
+        };
+        req.headers.insert(Self::HEADER, id.as_str());
+        next.run(req).with_header(Self::HEADER, id)
+    }
+}
+
+/// Prints a line per request once it has been answered.
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        let started = Instant::now();
+        let (method, target) = (req.method.clone(), req.target());
+        let response = next.run(req);
+        println!(
+            "{} {} {} {}B {:?} id={}",
+            method,
+            target,
+            response.status().code(),
+            response.content_length(),
+            started.elapsed(),
+            response.headers().get(RequestId::HEADER).unwrap_or("-"),
+        );
+        response
+    }
+}
+
+/// Cross-origin access for the listed origins, or for any with `Cors::any`.
+/// Answers preflight requests itself.
+pub struct Cors {
+    origins: Option<Vec<String>>,
+    max_age: u32,
+}
+
+impl Cors {
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(origins: I) -> Self {
+        Cors { origins: Some(origins.into_iter().map(Into::into).collect()), max_age: 600 }
+    }
+
+    pub fn any() -> Self {
+        Cors { origins: None, max_age: 600 }
+    }
+
+    fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        match &self.origins {
+            None => Some("*"),
+            Some(origins) => origins.iter().any(|o| o == origin).then_some(origin),
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        let Some(origin) = req.header("origin").map(str::to_string) else {
+            return next.run(req);
+        };
+        let allowed = self.allow_origin(&origin).map(str::to_string);
+
+        if req.method == Method::OPTIONS && req.header("access-control-request-method").is_some() {
+            let Some(allowed) = allowed else {
+                return HttpResponse::new(StatusCode::Forbidden).with_header("Vary", "Origin");
+            };
+            let mut response = HttpResponse::new(StatusCode::NoContent)
+                .with_header("Access-Control-Allow-Origin", allowed)
+                .with_header("Access-Control-Allow-Methods", "GET, HEAD, POST, PUT, DELETE, OPTIONS")
+                .with_header("Access-Control-Max-Age", self.max_age.to_string())
+                .with_header("Vary", "Origin");
+            if let Some(headers) = req.header("access-control-request-headers") {
+                response.headers_mut().insert("Access-Control-Allow-Headers", headers);
+            }
+            return response;
+        }
+
+        let mut response = next.run(req);
+        if let Some(allowed) = allowed {
+            response.headers_mut().insert("Access-Control-Allow-Origin", allowed);
+            response.headers_mut().insert("Access-Control-Expose-Headers", RequestId::HEADER);
+        }
+        if self.origins.is_some() {
+            response.headers_mut().append("Vary", "Origin");
+        }
+        response
+    }
+}
+
+/// Gzips text-like response bodies for clients that accept it.
+pub struct Compression {
+    min_size: usize,
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        // Below this the gzip framing costs more than it saves.
+        Compression { min_size: 256 }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new()
+    }
+}
+
+/// Whether `Accept-Encoding` lists gzip, or `*`, without `q=0`.
+fn accepts_gzip(req: &HttpRequest) -> bool {
+    req.headers.get_list("accept-encoding").any(|coding| {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let refused = parts.any(|p| matches!(p.strip_prefix("q="), Some(q) if q.parse::<f32>() == Ok(0.0)));
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("text/")
+        || ["application/json", "application/javascript", "application/xml", "image/svg+xml"].contains(&essence)
+}
+
+impl Middleware for Compression {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        let gzip = accepts_gzip(req);
+        let mut response = next.run(req);
+        if !response.headers().get("content-type").is_some_and(is_compressible) {
+            return response;
+        }
+        response.headers_mut().append("Vary", "Accept-Encoding");
+        // A range is of the uncompressed body, so partial responses stay as they are.
+        let encoded = ["content-encoding", "content-range"].iter().any(|name| response.headers().contains(name));
+        if !gzip || encoded || response.body().len() < self.min_size {
+            return response;
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+        let compressed = encoder.write_all(response.body()).and_then(|_| encoder.finish());
+        match compressed {
+            Ok(body) if body.len() < response.body().len() => {
+                // The bytes differ, so a strong validator would be wrong.
+                let headers = response.headers_mut();
+                if let Some(etag) = headers.get("etag").filter(|tag| tag.starts_with('"')) {
+                    headers.insert("ETag", format!("W/{}", etag));
+                }
+                headers.remove("accept-ranges");
+                response.with_header("Content-Encoding", "gzip").with_body(body)
+            }
+            _ => response,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::router::Params;
+
+    fn app() -> App {
+        let router = Router::new(|req: &HttpRequest, _: &Params| {
+            let id = req.header(RequestId::HEADER).unwrap_or_default();
+            HttpResponse::text(StatusCode::Ok, format!("{}\n", id).repeat(100))
+        });
+        App::new(router).wrap(RequestId::new()).wrap(Cors::new(["https://app.example"])).wrap(Compression::new())
+    }
+
+    #[test]
+    fn request_ids_reach_the_handler() {
+        let app = app();
+        let first = app.handle(&mut HttpRequest::new(Method::GET, "/"));
+        let second = app.handle(&mut HttpRequest::new(Method::GET, "/"));
+        let id = first.headers().get(RequestId::HEADER).unwrap();
+        assert_ne!(Some(id), second.headers().get(RequestId::HEADER));
+        assert!(first.body().starts_with(id.as_bytes()));
+
+        let mut req = HttpRequest::new(Method::GET, "/");
+        req.headers.insert("x-request-id", "client-1");
+        assert_eq!(app.handle(&mut req).headers().get(RequestId::HEADER), Some("client-1"));
+        req.headers.insert("x-request-id", "bad id\u{7f}");
+        assert_ne!(app.handle(&mut req).headers().get(RequestId::HEADER), Some("bad id\u{7f}"));
+    }
+
+    #[test]
+    fn cors_preflight_and_simple_requests() {
+        let app = app();
+        let mut preflight = HttpRequest::new(Method::OPTIONS, "/api/orders");
+        preflight.headers.insert("Origin", "https://app.example");
+        preflight.headers.insert("Access-Control-Request-Method", "PUT");
+        preflight.headers.insert("Access-Control-Request-Headers", "content-type");
+        let response = app.handle(&mut preflight);
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_eq!(response.headers().get("access-control-allow-origin"), Some("https://app.example"));
+        assert_eq!(response.headers().get("access-control-allow-headers"), Some("content-type"));
+
+        preflight.headers.insert("Origin", "https://evil.example");
+        assert_eq!(app.handle(&mut preflight).status(), StatusCode::Forbidden);
+
+        let mut req = HttpRequest::new(Method::GET, "/");
+        req.headers.insert("Origin", "https://evil.example");
+        let response = app.handle(&mut req);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert!(!response.headers().contains("access-control-allow-origin"));
+    }
+
+    #[test]
+    fn compresses_when_accepted() {
+        let app = app();
+        let mut req = HttpRequest::new(Method::GET, "/");
+        let plain = app.handle(&mut req);
+        assert!(!plain.headers().contains("content-encoding"));
+
+        req.headers.insert("Accept-Encoding", "br;q=1.0, gzip;q=0.5");
+        let response = app.handle(&mut req);
+        assert_eq!(response.headers().get("content-encoding"), Some("gzip"));
+        assert!(response.headers().get_list("vary").any(|v| v == "Accept-Encoding"));
+        let mut body = String::new();
+        GzDecoder::new(response.body()).read_to_string(&mut body).unwrap();
+        assert_eq!(body.len(), plain.body().len());
+
+        req.headers.insert("Accept-Encoding", "gzip;q=0, identity");
+        assert!(!app.handle(&mut req).headers().contains("content-encoding"));
+    }
+}
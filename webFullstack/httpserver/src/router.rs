@@ -1,36 +1,181 @@
-use std::{io::Write, net::TcpStream};
-use http::httprequest::*;
-use http::httpresponse::*;
-use super::handler::*;
+use std::collections::HashMap;
 
-pub struct Router;
+use http::httprequest::{HttpRequest, Method, Resource};
+use http::httpresponse::{HttpResponse, StatusCode};
 
-impl Router {
-    pub fn route(req: HttpRequest, stream: &mut impl Write){
-        match req.method{
-            Method::GET => match &req.resource {
-                Resource::Path(s) =>{
-                    let route:Vec<&str> = s.split("/").collect();
-                    match route[1] {
-                        "api" => {
-                            let resp: HttpResponse = WebServiceHandler::handle(&req);
-                            let _ = resp.send_response(stream);
-                        },
-                        _ => {
-                            let resp: HttpResponse = StaticPageHandler::handle(&req);
-                            let _ = resp.send_response(stream);
-                        }
-                    }
-                },
-                _ => {
-                    let resp: HttpResponse = PageNotFoundHandler::handle(&req);
-                    let _ = resp.send_response(stream);
+/// Path parameters captured by `:name` segments.
+pub type Params = HashMap<String, String>;
+
+pub trait Handler: Send + Sync {
+    fn handle(&self, req: &HttpRequest, params: &Params) -> HttpResponse;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&HttpRequest, &Params) -> HttpResponse + Send + Sync,
+{
+    fn handle(&self, req: &HttpRequest, params: &Params) -> HttpResponse {
+        self(req, params)
+    }
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+struct Route {
+    method: Method,
+    pattern: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+impl Route {
+    fn matches(&self, segments: &[&str]) -> Option<Params> {
+        if self.pattern.len() != segments.len() {
+            return None;
+        }
+        let mut params = Params::new();
+        for (pattern, segment) in self.pattern.iter().zip(segments) {
+            match pattern {
+                Segment::Literal(literal) if literal == segment => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), segment.to_string());
                 }
             }
-            _ => {
-                let resp: HttpResponse = PageNotFoundHandler::handle(&req);
-                let _ = resp.send_response(stream);
+        }
+        Some(params)
+    }
+}
+
+/// Dispatches requests on method and path.  Patterns are paths whose
+/// `:name` segments match any one segment, as in `/api/orders/:id`; routes
+/// are tried in the order they were added.  A path that matches a route
+/// under another method gets `405 Method Not Allowed`; any other goes to the
+/// fallback.
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Box<dyn Handler>,
+}
+
+impl Router {
+    pub fn new(fallback: impl Handler + 'static) -> Self {
+        Router { routes: Vec::new(), fallback: Box::new(fallback) }
+    }
+
+    pub fn route(mut self, method: Method, pattern: &str, handler: impl Handler + 'static) -> Self {
+        let pattern = segments(pattern)
+            .map(|s| match s.strip_prefix(':') {
+                Some(name) => Segment::Param(name.to_string()),
+                None => Segment::Literal(s.to_string()),
+            })
+            .collect();
+        self.routes.push(Route { method, pattern, handler: Box::new(handler) });
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(Method::GET, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(Method::POST, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(Method::PUT, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /// `HEAD` is answered by the `GET` route when it has none of its own.
+    pub fn dispatch(&self, req: &HttpRequest) -> HttpResponse {
+        let Resource::Path(path) = &req.resource else {
+            return HttpResponse::text(StatusCode::BadRequest, "Expected a path\n");
+        };
+        let segments: Vec<&str> = segments(path).collect();
+
+        let mut allowed: Vec<&Method> = Vec::new();
+        let mut get = None;
+        for route in &self.routes {
+            let Some(params) = route.matches(&segments) else { continue };
+            if route.method == req.method {
+                return route.handler.handle(req, &params);
+            }
+            if route.method == Method::GET && req.method == Method::HEAD && get.is_none() {
+                get = Some((route, params));
+            }
+            if !allowed.contains(&&route.method) {
+                allowed.push(&route.method);
+            }
+        }
+        if let Some((route, params)) = get {
+            return route.handler.handle(req, &params);
+        }
+        if allowed.is_empty() {
+            return self.fallback.handle(req, &Params::new());
+        }
+
+        if allowed.contains(&&Method::GET) {
+            allowed.push(&Method::HEAD);
+        }
+        let allow = allowed.iter().map(|m| m.as_str()).collect::<Vec<_>>().join(", ");
+        HttpResponse::text(StatusCode::MethodNotAllowed, format!("{} is not allowed here\n", req.method))
+            .with_header("Allow", allow)
+    }
+}
+
+/// Non-empty segments, so `/a//b/` and `/a/b` route alike.
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> Router {
+        let echo = |name: &'static str| {
+            move |_: &HttpRequest, params: &Params| {
+                let mut params: Vec<_> = params.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                params.sort();
+                HttpResponse::text(StatusCode::Ok, format!("{} {}", name, params.join(" ")))
             }
+        };
+        Router::new(|_: &HttpRequest, _: &Params| HttpResponse::new(StatusCode::NotFound))
+            .get("/api/orders", echo("list"))
+            .post("/api/orders", echo("create"))
+            .get("/api/orders/:id", echo("show"))
+            .delete("/api/orders/:id", echo("delete"))
+            .get("/api/:kind/:id/items", echo("items"))
+    }
+
+    fn body(response: &HttpResponse) -> &str {
+        std::str::from_utf8(response.body()).unwrap()
+    }
+
+    #[test]
+    fn matches_method_and_captures_params() {
+        let router = router();
+        assert_eq!(body(&router.dispatch(&HttpRequest::new(Method::GET, "/api/orders/"))), "list ");
+        assert_eq!(body(&router.dispatch(&HttpRequest::new(Method::POST, "/api/orders"))), "create ");
+        assert_eq!(body(&router.dispatch(&HttpRequest::new(Method::DELETE, "/api/orders/7"))), "delete id=7");
+        assert_eq!(body(&router.dispatch(&HttpRequest::new(Method::HEAD, "/api/orders/7"))), "show id=7");
+        assert_eq!(body(&router.dispatch(&HttpRequest::new(Method::GET, "/api/x/1/items"))), "items id=1 kind=x");
+    }
+
+    #[test]
+    fn unmatched_requests() {
+        let router = router();
+        for path in ["/", "/api", "/api/orders/7/extra"] {
+            assert_eq!(router.dispatch(&HttpRequest::new(Method::GET, path)).status(), StatusCode::NotFound, "{path}");
         }
+
+        let response = router.dispatch(&HttpRequest::new(Method::PUT, "/api/orders/7"));
+        assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(response.headers().get("allow"), Some("GET, DELETE, HEAD"));
     }
-}
\ No newline at end of file
+}
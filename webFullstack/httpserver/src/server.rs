@@ -1,31 +1,171 @@
-use std::{io::Read, net::TcpListener};
-
-use super::router::Router;
-use http::httprequest::HttpRequest;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
 
+use super::middleware::App;
+use super::pool::ThreadPool;
+use http::httprequest::{HttpRequest, Method, Version};
+use http::httpresponse::{HttpResponse, StatusCode};
+use http::ParseError;
 
 pub struct Server<'a> {
     socker_addr: &'a str,
+    app: Arc<App>,
+    workers: usize,
+    /// How long a kept-alive connection may wait for its next request.
+    idle_timeout: Duration,
 }
 
-impl<'a> Server<'a>{
-    pub fn new(socker_addr : &'a str) -> Self{
-        Server{
-            socker_addr
-        }
+impl<'a> Server<'a> {
+    pub fn new(socker_addr: &'a str, app: App) -> Self {
+        let workers = std::thread::available_parallelism().map_or(4, |n| n.get() * 2);
+        Server { socker_addr, app: Arc::new(app), workers, idle_timeout: Duration::from_secs(5) }
+    }
+
+    /// Connections served at once.  Each holds a worker for as long as it
+    /// stays open, so this is also the number of idle keep-alive clients
+    /// that can hold the rest up.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
     }
 
-    pub fn run(&self){
+    pub fn run(&self) {
         let conection_lisiner = TcpListener::bind(self.socker_addr).unwrap();
-        println!("Server is running on {}", self.socker_addr);
-
-        for steam in conection_lisiner.incoming(){
-            let mut stream = steam.unwrap();
-            println!("Connection established!");
-            let mut buffer = [0; 1024];
-            stream.read(&mut buffer).unwrap();
-            let req : HttpRequest = String::from_utf8(buffer.to_vec()).unwrap().into();
-            Router::route(req,&mut stream);
+        println!("Server is running on {} with {} workers", self.socker_addr, self.workers);
+        self.serve(conection_lisiner);
+    }
+
+    pub(crate) fn serve(&self, listener: TcpListener) {
+        let pool = ThreadPool::new(self.workers);
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("accept failed: {}", e);
+                    continue;
+                }
+            };
+            let app = Arc::clone(&self.app);
+            let idle_timeout = self.idle_timeout;
+            pool.execute(move || {
+                if let Err(e) = handle_connection(stream, &app, idle_timeout) {
+                    if !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) {
+                        eprintln!("connection failed: {}", e);
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Answer requests on `stream` until the client or an error closes it.
+fn handle_connection(mut stream: TcpStream, app: &App, idle_timeout: Duration) -> io::Result<()> {
+    stream.set_read_timeout(Some(idle_timeout))?;
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        // Read until the request is complete, however it is split; any
+        // pipelined requests after it stay in `buffer`.
+        let parsed = loop {
+            match HttpRequest::parse(&buffer) {
+                Err(ParseError::Incomplete) => {}
+                parsed => break parsed,
+            }
+            match stream.read(&mut chunk)? {
+                0 => return Ok(()),
+                n => buffer.extend_from_slice(&chunk[..n]),
+            }
+        };
+
+        let (mut req, used) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let response = HttpResponse::text(StatusCode::from(e), format!("{}\n", e));
+                return stream.write_all(&response.with_header("Connection", "close").to_bytes());
+            }
+        };
+        buffer.drain(..used);
+
+        let keep_alive = req.keep_alive();
+        let (head, version) = (req.method == Method::HEAD, req.version);
+        let mut response = app.handle(&mut req);
+        match (keep_alive, version) {
+            (false, Version::HTTP1_1) => response.headers_mut().insert("Connection", "close"),
+            (true, Version::HTTP1_0) => response.headers_mut().insert("Connection", "keep-alive"),
+            _ => {}
+        }
+        response.write_to(&mut stream, !head)?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{Params, Router};
+    use std::thread;
+
+    fn spawn() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let router = Router::new(|req: &HttpRequest, _: &Params| {
+                // Slow enough that serving connections one at a time would show.
+                thread::sleep(Duration::from_millis(200));
+                HttpResponse::text(StatusCode::Ok, req.target())
+            });
+            Server::new("unused", App::new(router)).workers(4).serve(listener);
+        });
+        addr
+    }
+
+    fn read_response(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> HttpResponse {
+        let mut chunk = [0; 1024];
+        loop {
+            match HttpResponse::parse(buffer) {
+                Ok((response, used)) => {
+                    buffer.drain(..used);
+                    return response;
+                }
+                Err(ParseError::Incomplete) => {}
+                Err(e) => panic!("{}", e),
+            }
+            let n = stream.read(&mut chunk).unwrap();
+            assert_ne!(n, 0, "connection closed early");
+            buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    #[test]
+    fn serves_connections_concurrently_with_keep_alive() {
+        let addr = spawn();
+        let started = std::time::Instant::now();
+        let clients: Vec<_> = (0..4)
+            .map(|i| {
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    let mut buffer = Vec::new();
+                    // Two pipelined requests, then one after the answers.
+                    write!(stream, "GET /{i}/a HTTP/1.1\r\n\r\nGET /{i}/b HTTP/1.1\r\n\r\n").unwrap();
+                    assert_eq!(read_response(&mut stream, &mut buffer).body(), format!("/{i}/a").as_bytes());
+                    assert_eq!(read_response(&mut stream, &mut buffer).body(), format!("/{i}/b").as_bytes());
+
+                    write!(stream, "HEAD /{i}/c HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+                    stream.read_to_end(&mut buffer).unwrap();
+                    let head = String::from_utf8(buffer).unwrap();
+                    assert!(head.contains("Content-Length: 4\r\n") && head.contains("Connection: close\r\n"), "{head}");
+                    assert!(head.ends_with("\r\n\r\n"), "{head}");
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
         }
+        // Three requests each at 200ms; sequential handling would take 2.4s.
+        assert!(started.elapsed() < Duration::from_millis(1500), "{:?}", started.elapsed());
     }
 }
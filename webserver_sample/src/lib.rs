@@ -1,105 +1,510 @@
-use std::thread::{self};
-use std::sync::mpsc::{self};
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
 
-enum Message {
-    NewJob(Job),
-    Terminate
+/// A queued job.  Returns whether the closure it wraps panicked, in which
+/// case the worker that ran it is replaced.
+type Job = Box<dyn FnOnce() -> bool + Send + 'static>;
+
+/// What `execute` does when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectPolicy {
+    /// Hand the closure back in `Rejected`.
+    Reject,
+    /// Wait for room in the queue.
+    Block,
+    /// Run the closure on the calling thread, which slows the caller down
+    /// to the pool's pace.
+    CallerRuns,
+    /// Drop the job that has waited longest to make room.  Its handle
+    /// reports `JobError::Discarded`.
+    DiscardOldest,
 }
 
-struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+/// Configures a `ThreadPool`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    min_threads: usize,
+    max_threads: usize,
+    queue_capacity: usize,
+    keep_alive: Duration,
+    policy: RejectPolicy,
 }
 
-pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            min_threads: 1,
+            max_threads: 4,
+            queue_capacity: 64,
+            keep_alive: Duration::from_secs(30),
+            policy: RejectPolicy::Reject,
+        }
+    }
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    /// Threads kept even when idle.  May be zero.
+    pub fn min_threads(mut self, min_threads: usize) -> Builder {
+        self.min_threads = min_threads;
+        self
+    }
+
+    /// Threads the pool grows to while jobs are waiting.
+    pub fn max_threads(mut self, max_threads: usize) -> Builder {
+        self.max_threads = max_threads;
+        self
+    }
+
+    /// Jobs that may wait for a thread before `execute` applies the policy.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Builder {
+        self.queue_capacity = queue_capacity;
+        self
+    }
+
+    /// How long a thread above the minimum waits for work before exiting.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Builder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn reject_policy(mut self, policy: RejectPolicy) -> Builder {
+        self.policy = policy;
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If `max_threads` or `queue_capacity` is zero, or `min_threads` is
+    /// more than `max_threads`.
+    pub fn build(self) -> ThreadPool {
+        assert!(self.max_threads > 0, "a thread pool needs at least one thread");
+        assert!(self.min_threads <= self.max_threads, "min_threads is more than max_threads");
+        assert!(self.queue_capacity > 0, "a thread pool needs room to queue a job");
+
+        let shared = Arc::new(Shared {
+            config: self,
+            state: Mutex::new(State::default()),
+            work: Condvar::new(),
+            space: Condvar::new(),
+        });
+        let mut state = shared.lock();
+        for _ in 0..shared.config.min_threads {
+            spawn_worker(&shared, &mut state);
+        }
+        drop(state);
+        ThreadPool { shared }
+    }
+}
+
+/// A snapshot of what a pool is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metrics {
+    /// Threads alive, busy or not.
+    pub threads: usize,
+    /// Threads running a job.
+    pub active: usize,
+    /// Jobs waiting for a thread.
+    pub queued: usize,
+    pub completed: u64,
+    pub panicked: u64,
+    /// Jobs turned away or discarded because the queue was full.
+    pub rejected: u64,
 }
 
-//for executing closures
-trait FnBox {
-    fn call_box(self: Box<Self>);
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    active: usize,
+    shutdown: bool,
+    next_id: usize,
+    handles: Vec<thread::JoinHandle<()>>,
+    completed: u64,
+    panicked: u64,
+    rejected: u64,
 }
 
-//implement FnBox for closures, for all types that implement FnOnce
-impl<F: FnOnce()> FnBox for F {
-    fn call_box(self: Box<F>) {
-        (*self)()
+struct Shared {
+    config: Builder,
+    state: Mutex<State>,
+    /// Signalled when a job is queued or the pool shuts down.
+    work: Condvar,
+    /// Signalled when a job leaves the queue.
+    space: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // Jobs run with the lock released, so nothing panics holding it.
+        self.state.lock().unwrap()
     }
 }
 
-//struct Job;
-type Job = Box<dyn FnBox + Send + 'static>;
+/// A pool of worker threads with a bounded job queue.  It keeps
+/// `min_threads` alive, grows towards `max_threads` while jobs wait, and
+/// lets the extra threads go once they have sat idle for `keep_alive`.
+///
+/// Dropping the pool runs the jobs already queued, then joins every thread.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+}
 
 impl ThreadPool {
     /// Create a new ThreadPool.
-    /// 
-    /// The size is the number of threads in the pool.
-    /// 
+    ///
+    /// The size is the number of threads in the pool, which stays fixed.
+    /// The queue holds up to 64 jobs and rejects more.
+    ///
     /// # Panics
-    /// 
+    ///
     /// The `new` function will panic if the size is zero.
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
-        }
+        ThreadPool::builder().min_threads(size).max_threads(size).build()
+    }
 
-        ThreadPool { workers,sender } 
+    pub fn builder() -> Builder {
+        Builder::new()
     }
 
-    
-    pub fn execute<F>(&self, f: F)
+    /// Queue `f` to run on a worker.  If the queue is full, what happens
+    /// depends on the pool's `RejectPolicy`; only `Reject` gives `f` back.
+    pub fn execute<F, T>(&self, f: F) -> Result<JobHandle<T>, Rejected<F>>
     where
-        F: FnOnce() + Send + 'static
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender.send(Message::NewJob(job)).unwrap();
+        let mut state = self.shared.lock();
+        while state.queue.len() >= self.shared.config.queue_capacity {
+            match self.shared.config.policy {
+                RejectPolicy::Reject => {
+                    state.rejected += 1;
+                    return Err(Rejected(f));
+                }
+                RejectPolicy::Block => state = self.shared.space.wait(state).unwrap(),
+                RejectPolicy::CallerRuns => {
+                    drop(state);
+                    let (job, handle) = wrap(f);
+                    job();
+                    return Ok(handle);
+                }
+                RejectPolicy::DiscardOldest => {
+                    // Dropping the job drops its result sender, which is
+                    // what tells the handle.
+                    state.queue.pop_front();
+                    state.rejected += 1;
+                }
+            }
+        }
+
+        let (job, handle) = wrap(f);
+        state.queue.push_back(job);
+        if state.queue.len() > state.idle && state.threads < self.shared.config.max_threads {
+            spawn_worker(&self.shared, &mut state);
+        } else {
+            self.shared.work.notify_one();
+        }
+        Ok(handle)
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let state = self.shared.lock();
+        Metrics {
+            threads: state.threads,
+            active: state.active,
+            queued: state.queue.len(),
+            completed: state.completed,
+            panicked: state.panicked,
+            rejected: state.rejected,
+        }
     }
 }
 
-impl Drop for ThreadPool{
+impl Drop for ThreadPool {
     fn drop(&mut self) {
-        println!("Sending terminate message to all workers.");
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap();
-        }
         println!("Shutting down all workers.");
+        self.shared.lock().shutdown = true;
+        self.shared.work.notify_all();
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+        // Workers replacing ones that panicked while draining add handles
+        // of their own, so go round until there are none left.
+        loop {
+            let handles = std::mem::take(&mut self.shared.lock().handles);
+            if handles.is_empty() {
+                break;
+            }
+            for handle in handles {
+                let _ = handle.join();
             }
         }
     }
 }
 
-impl Worker {
-    fn new(id:usize, reciever :Arc<Mutex< mpsc::Receiver<Message>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = reciever.lock().unwrap().recv().unwrap();
-            match message {
-                Message::NewJob(job) => {
-                    println!("Worker {} got a job; executing.", id);
-                    job.call_box();
-                },
-                Message::Terminate => {
-                    println!("Worker {} was told to terminate.", id);
-                    break;
-                }
+/// Box `f` as a job that reports its result, or its panic, to the handle.
+fn wrap<F, T>(f: F) -> (Job, JobHandle<T>)
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    let job = Box::new(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let panicked = result.is_err();
+        // The handle may have been dropped; nobody wants the result then.
+        let _ = sender.send(result);
+        panicked
+    });
+    (job, JobHandle { result: receiver })
+}
+
+fn spawn_worker(shared: &Arc<Shared>, state: &mut State) {
+    let id = state.next_id;
+    state.next_id += 1;
+    state.handles.retain(|handle| !handle.is_finished());
+
+    let worker = Arc::clone(shared);
+    match thread::Builder::new().name(format!("worker-{}", id)).spawn(move || run_worker(id, &worker)) {
+        Ok(handle) => {
+            state.threads += 1;
+            state.handles.push(handle);
+        }
+        // The jobs wait for the threads there are.
+        Err(e) => println!("Failed to spawn worker {}: {}", id, e),
+    }
+}
+
+fn run_worker(id: usize, shared: &Arc<Shared>) {
+    let config = &shared.config;
+    let mut state = shared.lock();
+    loop {
+        let Some(job) = state.queue.pop_front() else {
+            if state.shutdown {
+                break;
             }
-        });
+            state.idle += 1;
+            let timed_out = if state.threads > config.min_threads {
+                let (guard, timeout) = shared.work.wait_timeout(state, config.keep_alive).unwrap();
+                state = guard;
+                timeout.timed_out()
+            } else {
+                state = shared.work.wait(state).unwrap();
+                false
+            };
+            state.idle -= 1;
+            if timed_out && state.queue.is_empty() && state.threads > config.min_threads {
+                break;
+            }
+            continue;
+        };
+
+        state.active += 1;
+        shared.space.notify_one();
+        drop(state);
+        println!("Worker {} got a job; executing.", id);
+        let panicked = job();
+        state = shared.lock();
+        state.active -= 1;
+
+        if !panicked {
+            state.completed += 1;
+            continue;
+        }
+        // The panic may have left this thread's thread-locals in a bad
+        // state, so a fresh thread takes over.
+        println!("Worker {} panicked; replacing it.", id);
+        state.panicked += 1;
+        if !state.shutdown || !state.queue.is_empty() {
+            spawn_worker(shared, &mut state);
+        }
+        break;
+    }
+    state.threads -= 1;
+}
+
+/// The eventual result of a job passed to `ThreadPool::execute`.
+pub struct JobHandle<T> {
+    result: mpsc::Receiver<thread::Result<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Wait for the job to finish.
+    pub fn join(self) -> Result<T, JobError> {
+        match self.result.recv() {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(payload)) => Err(JobError::Panicked(payload)),
+            Err(_) => Err(JobError::Discarded),
+        }
+    }
+}
+
+/// Why a job has no result.
+#[derive(Debug)]
+pub enum JobError {
+    /// The job panicked with this payload.
+    Panicked(Box<dyn Any + Send>),
+    /// The job was dropped from a full queue without running.
+    Discarded,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Panicked(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("non-string payload");
+                write!(f, "job panicked: {}", message)
+            }
+            JobError::Discarded => write!(f, "job was discarded from a full queue"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+/// A closure `execute` turned away, handed back to the caller.
+pub struct Rejected<F>(pub F);
 
-        Worker {
-            id,
-            thread: Some(thread),
+impl<F> fmt::Debug for Rejected<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Rejected(..)")
+    }
+}
+
+impl<F> fmt::Display for Rejected<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "thread pool queue is full")
+    }
+}
+
+impl<F> std::error::Error for Rejected<F> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use std::time::Instant;
+
+    /// Occupy `threads` workers until the returned barrier is waited on.
+    fn occupy(pool: &ThreadPool, threads: usize) -> Arc<Barrier> {
+        let release = Arc::new(Barrier::new(threads + 1));
+        let started = Arc::new(Barrier::new(threads + 1));
+        for _ in 0..threads {
+            let (release, started) = (Arc::clone(&release), Arc::clone(&started));
+            pool.execute(move || {
+                started.wait();
+                release.wait();
+            })
+            .unwrap();
+        }
+        started.wait();
+        release
+    }
+
+    fn wait_for(pool: &ThreadPool, done: impl Fn(&Metrics) -> bool) -> Metrics {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let metrics = pool.metrics();
+            if done(&metrics) {
+                return metrics;
+            }
+            assert!(Instant::now() < deadline, "{:?}", metrics);
+            thread::sleep(Duration::from_millis(5));
         }
     }
+
+    #[test]
+    fn handles_return_results_and_panics() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..10).map(|i| pool.execute(move || i * i).unwrap()).collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, (0..10).map(|i| i * i).collect::<Vec<_>>());
+
+        let error = pool.execute(|| panic!("boom")).unwrap().join().unwrap_err();
+        assert_eq!(error.to_string(), "job panicked: boom");
+        // The worker that panicked has been replaced.
+        let metrics = wait_for(&pool, |m| m.panicked == 1 && m.threads == 2);
+        assert_eq!(metrics.completed, 10);
+        assert_eq!(pool.execute(|| "still working").unwrap().join().unwrap(), "still working");
+    }
+
+    #[test]
+    fn full_queue_rejects_or_discards() {
+        let pool = ThreadPool::builder().max_threads(1).queue_capacity(2).build();
+        let release = occupy(&pool, 1);
+        let queued: Vec<_> = (0..2).map(|i| pool.execute(move || i).unwrap()).collect();
+        let Err(Rejected(job)) = pool.execute(|| 2) else { panic!("queue should be full") };
+        assert_eq!(job(), 2);
+        assert_eq!((pool.metrics().queued, pool.metrics().rejected), (2, 1));
+        release.wait();
+        assert_eq!(queued.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>(), [0, 1]);
+
+        let single = || ThreadPool::builder().max_threads(1).queue_capacity(1);
+        let pool = single().reject_policy(RejectPolicy::DiscardOldest).build();
+        let release = occupy(&pool, 1);
+        let oldest = pool.execute(|| 0).unwrap();
+        let newest = pool.execute(|| 1).unwrap();
+        release.wait();
+        assert!(matches!(oldest.join(), Err(JobError::Discarded)));
+        assert_eq!(newest.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn full_queue_blocks_or_runs_on_caller() {
+        let single = || ThreadPool::builder().max_threads(1).queue_capacity(1);
+        let pool = single().reject_policy(RejectPolicy::CallerRuns).build();
+        let release = occupy(&pool, 1);
+        pool.execute(|| ()).unwrap();
+        let caller = thread::current().id();
+        assert_eq!(pool.execute(move || thread::current().id()).unwrap().join().unwrap(), caller);
+        release.wait();
+
+        let pool = Arc::new(single().reject_policy(RejectPolicy::Block).build());
+        let release = occupy(&pool, 1);
+        pool.execute(|| ()).unwrap();
+        let blocked = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || pool.execute(|| "ran").unwrap().join().unwrap())
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!blocked.is_finished());
+        release.wait();
+        assert_eq!(blocked.join().unwrap(), "ran");
+    }
+
+    #[test]
+    fn grows_under_load_and_shrinks_when_idle() {
+        let pool = ThreadPool::builder().min_threads(1).max_threads(4).keep_alive(Duration::from_millis(50)).build();
+        assert_eq!(pool.metrics().threads, 1);
+        let release = occupy(&pool, 4);
+        assert_eq!((pool.metrics().threads, pool.metrics().active), (4, 4));
+        let queued = pool.execute(|| ()).unwrap();
+        assert_eq!(pool.metrics().threads, 4);
+        release.wait();
+        queued.join().unwrap();
+        wait_for(&pool, |m| m.threads == 1 && m.active == 0);
+    }
+
+    #[test]
+    fn drop_drains_the_queue() {
+        let pool = ThreadPool::builder().max_threads(2).queue_capacity(100).build();
+        let handles: Vec<_> = (0..50).map(|i| pool.execute(move || i).unwrap()).collect();
+        let panicky = pool.execute(|| panic!("while draining")).unwrap();
+        let after: Vec<_> = (50..60).map(|i| pool.execute(move || i).unwrap()).collect();
+        drop(pool);
+        assert!(handles.into_iter().chain(after).all(|h| h.join().is_ok()));
+        assert!(panicky.join().is_err());
+    }
 }
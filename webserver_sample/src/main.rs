@@ -1,16 +1,28 @@
 use std::net::{TcpListener, TcpStream};
 use std::io::prelude::*;
 use std::fs;
+use std::time::Duration;
 use webserver_sample::ThreadPool;
 
 fn main() {
     let listener = TcpListener::bind("127.0.0.1:8811").unwrap();
-    let pool = ThreadPool::new(4);
+    let pool = ThreadPool::builder()
+        .min_threads(2)
+        .max_threads(8)
+        .queue_capacity(16)
+        .keep_alive(Duration::from_secs(10))
+        .build();
     for stream in listener.incoming().take(2) {
         let stream = stream.unwrap();
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+        // Kept to answer with if the pool has no room for the connection.
+        let overflow = stream.try_clone();
+        if pool.execute(|| handle_connection(stream)).is_err() {
+            println!("Pool is full: {:?}", pool.metrics());
+            if let Ok(mut overflow) = overflow {
+                let busy = "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nContent-Length: 0\r\n\r\n";
+                let _ = overflow.write_all(busy.as_bytes());
+            }
+        }
     }
 
     println!("Shutting down.");
@@ -19,7 +31,8 @@ fn main() {
 fn handle_connection(mut stream: TcpStream) {
     let mut buffer = [0; 512];
     match stream.read(&mut buffer) {
-        Ok(_) => {
+        Ok(n) => {
+            let buffer = &buffer[..n];
             let get = b"GET / HTTP/1.1\r\n";
             let sleep = b"GET /sleep HTTP/1.1\r\n";
             let (status_line, filename) = if buffer.starts_with(get) {
@@ -38,7 +51,7 @@ fn handle_connection(mut stream: TcpStream) {
 
             let response = format!("{}Content-Length: {}\r\n\r\n{}", status_line, contents.len(), contents);
             //println!("Response: {}", response);
-            stream.write(response.as_bytes()).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
             stream.flush().unwrap();
         },
         Err(e) => {
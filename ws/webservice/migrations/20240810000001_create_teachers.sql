@@ -0,0 +1,6 @@
+CREATE TABLE teachers (
+    id          SERIAL PRIMARY KEY,
+    name        VARCHAR(100) NOT NULL,
+    picture_url VARCHAR(200),
+    profile     VARCHAR(2000)
+);
@@ -0,0 +1,15 @@
+CREATE TABLE courses (
+    id          SERIAL PRIMARY KEY,
+    teacher_id  INT NOT NULL REFERENCES teachers (id) ON DELETE CASCADE,
+    name        VARCHAR(140) NOT NULL,
+    time        TIMESTAMP NOT NULL DEFAULT now(),
+    description VARCHAR(2000),
+    format      VARCHAR(30),
+    structure   VARCHAR(200),
+    duration    VARCHAR(30),
+    price       REAL,
+    language    VARCHAR(30),
+    level       VARCHAR(30)
+);
+
+CREATE INDEX courses_teacher_id_idx ON courses (teacher_id);
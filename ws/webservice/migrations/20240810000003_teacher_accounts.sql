@@ -0,0 +1,17 @@
+-- Teachers created before accounts existed keep NULL credentials and can't log in.  Promote an
+-- account with: UPDATE teachers SET role = 'admin' WHERE email = '...';
+CREATE TYPE teacher_role AS ENUM ('teacher', 'admin');
+
+ALTER TABLE teachers
+    ADD COLUMN email         VARCHAR(254) UNIQUE,
+    ADD COLUMN password_hash VARCHAR(200),
+    ADD COLUMN role          teacher_role NOT NULL DEFAULT 'teacher';
+
+CREATE TABLE refresh_tokens (
+    jti         VARCHAR(64) PRIMARY KEY,
+    teacher_id  INT NOT NULL REFERENCES teachers (id) ON DELETE CASCADE,
+    expires_at  TIMESTAMP NOT NULL,
+    revoked_at  TIMESTAMP
+);
+
+CREATE INDEX refresh_tokens_teacher_id_idx ON refresh_tokens (teacher_id);
@@ -0,0 +1,215 @@
+use std::fmt;
+use std::future::{ready, Ready};
+use std::sync::OnceLock;
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{NaiveDateTime, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use crate::errors::MyError;
+use crate::models::auth::Role;
+use crate::state::AppState;
+
+const ACCESS_TTL_SECS: i64 = 15 * 60;
+const REFRESH_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind{
+    Access,
+    Refresh
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims{
+    pub sub: String,
+    pub role: Role,
+    pub typ: TokenKind,
+    pub jti: String,
+    pub iat: i64,
+    pub exp: i64
+}
+
+impl Claims{
+    pub fn teacher_id(&self) -> Result<i32, MyError>{
+        self.sub.parse().map_err(|_| MyError::UnauthorizedError("Invalid token".into()))
+    }
+
+    /// Expiry as a naive UTC timestamp, the way the database stores it.
+    pub fn expires_at(&self) -> NaiveDateTime{
+        chrono::DateTime::from_timestamp(self.exp, 0).unwrap_or_default().naive_utc()
+    }
+}
+
+/// HS256 signing keys for access and refresh tokens.
+#[derive(Clone)]
+pub struct JwtKeys{
+    encoding: EncodingKey,
+    decoding: DecodingKey
+}
+
+impl fmt::Debug for JwtKeys{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        f.write_str("JwtKeys(..)")
+    }
+}
+
+impl JwtKeys{
+    pub fn new(secret: &[u8]) -> Self{
+        JwtKeys{ encoding: EncodingKey::from_secret(secret), decoding: DecodingKey::from_secret(secret) }
+    }
+
+    pub fn access_ttl(&self) -> i64{
+        ACCESS_TTL_SECS
+    }
+
+    pub fn issue(&self, teacher_id: i32, role: Role, typ: TokenKind) -> Result<(String, Claims), MyError>{
+        let now = Utc::now().timestamp();
+        let ttl = match typ {
+            TokenKind::Access => ACCESS_TTL_SECS,
+            TokenKind::Refresh => REFRESH_TTL_SECS
+        };
+        let claims = Claims{ sub: teacher_id.to_string(), role, typ, jti: new_token_id(), iat: now, exp: now + ttl };
+        let token = encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)
+            .map_err(|e| MyError::ActixError(e.to_string()))?;
+        Ok((token, claims))
+    }
+
+    /// Checks signature, expiry and that the token is of the expected kind.
+    pub fn verify(&self, token: &str, typ: TokenKind) -> Result<Claims, MyError>{
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        let claims = decode::<Claims>(token, &self.decoding, &validation)
+            .map_err(|_| MyError::UnauthorizedError("Invalid or expired token".into()))?
+            .claims;
+        if claims.typ != typ {
+            return Err(MyError::UnauthorizedError("Wrong token type".into()));
+        }
+        Ok(claims)
+    }
+}
+
+fn new_token_id() -> String{
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn hash_password(password: &str) -> Result<String, MyError>{
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| MyError::ActixError(e.to_string()))
+}
+
+/// Accounts without a password never verify.  Still hashes once so a missing account takes as long as a wrong
+/// password.
+pub fn verify_password(password: &str, password_hash: Option<&str>) -> bool{
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    let (stored, exists) = match password_hash {
+        Some(hash) => (hash, true),
+        None => (DUMMY_HASH.get_or_init(|| hash_password("not a real password").unwrap()).as_str(), false)
+    };
+    let matches = PasswordHash::new(stored)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false);
+    matches && exists
+}
+
+/// The caller of a request, taken from its `Authorization: Bearer` access token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthUser{
+    pub teacher_id: i32,
+    pub role: Role
+}
+
+impl AuthUser{
+    pub fn ensure_admin(&self) -> Result<(), MyError>{
+        match self.role {
+            Role::Admin => Ok(()),
+            Role::Teacher => Err(MyError::ForbiddenError("Admin role required".into()))
+        }
+    }
+
+    /// Teachers may only change their own profile and courses; admins may change anyone's.
+    pub fn ensure_can_manage(&self, teacher_id: i32) -> Result<(), MyError>{
+        if self.role == Role::Admin || self.teacher_id == teacher_id {
+            Ok(())
+        } else {
+            Err(MyError::ForbiddenError("Not allowed to manage another teacher's data".into()))
+        }
+    }
+
+    fn from_request_head(req: &HttpRequest) -> Result<Self, MyError>{
+        let state = req.app_data::<web::Data<AppState>>()
+            .ok_or_else(|| MyError::ActixError("AppState is not registered".into()))?;
+        let token = req.headers().get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| MyError::UnauthorizedError("Missing bearer token".into()))?;
+        let claims = state.jwt.verify(token.trim(), TokenKind::Access)?;
+        Ok(AuthUser{ teacher_id: claims.teacher_id()?, role: claims.role })
+    }
+}
+
+impl FromRequest for AuthUser{
+    type Error = MyError;
+    type Future = Ready<Result<Self, MyError>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future{
+        ready(Self::from_request_head(req))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_tokens_round_trip_and_check_kind(){
+        let keys = JwtKeys::new(b"secret");
+        let (token, issued) = keys.issue(7, Role::Teacher, TokenKind::Access).unwrap();
+        let claims = keys.verify(&token, TokenKind::Access).unwrap();
+        assert_eq!((claims.teacher_id().unwrap(), claims.role, claims.jti), (7, Role::Teacher, issued.jti));
+        assert_eq!(claims.exp - claims.iat, keys.access_ttl());
+
+        let (refresh, _) = keys.issue(7, Role::Teacher, TokenKind::Refresh).unwrap();
+        assert!(keys.verify(&refresh, TokenKind::Access).is_err());
+        assert!(keys.verify(&token, TokenKind::Refresh).is_err());
+    }
+
+    #[test]
+    fn test_tampered_or_foreign_tokens_are_rejected(){
+        let keys = JwtKeys::new(b"secret");
+        let (token, _) = keys.issue(7, Role::Teacher, TokenKind::Access).unwrap();
+        assert!(JwtKeys::new(b"other").verify(&token, TokenKind::Access).is_err());
+
+        // Swap in a payload claiming the admin role; the signature no longer matches.
+        let (admin, _) = keys.issue(7, Role::Admin, TokenKind::Access).unwrap();
+        let parts: Vec<_> = token.split('.').collect();
+        let forged = format!("{}.{}.{}", parts[0], admin.split('.').nth(1).unwrap(), parts[2]);
+        assert!(keys.verify(&forged, TokenKind::Access).is_err());
+    }
+
+    #[test]
+    fn test_password_hashing(){
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", Some(&hash)));
+        assert!(!verify_password("wrong horse", Some(&hash)));
+        assert!(!verify_password("correct horse", None));
+    }
+
+    #[test]
+    fn test_authorization_rules(){
+        let teacher = AuthUser{ teacher_id: 1, role: Role::Teacher };
+        let admin = AuthUser{ teacher_id: 2, role: Role::Admin };
+        assert!(teacher.ensure_can_manage(1).is_ok());
+        assert!(teacher.ensure_can_manage(2).is_err());
+        assert!(teacher.ensure_admin().is_err());
+        assert!(admin.ensure_can_manage(1).is_ok());
+        assert!(admin.ensure_admin().is_ok());
+    }
+}
@@ -1,7 +1,10 @@
 use std::{io, sync::Mutex};
-use actix_web::{web,App,HttpResponse,HttpServer,Responder};
+use actix_web::{middleware,web,App,HttpServer};
+use dotenv::dotenv;
 use routers::*;
+use sqlx::postgres::PgPool;
 use state::AppState;
+use tracing_subscriber::EnvFilter;
 use std::env;
 
 
@@ -15,23 +18,51 @@ mod state;
 mod models;
 #[path = "../errors.rs"]
 mod errors;
+#[path = "../dbaccess/mod.rs"]
+mod dbaccess;
+#[path = "../openapi.rs"]
+mod openapi;
+#[path = "../auth.rs"]
+mod auth;
+#[path = "../observability.rs"]
+mod observability;
 
 
 #[actix_rt::main]
 async fn main() -> io::Result<()>{
+    dotenv().ok();
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set in .env");
+    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET is not set in .env");
+    let db_pool = PgPool::connect(&database_url).await.map_err(io::Error::other)?;
+    sqlx::migrate!("./migrations").run(&db_pool).await.map_err(io::Error::other)?;
+
     let shared_data = web::Data::new(AppState{
         health_check_response: "Actix WebService is running".to_string(),
         visit_count: Mutex::new(0),
-        courses: Mutex::new(vec![])
+        db: db_pool,
+        jwt: auth::JwtKeys::new(jwt_secret.as_bytes()),
+        metrics: Default::default()
     });
     let app = move || {
         App::new().app_data(shared_data.clone())
             .app_data(web::JsonConfig::default().error_handler(|err,_req| {
                 errors::MyError::InputInvalidError(err.to_string()).into()
             }))
+            .app_data(web::QueryConfig::default().error_handler(|err,_req| {
+                errors::MyError::InputInvalidError(err.to_string()).into()
+            }))
+            .app_data(web::PathConfig::default().error_handler(|err,_req| {
+                errors::MyError::InputInvalidError(err.to_string()).into()
+            }))
             .configure(general_routes)
+            .configure(auth_routes)
             .configure(course_routes)
+            .configure(teacher_routes)
+            .default_service(web::to(handlers::general::not_found_handler))
+            .wrap(middleware::from_fn(observability::observe))
     };
-    let _ = HttpServer::new(app).bind("127.0.0.1:3005")?.run().await;
-    Ok(())
-}
\ No newline at end of file
+    HttpServer::new(app).bind("127.0.0.1:3005")?.run().await
+}
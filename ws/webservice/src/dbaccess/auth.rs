@@ -0,0 +1,79 @@
+use chrono::NaiveDateTime;
+use crate::errors::MyError;
+use crate::models::auth::{Account, RegisterTeacher};
+use crate::models::teacher::Teacher;
+use sqlx::postgres::PgPool;
+
+pub async fn post_new_account_db(
+    pool: &PgPool,
+    new_teacher: RegisterTeacher,
+    password_hash: String
+) -> Result<Teacher, MyError>{
+    sqlx::query_as::<_, Teacher>(
+        "INSERT INTO teachers (name, email, password_hash, picture_url, profile)
+         VALUES ($1, lower($2), $3, $4, $5)
+         RETURNING id, name, picture_url, profile"
+    )
+    .bind(new_teacher.name)
+    .bind(new_teacher.email)
+    .bind(password_hash)
+    .bind(new_teacher.picture_url)
+    .bind(new_teacher.profile)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+            MyError::ValidationError([("email".into(), vec!["is already registered".into()])].into())
+        }
+        _ => e.into(),
+    })
+}
+
+pub async fn get_account_by_email_db(pool: &PgPool, email: &str) -> Result<Option<Account>, MyError>{
+    let account = sqlx::query_as::<_, Account>(
+        "SELECT id, password_hash, role FROM teachers WHERE email = lower($1)"
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await?;
+    Ok(account)
+}
+
+pub async fn get_account_db(pool: &PgPool, teacher_id: i32) -> Result<Option<Account>, MyError>{
+    let account = sqlx::query_as::<_, Account>("SELECT id, password_hash, role FROM teachers WHERE id = $1")
+        .bind(teacher_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(account)
+}
+
+pub async fn post_refresh_token_db(
+    pool: &PgPool,
+    jti: &str,
+    teacher_id: i32,
+    expires_at: NaiveDateTime
+) -> Result<(), MyError>{
+    sqlx::query("INSERT INTO refresh_tokens (jti, teacher_id, expires_at) VALUES ($1, $2, $3)")
+        .bind(jti)
+        .bind(teacher_id)
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Marks a live refresh token as used.  Returns false if it was unknown, expired or already revoked, so
+/// each token can be exchanged at most once.
+pub async fn revoke_refresh_token_db(pool: &PgPool, jti: &str) -> Result<bool, MyError>{
+    let result = sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = now() AT TIME ZONE 'UTC'
+         WHERE jti = $1 AND revoked_at IS NULL AND expires_at > now() AT TIME ZONE 'UTC'"
+    )
+    .bind(jti)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// SQLSTATE for an insert that collides with a unique constraint.
+const UNIQUE_VIOLATION: &str = "23505";
@@ -0,0 +1,125 @@
+use crate::errors::MyError;
+use crate::models::course::{Course, CoursePage, CourseQuery, CreateCourse, UpdateCourse};
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::{QueryBuilder, Row};
+
+pub async fn get_courses_for_teacher_db(
+    pool: &PgPool,
+    teacher_id: i32,
+    query: &CourseQuery
+) -> Result<CoursePage, MyError>{
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM courses");
+    push_course_filters(&mut count, teacher_id, query);
+    let total: i64 = count.build().fetch_one(pool).await?.get(0);
+
+    let (page, per_page) = (query.page(), query.per_page());
+    let sort = query.sort.unwrap_or_default().column();
+    let order = query.order.unwrap_or_default().keyword();
+    let mut select = QueryBuilder::new("SELECT * FROM courses");
+    push_course_filters(&mut select, teacher_id, query);
+    // Sort columns come from a fixed enum, never from the raw query string.  Ties fall back to id so pages
+    // don't overlap.
+    select.push(format!(" ORDER BY {sort} {order} NULLS LAST, id {order}"));
+    select.push(" LIMIT ").push_bind(i64::from(per_page));
+    select.push(" OFFSET ").push_bind((i64::from(page) - 1) * i64::from(per_page));
+    let items = select.build_query_as::<Course>().fetch_all(pool).await?;
+
+    Ok(CoursePage{ items, page, per_page, total })
+}
+
+fn push_course_filters(builder: &mut QueryBuilder<'_, Postgres>, teacher_id: i32, query: &CourseQuery){
+    builder.push(" WHERE teacher_id = ").push_bind(teacher_id);
+    if let Some(name) = &query.name {
+        builder.push(" AND strpos(lower(name), lower(").push_bind(name.clone()).push(")) > 0");
+    }
+    if let Some(language) = &query.language {
+        builder.push(" AND language = ").push_bind(language.clone());
+    }
+    if let Some(level) = &query.level {
+        builder.push(" AND level = ").push_bind(level.clone());
+    }
+}
+
+pub async fn get_course_details_db(pool: &PgPool, teacher_id: i32, course_id: i32) -> Result<Course, MyError>{
+    sqlx::query_as::<_, Course>("SELECT * FROM courses WHERE teacher_id = $1 AND id = $2")
+        .bind(teacher_id)
+        .bind(course_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| MyError::NotFoundError("Course id not found".into()))
+}
+
+pub async fn post_new_course_db(pool: &PgPool, new_course: CreateCourse) -> Result<Course, MyError>{
+    let course = sqlx::query_as::<_, Course>(
+        "INSERT INTO courses (teacher_id, name, description, format, structure, duration, price, language, level)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+         RETURNING *"
+    )
+    .bind(new_course.teacher_id)
+    .bind(new_course.name)
+    .bind(new_course.description)
+    .bind(new_course.format)
+    .bind(new_course.structure)
+    .bind(new_course.duration)
+    .bind(new_course.price)
+    .bind(new_course.language)
+    .bind(new_course.level)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => {
+            MyError::InputInvalidError("Teacher id not found".into())
+        }
+        _ => e.into(),
+    })?;
+    Ok(course)
+}
+
+pub async fn update_course_details_db(
+    pool: &PgPool,
+    teacher_id: i32,
+    course_id: i32,
+    update_course: UpdateCourse
+) -> Result<Course, MyError>{
+    sqlx::query_as::<_, Course>(
+        "UPDATE courses SET
+            name = COALESCE($3, name),
+            description = COALESCE($4, description),
+            format = COALESCE($5, format),
+            structure = COALESCE($6, structure),
+            duration = COALESCE($7, duration),
+            price = COALESCE($8, price),
+            language = COALESCE($9, language),
+            level = COALESCE($10, level)
+         WHERE teacher_id = $1 AND id = $2
+         RETURNING *"
+    )
+    .bind(teacher_id)
+    .bind(course_id)
+    .bind(update_course.name)
+    .bind(update_course.description)
+    .bind(update_course.format)
+    .bind(update_course.structure)
+    .bind(update_course.duration)
+    .bind(update_course.price)
+    .bind(update_course.language)
+    .bind(update_course.level)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| MyError::NotFoundError("Course id not found".into()))
+}
+
+pub async fn delete_course_db(pool: &PgPool, teacher_id: i32, course_id: i32) -> Result<(), MyError>{
+    let result = sqlx::query("DELETE FROM courses WHERE teacher_id = $1 AND id = $2")
+        .bind(teacher_id)
+        .bind(course_id)
+        .execute(pool)
+        .await?;
+    match result.rows_affected() {
+        0 => Err(MyError::NotFoundError("Course id not found".into())),
+        _ => Ok(())
+    }
+}
+
+/// SQLSTATE for an insert whose foreign key points at nothing.
+const FOREIGN_KEY_VIOLATION: &str = "23503";
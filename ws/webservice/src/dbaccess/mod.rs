@@ -0,0 +1,3 @@
+pub mod auth;
+pub mod course;
+pub mod teacher;
\ No newline at end of file
@@ -0,0 +1,65 @@
+use crate::errors::MyError;
+use crate::models::teacher::{CreateTeacher, Teacher, UpdateTeacher};
+use sqlx::postgres::PgPool;
+
+pub async fn get_all_teachers_db(pool: &PgPool) -> Result<Vec<Teacher>, MyError>{
+    let teachers = sqlx::query_as::<_, Teacher>("SELECT id, name, picture_url, profile FROM teachers ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    Ok(teachers)
+}
+
+pub async fn get_teacher_details_db(pool: &PgPool, teacher_id: i32) -> Result<Teacher, MyError>{
+    sqlx::query_as::<_, Teacher>("SELECT id, name, picture_url, profile FROM teachers WHERE id = $1")
+        .bind(teacher_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| MyError::NotFoundError("Teacher id not found".into()))
+}
+
+pub async fn post_new_teacher_db(pool: &PgPool, new_teacher: CreateTeacher) -> Result<Teacher, MyError>{
+    let teacher = sqlx::query_as::<_, Teacher>(
+        "INSERT INTO teachers (name, picture_url, profile) VALUES ($1, $2, $3)
+         RETURNING id, name, picture_url, profile"
+    )
+    .bind(new_teacher.name)
+    .bind(new_teacher.picture_url)
+    .bind(new_teacher.profile)
+    .fetch_one(pool)
+    .await?;
+    Ok(teacher)
+}
+
+pub async fn update_teacher_details_db(
+    pool: &PgPool,
+    teacher_id: i32,
+    update_teacher: UpdateTeacher
+) -> Result<Teacher, MyError>{
+    sqlx::query_as::<_, Teacher>(
+        "UPDATE teachers SET
+            name = COALESCE($2, name),
+            picture_url = COALESCE($3, picture_url),
+            profile = COALESCE($4, profile)
+         WHERE id = $1
+         RETURNING id, name, picture_url, profile"
+    )
+    .bind(teacher_id)
+    .bind(update_teacher.name)
+    .bind(update_teacher.picture_url)
+    .bind(update_teacher.profile)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| MyError::NotFoundError("Teacher id not found".into()))
+}
+
+/// Deletes the teacher's courses with them.
+pub async fn delete_teacher_db(pool: &PgPool, teacher_id: i32) -> Result<(), MyError>{
+    let result = sqlx::query("DELETE FROM teachers WHERE id = $1")
+        .bind(teacher_id)
+        .execute(pool)
+        .await?;
+    match result.rows_affected() {
+        0 => Err(MyError::NotFoundError("Teacher id not found".into())),
+        _ => Ok(())
+    }
+}
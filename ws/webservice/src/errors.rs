@@ -1,62 +1,122 @@
 use core::fmt;
-use actix_web::{error,http::StatusCode,HttpResponse};
-use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::PoisonError;
+use actix_web::{ResponseError,http::{header,StatusCode},HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug,Serialize)]
 pub enum MyError{
     DBError(String),
     ActixError(String),
     NotFoundError(String),
-    InputInvalidError(String)
+    InputInvalidError(String),
+    UnauthorizedError(String),
+    ForbiddenError(String),
+    /// Messages per offending request field.
+    ValidationError(BTreeMap<String,Vec<String>>)
 }
 
-#[derive(Debug,Serialize)]
-pub struct MyErrorResponder{
-    pub error_message: String
+/// RFC 7807 body sent for every error, as `application/problem+json`.
+#[derive(Debug,Serialize,Deserialize,ToSchema)]
+pub struct ProblemDetails{
+    /// Always `about:blank`; `code` tells problems apart.
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// Reason phrase of `status`.
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Stable, machine-readable error code.
+    pub code: String,
+    /// Messages per offending request field, for `validation_failed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<BTreeMap<String,Vec<String>>>
 }
 
 impl MyError {
-    pub fn error_response(&self) -> String{
+    pub fn code(&self) -> &'static str{
         match self {
-            MyError::DBError(message) =>{
-                println!("DB Error: {:?}", message);
-                "Database error".into()
-            }
-            MyError::ActixError(message) =>{
-                println!("Actix Error: {:?}", message);
-                "internal server error".into()
-            }
-            MyError::NotFoundError(message) =>{
-                println!("Not Found Error: {:?}", message);
-                message.into()
-            }
-            MyError::InputInvalidError(message) =>{
-                println!("Input Invalid Error: {:?}", message);
-                message.into()
+            MyError::DBError(_) => "database_error",
+            MyError::ActixError(_) => "internal_error",
+            MyError::NotFoundError(_) => "not_found",
+            MyError::InputInvalidError(_) => "invalid_input",
+            MyError::UnauthorizedError(_) => "unauthorized",
+            MyError::ForbiddenError(_) => "forbidden",
+            MyError::ValidationError(_) => "validation_failed"
+        }
+    }
+
+    /// What the client gets to see.  Server-side failures are logged in full but only described generically.
+    pub fn detail(&self) -> String{
+        match self {
+            MyError::DBError(_) => "Database error".into(),
+            MyError::ActixError(_) => "Internal server error".into(),
+            MyError::NotFoundError(message)
+            | MyError::InputInvalidError(message)
+            | MyError::UnauthorizedError(message)
+            | MyError::ForbiddenError(message) => message.clone(),
+            MyError::ValidationError(_) => "Invalid input".into()
+        }
+    }
+
+    pub fn problem(&self) -> ProblemDetails{
+        let status = self.status_code();
+        ProblemDetails{
+            type_: "about:blank".into(),
+            title: status.canonical_reason().unwrap_or_default().into(),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code().into(),
+            errors: match self {
+                MyError::ValidationError(fields) => Some(fields.clone()),
+                _ => None
             }
         }
     }
 }
 
-impl error::ResponseError for MyError{
+impl ResponseError for MyError{
     fn status_code(&self) -> StatusCode {
         match self {
             MyError::DBError(_) | MyError::ActixError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            MyError::NotFoundError(_) => StatusCode::NOT_FOUND,    
-            MyError::InputInvalidError(_) => StatusCode::BAD_REQUEST
+            MyError::NotFoundError(_) => StatusCode::NOT_FOUND,
+            MyError::InputInvalidError(_) | MyError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            MyError::UnauthorizedError(_) => StatusCode::UNAUTHORIZED,
+            MyError::ForbiddenError(_) => StatusCode::FORBIDDEN
         }
     }
 
     fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
-        HttpResponse::build(self.status_code())
-            .json(MyErrorResponder{error_message: self.error_response()})
-            .into()
+        let status = self.status_code();
+        if status.is_server_error() {
+            tracing::error!(code = self.code(), error = %self, "request failed");
+        } else {
+            tracing::info!(code = self.code(), error = %self, "request rejected");
+        }
+        let mut response = HttpResponse::build(status);
+        response.content_type(PROBLEM_JSON);
+        if let MyError::UnauthorizedError(_) = self {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(self.problem())
     }
 }
 
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
 impl fmt::Display for MyError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f,"{}",self)
+        match self {
+            MyError::DBError(message) => write!(f, "DBError: {}", message),
+            MyError::ActixError(message) => write!(f, "ActixError: {}", message),
+            MyError::NotFoundError(message) => write!(f, "NotFoundError: {}", message),
+            MyError::InputInvalidError(message) => write!(f, "InputInvalidError: {}", message),
+            MyError::UnauthorizedError(message) => write!(f, "UnauthorizedError: {}", message),
+            MyError::ForbiddenError(message) => write!(f, "ForbiddenError: {}", message),
+            MyError::ValidationError(fields) => write!(f, "ValidationError: {:?}", fields),
+        }
     }
 }
 
@@ -65,3 +125,31 @@ impl From<actix_web::error::Error> for MyError{
         MyError::ActixError(error.to_string())
     }
 }
+
+impl From<sqlx::error::Error> for MyError{
+    fn from(error: sqlx::error::Error) -> Self{
+        MyError::DBError(error.to_string())
+    }
+}
+
+impl From<validator::ValidationErrors> for MyError{
+    fn from(errors: validator::ValidationErrors) -> Self{
+        let fields = errors.field_errors().into_iter()
+            .map(|(field, errors)| {
+                let messages = errors.iter()
+                    .map(|e| e.message.as_ref().map_or_else(|| e.code.to_string(), |m| m.to_string()))
+                    .collect();
+                (field.to_string(), messages)
+            })
+            .collect();
+        MyError::ValidationError(fields)
+    }
+}
+
+/// A panic while holding an `AppState` lock leaves it poisoned; fail that request with a 500 instead of
+/// panicking the worker again.
+impl<T> From<PoisonError<T>> for MyError{
+    fn from(error: PoisonError<T>) -> Self{
+        MyError::ActixError(error.to_string())
+    }
+}
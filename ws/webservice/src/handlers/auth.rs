@@ -0,0 +1,184 @@
+use crate::auth::{hash_password, verify_password, TokenKind};
+use crate::dbaccess::auth::*;
+use crate::errors::{MyError, ProblemDetails};
+use crate::models::auth::{Login, RefreshToken, RegisterTeacher, Role, TokenPair};
+use crate::state::AppState;
+use actix_web::{web,HttpResponse};
+use validator::Validate;
+
+
+async fn issue_tokens(app_state: &AppState, teacher_id: i32, role: Role) -> Result<TokenPair,MyError>{
+    let (access_token, _) = app_state.jwt.issue(teacher_id, role, TokenKind::Access)?;
+    let (refresh_token, claims) = app_state.jwt.issue(teacher_id, role, TokenKind::Refresh)?;
+    post_refresh_token_db(&app_state.db, &claims.jti, teacher_id, claims.expires_at()).await?;
+    Ok(TokenPair{
+        teacher_id,
+        role,
+        access_token,
+        refresh_token,
+        token_type: "Bearer".into(),
+        expires_in: app_state.jwt.access_ttl()
+    })
+}
+
+#[utoipa::path(
+    post, path = "/auth/register", tag = "auth",
+    request_body = RegisterTeacher,
+    responses(
+        (status = 200, description = "Account created and signed in", body = TokenPair),
+        (status = 400, description = "Invalid input or email already registered",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn register(
+    app_state : web::Data<AppState>,
+    new_teacher: web::Json<RegisterTeacher>
+) -> Result<HttpResponse,MyError>{
+    new_teacher.validate()?;
+    let new_teacher = new_teacher.into_inner();
+    let password_hash = hash_password(&new_teacher.password)?;
+    let teacher = post_new_account_db(&app_state.db, new_teacher, password_hash).await?;
+    let tokens = issue_tokens(&app_state, teacher.id, Role::Teacher).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[utoipa::path(
+    post, path = "/auth/login", tag = "auth",
+    request_body = Login,
+    responses(
+        (status = 200, description = "Signed in", body = TokenPair),
+        (status = 401, description = "Wrong email or password",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn login(
+    app_state : web::Data<AppState>,
+    login: web::Json<Login>
+) -> Result<HttpResponse,MyError>{
+    let account = get_account_by_email_db(&app_state.db, &login.email).await?;
+    let verified = verify_password(&login.password, account.as_ref().and_then(|a| a.password_hash.as_deref()));
+    match account {
+        Some(account) if verified => {
+            let tokens = issue_tokens(&app_state, account.id, account.role).await?;
+            Ok(HttpResponse::Ok().json(tokens))
+        }
+        _ => Err(MyError::UnauthorizedError("Wrong email or password".into()))
+    }
+}
+
+#[utoipa::path(
+    post, path = "/auth/refresh", tag = "auth",
+    request_body = RefreshToken,
+    responses(
+        (status = 200, description = "A fresh token pair; the old refresh token is spent", body = TokenPair),
+        (status = 401, description = "Refresh token invalid, expired or already used",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn refresh(
+    app_state : web::Data<AppState>,
+    token: web::Json<RefreshToken>
+) -> Result<HttpResponse,MyError>{
+    let claims = app_state.jwt.verify(&token.refresh_token, TokenKind::Refresh)?;
+    if !revoke_refresh_token_db(&app_state.db, &claims.jti).await? {
+        return Err(MyError::UnauthorizedError("Refresh token already used or revoked".into()));
+    }
+    // Re-read the role so promotions and demotions apply from the next refresh on.
+    let account = get_account_db(&app_state.db, claims.teacher_id()?).await?
+        .ok_or_else(|| MyError::UnauthorizedError("Account no longer exists".into()))?;
+    let tokens = issue_tokens(&app_state, account.id, account.role).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[utoipa::path(
+    post, path = "/auth/logout", tag = "auth",
+    request_body = RefreshToken,
+    responses(
+        (status = 200, description = "Refresh token revoked", body = String),
+        (status = 401, description = "Refresh token invalid or expired",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn logout(
+    app_state : web::Data<AppState>,
+    token: web::Json<RefreshToken>
+) -> Result<HttpResponse,MyError>{
+    let claims = app_state.jwt.verify(&token.refresh_token, TokenKind::Refresh)?;
+    revoke_refresh_token_db(&app_state.db, &claims.jti).await?;
+    Ok(HttpResponse::Ok().json("Logged out"))
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::auth::JwtKeys;
+    use crate::routers::*;
+    use crate::state::AppState;
+    use actix_web::{http::StatusCode, test, web, App};
+    use serde_json::{json, Value};
+    use sqlx::postgres::PgPool;
+    use std::sync::Mutex;
+
+    #[sqlx::test]
+    #[ignore = "needs DATABASE_URL pointing at Postgres; see ws/docker-compose.yml"]
+    async fn test_register_login_refresh_and_ownership(db: PgPool){
+        let app_state = web::Data::new(AppState{
+            health_check_response: "Actix WebService is running".to_string(),
+            visit_count: Mutex::new(0),
+            db,
+            jwt: JwtKeys::new(b"test secret"),
+            metrics: Default::default()
+        });
+        let app = test::init_service(
+            App::new().app_data(app_state.clone()).configure(auth_routes).configure(course_routes)
+        ).await;
+        let post = |uri: &str, body: Value| test::TestRequest::post().uri(uri).set_json(body);
+
+        let register = |email: &str| {
+            post("/auth/register", json!({"name": "Ada", "email": email, "password": "hunter22"}))
+        };
+        let ada: Value = test::call_and_read_body_json(&app, register("Ada@Example.com").to_request()).await;
+        let bob: Value = test::call_and_read_body_json(&app, register("bob@example.com").to_request()).await;
+        let resp = test::call_service(&app, register("ada@example.com").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let login = |password: &str| post("/auth/login", json!({"email": "ada@example.com", "password": password}));
+        let resp = test::call_service(&app, login("wrong password").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers().get("www-authenticate").unwrap(), "Bearer");
+        let tokens: Value = test::call_and_read_body_json(&app, login("hunter22").to_request()).await;
+        assert_eq!((&tokens["teacher_id"], &tokens["role"]), (&ada["teacher_id"], &json!("teacher")));
+
+        let course = json!({"teacher_id": ada["teacher_id"], "name": "Rust"});
+        let create = |token: &Value| post("/courses", course.clone())
+            .insert_header(("Authorization", format!("Bearer {}", token.as_str().unwrap())));
+        let resp = test::call_service(&app, post("/courses", course.clone()).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = test::call_service(&app, create(&bob["access_token"]).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = test::call_service(&app, create(&tokens["refresh_token"]).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = test::call_service(&app, create(&tokens["access_token"]).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // A refresh token can be exchanged once; replaying it fails.
+        let refresh = post("/auth/refresh", json!({"refresh_token": tokens["refresh_token"]}));
+        let renewed: Value = test::call_and_read_body_json(&app, refresh.to_request()).await;
+        assert_ne!(renewed["refresh_token"], tokens["refresh_token"]);
+        let replay = post("/auth/refresh", json!({"refresh_token": tokens["refresh_token"]}));
+        assert_eq!(test::call_service(&app, replay.to_request()).await.status(), StatusCode::UNAUTHORIZED);
+
+        let logout = post("/auth/logout", json!({"refresh_token": renewed["refresh_token"]}));
+        assert_eq!(test::call_service(&app, logout.to_request()).await.status(), StatusCode::OK);
+        let refresh = post("/auth/refresh", json!({"refresh_token": renewed["refresh_token"]}));
+        assert_eq!(test::call_service(&app, refresh.to_request()).await.status(), StatusCode::UNAUTHORIZED);
+
+        // Promoted accounts pick up the admin role on their next refresh and may manage anyone's courses.
+        sqlx::query("UPDATE teachers SET role = 'admin' WHERE email = 'bob@example.com'")
+            .execute(&app_state.db).await.unwrap();
+        let refresh = post("/auth/refresh", json!({"refresh_token": bob["refresh_token"]}));
+        let bob: Value = test::call_and_read_body_json(&app, refresh.to_request()).await;
+        assert_eq!(bob["role"], "admin");
+        let resp = test::call_service(&app, create(&bob["access_token"]).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
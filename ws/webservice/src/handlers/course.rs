@@ -1,154 +1,393 @@
-use crate::{errors::MyError, models::course::Course};
+use crate::auth::AuthUser;
+use crate::dbaccess::course::*;
+use crate::errors::{MyError, ProblemDetails};
+use crate::models::course::{Course, CoursePage, CourseQuery, CreateCourse, UpdateCourse};
 use crate::state::AppState;
 use actix_web::{web,HttpResponse};
-use chrono::Utc;
+use validator::Validate;
 
 
-pub async fn new_course_handler(
-    app_state : web::Data<AppState>, 
-    new_course: web::Json<Course>
+#[utoipa::path(
+    post, path = "/courses", tag = "courses",
+    request_body = CreateCourse,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Course created", body = Course),
+        (status = 400, description = "Invalid input or unknown teacher",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid access token",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Not this teacher's data",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn post_new_course(
+    app_state : web::Data<AppState>,
+    auth: AuthUser,
+    new_course: web::Json<CreateCourse>
 ) -> Result<HttpResponse,MyError>{
-    println!("Received course: {:?}", new_course);
-    let course_count = app_state.courses
-        .lock()
-        .unwrap()
-        .clone()
-        .into_iter()
-        .filter(|course| course.teacher_id == new_course.teacher_id)
-        .collect::<Vec<Course>>()
-        .len();
-    let new_course = Course{
-        teacher_id: new_course.teacher_id,
-        id: Some(course_count + 1),
-        name: new_course.name.clone(),
-        time: Some(Utc::now().naive_utc())
-    };
-    app_state.courses.lock().unwrap().push(new_course);
-    Ok(HttpResponse::Ok().json("Course created"))
+    auth.ensure_can_manage(new_course.teacher_id)?;
+    new_course.validate()?;
+    let course = post_new_course_db(&app_state.db, new_course.into()).await?;
+    Ok(HttpResponse::Ok().json(course))
 }
 
-pub async fn get_courses_for_teacher_handler(
+#[utoipa::path(
+    get, path = "/courses/{teacher_id}", tag = "courses",
+    params(("teacher_id" = i32, Path, description = "Teacher id"), CourseQuery),
+    responses(
+        (status = 200, description = "One page of the teacher's courses", body = CoursePage),
+        (status = 400, description = "Invalid query string",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn get_courses_for_teacher(
     app_state : web::Data<AppState>,
-    teacher_id: web::Path<usize>
+    teacher_id: web::Path<i32>,
+    query: web::Query<CourseQuery>
 ) -> Result<HttpResponse,MyError>{
-    let courses = app_state.courses
-        .lock()
-        .unwrap()
-        .clone()
-        .into_iter()
-        .filter(|course| course.teacher_id == *teacher_id)
-        .collect::<Vec<Course>>();
-
-    match courses.len() {
-        0 => Err(MyError::NotFoundError("couses not found".into())),
-        _ => Ok(HttpResponse::Ok().json(courses))
-    }
+    query.validate()?;
+    let courses = get_courses_for_teacher_db(&app_state.db, teacher_id.into_inner(), &query).await?;
+    Ok(HttpResponse::Ok().json(courses))
 }
 
-pub async fn new_course_detail_handler(
+#[utoipa::path(
+    get, path = "/courses/{teacher_id}/{course_id}", tag = "courses",
+    params(
+        ("teacher_id" = i32, Path, description = "Teacher id"),
+        ("course_id" = i32, Path, description = "Course id")
+    ),
+    responses(
+        (status = 200, description = "The course", body = Course),
+        (status = 404, description = "No such course for this teacher",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn get_course_details(
     app_state : web::Data<AppState>,
-    params: web::Path<(usize,usize)>
+    params: web::Path<(i32,i32)>
 ) -> Result<HttpResponse,MyError>{
-    let (teacher_id,course_id) = *params;
-    let selected_course = app_state.courses
-        .lock()
-        .unwrap()
-        .clone()
-        .into_iter()
-        .find(|c| c.teacher_id == teacher_id && c.id == Some(course_id))
-        .ok_or("Course not found");
-
-    match selected_course{
-        Ok(course) => Ok(HttpResponse::Ok().json(course)),
-        Err(e) => Err(MyError::NotFoundError(e.to_string()))
-    }
+    let (teacher_id,course_id) = params.into_inner();
+    let course = get_course_details_db(&app_state.db, teacher_id, course_id).await?;
+    Ok(HttpResponse::Ok().json(course))
+}
+
+#[utoipa::path(
+    put, path = "/courses/{teacher_id}/{course_id}", tag = "courses",
+    params(
+        ("teacher_id" = i32, Path, description = "Teacher id"),
+        ("course_id" = i32, Path, description = "Course id")
+    ),
+    request_body = UpdateCourse,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The updated course", body = Course),
+        (status = 400, description = "Invalid input", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such course for this teacher",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid access token",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Not this teacher's data",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn update_course_details(
+    app_state : web::Data<AppState>,
+    auth: AuthUser,
+    params: web::Path<(i32,i32)>,
+    update_course: web::Json<UpdateCourse>
+) -> Result<HttpResponse,MyError>{
+    let (teacher_id,course_id) = params.into_inner();
+    auth.ensure_can_manage(teacher_id)?;
+    update_course.validate()?;
+    let course = update_course_details_db(&app_state.db, teacher_id, course_id, update_course.into()).await?;
+    Ok(HttpResponse::Ok().json(course))
+}
+
+#[utoipa::path(
+    delete, path = "/courses/{teacher_id}/{course_id}", tag = "courses",
+    params(
+        ("teacher_id" = i32, Path, description = "Teacher id"),
+        ("course_id" = i32, Path, description = "Course id")
+    ),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Course deleted", body = String),
+        (status = 404, description = "No such course for this teacher",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid access token",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Not this teacher's data",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn delete_course(
+    app_state : web::Data<AppState>,
+    auth: AuthUser,
+    params: web::Path<(i32,i32)>
+) -> Result<HttpResponse,MyError>{
+    let (teacher_id,course_id) = params.into_inner();
+    auth.ensure_can_manage(teacher_id)?;
+    delete_course_db(&app_state.db, teacher_id, course_id).await?;
+    Ok(HttpResponse::Ok().json("Course deleted"))
 }
 
 #[cfg(test)]
 mod tests{
     use super::*;
     use actix_web::http::StatusCode;
+    use crate::auth::JwtKeys;
+    use crate::models::auth::Role;
     use actix_web::ResponseError;
     use actix_web::{body::to_bytes,web};
-    use crate::models::course::Course;
+    use crate::dbaccess::teacher::post_new_teacher_db;
     use crate::handlers::general::health_check_handler;
-    use crate::state::AppState;
+    use crate::models::teacher::CreateTeacher;
+    use sqlx::postgres::PgPool;
     use std::sync::Mutex;
 
-    #[actix_rt::test]
-    async fn test_health_check_handler(){
-        let app_state = web::Data::new(AppState{
+    fn app_state(db: PgPool) -> web::Data<AppState>{
+        web::Data::new(AppState{
             health_check_response: "Actix WebService is running".to_string(),
             visit_count: Mutex::new(0),
-            courses: Mutex::new(vec![])
-        });
+            db,
+            jwt: JwtKeys::new(b"test secret"),
+            metrics: Default::default()
+        })
+    }
+
+    fn as_teacher(teacher_id: i32) -> AuthUser{
+        AuthUser{ teacher_id, role: Role::Teacher }
+    }
+
+    async fn new_teacher(db: &PgPool) -> i32{
+        let teacher = CreateTeacher{ name: "Test Teacher".into(), picture_url: None, profile: None };
+        post_new_teacher_db(db, teacher).await.unwrap().id
+    }
+
+    fn new_course(teacher_id: i32) -> CreateCourse{
+        CreateCourse{
+            teacher_id,
+            name: "Test Course".to_string(),
+            description: Some("An introduction".into()),
+            format: None,
+            structure: None,
+            duration: None,
+            price: Some(99.0),
+            language: Some("English".into()),
+            level: Some("Beginner".into())
+        }
+    }
+
+    fn query(query_string: &str) -> web::Query<CourseQuery>{
+        web::Query::from_query(query_string).unwrap()
+    }
 
-        let resp = health_check_handler(app_state).await.unwrap();
+    async fn json<T: serde::de::DeserializeOwned>(resp: HttpResponse) -> T{
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_health_check_handler(){
+        // Never connects; the handler doesn't touch the database.
+        let db = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let resp = health_check_handler(app_state(db)).await.unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
         let body = to_bytes(resp.into_body()).await.unwrap();
         assert_eq!(body, r##""Actix WebService is running - Visitor Count: 0""##);
     }
 
     #[actix_rt::test]
-    async fn test_new_course_handler(){
-        let app_state = web::Data::new(AppState{
-            health_check_response: "Actix WebService is running".to_string(),
-            visit_count: Mutex::new(0),
-            courses: Mutex::new(vec![])
-        });
-        let new_course = Course{
-            teacher_id: 1,
-            id: None,
-            name: "Test Course".to_string(),
-            time: None
-        };
+    async fn test_poisoned_visit_count_is_a_500(){
+        let db = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let app_state = app_state(db);
+        let poisoner = app_state.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.visit_count.lock().unwrap();
+            panic!("poison the lock");
+        }).join();
+        let err = health_check_handler(app_state).await.err().unwrap();
+        let body: serde_json::Value = json(err.error_response()).await;
+        assert_eq!((body["status"].as_u64(), body["code"].as_str()), (Some(500), Some("internal_error")));
+        assert_eq!(body["detail"], "Internal server error");
+    }
 
-        let req = web::Json(new_course);
-        let resp = new_course_handler(app_state.clone(),req).await.unwrap();
-        let courses = app_state.courses.lock().unwrap().clone();
-        let expect_status = resp.status();
-        let body = to_bytes(resp.into_body()).await.unwrap();
-        assert_eq!(expect_status, StatusCode::OK);
-        assert_eq!(body, r##""Course created""##);
-        assert_eq!(courses.len(), 1);
-        assert_eq!(courses[0].teacher_id, 1);
-        assert_eq!(courses[0].name, "Test Course");
-        assert!(courses[0].id.is_some());
-        assert!(courses[0].time.is_some());
+    #[actix_rt::test]
+    async fn test_validation_reports_each_field(){
+        let db = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let course = CreateCourse{ name: String::new(), price: Some(-1.0), ..new_course(0) };
+        let err = post_new_course(app_state(db.clone()), as_teacher(0), web::Json(course)).await.err().unwrap();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = json(ResponseError::error_response(&err)).await;
+        assert_eq!(err.error_response().headers().get("content-type").unwrap(), "application/problem+json");
+        assert_eq!((body["status"].as_u64(), body["title"].as_str()), (Some(400), Some("Bad Request")));
+        assert_eq!(body["code"], "validation_failed");
+        assert_eq!(body["detail"], "Invalid input");
+        assert_eq!(body["errors"]["name"][0], "must be 1 to 140 characters");
+        assert_eq!(body["errors"]["price"][0], "must not be negative");
+        assert_eq!(body["errors"]["teacher_id"][0], "must be a positive id");
+
+        let resp = get_courses_for_teacher(app_state(db), web::Path::from(1), query("per_page=500")).await;
+        let err = resp.err().unwrap();
+        let body: serde_json::Value = json(ResponseError::error_response(&err)).await;
+        assert_eq!(body["errors"]["per_page"][0], "must be between 1 and 100");
+        assert!(web::Query::<CourseQuery>::from_query("sort=teacher_id").is_err());
     }
 
     #[actix_rt::test]
-    async fn test_get_courses_for_teacher_handler(){
-        let app_state = web::Data::new(AppState{
-            health_check_response: "Actix WebService is running".to_string(),
-            visit_count: Mutex::new(0),
-            courses: Mutex::new(vec![])
-        });
+    async fn test_authorization_is_checked_before_validation(){
+        use crate::handlers::teacher::{post_new_teacher, update_teacher_details};
+        use crate::models::teacher::UpdateTeacher;
 
-        let teacher_id = web::Path::from(1);
-        let resp = get_courses_for_teacher_handler(app_state, teacher_id).await;
-        //assert_eq!(resp.err().unwrap().to_string(), "NotFoundError: couses not found");
-        assert_eq!(resp.err().unwrap().status_code(), StatusCode::NOT_FOUND);
+        // Someone else's data is 403 whether or not the input is valid, so the reply never reveals which.
+        let db = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let stranger = as_teacher(2);
+        let course = CreateCourse{ name: String::new(), ..new_course(1) };
+        let resp = post_new_course(app_state(db.clone()), stranger, web::Json(course)).await;
+        assert_eq!(resp.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+
+        let update = UpdateCourse{ name: Some(String::new()), ..Default::default() };
+        let params = web::Path::from((1, 1));
+        let resp = update_course_details(app_state(db.clone()), stranger, params, web::Json(update)).await;
+        assert_eq!(resp.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+
+        let teacher = CreateTeacher{ name: String::new(), picture_url: None, profile: None };
+        let resp = post_new_teacher(app_state(db.clone()), stranger, web::Json(teacher)).await;
+        assert_eq!(resp.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+        let update = UpdateTeacher{ name: Some(String::new()), ..Default::default() };
+        let resp = update_teacher_details(app_state(db), stranger, web::Path::from(1), web::Json(update)).await;
+        assert_eq!(resp.err().unwrap().status_code(), StatusCode::FORBIDDEN);
     }
 
     #[actix_rt::test]
-    async fn test_new_course_detail_handler(){
-        let app_state = web::Data::new(AppState{
-            health_check_response: "Actix WebService is running".to_string(),
-            visit_count: Mutex::new(0),
-            courses: Mutex::new(vec![])
-        });
-        let new_course = Course{
-            teacher_id: 1,
-            id: None,
-            name: "Test Course".to_string(),
-            time: None
+    async fn test_openapi_lists_every_route(){
+        use crate::openapi::ApiDoc;
+        use utoipa::OpenApi;
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = doc["paths"].as_object().unwrap();
+        let mut routes: Vec<_> = paths.iter()
+            .flat_map(|(path, ops)| ops.as_object().unwrap().keys().map(move |method| format!("{method} {path}")))
+            .collect();
+        routes.sort();
+        assert_eq!(routes, [
+            "delete /courses/{teacher_id}/{course_id}",
+            "delete /teachers/{teacher_id}",
+            "get /courses/{teacher_id}",
+            "get /courses/{teacher_id}/{course_id}",
+            "get /teachers",
+            "get /teachers/{teacher_id}",
+            "post /auth/login",
+            "post /auth/logout",
+            "post /auth/refresh",
+            "post /auth/register",
+            "post /courses",
+            "post /teachers",
+            "put /courses/{teacher_id}/{course_id}",
+            "put /teachers/{teacher_id}"
+        ]);
+        assert!(doc["components"]["schemas"]["CoursePage"].is_object());
+        assert_eq!(doc["components"]["securitySchemes"]["bearer_auth"]["scheme"], "bearer");
+        assert_eq!(doc["paths"]["/courses"]["post"]["security"][0]["bearer_auth"], serde_json::json!([]));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs DATABASE_URL pointing at Postgres; see ws/docker-compose.yml"]
+    async fn test_list_pagination_sorting_and_filters(db: PgPool){
+        let app_state = app_state(db);
+        let teacher_id = new_teacher(&app_state.db).await;
+        let courses = [("Rust", 30.0, "Beginner"), ("Go", 10.0, "Advanced"), ("Rust II", 20.0, "Advanced")];
+        for (name, price, level) in courses {
+            let course = CreateCourse{
+                name: name.into(), price: Some(price), level: Some(level.into()), ..new_course(teacher_id)
+            };
+            post_new_course_db(&app_state.db, course).await.unwrap();
+        }
+        let list = |q: &'static str| {
+            let app_state = app_state.clone();
+            async move {
+                let resp = get_courses_for_teacher(app_state, web::Path::from(teacher_id), query(q)).await.unwrap();
+                let page: serde_json::Value = json(resp).await;
+                let names: Vec<String> = page["items"].as_array().unwrap().iter()
+                    .map(|c| c["name"].as_str().unwrap().to_string())
+                    .collect();
+                (names, page["total"].as_i64().unwrap())
+            }
         };
-        let req = web::Json(new_course);
-        let _ = new_course_handler(app_state.clone(),req).await;
 
-        let params = web::Path::from((1,1));
-        let resp = new_course_detail_handler(app_state, params).await.unwrap();
+        assert_eq!(list("sort=price").await, (vec!["Go".into(), "Rust II".into(), "Rust".into()], 3));
+        assert_eq!(list("sort=name&order=desc&per_page=2").await, (vec!["Rust II".into(), "Rust".into()], 3));
+        assert_eq!(list("sort=name&order=desc&per_page=2&page=2").await, (vec!["Go".into()], 3));
+        assert_eq!(list("page=3&per_page=2").await, (vec![], 3));
+        assert_eq!(list("name=rust&level=Advanced").await, (vec!["Rust II".into()], 1));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs DATABASE_URL pointing at Postgres; see ws/docker-compose.yml"]
+    async fn test_post_and_get_courses(db: PgPool){
+        let app_state = app_state(db);
+        let teacher_id = new_teacher(&app_state.db).await;
+
+        let course = web::Json(new_course(teacher_id));
+        let resp = post_new_course(app_state.clone(), as_teacher(teacher_id), course).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let course: serde_json::Value = json(resp).await;
+        assert_eq!(course["name"], "Test Course");
+        assert_eq!(course["price"], 99.0);
+        assert!(course["time"].is_string());
+        let course_id = course["id"].as_i64().unwrap() as i32;
+
+        let resp = get_courses_for_teacher(app_state.clone(), web::Path::from(teacher_id), query("")).await.unwrap();
+        let courses: serde_json::Value = json(resp).await;
+        assert_eq!((courses["items"].as_array().unwrap().len(), courses["total"].as_i64()), (1, Some(1)));
+
+        let resp = get_course_details(app_state.clone(), web::Path::from((teacher_id, course_id))).await.unwrap();
+        let course: serde_json::Value = json(resp).await;
+        assert_eq!(course["level"], "Beginner");
+
+        let missing = get_course_details(app_state, web::Path::from((teacher_id, course_id + 1))).await;
+        assert_eq!(missing.err().unwrap().status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs DATABASE_URL pointing at Postgres; see ws/docker-compose.yml"]
+    async fn test_course_for_missing_teacher(db: PgPool){
+        let app_state = app_state(db);
+        let admin = AuthUser{ teacher_id: 1, role: Role::Admin };
+        let resp = post_new_course(app_state.clone(), admin, web::Json(new_course(404))).await;
+        assert_eq!(resp.err().unwrap().status_code(), StatusCode::BAD_REQUEST);
+
+        let resp = get_courses_for_teacher(app_state, web::Path::from(404), query("")).await.unwrap();
+        let courses: serde_json::Value = json(resp).await;
+        assert_eq!(courses["total"], 0);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs DATABASE_URL pointing at Postgres; see ws/docker-compose.yml"]
+    async fn test_update_and_delete_course(db: PgPool){
+        let app_state = app_state(db);
+        let teacher_id = new_teacher(&app_state.db).await;
+        let course: Course = post_new_course_db(&app_state.db, new_course(teacher_id)).await.unwrap();
+        let params = || web::Path::from((teacher_id, course.id));
+
+        let update = UpdateCourse{ name: Some("Renamed".into()), level: Some("Advanced".into()), ..Default::default() };
+        let other = as_teacher(teacher_id + 1);
+        let resp = update_course_details(app_state.clone(), other, params(), web::Json(update.clone())).await;
+        assert_eq!(resp.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+        let resp = delete_course(app_state.clone(), other, params()).await;
+        assert_eq!(resp.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+
+        let owner = as_teacher(teacher_id);
+        let resp = update_course_details(app_state.clone(), owner, params(), web::Json(update)).await.unwrap();
+        let updated: serde_json::Value = json(resp).await;
+        assert_eq!((updated["name"].as_str(), updated["level"].as_str()), (Some("Renamed"), Some("Advanced")));
+        assert_eq!(updated["description"], "An introduction");
+
+        let resp = delete_course(app_state.clone(), owner, params()).await.unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
+        let again = delete_course(app_state.clone(), owner, params()).await;
+        assert_eq!(again.err().unwrap().status_code(), StatusCode::NOT_FOUND);
+        let update = update_course_details(app_state, owner, params(), web::Json(UpdateCourse::default())).await;
+        assert_eq!(update.err().unwrap().status_code(), StatusCode::NOT_FOUND);
     }
 }
@@ -1,12 +1,25 @@
-use crate::{errors::MyError, models::course::Course};
+use crate::errors::MyError;
+use crate::openapi::ApiDoc;
 use crate::state::AppState;
 use actix_web::{web,HttpResponse};
-use chrono::Utc;
+use utoipa::OpenApi;
 
 pub async fn health_check_handler(app_state : web::Data<AppState>) -> Result<HttpResponse,MyError>{
     let health_check_response = &app_state.health_check_response;
-    let mut visitor_count = app_state.visit_count.lock().unwrap();
+    let mut visitor_count = app_state.visit_count.lock()?;
     let res = format!("{} - Visitor Count: {}", health_check_response, *visitor_count);
     *visitor_count +=1;
     Ok(HttpResponse::Ok().json(&res))
-}
\ No newline at end of file
+}
+
+pub async fn openapi_handler() -> HttpResponse{
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+pub async fn metrics_handler(app_state : web::Data<AppState>) -> HttpResponse{
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(app_state.metrics.render())
+}
+
+pub async fn not_found_handler() -> Result<HttpResponse,MyError>{
+    Err(MyError::NotFoundError("No such route".into()))
+}
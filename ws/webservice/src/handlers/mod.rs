@@ -1,2 +1,4 @@
+pub mod auth;
 pub mod course;
-pub mod general;
\ No newline at end of file
+pub mod general;
+pub mod teacher;
\ No newline at end of file
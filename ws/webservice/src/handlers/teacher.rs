@@ -0,0 +1,180 @@
+use crate::auth::AuthUser;
+use crate::dbaccess::teacher::*;
+use crate::errors::{MyError, ProblemDetails};
+use crate::models::teacher::{CreateTeacher, Teacher, UpdateTeacher};
+use crate::state::AppState;
+use actix_web::{web,HttpResponse};
+use validator::Validate;
+
+
+#[utoipa::path(
+    get, path = "/teachers", tag = "teachers",
+    responses((status = 200, description = "All teachers", body = Vec<Teacher>))
+)]
+pub async fn get_all_teachers(app_state : web::Data<AppState>) -> Result<HttpResponse,MyError>{
+    let teachers = get_all_teachers_db(&app_state.db).await?;
+    Ok(HttpResponse::Ok().json(teachers))
+}
+
+#[utoipa::path(
+    get, path = "/teachers/{teacher_id}", tag = "teachers",
+    params(("teacher_id" = i32, Path, description = "Teacher id")),
+    responses(
+        (status = 200, description = "The teacher", body = Teacher),
+        (status = 404, description = "No such teacher",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn get_teacher_details(
+    app_state : web::Data<AppState>,
+    teacher_id: web::Path<i32>
+) -> Result<HttpResponse,MyError>{
+    let teacher = get_teacher_details_db(&app_state.db, teacher_id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(teacher))
+}
+
+#[utoipa::path(
+    post, path = "/teachers", tag = "teachers",
+    request_body = CreateTeacher,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Teacher created", body = Teacher),
+        (status = 400, description = "Invalid input", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid access token",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Admin role required",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn post_new_teacher(
+    app_state : web::Data<AppState>,
+    auth: AuthUser,
+    new_teacher: web::Json<CreateTeacher>
+) -> Result<HttpResponse,MyError>{
+    auth.ensure_admin()?;
+    new_teacher.validate()?;
+    let teacher = post_new_teacher_db(&app_state.db, new_teacher.into()).await?;
+    Ok(HttpResponse::Ok().json(teacher))
+}
+
+#[utoipa::path(
+    put, path = "/teachers/{teacher_id}", tag = "teachers",
+    params(("teacher_id" = i32, Path, description = "Teacher id")),
+    request_body = UpdateTeacher,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The updated teacher", body = Teacher),
+        (status = 400, description = "Invalid input", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such teacher",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid access token",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Not this teacher's data",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn update_teacher_details(
+    app_state : web::Data<AppState>,
+    auth: AuthUser,
+    teacher_id: web::Path<i32>,
+    update_teacher: web::Json<UpdateTeacher>
+) -> Result<HttpResponse,MyError>{
+    let teacher_id = teacher_id.into_inner();
+    auth.ensure_can_manage(teacher_id)?;
+    update_teacher.validate()?;
+    let teacher = update_teacher_details_db(&app_state.db, teacher_id, update_teacher.into()).await?;
+    Ok(HttpResponse::Ok().json(teacher))
+}
+
+#[utoipa::path(
+    delete, path = "/teachers/{teacher_id}", tag = "teachers",
+    params(("teacher_id" = i32, Path, description = "Teacher id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Teacher and their courses deleted", body = String),
+        (status = 404, description = "No such teacher",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid access token",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Not this teacher's data",
+            body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn delete_teacher(
+    app_state : web::Data<AppState>,
+    auth: AuthUser,
+    teacher_id: web::Path<i32>
+) -> Result<HttpResponse,MyError>{
+    let teacher_id = teacher_id.into_inner();
+    auth.ensure_can_manage(teacher_id)?;
+    delete_teacher_db(&app_state.db, teacher_id).await?;
+    Ok(HttpResponse::Ok().json("Teacher deleted"))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use actix_web::http::StatusCode;
+    use crate::auth::JwtKeys;
+    use crate::models::auth::Role;
+    use actix_web::ResponseError;
+    use crate::dbaccess::course::{get_courses_for_teacher_db, post_new_course_db};
+    use crate::models::course::CreateCourse;
+    use sqlx::postgres::PgPool;
+    use std::sync::Mutex;
+
+    fn app_state(db: PgPool) -> web::Data<AppState>{
+        web::Data::new(AppState{
+            health_check_response: "Actix WebService is running".to_string(),
+            visit_count: Mutex::new(0),
+            db,
+            jwt: JwtKeys::new(b"test secret"),
+            metrics: Default::default()
+        })
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs DATABASE_URL pointing at Postgres; see ws/docker-compose.yml"]
+    async fn test_teacher_lifecycle(db: PgPool){
+        let app_state = app_state(db);
+        let new_teacher = CreateTeacher{
+            name: "Ada".into(),
+            picture_url: Some("https://example.com/ada.png".into()),
+            profile: Some("Teaches Rust".into())
+        };
+        let teacher_only = AuthUser{ teacher_id: 1, role: Role::Teacher };
+        let resp = post_new_teacher(app_state.clone(), teacher_only, web::Json(new_teacher.clone())).await;
+        assert_eq!(resp.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+        let admin = AuthUser{ teacher_id: 0, role: Role::Admin };
+        let resp = post_new_teacher(app_state.clone(), admin, web::Json(new_teacher)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let teacher = get_all_teachers_db(&app_state.db).await.unwrap().pop().unwrap();
+        assert_eq!(teacher.name, "Ada");
+
+        let update = UpdateTeacher{ profile: Some("Teaches Rust and SQL".into()), ..Default::default() };
+        let owner = AuthUser{ teacher_id: teacher.id, role: Role::Teacher };
+        update_teacher_details(app_state.clone(), owner, web::Path::from(teacher.id), web::Json(update)).await.unwrap();
+        let updated = get_teacher_details_db(&app_state.db, teacher.id).await.unwrap();
+        assert_eq!((updated.name.as_str(), updated.profile.as_deref()), ("Ada", Some("Teaches Rust and SQL")));
+
+        // Deleting a teacher takes their courses with them.
+        let course = CreateCourse{
+            teacher_id: teacher.id,
+            name: "Rust".into(),
+            description: None,
+            format: None,
+            structure: None,
+            duration: None,
+            price: None,
+            language: None,
+            level: None
+        };
+        post_new_course_db(&app_state.db, course).await.unwrap();
+        delete_teacher(app_state.clone(), owner, web::Path::from(teacher.id)).await.unwrap();
+        let courses = get_courses_for_teacher_db(&app_state.db, teacher.id, &Default::default()).await.unwrap();
+        assert_eq!(courses.total, 0);
+
+        let missing = get_teacher_details(app_state, web::Path::from(teacher.id)).await;
+        assert_eq!(missing.err().unwrap().status_code(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "teacher_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role{
+    Teacher,
+    Admin
+}
+
+/// Credentials row; never serialized.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Account{
+    pub id: i32,
+    pub password_hash: Option<String>,
+    pub role: Role
+}
+
+#[derive(Debug, Clone, Deserialize, Validate, ToSchema)]
+pub struct RegisterTeacher{
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
+    pub name: String,
+    #[validate(
+        email(message = "must be an email address"),
+        length(max = 254, message = "must be at most 254 characters")
+    )]
+    pub email: String,
+    #[validate(length(min = 8, max = 128, message = "must be 8 to 128 characters"))]
+    pub password: String,
+    #[validate(url(message = "must be a URL"), length(max = 200, message = "must be at most 200 characters"))]
+    pub picture_url: Option<String>,
+    #[validate(length(max = 2000, message = "must be at most 2000 characters"))]
+    pub profile: Option<String>
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct Login{
+    pub email: String,
+    pub password: String
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct RefreshToken{
+    pub refresh_token: String
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TokenPair{
+    pub teacher_id: i32,
+    pub role: Role,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    /// Seconds until the access token expires.
+    pub expires_in: i64
+}
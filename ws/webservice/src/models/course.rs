@@ -1,72 +1,151 @@
 use chrono::NaiveDateTime;
 use actix_web::web;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
 
-#[derive(Debug, Clone,Deserialize,Serialize)]
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
 pub struct Course{
-    pub teacher_id : usize,
-    pub id:Option<usize>,
+    pub teacher_id : i32,
+    pub id: i32,
     pub name:String,
-    pub time: Option<NaiveDateTime>
+    pub time: Option<NaiveDateTime>,
+    pub description: Option<String>,
+    pub format: Option<String>,
+    pub structure: Option<String>,
+    pub duration: Option<String>,
+    pub price: Option<f32>,
+    pub language: Option<String>,
+    pub level: Option<String>
 }
 
-impl From<web::Json<Course>> for Course{
-    fn from(course: web::Json<Course>) -> Self{
-        Course{
-            teacher_id: course.teacher_id,
-            id: course.id,
-            name: course.name.clone(),
-            time: course.time
+// Length limits mirror the column sizes in migrations/.
+#[derive(Debug, Clone, Deserialize, Validate, ToSchema)]
+pub struct CreateCourse{
+    #[validate(range(min = 1, message = "must be a positive id"))]
+    pub teacher_id : i32,
+    #[validate(length(min = 1, max = 140, message = "must be 1 to 140 characters"))]
+    pub name:String,
+    #[validate(length(max = 2000, message = "must be at most 2000 characters"))]
+    pub description: Option<String>,
+    #[validate(length(max = 30, message = "must be at most 30 characters"))]
+    pub format: Option<String>,
+    #[validate(length(max = 200, message = "must be at most 200 characters"))]
+    pub structure: Option<String>,
+    #[validate(length(max = 30, message = "must be at most 30 characters"))]
+    pub duration: Option<String>,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub price: Option<f32>,
+    #[validate(length(max = 30, message = "must be at most 30 characters"))]
+    pub language: Option<String>,
+    #[validate(length(max = 30, message = "must be at most 30 characters"))]
+    pub level: Option<String>
+}
+
+impl From<web::Json<CreateCourse>> for CreateCourse{
+    fn from(course: web::Json<CreateCourse>) -> Self{
+        course.into_inner()
+    }
+}
+
+/// Fields left out keep their current value.
+#[derive(Debug, Clone, Default, Deserialize, Validate, ToSchema)]
+pub struct UpdateCourse{
+    #[validate(length(min = 1, max = 140, message = "must be 1 to 140 characters"))]
+    pub name: Option<String>,
+    #[validate(length(max = 2000, message = "must be at most 2000 characters"))]
+    pub description: Option<String>,
+    #[validate(length(max = 30, message = "must be at most 30 characters"))]
+    pub format: Option<String>,
+    #[validate(length(max = 200, message = "must be at most 200 characters"))]
+    pub structure: Option<String>,
+    #[validate(length(max = 30, message = "must be at most 30 characters"))]
+    pub duration: Option<String>,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub price: Option<f32>,
+    #[validate(length(max = 30, message = "must be at most 30 characters"))]
+    pub language: Option<String>,
+    #[validate(length(max = 30, message = "must be at most 30 characters"))]
+    pub level: Option<String>
+}
+
+impl From<web::Json<UpdateCourse>> for UpdateCourse{
+    fn from(course: web::Json<UpdateCourse>) -> Self{
+        course.into_inner()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CourseSort{
+    #[default]
+    Id,
+    Name,
+    Time,
+    Price
+}
+
+impl CourseSort{
+    pub fn column(self) -> &'static str{
+        match self {
+            CourseSort::Id => "id",
+            CourseSort::Name => "name",
+            CourseSort::Time => "time",
+            CourseSort::Price => "price"
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder{
+    #[default]
+    Asc,
+    Desc
+}
 
-// use chrono::NaiveDateTime;
-// use actix_web::web;
-// use serde::{Deserialize, Serialize};
+impl SortOrder{
+    pub fn keyword(self) -> &'static str{
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC"
+        }
+    }
+}
 
-// #[derive(Debug,Deserialize,Clone,Serialize)]
-// pub struct Course{
-//     pub teacher_id : i32,
-//     pub id: i32,
-//     pub name:String,
-//     pub time: Option<NaiveDateTime>,
-//     pub description: Option<String>,
-//     pub format: Option<String>,
-//     pub duration: Option<String>,
-//     pub struction: Option<String>,
-//     pub price: Option<f32>,
-//     pub language: Option<String>,
-//     pub level: Option<String>
-// }
+/// Query string for listing a teacher's courses.
+#[derive(Debug, Clone, Default, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CourseQuery{
+    /// 1-based page number, default 1.
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub page: Option<u32>,
+    /// Page size, default 20.
+    #[validate(range(min = 1, max = 100, message = "must be between 1 and 100"))]
+    pub per_page: Option<u32>,
+    pub sort: Option<CourseSort>,
+    pub order: Option<SortOrder>,
+    /// Case-insensitive substring of the course name.
+    pub name: Option<String>,
+    pub language: Option<String>,
+    pub level: Option<String>
+}
 
+impl CourseQuery{
+    pub fn page(&self) -> u32{
+        self.page.unwrap_or(1)
+    }
 
-// #[derive(Debug, Clone,Serialize)]
-// pub struct CreateCourse{
-//     pub teacher_id : i32,
-//     pub name:String,
-//     pub description: Option<String>,
-//     pub format: Option<String>,
-//     pub duration: Option<String>,
-//     pub struction: Option<String>,
-//     pub price: Option<f32>,
-//     pub language: Option<String>,
-//     pub level: Option<String>
-// }
+    pub fn per_page(&self) -> u32{
+        self.per_page.unwrap_or(20)
+    }
+}
 
-// impl From<web::Json<CreateCourse>> for CreateCourse{
-//     fn from(course: web::Json<CreateCourse>) -> Self{
-//         CreateCourse{
-//             teacher_id: course.teacher_id,
-//             name: course.name.clone(),
-//             description: course.description.clone(),
-//             format: course.format.clone(),
-//             struction: course.duration.clone(),
-//             duration: course.duration.clone(),
-//             price: course.price.clone(),
-//             language: course.language.clone(),
-//             level: course.level.clone()
-//         }
-//     }
-// }
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CoursePage{
+    pub items: Vec<Course>,
+    pub page: u32,
+    pub per_page: u32,
+    /// Matching courses across all pages.
+    pub total: i64
+}
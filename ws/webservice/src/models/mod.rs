@@ -1 +1,3 @@
-pub mod course;
\ No newline at end of file
+pub mod auth;
+pub mod course;
+pub mod teacher;
\ No newline at end of file
@@ -0,0 +1,45 @@
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct Teacher{
+    pub id: i32,
+    pub name: String,
+    pub picture_url: Option<String>,
+    pub profile: Option<String>
+}
+
+#[derive(Debug, Clone, Deserialize, Validate, ToSchema)]
+pub struct CreateTeacher{
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
+    pub name: String,
+    #[validate(url(message = "must be a URL"), length(max = 200, message = "must be at most 200 characters"))]
+    pub picture_url: Option<String>,
+    #[validate(length(max = 2000, message = "must be at most 2000 characters"))]
+    pub profile: Option<String>
+}
+
+impl From<web::Json<CreateTeacher>> for CreateTeacher{
+    fn from(teacher: web::Json<CreateTeacher>) -> Self{
+        teacher.into_inner()
+    }
+}
+
+/// Fields left out keep their current value.
+#[derive(Debug, Clone, Default, Deserialize, Validate, ToSchema)]
+pub struct UpdateTeacher{
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
+    pub name: Option<String>,
+    #[validate(url(message = "must be a URL"), length(max = 200, message = "must be at most 200 characters"))]
+    pub picture_url: Option<String>,
+    #[validate(length(max = 2000, message = "must be at most 2000 characters"))]
+    pub profile: Option<String>
+}
+
+impl From<web::Json<UpdateTeacher>> for UpdateTeacher{
+    fn from(teacher: web::Json<UpdateTeacher>) -> Self{
+        teacher.into_inner()
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web;
+use tracing::{field, Instrument};
+use crate::state::AppState;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Upper bounds in seconds of the latency histogram buckets.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram{
+    /// Per-bucket counts, not yet cumulative.
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64
+}
+
+/// Request latencies keyed by method, route pattern and status.
+#[derive(Debug, Default)]
+pub struct Metrics{
+    routes: Mutex<BTreeMap<(String, String, u16), Histogram>>
+}
+
+impl Metrics{
+    pub fn observe(&self, method: &str, route: &str, status: u16, elapsed: Duration){
+        // A panic mid-update can at worst lose one sample, so a poisoned lock is still usable.
+        let mut routes = self.routes.lock().unwrap_or_else(PoisonError::into_inner);
+        let histogram = routes.entry((method.to_string(), route.to_string(), status)).or_default();
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|&le| secs <= le) {
+            histogram.buckets[i] += 1;
+        }
+        histogram.sum += secs;
+        histogram.count += 1;
+    }
+
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String{
+        let routes = self.routes.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut out = String::from(
+            "# HELP http_request_duration_seconds Request latency by route.\n\
+             # TYPE http_request_duration_seconds histogram\n"
+        );
+        for ((method, route, status), histogram) in routes.iter() {
+            let labels = format!("method=\"{method}\",route=\"{}\",status=\"{status}\"", escape_label(route));
+            let mut cumulative = 0;
+            for (le, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "http_request_duration_seconds_bucket{{{labels},le=\"{le}\"}} {cumulative}");
+            }
+            let _ = writeln!(out, "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}", histogram.count);
+            let _ = writeln!(out, "http_request_duration_seconds_sum{{{labels}}} {}", histogram.sum);
+            let _ = writeln!(out, "http_request_duration_seconds_count{{{labels}}} {}", histogram.count);
+        }
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Keeps a caller's request id if it looks sane, otherwise makes one up.
+fn request_id(req: &ServiceRequest) -> String{
+    req.headers().get(&REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            (1..=128).contains(&id.len())
+                && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()))
+}
+
+/// Runs each request inside a `request` span carrying its id, echoes the id in `X-Request-Id` and records
+/// latency per route.
+pub async fn observe(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error>{
+    let id = request_id(&req);
+    let method = req.method().to_string();
+    let span = tracing::info_span!(
+        "request", request_id = %id, method = %method, path = %req.path(), status = field::Empty
+    );
+    let metrics = req.app_data::<web::Data<AppState>>().cloned();
+    let start = Instant::now();
+
+    let mut res = next.call(req).instrument(span.clone()).await?;
+
+    let elapsed = start.elapsed();
+    let status = res.status().as_u16();
+    let route = res.request().match_pattern().unwrap_or_else(|| "unmatched".into());
+    if let Some(app_state) = metrics {
+        app_state.metrics.observe(&method, &route, status, elapsed);
+    }
+    span.record("status", status);
+    tracing::info!(parent: &span, latency_ms = elapsed.as_secs_f64() * 1000.0, "request completed");
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID, value);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative(){
+        let metrics = Metrics::default();
+        metrics.observe("GET", "/courses/{teacher_id}", 200, Duration::from_millis(3));
+        metrics.observe("GET", "/courses/{teacher_id}", 200, Duration::from_millis(40));
+        metrics.observe("GET", "/courses/{teacher_id}", 200, Duration::from_secs(30));
+        let text = metrics.render();
+        let labels = r#"method="GET",route="/courses/{teacher_id}",status="200""#;
+        assert!(text.contains(&format!("http_request_duration_seconds_bucket{{{labels},le=\"0.005\"}} 1\n")));
+        assert!(text.contains(&format!("http_request_duration_seconds_bucket{{{labels},le=\"0.05\"}} 2\n")));
+        assert!(text.contains(&format!("http_request_duration_seconds_bucket{{{labels},le=\"10\"}} 2\n")));
+        assert!(text.contains(&format!("http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 3\n")));
+        assert!(text.contains(&format!("http_request_duration_seconds_count{{{labels}}} 3\n")));
+    }
+
+    #[actix_rt::test]
+    async fn test_requests_get_ids_and_are_measured(){
+        use crate::auth::JwtKeys;
+        use crate::handlers::general::not_found_handler;
+        use crate::routers::general_routes;
+        use actix_web::{middleware, test, App};
+        use sqlx::postgres::PgPool;
+
+        let app_state = web::Data::new(AppState{
+            health_check_response: "Actix WebService is running".to_string(),
+            visit_count: Mutex::new(0),
+            db: PgPool::connect_lazy("postgres://localhost/unused").unwrap(),
+            jwt: JwtKeys::new(b"test secret"),
+            metrics: Default::default()
+        });
+        let app = test::init_service(
+            App::new().app_data(app_state.clone())
+                .configure(general_routes)
+                .default_service(web::to(not_found_handler))
+                .wrap(middleware::from_fn(observe))
+        ).await;
+
+        let req = test::TestRequest::get().uri("/health").insert_header((REQUEST_ID, "abc-123")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(REQUEST_ID).unwrap(), "abc-123");
+
+        // Ids that could smuggle anything into logs or headers are replaced.
+        let req = test::TestRequest::get().uri("/health").insert_header((REQUEST_ID, "a b")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(REQUEST_ID).unwrap().len(), 32);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/nope/42").to_request()).await;
+        assert_eq!(resp.status(), 404);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "application/problem+json");
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let text = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+        assert!(text.contains(r#"_count{method="GET",route="/health",status="200"} 2"#));
+        assert!(text.contains(r#"_count{method="GET",route="unmatched",status="404"} 1"#));
+    }
+
+    #[test]
+    fn test_poisoned_metrics_still_record(){
+        let metrics = std::sync::Arc::new(Metrics::default());
+        let poisoner = metrics.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.routes.lock().unwrap();
+            panic!("poison the lock");
+        }).join();
+        metrics.observe("GET", "/health", 200, Duration::from_millis(1));
+        assert!(metrics.render().contains(r#"route="/health""#));
+    }
+}
@@ -0,0 +1,43 @@
+use crate::handlers::{auth, course, teacher};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// OpenAPI document assembled from the `#[utoipa::path]` annotations on the handlers.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Teacher Service"),
+    paths(
+        auth::register,
+        auth::login,
+        auth::refresh,
+        auth::logout,
+        course::post_new_course,
+        course::get_courses_for_teacher,
+        course::get_course_details,
+        course::update_course_details,
+        course::delete_course,
+        teacher::get_all_teachers,
+        teacher::get_teacher_details,
+        teacher::post_new_teacher,
+        teacher::update_teacher_details,
+        teacher::delete_teacher
+    ),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "auth", description = "Teacher accounts and tokens"),
+        (name = "courses", description = "Courses offered by a teacher"),
+        (name = "teachers", description = "Teacher profiles")
+    )
+)]
+pub struct ApiDoc;
+
+/// Declares the `bearer_auth` scheme the protected paths refer to.
+struct BearerAuth;
+
+impl Modify for BearerAuth{
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi){
+        let scheme = Http::builder().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build();
+        openapi.components.get_or_insert_with(Default::default)
+            .add_security_scheme("bearer_auth", SecurityScheme::Http(scheme));
+    }
+}
@@ -1,13 +1,43 @@
-use actix_web::{web,App,HttpResponse,HttpServer,Responder};
+use actix_web::web;
+use crate::handlers::auth::*;
 use crate::handlers::general::*;
 use crate::handlers::course::*;
+use crate::handlers::teacher::*;
 
 pub fn general_routes(cfg:&mut web::ServiceConfig){
-    cfg.route("/health", web::get().to(health_check_handler));
+    cfg.route("/health", web::get().to(health_check_handler))
+        .route("/api-docs/openapi.json", web::get().to(openapi_handler))
+        .route("/metrics", web::get().to(metrics_handler));
 }
 
 pub fn course_routes(cfg:&mut web::ServiceConfig){
-    cfg.route("/courses", web::post().to(new_course_handler))
-    .route("/courses/{teacher_id}", web::get().to(get_courses_for_teacher_handler))
-    .route("/courses/{teacher_id}/{course_id}", web::get().to(new_course_detail_handler));
-}
\ No newline at end of file
+    cfg.service(
+        web::scope("/courses")
+            .route("", web::post().to(post_new_course))
+            .route("/{teacher_id}", web::get().to(get_courses_for_teacher))
+            .route("/{teacher_id}/{course_id}", web::get().to(get_course_details))
+            .route("/{teacher_id}/{course_id}", web::put().to(update_course_details))
+            .route("/{teacher_id}/{course_id}", web::delete().to(delete_course))
+    );
+}
+
+pub fn teacher_routes(cfg:&mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/teachers")
+            .route("", web::post().to(post_new_teacher))
+            .route("", web::get().to(get_all_teachers))
+            .route("/{teacher_id}", web::get().to(get_teacher_details))
+            .route("/{teacher_id}", web::put().to(update_teacher_details))
+            .route("/{teacher_id}", web::delete().to(delete_teacher))
+    );
+}
+
+pub fn auth_routes(cfg:&mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/auth")
+            .route("/register", web::post().to(register))
+            .route("/login", web::post().to(login))
+            .route("/refresh", web::post().to(refresh))
+            .route("/logout", web::post().to(logout))
+    );
+}
@@ -1,9 +1,13 @@
 use std::sync::Mutex;
-use crate::models::course::Course;
+use sqlx::postgres::PgPool;
+use crate::auth::JwtKeys;
+use crate::observability::Metrics;
 
 #[derive(Debug)]
 pub struct AppState{
     pub health_check_response:String,
     pub visit_count:Mutex<u32>,
-    pub courses: Mutex<Vec<Course>>
-}
\ No newline at end of file
+    pub db: PgPool,
+    pub jwt: JwtKeys,
+    pub metrics: Metrics
+}